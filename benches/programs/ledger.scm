@@ -0,0 +1,39 @@
+;; Posts transactions to accounts kept as records in an association list,
+;; and makes a statement: records, strings, vectors and closures.
+
+(define-record-type account
+  (make-account name balance history)
+  account?
+  (name account-name)
+  (balance account-balance set-account-balance!)
+  (history account-history set-account-history!))
+
+(define names (vector "alice" "bob" "carol" "dave" "erin" "frank" "grace"))
+
+(define accounts
+  (map (lambda (name) (cons name (make-account name 0 (quote ()))))
+       (vector->list names)))
+
+(define (post! name amount memo)
+  (let ((a (cdr (assoc name accounts))))
+    (set-account-balance! a (+ (account-balance a) amount))
+    (set-account-history! a (cons (string-append memo ": " (number->string amount))
+                                  (account-history a)))))
+
+(define (transactions n)
+  (do ((i 0 (+ i 1)))
+      ((= i n))
+    (let ((from (vector-ref names (modulo i 7)))
+          (to (vector-ref names (modulo (* i 3) 7)))
+          (amount (+ 1 (modulo (* i 37) 100))))
+      (post! from (- amount) (string-append "to " to))
+      (post! to amount (string-append "from " from)))))
+
+(transactions 500)
+
+(define (statement a)
+  (string-append (account-name a) " "
+                 (number->string (account-balance a)) " "
+                 (number->string (length (account-history a)))))
+
+(map (lambda (entry) (statement (cdr entry))) accounts)
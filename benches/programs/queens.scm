@@ -0,0 +1,23 @@
+;; Counts the solutions to the eight queens problem.
+
+(define (ok? row dist placed)
+  (or (null? placed)
+      (and (not (= (car placed) (+ row dist)))
+           (not (= (car placed) (- row dist)))
+           (not (= (car placed) row))
+           (ok? row (+ dist 1) (cdr placed)))))
+
+(define (queens n)
+  (define (place k placed)
+    (if (= k 0)
+        1
+        (let loop ((row 1) (total 0))
+          (if (> row n)
+              total
+              (loop (+ row 1)
+                    (if (ok? row 1 placed)
+                        (+ total (place (- k 1) (cons row placed)))
+                        total))))))
+  (place n (quote ())))
+
+(queens 8)
@@ -0,0 +1,34 @@
+;; Sorts a list of pseudo-random numbers with merge sort, and checks the
+;; result.
+
+(define (random-list n seed)
+  (let loop ((i 0) (x seed) (acc (quote ())))
+    (if (= i n)
+        acc
+        (let ((next (modulo (+ (* x 75) 74) 65537)))
+          (loop (+ i 1) next (cons next acc))))))
+
+(define (split l)
+  (if (or (null? l) (null? (cdr l)))
+      (values l (quote ()))
+      (let-values (((a b) (split (cddr l))))
+        (values (cons (car l) a) (cons (cadr l) b)))))
+
+(define (merge a b)
+  (cond ((null? a) b)
+        ((null? b) a)
+        ((< (car b) (car a)) (cons (car b) (merge a (cdr b))))
+        (else (cons (car a) (merge (cdr a) b)))))
+
+(define (sort l)
+  (if (or (null? l) (null? (cdr l)))
+      l
+      (let-values (((a b) (split l)))
+        (merge (sort a) (sort b)))))
+
+(define (sorted? l)
+  (or (null? l) (null? (cdr l))
+      (and (<= (car l) (cadr l)) (sorted? (cdr l)))))
+
+(define sorted (sort (random-list 2000 42)))
+(list (length sorted) (sorted? sorted))
@@ -0,0 +1,274 @@
+//! Throughput of the lexer and the reader (owned, borrowed and on all
+//! processors), syntax analysis (to `syntax` and to the `ast` arena) and
+//! evaluation, over synthetic corpora and the programs in
+//! `benches/programs`, and of the VM against a tree-walking interpreter.
+//! Run with
+//!
+//! ```text
+//! cargo bench [-- [--save FILE] [--baseline FILE] [FILTER...]]
+//! ```
+//!
+//! Only benchmarks whose names contain one of the filters run. `--save`
+//! writes the median time of each benchmark to FILE, and `--baseline`
+//! prints the change from the times saved in FILE.
+
+extern crate scheme_syntax;
+
+mod tree_walk;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use scheme_syntax::ast::Ast;
+use scheme_syntax::{borrowed, parallel};
+use scheme_syntax::{parse_expression, Interpreter, Node, Parser, ReadLimits, RegexLexer,
+                    Value};
+use scheme_syntax::syntax::Expression;
+
+/// How long each benchmark runs for, after one untimed run.
+const TARGET: Duration = Duration::from_secs(2);
+const MIN_SAMPLES: usize = 5;
+
+struct Corpus {
+    name: String,
+    text: String,
+}
+
+/// `(+ 1 (+ 1 ... 1))`, nested `depth` deep, `count` times.
+fn deep_nesting(depth: usize, count: usize) -> String {
+    let one = format!("{}1{}\n", "(+ 1 ".repeat(depth), ")".repeat(depth));
+    one.repeat(count)
+}
+
+/// Quoted lists of `length` numbers and identifiers, `count` times.
+fn long_lists(length: usize, count: usize) -> String {
+    let items: Vec<String> = (0..length)
+        .map(|i| if i % 2 == 0 { i.to_string() } else { format!("x{}", i) })
+        .collect();
+    format!("(quote ({}))\n", items.join(" ")).repeat(count)
+}
+
+/// Definitions of strings with escapes, `count` times.
+fn many_strings(count: usize) -> String {
+    (0..count)
+        .map(|i| format!("(define s{} \"line {}\\n\\t\\\"quoted\\\" \\\\ and more text\")\n", i, i))
+        .collect()
+}
+
+fn corpora() -> Vec<Corpus> {
+    let mut res = vec![
+        Corpus { name: "deep-nesting".to_string(), text: deep_nesting(100, 400) },
+        Corpus { name: "long-lists".to_string(), text: long_lists(10000, 4) },
+        Corpus { name: "many-strings".to_string(), text: many_strings(5000) },
+    ];
+    // Real programs, repeated to be about as large as the synthetic ones.
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut programs = String::new();
+    for path in programs_in(&root.join("benches").join("programs"))
+        .into_iter()
+        .chain(Some(root.join("src").join("prelude.scm")))
+    {
+        programs.push_str(&fs::read_to_string(&path).unwrap());
+        programs.push('\n');
+    }
+    let copies = (200_000 / programs.len()).max(1);
+    res.push(Corpus { name: "programs".to_string(), text: programs.repeat(copies) });
+    res
+}
+
+fn programs_in(dir: &Path) -> Vec<::std::path::PathBuf> {
+    let mut paths: Vec<_> = fs::read_dir(dir).unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension() == Some("scm".as_ref()))
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn count_nodes(node: &Node) -> usize {
+    match node {
+        Node::List(items) => 1 + items.iter().map(count_nodes).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+fn count_borrowed(node: &borrowed::Node) -> usize {
+    match node {
+        borrowed::Node::List(items) => 1 + items.iter().map(count_borrowed).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+fn read(text: &str) -> Vec<Node> {
+    Parser::new(RegexLexer::new(text)).map(|n| n.unwrap()).collect()
+}
+
+/// Programs that both the VM and the tree-walking baseline run, with
+/// their values.
+const BASELINE: &[(&str, &str, i32)] = &[
+    ("fib", "(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))) (fib 22)", 17711),
+    ("tak", "(define (tak x y z) \
+               (if (< y x) (tak (tak (- x 1) y z) (tak (- y 1) z x) (tak (- z 1) x y)) z)) \
+             (tak 18 12 6)", 7),
+];
+
+fn expressions(text: &str) -> Vec<Expression> {
+    read(text).into_iter().map(|n| parse_expression(n).unwrap()).collect()
+}
+
+struct Bench {
+    filters: Vec<String>,
+    baseline: HashMap<String, f64>,
+    results: Vec<(String, f64)>,
+}
+
+impl Bench {
+    /// Times `run` on the value made by `setup`, which is not timed, and
+    /// prints the median time and how many `unit`s `run` processes a
+    /// second.
+    fn run<T, S, F>(&mut self, name: &str, unit: &str, mut setup: S, mut run: F)
+        where S: FnMut() -> T, F: FnMut(T) -> usize
+    {
+        if !self.filters.is_empty() && !self.filters.iter().any(|f| name.contains(f.as_str())) {
+            return;
+        }
+        let units = run(setup());
+        let mut samples = Vec::new();
+        let start = Instant::now();
+        while samples.len() < MIN_SAMPLES || start.elapsed() < TARGET {
+            let input = setup();
+            let t = Instant::now();
+            run(input);
+            samples.push(t.elapsed().as_secs_f64());
+        }
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median = samples[samples.len() / 2];
+        let change = match self.baseline.get(name) {
+            Some(old) => format!("  {:+6.1}%", (median / old - 1.0) * 100.0),
+            None => String::new(),
+        };
+        println!("{:<32} {:>10.3} ms  {:>12.0} {}/s{}",
+                 name, median * 1e3, units as f64 / median, unit, change);
+        self.results.push((name.to_string(), median));
+    }
+}
+
+fn main() {
+    let mut filters = Vec::new();
+    let mut save = None;
+    let mut baseline = HashMap::new();
+    let mut args = ::std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--save" => save = args.next(),
+            "--baseline" => {
+                let path = args.next().expect("--baseline requires a file");
+                for line in fs::read_to_string(&path).unwrap().lines() {
+                    let mut words = line.split_whitespace();
+                    if let (Some(name), Some(time)) = (words.next(), words.next()) {
+                        baseline.insert(name.to_string(), time.parse().unwrap());
+                    }
+                }
+            },
+            // Flags such as --bench, which cargo passes.
+            a if a.starts_with("--") => (),
+            _ => filters.push(arg),
+        }
+    }
+    let mut bench = Bench { filters, baseline, results: Vec::new() };
+
+    for corpus in corpora() {
+        let text = &corpus.text;
+        bench.run(&format!("lex/{}", corpus.name), "tokens", || (), |()| {
+            let mut count = 0;
+            for token in RegexLexer::new(text) {
+                token.unwrap();
+                count += 1;
+            }
+            count
+        });
+        bench.run(&format!("lex-borrowed/{}", corpus.name), "tokens", || (), |()| {
+            let mut count = 0;
+            for token in borrowed::Tokens::new(text) {
+                token.unwrap();
+                count += 1;
+            }
+            count
+        });
+        bench.run(&format!("read/{}", corpus.name), "nodes", || (), |()| {
+            read(text).iter().map(count_nodes).sum()
+        });
+        bench.run(&format!("read-parallel/{}", corpus.name), "nodes", || (), |()| {
+            let data = parallel::read(text, parallel::threads(), ReadLimits::default()).unwrap();
+            data.iter().map(|(node, _)| count_nodes(node)).sum()
+        });
+        bench.run(&format!("read-borrowed/{}", corpus.name), "nodes", || (), |()| {
+            borrowed::Parser::new(text).map(|n| count_borrowed(&n.unwrap())).sum()
+        });
+        let nodes = read(text);
+        let count = nodes.iter().map(count_nodes).sum();
+        bench.run(&format!("expand/{}", corpus.name), "nodes", || nodes.clone(), |nodes| {
+            for node in nodes {
+                parse_expression(node).unwrap();
+            }
+            count
+        });
+        bench.run(&format!("arena/{}", corpus.name), "nodes", Ast::new, |mut ast| {
+            for node in &nodes {
+                ast.parse(node).unwrap();
+            }
+            count
+        });
+    }
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches").join("programs");
+    for path in programs_in(&dir) {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let text = fs::read_to_string(&path).unwrap();
+        bench.run(&format!("eval/{}", name), "runs", Interpreter::new, |mut interp| {
+            interp.eval::<Value>(&text).unwrap();
+            1
+        });
+    }
+    let text = deep_nesting(100, 40);
+    bench.run("eval/deep-nesting", "runs", Interpreter::new, |mut interp| {
+        interp.eval::<Value>(&text).unwrap();
+        1
+    });
+    bench.run("eval/startup", "runs", || (), |()| {
+        Interpreter::new();
+        1
+    });
+
+    for &(name, text, value) in BASELINE {
+        bench.run(&format!("eval/{}", name), "runs", Interpreter::new, |mut interp| {
+            assert_eq!(interp.eval::<i32>(text).unwrap(), value);
+            1
+        });
+        bench.run(&format!("tree-walk/{}", name), "runs", || (), |()| {
+            let program = expressions(text);
+            let env = tree_walk::Env::global();
+            let mut v = tree_walk::Val::Unspecified;
+            for e in &program {
+                v = tree_walk::eval(e, &env);
+            }
+            assert_eq!(tree_walk::number(&v), value);
+            1
+        });
+    }
+    for &(name, _, _) in BASELINE {
+        let time = |prefix: &str| bench.results.iter()
+            .find(|r| r.0 == format!("{}/{}", prefix, name))
+            .map(|r| r.1);
+        if let (Some(vm), Some(tree)) = (time("eval"), time("tree-walk")) {
+            println!("{:<32} {:>10.2}x faster than tree walking", format!("eval/{}", name), tree / vm);
+        }
+    }
+
+    if let Some(path) = save {
+        let text: String = bench.results.iter().map(|(n, t)| format!("{} {}\n", n, t)).collect();
+        fs::write(&path, text).unwrap();
+    }
+}
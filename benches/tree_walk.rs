@@ -0,0 +1,104 @@
+//! A direct interpreter of `syntax` expressions, as a baseline for the
+//! bytecode VM. It walks the expression tree on every evaluation, looks
+//! variables up by name in a chain of environments, and knows only the
+//! forms and procedures that the baseline programs use.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use scheme_syntax::syntax::{Expression, Formals};
+use scheme_syntax::Literal;
+
+/// A value, whose closures borrow their code from the program.
+#[derive(Clone)]
+pub enum Val<'e> {
+    Number(i32),
+    Boolean(bool),
+    Primitive(fn(&[Val<'e>]) -> Val<'e>),
+    Closure(&'e [String], &'e [Expression], Rc<Env<'e>>),
+    Unspecified,
+}
+
+pub struct Env<'e> {
+    vars: RefCell<HashMap<&'e str, Val<'e>>>,
+    parent: Option<Rc<Env<'e>>>,
+}
+
+impl <'e> Env<'e> {
+    /// An environment with the primitives the baseline programs use.
+    pub fn global() -> Rc<Env<'e>> {
+        let mut vars: HashMap<&str, Val> = HashMap::new();
+        vars.insert("+", Val::Primitive(|a| Val::Number(a.iter().map(number).sum())));
+        vars.insert("-", Val::Primitive(|a| Val::Number(number(&a[0]) - number(&a[1]))));
+        vars.insert("<", Val::Primitive(|a| Val::Boolean(number(&a[0]) < number(&a[1]))));
+        vars.insert("=", Val::Primitive(|a| Val::Boolean(number(&a[0]) == number(&a[1]))));
+        Rc::new(Env { vars: RefCell::new(vars), parent: None })
+    }
+
+    fn lookup(&self, name: &str) -> Val<'e> {
+        if let Some(v) = self.vars.borrow().get(name) {
+            return v.clone();
+        }
+        match self.parent {
+            Some(ref parent) => parent.lookup(name),
+            None => panic!("unbound variable {}", name),
+        }
+    }
+}
+
+pub fn number(v: &Val) -> i32 {
+    match v {
+        Val::Number(n) => *n,
+        _ => panic!("not a number"),
+    }
+}
+
+pub fn eval<'e>(e: &'e Expression, env: &Rc<Env<'e>>) -> Val<'e> {
+    match e {
+        Expression::Literal(Literal::Number(n)) => Val::Number(*n),
+        Expression::Literal(Literal::Boolean(b)) => Val::Boolean(*b),
+        Expression::Variable(name) => env.lookup(name),
+        Expression::If(test, consequent, alternative) => match eval(test, env) {
+            Val::Boolean(false) => match alternative {
+                Some(alternative) => eval(alternative, env),
+                None => Val::Unspecified,
+            },
+            _ => eval(consequent, env),
+        },
+        Expression::Begin(es) => sequence(es, env),
+        Expression::Lambda(Formals { required, rest: None }, body) =>
+            Val::Closure(required, body, env.clone()),
+        Expression::Define(name, value) => {
+            let v = eval(value, env);
+            env.vars.borrow_mut().insert(name, v);
+            Val::Unspecified
+        },
+        Expression::Application(operator, operands) => {
+            let f = eval(operator, env);
+            let args: Vec<Val> = operands.iter().map(|e| eval(e, env)).collect();
+            apply(&f, args)
+        },
+        e => panic!("not supported by the baseline: {:?}", e),
+    }
+}
+
+fn sequence<'e>(es: &'e [Expression], env: &Rc<Env<'e>>) -> Val<'e> {
+    let mut v = Val::Unspecified;
+    for e in es {
+        v = eval(e, env);
+    }
+    v
+}
+
+fn apply<'e>(f: &Val<'e>, args: Vec<Val<'e>>) -> Val<'e> {
+    match f {
+        Val::Primitive(p) => p(&args),
+        Val::Closure(params, body, env) => {
+            let vars = params.iter().map(|p| p.as_str()).zip(args).collect();
+            let env = Rc::new(Env { vars: RefCell::new(vars), parent: Some(env.clone()) });
+            sequence(body, &env)
+        },
+        _ => panic!("not a procedure"),
+    }
+}
@@ -0,0 +1,114 @@
+;; A subset of the R7RS small test suite, taken from the examples in
+;; chapter 4 of the report as collected in chibi-scheme's r7rs-tests.scm.
+;; Quoted data use `quote`, since the reader has no `'` abbreviation, and
+;; vectors are built with `vector`, since it has no `#(...)` syntax.
+
+(test-begin "4.1 Primitive expression types")
+(define x 28)
+(test 28 x)
+(test (quote a) (quote a))
+(test (list (quote +) 1 2) (quote (+ 1 2)))
+(test "abc" "abc")
+(test 145932 145932)
+(test #t #t)
+(test 7 (+ 3 4))
+(test 12 ((if #f + *) 3 4))
+(test 8 ((lambda (x) (+ x x)) 4))
+(define reverse-subtract
+  (lambda (x y) (- y x)))
+(test 3 (reverse-subtract 7 10))
+(define add4
+  (let ((x 4))
+    (lambda (y) (+ x y))))
+(test 10 (add4 6))
+(test (list 3 4 5 6) ((lambda x x) 3 4 5 6))
+(test (list 5 6) ((lambda (x y . z) z) 3 4 5 6))
+(test (quote yes) (if (> 3 2) (quote yes) (quote no)))
+(test (quote no) (if (> 2 3) (quote yes) (quote no)))
+(test 1 (if (> 3 2) (- 3 2) (+ 3 2)))
+(define x 2)
+(test 3 (+ x 1))
+(set! x 4)
+(test 5 (+ x 1))
+(test-end)
+
+(test-begin "4.2 Derived expression types")
+(test (quote greater) (cond ((> 3 2) (quote greater)) ((< 3 2) (quote less))))
+(test (quote equal) (cond ((> 3 3) (quote greater)) ((< 3 3) (quote less)) (else (quote equal))))
+(test 2 (cond ((assv (quote b) (quote ((a 1) (b 2)))) => cadr) (else #f)))
+(test (quote composite) (case (* 2 3) ((2 3 5 7) (quote prime)) ((1 4 6 8 9) (quote composite))))
+(test (quote c) (case (car (quote (c d))) ((a e i o u) (quote vowel)) ((w y) (quote semivowel)) (else => (lambda (x) x))))
+(test #t (and (= 2 2) (> 2 1)))
+(test #f (and (= 2 2) (< 2 1)))
+(test (quote (f g)) (and 1 2 (quote c) (quote (f g))))
+(test #t (and))
+(test #t (or (= 2 2) (> 2 1)))
+(test #t (or (= 2 2) (< 2 1)))
+(test #f (or #f #f #f))
+(test (quote (b c)) (or (memq (quote b) (quote (a b c))) (/ 3 0)))
+(test-output "12" (when (= 1 1.0) (display "1") (display "2")))
+(test-output "" (unless (= 1 1.0) (display "1") (display "2")))
+(test 6 (let ((x 2) (y 3)) (* x y)))
+(test 35 (let ((x 2) (y 3)) (let ((x 7) (z (+ x y))) (* z x))))
+;; let*, letrec and letrec* are not supported yet.
+(test-expect-fail 3)
+(test 70 (let ((x 2) (y 3)) (let* ((x 7) (z (+ x y))) (* z x))))
+(test #t (letrec ((even? (lambda (n) (if (zero? n) #t (odd? (- n 1)))))
+                  (odd? (lambda (n) (if (zero? n) #f (even? (- n 1))))))
+           (even? 88)))
+(test 5 (letrec* ((p (lambda (x) (+ 1 (q (- x 1)))))
+                  (q (lambda (y) (if (zero? y) 0 (+ 1 (p (- y 1))))))
+                  (x (p 5))
+                  (y x))
+          y))
+(test 35 (let-values (((root rem) (values 5 0)) ((x y) (values 7 5))) (* root x)))
+(test (quote (x y x y)) (let ((a (quote a)) (b (quote b)) (x (quote x)) (y (quote y)))
+                          (let*-values (((a b) (values x y)) ((x y) (values a b)))
+                            (list a b x y))))
+(define x 0)
+(test 5 (begin (set! x 5) (+ x 1) x))
+(test-output "4 plus 1 equals 5" (begin (display "4 plus 1 equals ") (display (+ 4 1))))
+(test (vector 0 1 2 3 4) (do ((vec (make-vector 5)) (i 0 (+ i 1))) ((= i 5) vec) (vector-set! vec i i)))
+(test 25 (let ((x (quote (1 3 5 7 9)))) (do ((x x (cdr x)) (sum 0 (+ sum (car x)))) ((null? x) sum))))
+(test (quote ((6 1 3) (-5 -2)))
+      (let loop ((numbers (quote (3 -2 1 6 -5))) (nonneg (quote ())) (neg (quote ())))
+        (cond ((null? numbers) (list nonneg neg))
+              ((>= (car numbers) 0) (loop (cdr numbers) (cons (car numbers) nonneg) neg))
+              ((< (car numbers) 0) (loop (cdr numbers) nonneg (cons (car numbers) neg))))))
+(test 3 (force (delay (+ 1 2))))
+(test (quote (3 3)) (let ((p (delay (+ 1 2)))) (list (force p) (force p))))
+(test-expect-fail 1)
+(define integers
+  (letrec ((next (lambda (n) (delay (cons n (next (+ n 1)))))))
+    (next 0)))
+(define (head stream) (car (force stream)))
+(define (tail stream) (cdr (force stream)))
+(test-expect-fail 1)
+(test 2 (head (tail (tail integers))))
+(define (stream-filter p? s)
+  (delay-force
+   (if (null? (force s))
+       (delay (quote ()))
+       (let ((h (car (force s))) (t (cdr (force s))))
+         (if (p? h)
+             (delay (cons h (stream-filter p? t)))
+             (stream-filter p? t))))))
+(test-expect-fail 1)
+(test 5 (head (tail (tail (stream-filter odd? integers)))))
+(define count 0)
+(define p (delay (begin (set! count (+ count 1)) (if (> count x) count (force p)))))
+(define x 5)
+(test 6 (force p))
+(test 6 (begin (set! x 10) (force p)))
+(test #t (promise? (delay (+ 2 2))))
+(test 3 (force (make-promise 3)))
+(define radix (make-parameter 10 (lambda (x) (if (and (integer? x) (<= 2 x 16)) x (error "invalid radix")))))
+(define (f n) (number->string n (radix)))
+(test "12" (f 12))
+(test "1100" (parameterize ((radix 2)) (f 12)))
+(test "12" (f 12))
+(test 42 (guard (condition ((assq (quote a) condition) => cdr) ((assq (quote b) condition)))
+           (raise (list (cons (quote a) 42)))))
+(test (quote (b . 23)) (guard (condition ((assq (quote a) condition) => cdr) ((assq (quote b) condition)))
+                         (raise (list (cons (quote b) 23)))))
+(test-end)
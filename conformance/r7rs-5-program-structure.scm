@@ -0,0 +1,30 @@
+;; A subset of the R7RS small test suite, taken from the examples in
+;; chapter 5 of the report as collected in chibi-scheme's r7rs-tests.scm.
+
+(test-begin "5.3 Variable definitions")
+(define add3 (lambda (x) (+ x 3)))
+(test 6 (add3 3))
+(define first car)
+(test 1 (first (quote (1 2))))
+(test 45 (let ((x 5))
+           (define foo (lambda (y) (bar x y)))
+           (define bar (lambda (a b) (+ (* a b) a)))
+           (foo (+ x 3))))
+(define-values (x y) (values 1 2))
+(test 3 (+ x y))
+(test 3 (let () (define-values (x y) (values 1 2)) (+ x y)))
+(test (quote (1 2 (3 4))) (let () (define-values (a b . c) (values 1 2 3 4)) (list a b c)))
+(test-end)
+
+(test-begin "5.5 Record-type definitions")
+(define-record-type pare
+  (kons x y)
+  pare?
+  (x kar set-kar!)
+  (y kdr))
+(test #t (pare? (kons 1 2)))
+(test #f (pare? (cons 1 2)))
+(test 1 (kar (kons 1 2)))
+(test 2 (kdr (kons 1 2)))
+(test 3 (let ((k (kons 1 2))) (set-kar! k 3) (kar k)))
+(test-end)
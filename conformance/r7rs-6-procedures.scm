@@ -0,0 +1,272 @@
+;; A subset of the R7RS small test suite, taken from the examples in
+;; chapter 6 of the report as collected in chibi-scheme's r7rs-tests.scm.
+;; Quoted data use `quote`, since the reader has no `'` abbreviation, and
+;; vectors are built with `vector`, since it has no `#(...)` syntax.
+
+(test-begin "6.1 Equivalence predicates")
+(test #t (eqv? (quote a) (quote a)))
+(test #f (eqv? (quote a) (quote b)))
+(test #t (eqv? 2 2))
+(test #t (eqv? (quote ()) (quote ())))
+(test #t (eqv? 100000000 100000000))
+(test #f (eqv? (cons 1 2) (cons 1 2)))
+(test #f (eqv? (lambda () 1) (lambda () 2)))
+(test #t (let ((p (lambda (x) x))) (eqv? p p)))
+(test #f (eqv? #f (quote nil)))
+(test #t (eq? (quote a) (quote a)))
+(test #f (eq? (list (quote a)) (list (quote a))))
+(test #t (eq? (quote ()) (quote ())))
+(test #t (eq? car car))
+(test #t (let ((x (quote (a)))) (eq? x x)))
+(test #t (equal? (quote a) (quote a)))
+(test #t (equal? (quote (a)) (quote (a))))
+(test #t (equal? (quote (a (b) c)) (quote (a (b) c))))
+(test #t (equal? "abc" "abc"))
+(test #t (equal? 2 2))
+(test #t (equal? (make-vector 5 (quote a)) (make-vector 5 (quote a))))
+(test-end)
+
+(test-begin "6.2 Numbers")
+(test #t (number? 3))
+(test #t (integer? 3.0))
+(test #f (integer? 3.5))
+(test #t (exact? 3))
+(test #t (inexact? 3.0))
+(test #t (= 1 1.0))
+(test #t (< 1 2 3))
+(test #f (< 1 3 2))
+(test #t (zero? 0))
+(test #t (positive? 1))
+(test #t (negative? -1))
+(test #t (odd? 3))
+(test #t (even? 0))
+(test 4 (max 3 4))
+(test 4.0 (max 3.9 4))
+(test 3 (min 3 4))
+(test 7 (+ 3 4))
+(test 3 (+ 3))
+(test 0 (+))
+(test 4 (* 4))
+(test 1 (*))
+(test -1 (- 3 4))
+(test -6 (- 3 4 5))
+(test -3 (- 3))
+(test 1.5 (/ 3 2.0))
+(test 7 (abs -7))
+(test 2 (quotient 7 3))
+(test 1 (remainder 7 3))
+(test -1 (remainder -7 3))
+(test 2 (modulo -7 3))
+(test -5.0 (floor -4.3))
+(test -4.0 (ceiling -4.3))
+(test -4.0 (truncate -4.3))
+(test -4.0 (round -4.3))
+(test 3.0 (floor 3.5))
+(test 4.0 (ceiling 3.5))
+(test 4.0 (round 3.5))
+(test 4.0 (round 4.5))
+(test 7 (round 7))
+(test 3 (sqrt 9))
+(test 1.5 (sqrt 2.25))
+(test 8 (expt 2 3))
+(test 4 (exact 4.0))
+(test 4.0 (inexact 4))
+(test "100" (number->string 256 16))
+(test "256" (number->string 256))
+(test 256 (string->number "100" 16))
+(test 100 (string->number "100"))
+(test #f (string->number "abc"))
+(test-end)
+
+(test-begin "6.3 Booleans")
+(test #t #t)
+(test #f #f)
+(test #f (quote #f))
+(test #f (not #t))
+(test #f (not 3))
+(test #f (not (list 3)))
+(test #t (not #f))
+(test #f (not (quote ())))
+(test #f (not (list)))
+(test #f (not (quote nil)))
+(test #t (boolean? #f))
+(test #f (boolean? 0))
+(test #f (boolean? (quote ())))
+(test-end)
+
+(test-begin "6.4 Pairs and lists")
+(define x (list (quote a) (quote b) (quote c)))
+(define y x)
+(test (quote (a b c)) y)
+(test #t (list? y))
+(set-cdr! x 4)
+(test (quote (a . 4)) x)
+(test #t (eqv? x y))
+(test (quote (a . 4)) y)
+(test #f (list? y))
+(set-cdr! x x)
+(test #f (list? x))
+(test #t (pair? (quote (a . b))))
+(test #t (pair? (quote (a b c))))
+(test #f (pair? (quote ())))
+(test (quote (a)) (cons (quote a) (quote ())))
+(test (quote ((a) b c d)) (cons (quote (a)) (quote (b c d))))
+(test (quote ("a" b c)) (cons "a" (quote (b c))))
+(test (quote (a . 3)) (cons (quote a) 3))
+(test (quote ((a b) . c)) (cons (quote (a b)) (quote c)))
+(test (quote a) (car (quote (a b c))))
+(test (quote (a)) (car (quote ((a) b c d))))
+(test 1 (car (quote (1 . 2))))
+(test (quote (b c d)) (cdr (quote ((a) b c d))))
+(test 2 (cdr (quote (1 . 2))))
+(test #t (list? (quote (a b c))))
+(test #t (list? (quote ())))
+(test #f (list? (quote (a . b))))
+(test (quote (a 7 c)) (list (quote a) (+ 3 4) (quote c)))
+(test (quote ()) (list))
+(test 3 (length (quote (a b c))))
+(test 3 (length (quote (a (b) (c d e)))))
+(test 0 (length (quote ())))
+(test (quote (x y)) (append (quote (x)) (quote (y))))
+(test (quote (a b c d)) (append (quote (a)) (quote (b c d))))
+(test (quote (a (b) (c))) (append (quote (a (b))) (quote ((c)))))
+(test (quote (a b c . d)) (append (quote (a b)) (quote (c . d))))
+(test (quote a) (append (quote ()) (quote a)))
+(test (quote (c b a)) (reverse (quote (a b c))))
+(test (quote ((e (f)) d (b c) a)) (reverse (quote (a (b c) d (e (f))))))
+(test (quote (c d)) (list-tail (quote (a b c d)) 2))
+(test (quote c) (list-ref (quote (a b c d)) 2))
+(test (quote (a b c)) (memq (quote a) (quote (a b c))))
+(test (quote (b c)) (memq (quote b) (quote (a b c))))
+(test #f (memq (quote a) (quote (b c d))))
+(test #f (memq (list (quote a)) (quote (b (a) c))))
+(test (quote ((a) c)) (member (list (quote a)) (quote (b (a) c))))
+(test (quote ("b" "c")) (member "B" (quote ("a" "b" "c")) (lambda (a b) (string=? (string-downcase a) b))))
+(test (quote (101 102)) (memv 101 (quote (100 101 102))))
+(test (quote (a 1)) (assq (quote a) (quote ((a 1) (b 2) (c 3)))))
+(test (quote (b 2)) (assq (quote b) (quote ((a 1) (b 2) (c 3)))))
+(test #f (assq (quote d) (quote ((a 1) (b 2) (c 3)))))
+(test #f (assq (list (quote a)) (quote (((a)) ((b)) ((c))))))
+(test (quote ((a))) (assoc (list (quote a)) (quote (((a)) ((b)) ((c))))))
+(test (quote (5 7)) (assv 5 (quote ((2 3) (5 7) (11 13)))))
+(test (quote (1 2 3)) (list-copy (quote (1 2 3))))
+(test-end)
+
+(test-begin "6.5 Symbols")
+(test #t (symbol? (quote foo)))
+(test #t (symbol? (car (quote (a b)))))
+(test #f (symbol? "bar"))
+(test #t (symbol? (quote nil)))
+(test #f (symbol? (quote ())))
+(test #f (symbol? #f))
+(test "flying-fish" (symbol->string (quote flying-fish)))
+(test "Martin" (symbol->string (quote Martin)))
+(test (quote mISSISSIppi) (string->symbol "mISSISSIppi"))
+(test-end)
+
+(test-begin "6.6 Characters")
+(test #t (char? #\a))
+(test #t (char<? #\a #\b #\c))
+(test #t (char-alphabetic? #\a))
+(test #f (char-alphabetic? #\1))
+(test #t (char-numeric? #\1))
+(test #t (char-whitespace? #\space))
+(test #t (char-upper-case? #\A))
+(test #t (char-lower-case? #\a))
+(test #\A (char-upcase #\a))
+(test #\a (char-downcase #\A))
+(test 97 (char->integer #\a))
+(test #\a (integer->char 97))
+(test-end)
+
+(test-begin "6.7 Strings")
+(test #t (string? "abc"))
+(test "aaa" (make-string 3 #\a))
+(test "abc" (string #\a #\b #\c))
+(test 3 (string-length "abc"))
+(test #\b (string-ref "abc" 1))
+(test "axc" (let ((s (make-string 3 #\a))) (string-set! s 1 #\x) (string-set! s 2 #\c) s))
+(test #t (string=? "abc" "abc"))
+(test #t (string<? "abc" "abd"))
+(test "BC" (string-upcase (substring "abcd" 1 3)))
+(test "abc" (string-append "a" "bc"))
+(test (quote (#\a #\b)) (string->list "ab"))
+(test "ab" (list->string (list #\a #\b)))
+(test "abc" (string-copy "abc"))
+(test-end)
+
+(test-begin "6.8 Vectors")
+(test #t (vector? (vector 1 2)))
+(test (vector 0 0 0) (make-vector 3 0))
+(test 8 (vector-ref (vector 1 1 2 3 5 8 13 21) 5))
+(test 3 (vector-length (vector 1 2 3)))
+(test (vector 0 (quote ("Sue" "Sue")) "Anna")
+      (let ((vec (vector 0 (quote (2 2 2 2)) "Anna")))
+        (vector-set! vec 1 (quote ("Sue" "Sue")))
+        vec))
+(test (quote (dah dah didah)) (vector->list (vector (quote dah) (quote dah) (quote didah))))
+(test (vector (quote dididit) (quote dah)) (list->vector (quote (dididit dah))))
+(test (vector 7 7) (let ((v (vector 1 2))) (vector-fill! v 7) v))
+(test-end)
+
+(test-begin "6.10 Control features")
+(test #t (procedure? car))
+(test #f (procedure? (quote car)))
+(test #t (procedure? (lambda (x) (* x x))))
+(test #f (procedure? (quote (lambda (x) (* x x)))))
+(test #t (call-with-current-continuation procedure?))
+(test 7 (apply + (list 3 4)))
+(test 30 (apply + 1 2 (quote (3 4 5 6 9))))
+(test (quote (b e h)) (map cadr (quote ((a b) (d e) (g h)))))
+(test (quote (1 4 27 256 3125)) (map (lambda (n) (expt n n)) (quote (1 2 3 4 5))))
+(test (quote (5 7 9)) (map + (quote (1 2 3)) (quote (4 5 6))))
+(test (vector 0 1 4 9 16) (let ((v (make-vector 5)))
+                             (for-each (lambda (i) (vector-set! v i (* i i))) (quote (0 1 2 3 4)))
+                             v))
+(test (vector (quote b) (quote e) (quote h))
+      (vector-map cadr (vector (quote (a b)) (quote (d e)) (quote (g h)))))
+(test -3 (call-with-current-continuation
+          (lambda (exit)
+            (for-each (lambda (x) (if (negative? x) (exit x)))
+                      (quote (54 0 37 -3 245 19)))
+            #t)))
+(define list-length
+  (lambda (obj)
+    (call-with-current-continuation
+     (lambda (return)
+       ((lambda () (define (r obj)
+                     (cond ((null? obj) 0)
+                           ((pair? obj) (+ (r (cdr obj)) 1))
+                           (else (return #f))))
+                  (r obj)))))))
+(test 4 (list-length (quote (1 2 3 4))))
+(test #f (list-length (quote (a b . c))))
+(test 5 (call-with-values (lambda () (values 4 5)) (lambda (a b) b)))
+(test -1 (call-with-values * -))
+(test (quote (connect talk1 disconnect connect talk2 disconnect))
+      (let ((path (quote ())) (c #f))
+        (let ((add (lambda (s) (set! path (cons s path)))))
+          (dynamic-wind
+           (lambda () (add (quote connect)))
+           (lambda () (add (call-with-current-continuation (lambda (c0) (set! c c0) (quote talk1)))))
+           (lambda () (add (quote disconnect))))
+          (if (< (length path) 4)
+              (c (quote talk2))
+              (reverse path)))))
+(test-end)
+
+(test-begin "6.11 Exceptions")
+(test-output "condition: an-error"
+             (with-exception-handler
+              (lambda (e) (display "condition: ") (display e) 42)
+              (lambda () (+ 1 (raise-continuable (quote an-error))))))
+(test 65 (with-exception-handler
+          (lambda (con) (cond ((string? con) 42) (else 20)))
+          (lambda () (+ (raise-continuable "should be a number") 23))))
+(test "Something went wrong" (guard (e (#t (error-object-message e))) (error "Something went wrong" 1 2)))
+(test (quote (1 2)) (guard (e (#t (error-object-irritants e))) (error "Something went wrong" 1 2)))
+(test #t (guard (e (#t (error-object? e))) (error "x")))
+(test-error (raise (quote oops)))
+(test-error (car 1))
+(test-error (vector-ref (vector 1 2) 2))
+(test-end)
@@ -0,0 +1,33 @@
+;; Tests of the lexer and the reader. Expected tokens and data are printed
+;; as `tokens --format sexpr` and `read --format sexpr` print them.
+
+(test-begin "Lexer")
+(test-tokens "( a . b )" "(a . b)")
+(test-tokens "x ->x ... + - a.b" "x ->x ... + - a.b")
+(test-tokens "1 -17 1.5 #t #f" "1 -17 1.5 #t #f")
+(test-tokens "#\\a #\\space \"x\\n\"" "#\\a #\\space \"x\\n\"")
+(test-tokens "a b" "a ; a comment\n b")
+(test-tokens "+inf.0 -inf.0 +nan.0" "+inf.0 -inf.0 +nan.0")
+(test-end)
+
+(test-begin "Reader")
+(test-read "(a . b)" "(a . b)")
+(test-read "(a b c)" "(a\n  b c)")
+(test-read "(a . (b))" "(a . (b))")
+(test-read "(a b . c)" "(a b . c)")
+(test-read "() (())" "() (())")
+(test-read "(quote x)" "(quote x)")
+(test-read "x y" "  x\n\ty  ")
+(test-read "\"a\\\"b\"" "\"a\\\"b\"")
+(test-read "" "; only a comment")
+(test-read "x" "x ; a comment at the end")
+(test-end)
+
+(test-begin "Reader errors")
+(test-read-error "S0101" ")")
+(test-read-error "S0101" "(a))")
+(test-read-error "S0102" "(a")
+(test-read-error "S0102" "(a (b c)")
+(test-read-error "S0103" "#q")
+(test-read-error "S0104" "(((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((())))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))")
+(test-end)
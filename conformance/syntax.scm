@@ -0,0 +1,30 @@
+;; Tests of syntax analysis. Expected expressions are printed as
+;; `expand --format sexpr` prints them, and errors are given by code (see
+;; `--explain`).
+
+(test-begin "Derived forms")
+(test-expand "(define f (lambda (x) x))" (define (f x) x))
+(test-expand "(when a b)" (when a b))
+(test-expand "(let ((x 1)) x)" (let ((x 1)) x))
+(test-expand "(cond (a => b) (else c))" (cond (a => b) (else c)))
+(test-expand "(case x ((1) a) (else b))" (case x ((1) a) (else b)))
+(test-expand "(do ((i 0 (+ i 1))) ((= i 3) i))" (do ((i 0 (+ i 1))) ((= i 3) i)))
+(test-expand "(quote (a . b))" (quote (a . b)))
+(test-end)
+
+(test-begin "Syntax errors")
+(test-syntax-error "S0002" (if))
+(test-syntax-error "S0002" (quote a b))
+(test-syntax-error "S0003" (cond ()))
+(test-syntax-error "S0004" (cond (else a) (b c)))
+(test-syntax-error "S0006" (lambda (1) x))
+(test-syntax-error "S0008" (lambda (x x) x))
+(test-syntax-error "S0009" (lambda (x)))
+(test-syntax-error "S0011" (let ((x)) x))
+(test-syntax-error "S0012" (1 2))
+(test-syntax-error "S0013" ())
+(test-syntax-error "S0014" (do ((i 0)) i))
+(test-syntax-error "S0017" (quote (a . b c)))
+(test-syntax-error "S0017" (quote (a . . b)))
+(test-syntax-error "S0017" (quote (. a)))
+(test-end)
@@ -0,0 +1,6 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate scheme_syntax;
+
+fuzz_target!(|data: &[u8]| scheme_syntax::fuzz::read(data));
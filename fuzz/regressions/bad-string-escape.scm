@@ -0,0 +1 @@
+(display "a\qb")
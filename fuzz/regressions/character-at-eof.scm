@@ -0,0 +1 @@
+(list #\
\ No newline at end of file
@@ -0,0 +1,5 @@
+(define l (list 1 2))
+(set-cdr! (cdr l) l)
+(list? l)
+(equal? l l)
+(length l)
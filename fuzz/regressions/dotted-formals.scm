@@ -0,0 +1,4 @@
+(lambda (. . .) 1)
+(define (f . ) 1)
+(lambda (x . y . z) x)
+(quote (. a))
@@ -0,0 +1 @@
+(+ 99999999999 1)
@@ -0,0 +1 @@
+(make-vector 2147483647 0)
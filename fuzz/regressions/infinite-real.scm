@@ -0,0 +1 @@
+(quote (1e400 -1e400 +inf.0 +nan.0))
@@ -0,0 +1,2 @@
+(define (f n) (+ 1 (f n)))
+(f 1)
@@ -0,0 +1,1111 @@
+//! An arena-allocated form of the `syntax` tree, for reading large files.
+//!
+//! An `Ast` keeps every expression, datum and list of a program in a few
+//! vectors, and refers to them by index, so that analysing a form
+//! allocates no boxes and no vectors of its own. Identifiers are interned
+//! as `Symbol`s, which compare in constant time, and the keywords of
+//! special forms have fixed symbols, so that `Ast::parse` dispatches on
+//! them with a `match`:
+//!
+//! ```
+//! use scheme_syntax::{Parser, RegexLexer};
+//! use scheme_syntax::ast::{Ast, Expr, Symbol};
+//!
+//! let mut ast = Ast::new();
+//! let node = Parser::new(RegexLexer::new("(f x x)")).next().unwrap().unwrap();
+//! let id = ast.parse(&node).unwrap();
+//! match ast[id] {
+//!     Expr::Application(_, operands) => match (&ast[ast[operands][0]], &ast[ast[operands][1]]) {
+//!         (&Expr::Variable(a), &Expr::Variable(b)) => {
+//!             assert_eq!(a, b);
+//!             assert_eq!(ast.name(a), "x");
+//!             assert!(a != Symbol::QUOTE);
+//!         },
+//!         e => panic!("unexpected {:?}", e),
+//!     },
+//!     ref e => panic!("unexpected {:?}", e),
+//! }
+//! ```
+//!
+//! The arena accepts exactly the forms that `parse_expression` accepts.
+//! Forms it does not analyse itself, the rarer ones and any with a syntax
+//! error, are handed to `parse_expression`, which reports the error or
+//! returns an `Expression` to copy in.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Index;
+use std::rc::Rc;
+
+use lexer::Literal;
+use parser::Node;
+use scheme::{parse_expression, Result};
+use scheme::syntax::{self, Expression, Quotation};
+
+/// An interned identifier. Two symbols from the same `Interner` are equal
+/// if and only if their names are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(pub u32);
+
+macro_rules! keywords {
+    ($($name:ident = $text:expr,)*) => {
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        enum Keyword {
+            $($name,)*
+        }
+
+        impl Symbol {
+            $(pub const $name: Symbol = Symbol(Keyword::$name as u32);)*
+        }
+
+        /// The names that every interner starts with, in the order of
+        /// their symbols.
+        const KEYWORDS: &[&str] = &[$($text,)*];
+    };
+}
+
+keywords! {
+    QUOTE = "quote",
+    TIME = "time",
+    IF = "if",
+    AND = "and",
+    OR = "or",
+    BEGIN = "begin",
+    WHEN = "when",
+    UNLESS = "unless",
+    COND = "cond",
+    CASE = "case",
+    LAMBDA = "lambda",
+    DEFINE = "define",
+    LET = "let",
+    LET_VALUES = "let-values",
+    LET_STAR_VALUES = "let*-values",
+    DEFINE_VALUES = "define-values",
+    RECEIVE = "receive",
+    DO = "do",
+    SET = "set!",
+    DELAY = "delay",
+    DELAY_FORCE = "delay-force",
+    DEFINE_RECORD_TYPE = "define-record-type",
+    PARAMETERIZE = "parameterize",
+    GUARD = "guard",
+    ELSE = "else",
+    ARROW = "=>",
+    DOT = ".",
+}
+
+/// A table of names and their symbols. New interners know the keywords,
+/// as `Symbol::QUOTE` and so on.
+#[derive(Debug, Clone)]
+pub struct Interner {
+    names: Vec<Rc<str>>,
+    symbols: HashMap<Rc<str>, Symbol>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        let mut interner = Interner { names: Vec::new(), symbols: HashMap::new() };
+        for name in KEYWORDS {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    /// The symbol for `name`, which is added if it is new.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        let name: Rc<str> = Rc::from(name);
+        self.names.push(name.clone());
+        self.symbols.insert(name, symbol);
+        symbol
+    }
+
+    /// The symbol for `name`, if it has been interned.
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).cloned()
+    }
+
+    pub fn name(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Interner::new()
+    }
+}
+
+/// The index of an expression in an `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprId(u32);
+
+/// The index of a quoted datum in an `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DatumId(u32);
+
+/// Consecutive items in one of an `Ast`'s pools, such as the operands of
+/// an application. `ast[list]` is the slice of them.
+pub struct List<T> {
+    start: u32,
+    len: u32,
+    items: PhantomData<fn() -> T>,
+}
+
+impl<T> List<T> {
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T> Clone for List<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for List<T> {}
+
+impl<T> PartialEq for List<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.start, self.len) == (other.start, other.len)
+    }
+}
+
+impl<T> fmt::Debug for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "List({}..{})", self.start, self.start + self.len)
+    }
+}
+
+/// A quoted datum. Lists are pairs ending in `Nil`, or in another datum
+/// for an improper list.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datum {
+    Literal(Literal),
+    Symbol(Symbol),
+    Nil,
+    Pair(DatumId, DatumId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CondClause {
+    Simple(ExprId, List<ExprId>),
+    Binding(ExprId, ExprId),
+    Inconsequential(ExprId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClauseBody {
+    Sequence(List<ExprId>),
+    Receiver(ExprId),
+}
+
+pub type CaseClause = (List<DatumId>, ClauseBody);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Formals {
+    pub required: List<Symbol>,
+    pub rest: Option<Symbol>,
+}
+
+pub type Binding = (Symbol, ExprId);
+pub type ValuesBinding = (Formals, ExprId);
+pub type DoBinding = (Symbol, ExprId, Option<ExprId>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Field {
+    pub name: Symbol,
+    pub accessor: Symbol,
+    pub modifier: Option<Symbol>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordType {
+    pub name: Symbol,
+    pub constructor: (Symbol, List<Symbol>),
+    pub predicate: Symbol,
+    pub fields: List<Field>,
+}
+
+/// An expression in an `Ast`, with the same forms as `syntax::Expression`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    Variable(Symbol),
+    Quote(DatumId),
+    Time(ExprId),
+    If(ExprId, ExprId, Option<ExprId>),
+    And(List<ExprId>),
+    Or(List<ExprId>),
+    Begin(List<ExprId>),
+    When(ExprId, List<ExprId>),
+    Unless(ExprId, List<ExprId>),
+    Cond(List<CondClause>, Option<List<ExprId>>),
+    Case(ExprId, List<CaseClause>, Option<ClauseBody>),
+    Lambda(Formals, List<ExprId>),
+    Define(Symbol, ExprId),
+    Let(Option<Symbol>, List<Binding>, List<ExprId>),
+    LetValues(List<ValuesBinding>, List<ExprId>),
+    LetStarValues(List<ValuesBinding>, List<ExprId>),
+    DefineValues(Formals, ExprId),
+    Receive(Formals, ExprId, List<ExprId>),
+    Do(List<DoBinding>, ExprId, List<ExprId>, List<ExprId>),
+    Set(Symbol, ExprId),
+    Delay(ExprId),
+    DelayForce(ExprId),
+    DefineRecordType(RecordType),
+    Parameterize(List<(ExprId, ExprId)>, List<ExprId>),
+    Guard(Symbol, List<CondClause>, Option<List<ExprId>>, List<ExprId>),
+    Application(ExprId, List<ExprId>),
+}
+
+/// A vector of an `Ast` that lists are slices of.
+trait Pool<T> {
+    fn pool(&mut self) -> &mut Vec<T>;
+}
+
+macro_rules! pools {
+    ($($field:ident: $t:ty,)*) => {
+        #[derive(Debug, Default)]
+        struct Pools {
+            $($field: Vec<$t>,)*
+        }
+
+        impl Pools {
+            fn lengths(&self) -> Vec<usize> {
+                vec![$(self.$field.len(),)*]
+            }
+
+            fn truncate(&mut self, lengths: &[usize]) {
+                let mut lengths = lengths.iter();
+                $(self.$field.truncate(*lengths.next().unwrap());)*
+            }
+        }
+
+        $(
+            impl Pool<$t> for Pools {
+                fn pool(&mut self) -> &mut Vec<$t> {
+                    &mut self.$field
+                }
+            }
+
+            impl Index<List<$t>> for Ast {
+                type Output = [$t];
+
+                fn index(&self, list: List<$t>) -> &[$t] {
+                    &self.pools.$field[list.start as usize..(list.start + list.len) as usize]
+                }
+            }
+        )*
+    };
+}
+
+pools! {
+    exprs: Expr,
+    data: Datum,
+    expr_lists: ExprId,
+    datum_lists: DatumId,
+    symbol_lists: Symbol,
+    cond_clauses: CondClause,
+    case_clauses: CaseClause,
+    bindings: Binding,
+    values_bindings: ValuesBinding,
+    do_bindings: DoBinding,
+    fields: Field,
+    parameters: (ExprId, ExprId),
+}
+
+/// An arena of expressions. See the module documentation.
+#[derive(Debug, Default)]
+pub struct Ast {
+    symbols: Interner,
+    pools: Pools,
+    /// The expressions and symbols of the lists being built, which are
+    /// moved to their pools once complete, so that each list is
+    /// contiguous even though its items contain lists of their own.
+    expr_stack: Vec<ExprId>,
+    symbol_stack: Vec<Symbol>,
+}
+
+impl Index<ExprId> for Ast {
+    type Output = Expr;
+
+    fn index(&self, id: ExprId) -> &Expr {
+        &self.pools.exprs[id.0 as usize]
+    }
+}
+
+impl Index<DatumId> for Ast {
+    type Output = Datum;
+
+    fn index(&self, id: DatumId) -> &Datum {
+        &self.pools.data[id.0 as usize]
+    }
+}
+
+fn is_identifier(n: &Node, name: &str) -> bool {
+    match n {
+        Node::Identifier(s) => s == name,
+        _ => false,
+    }
+}
+
+/// Whether a `cond` or `case` clause that uses `=>` has one receiver.
+fn receiver_ok(l: &[Node]) -> bool {
+    !(l.len() >= 2 && is_identifier(&l[1], "=>") && l.len() != 3)
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Ast::default()
+    }
+
+    pub fn symbols(&self) -> &Interner {
+        &self.symbols
+    }
+
+    pub fn symbols_mut(&mut self) -> &mut Interner {
+        &mut self.symbols
+    }
+
+    pub fn name(&self, symbol: Symbol) -> &str {
+        self.symbols.name(symbol)
+    }
+
+    /// Removes every expression, keeping the symbols and the memory.
+    pub fn clear(&mut self) {
+        let empty = vec![0; self.pools.lengths().len()];
+        self.pools.truncate(&empty);
+    }
+
+    /// Analyses `node` as `parse_expression` does, adding the expression
+    /// to the arena.
+    pub fn parse(&mut self, node: &Node) -> Result<ExprId> {
+        let lengths = self.pools.lengths();
+        match self.expr(node) {
+            Some(id) => Ok(id),
+            None => {
+                self.pools.truncate(&lengths);
+                self.expr_stack.clear();
+                self.symbol_stack.clear();
+                parse_expression(node.clone()).map(|e| self.add(&e))
+            },
+        }
+    }
+
+    fn push<T>(&mut self, item: T) -> u32 where Pools: Pool<T> {
+        let pool = self.pools.pool();
+        pool.push(item);
+        (pool.len() - 1) as u32
+    }
+
+    fn push_expr(&mut self, e: Expr) -> ExprId {
+        ExprId(self.push(e))
+    }
+
+    fn push_datum(&mut self, d: Datum) -> DatumId {
+        DatumId(self.push(d))
+    }
+
+    fn list<T, I: IntoIterator<Item = T>>(&mut self, items: I) -> List<T> where Pools: Pool<T> {
+        let pool = self.pools.pool();
+        let start = pool.len();
+        pool.extend(items);
+        List { start: start as u32, len: (pool.len() - start) as u32, items: PhantomData }
+    }
+
+    /// Moves the expressions on the stack above `mark` to a list.
+    fn expr_list(&mut self, mark: usize) -> List<ExprId> {
+        let start = self.pools.expr_lists.len();
+        self.pools.expr_lists.extend(self.expr_stack.drain(mark..));
+        List { start: start as u32, len: (self.pools.expr_lists.len() - start) as u32, items: PhantomData }
+    }
+
+    fn symbol_list(&mut self, mark: usize) -> List<Symbol> {
+        let start = self.pools.symbol_lists.len();
+        self.pools.symbol_lists.extend(self.symbol_stack.drain(mark..));
+        List { start: start as u32, len: (self.pools.symbol_lists.len() - start) as u32, items: PhantomData }
+    }
+
+    // Analysis of nodes. Each method returns `None` where `parse_expression`
+    // might fail, leaving `parse` to ask it.
+
+    fn expr(&mut self, node: &Node) -> Option<ExprId> {
+        match node {
+            Node::Literal(l) => Some(self.push_expr(Expr::Literal(l.clone()))),
+            Node::Identifier(s) => {
+                let s = self.symbols.intern(s);
+                Some(self.push_expr(Expr::Variable(s)))
+            },
+            Node::List(items) => {
+                let (hd, tl) = items.split_first()?;
+                self.combination(node, hd, tl)
+            },
+        }
+    }
+
+    fn exprs(&mut self, nodes: &[Node]) -> Option<List<ExprId>> {
+        let mark = self.expr_stack.len();
+        for n in nodes {
+            let id = self.expr(n)?;
+            self.expr_stack.push(id);
+        }
+        Some(self.expr_list(mark))
+    }
+
+    fn identifier(&mut self, node: &Node) -> Option<Symbol> {
+        match node {
+            Node::Identifier(s) => Some(self.symbols.intern(s)),
+            _ => None,
+        }
+    }
+
+    fn combination(&mut self, node: &Node, hd: &Node, tl: &[Node]) -> Option<ExprId> {
+        let keyword = match hd {
+            Node::Identifier(s) => self.symbols.intern(s),
+            Node::Literal(_) => return None,
+            Node::List(_) => return self.application(hd, tl),
+        };
+        let e = match keyword {
+            Symbol::QUOTE if tl.len() == 1 => Expr::Quote(self.datum(&tl[0])?),
+            Symbol::TIME if tl.len() == 1 => Expr::Time(self.expr(&tl[0])?),
+            Symbol::DELAY if tl.len() == 1 => Expr::Delay(self.expr(&tl[0])?),
+            Symbol::DELAY_FORCE if tl.len() == 1 => Expr::DelayForce(self.expr(&tl[0])?),
+            Symbol::IF if tl.len() == 2 || tl.len() == 3 => {
+                let test = self.expr(&tl[0])?;
+                let consequent = self.expr(&tl[1])?;
+                let alternative = match tl.get(2) {
+                    Some(n) => Some(self.expr(n)?),
+                    None => None,
+                };
+                Expr::If(test, consequent, alternative)
+            },
+            Symbol::AND => Expr::And(self.exprs(tl)?),
+            Symbol::OR => Expr::Or(self.exprs(tl)?),
+            Symbol::BEGIN if !tl.is_empty() => Expr::Begin(self.exprs(tl)?),
+            Symbol::WHEN if tl.len() >= 2 => Expr::When(self.expr(&tl[0])?, self.exprs(&tl[1..])?),
+            Symbol::UNLESS if tl.len() >= 2 =>
+                Expr::Unless(self.expr(&tl[0])?, self.exprs(&tl[1..])?),
+            Symbol::COND => {
+                let (clauses, else_body) = self.cond_clauses(tl)?;
+                Expr::Cond(clauses, else_body)
+            },
+            Symbol::CASE => self.case(tl)?,
+            Symbol::LAMBDA if tl.len() >= 2 =>
+                Expr::Lambda(self.formals(&tl[0])?, self.exprs(&tl[1..])?),
+            Symbol::DEFINE => self.define(tl)?,
+            Symbol::LET => self.let_(tl)?,
+            Symbol::SET if tl.len() == 2 => Expr::Set(self.identifier(&tl[0])?, self.expr(&tl[1])?),
+            Symbol::QUOTE | Symbol::TIME | Symbol::DELAY | Symbol::DELAY_FORCE | Symbol::IF |
+            Symbol::BEGIN | Symbol::WHEN | Symbol::UNLESS | Symbol::LAMBDA | Symbol::SET =>
+                return None,
+            Symbol::LET_VALUES | Symbol::LET_STAR_VALUES | Symbol::DEFINE_VALUES |
+            Symbol::RECEIVE | Symbol::DO | Symbol::DEFINE_RECORD_TYPE | Symbol::PARAMETERIZE |
+            Symbol::GUARD => {
+                let e = parse_expression(node.clone()).ok()?;
+                return Some(self.add(&e));
+            },
+            _ => return self.application(hd, tl),
+        };
+        Some(self.push_expr(e))
+    }
+
+    fn application(&mut self, hd: &Node, tl: &[Node]) -> Option<ExprId> {
+        let operator = self.expr(hd)?;
+        let operands = self.exprs(tl)?;
+        Some(self.push_expr(Expr::Application(operator, operands)))
+    }
+
+    /// The datum that `parse_quotation` makes of `node`. Lists are built
+    /// from their last element back, so that long ones do not recurse.
+    fn datum(&mut self, node: &Node) -> Option<DatumId> {
+        match node {
+            Node::Literal(l) => Some(self.push_datum(Datum::Literal(l.clone()))),
+            Node::Identifier(s) => {
+                let s = self.symbols.intern(s);
+                Some(self.push_datum(Datum::Symbol(s)))
+            },
+            Node::List(items) => {
+                let n = items.len();
+                let (items, mut res) = match items.iter().position(|i| is_identifier(i, ".")) {
+                    Some(i) if i == 0 || i + 2 != n || is_identifier(&items[n - 1], ".") => return None,
+                    Some(_) => (&items[..n - 2], self.datum(&items[n - 1])?),
+                    None => (&items[..], self.push_datum(Datum::Nil)),
+                };
+                for item in items.iter().rev() {
+                    let hd = self.datum(item)?;
+                    res = self.push_datum(Datum::Pair(hd, res));
+                }
+                Some(res)
+            },
+        }
+    }
+
+    /// Splits off the else clause that `take_else` would, returning the
+    /// other clauses and its body.
+    fn split_else(clauses: &[Node], case: bool) -> Option<(&[Node], Option<&[Node]>)> {
+        let is_else = |c: &Node| match c {
+            Node::List(l) => l.first().is_some_and(|k| is_identifier(k, "else")),
+            _ => false,
+        };
+        match clauses.iter().position(is_else) {
+            None => Some((clauses, None)),
+            Some(i) if i + 1 == clauses.len() => match &clauses[i] {
+                Node::List(l) if l.len() >= 2 && (!case || receiver_ok(l)) =>
+                    Some((&clauses[..i], Some(&l[1..]))),
+                _ => None,
+            },
+            Some(_) => None,
+        }
+    }
+
+    fn cond_clauses(&mut self, clauses: &[Node]) -> Option<(List<CondClause>, Option<List<ExprId>>)> {
+        let (clauses, else_body) = Ast::split_else(clauses, false)?;
+        let else_body = match else_body {
+            Some(body) => Some(self.exprs(body)?),
+            None => None,
+        };
+        let mut res = Vec::with_capacity(clauses.len());
+        for c in clauses {
+            let l = match c {
+                Node::List(l) if !l.is_empty() && receiver_ok(l) => l,
+                _ => return None,
+            };
+            let test = self.expr(&l[0])?;
+            res.push(match l.get(1) {
+                None => CondClause::Inconsequential(test),
+                Some(arrow) if is_identifier(arrow, "=>") => CondClause::Binding(test, self.expr(&l[2])?),
+                Some(_) => CondClause::Simple(test, self.exprs(&l[1..])?),
+            });
+        }
+        Some((self.list(res), else_body))
+    }
+
+    fn case_body(&mut self, body: &[Node]) -> Option<ClauseBody> {
+        if body.first().is_some_and(|n| is_identifier(n, "=>")) {
+            Some(ClauseBody::Receiver(self.expr(&body[1])?))
+        } else {
+            Some(ClauseBody::Sequence(self.exprs(body)?))
+        }
+    }
+
+    fn case(&mut self, tl: &[Node]) -> Option<Expr> {
+        let (key, clauses) = tl.split_first()?;
+        let (clauses, else_body) = Ast::split_else(clauses, true)?;
+        let else_body = match else_body {
+            Some(body) => Some(self.case_body(body)?),
+            None => None,
+        };
+        let key = self.expr(key)?;
+        let mut res = Vec::with_capacity(clauses.len());
+        for c in clauses {
+            let (data, body) = match c {
+                Node::List(l) if l.len() >= 2 && receiver_ok(l) => match &l[0] {
+                    Node::List(data) => (data, &l[1..]),
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            let data = data.iter().map(|d| self.datum(d)).collect::<Option<Vec<DatumId>>>()?;
+            let data = self.list(data);
+            res.push((data, self.case_body(body)?));
+        }
+        Some(Expr::Case(key, self.list(res), else_body))
+    }
+
+    fn formals(&mut self, node: &Node) -> Option<Formals> {
+        match node {
+            Node::Identifier(s) => {
+                let rest = self.symbols.intern(s);
+                Some(Formals { required: self.list(None), rest: Some(rest) })
+            },
+            Node::List(l) => self.formals_list(l),
+            Node::Literal(_) => None,
+        }
+    }
+
+    fn formals_list(&mut self, l: &[Node]) -> Option<Formals> {
+        let mark = self.symbol_stack.len();
+        let mut rest = None;
+        for (i, p) in l.iter().enumerate() {
+            if is_identifier(p, ".") {
+                if i + 2 != l.len() {
+                    return None;
+                }
+                rest = Some(self.identifier(&l[i + 1])?);
+                break;
+            }
+            let p = self.identifier(p)?;
+            self.symbol_stack.push(p);
+        }
+        let names = &self.symbol_stack[mark..];
+        for (i, name) in names.iter().chain(&rest).enumerate() {
+            if names[..i.min(names.len())].contains(name) {
+                return None;
+            }
+        }
+        Some(Formals { required: self.symbol_list(mark), rest })
+    }
+
+    fn define(&mut self, tl: &[Node]) -> Option<Expr> {
+        match tl.first()? {
+            Node::Identifier(name) if tl.len() == 2 => {
+                let name = self.symbols.intern(name);
+                Some(Expr::Define(name, self.expr(&tl[1])?))
+            },
+            Node::List(header) if !header.is_empty() && tl.len() >= 2 => {
+                let name = self.identifier(&header[0])?;
+                let formals = self.formals_list(&header[1..])?;
+                let body = self.exprs(&tl[1..])?;
+                Some(Expr::Define(name, self.push_expr(Expr::Lambda(formals, body))))
+            },
+            _ => None,
+        }
+    }
+
+    fn let_(&mut self, tl: &[Node]) -> Option<Expr> {
+        let (name, tl) = match tl.first() {
+            Some(Node::Identifier(name)) => (Some(self.symbols.intern(name)), &tl[1..]),
+            _ => (None, tl),
+        };
+        if tl.len() < 2 {
+            return None;
+        }
+        let bindings = match &tl[0] {
+            Node::List(l) => l,
+            _ => return None,
+        };
+        let mut res: Vec<Binding> = Vec::with_capacity(bindings.len());
+        for b in bindings {
+            let (name, init) = match b {
+                Node::List(l) if l.len() == 2 => (self.identifier(&l[0])?, &l[1]),
+                _ => return None,
+            };
+            if res.iter().any(|b| b.0 == name) {
+                return None;
+            }
+            res.push((name, self.expr(init)?));
+        }
+        let bindings = self.list(res);
+        Some(Expr::Let(name, bindings, self.exprs(&tl[1..])?))
+    }
+
+    // Copying in expressions.
+
+    /// Adds a copy of `e` to the arena.
+    pub fn add(&mut self, e: &Expression) -> ExprId {
+        let e = match e {
+            Expression::Literal(l) => Expr::Literal(l.clone()),
+            Expression::Variable(s) => Expr::Variable(self.symbols.intern(s)),
+            Expression::Quote(q) => Expr::Quote(self.add_quotation(q)),
+            Expression::Time(e) => Expr::Time(self.add(e)),
+            Expression::If(test, consequent, alternative) => {
+                let test = self.add(test);
+                let consequent = self.add(consequent);
+                let alternative = alternative.as_ref().map(|e| self.add(e));
+                Expr::If(test, consequent, alternative)
+            },
+            Expression::And(es) => Expr::And(self.add_all(es)),
+            Expression::Or(es) => Expr::Or(self.add_all(es)),
+            Expression::Begin(es) => Expr::Begin(self.add_all(es)),
+            Expression::When(test, body) => Expr::When(self.add(test), self.add_all(body)),
+            Expression::Unless(test, body) => Expr::Unless(self.add(test), self.add_all(body)),
+            Expression::Cond(clauses, else_body) => {
+                let (clauses, else_body) = self.add_cond_clauses(clauses, else_body);
+                Expr::Cond(clauses, else_body)
+            },
+            Expression::Case(key, clauses, else_body) => {
+                let key = self.add(key);
+                let clauses: Vec<CaseClause> = clauses.iter().map(|(data, body)| {
+                    let data: Vec<DatumId> = data.iter().map(|d| self.add_quotation(d)).collect();
+                    (self.list(data), self.add_clause_body(body))
+                }).collect();
+                let else_body = else_body.as_ref().map(|b| self.add_clause_body(b));
+                Expr::Case(key, self.list(clauses), else_body)
+            },
+            Expression::Lambda(formals, body) => Expr::Lambda(self.add_formals(formals), self.add_all(body)),
+            Expression::Define(name, value) => Expr::Define(self.symbols.intern(name), self.add(value)),
+            Expression::Let(name, bindings, body) => {
+                let name = name.as_ref().map(|n| self.symbols.intern(n));
+                let bindings: Vec<Binding> = bindings.iter()
+                    .map(|(n, e)| (self.symbols.intern(n), self.add(e)))
+                    .collect();
+                let bindings = self.list(bindings);
+                Expr::Let(name, bindings, self.add_all(body))
+            },
+            Expression::LetValues(bindings, body) => {
+                let bindings = self.add_values_bindings(bindings);
+                Expr::LetValues(bindings, self.add_all(body))
+            },
+            Expression::LetStarValues(bindings, body) => {
+                let bindings = self.add_values_bindings(bindings);
+                Expr::LetStarValues(bindings, self.add_all(body))
+            },
+            Expression::DefineValues(formals, value) =>
+                Expr::DefineValues(self.add_formals(formals), self.add(value)),
+            Expression::Receive(formals, value, body) => {
+                let formals = self.add_formals(formals);
+                let value = self.add(value);
+                Expr::Receive(formals, value, self.add_all(body))
+            },
+            Expression::Do(bindings, test, result, commands) => {
+                let bindings: Vec<DoBinding> = bindings.iter().map(|(n, init, step)| {
+                    (self.symbols.intern(n), self.add(init), step.as_ref().map(|s| self.add(s)))
+                }).collect();
+                let bindings = self.list(bindings);
+                let test = self.add(test);
+                let result = self.add_all(result);
+                Expr::Do(bindings, test, result, self.add_all(commands))
+            },
+            Expression::Set(name, value) => Expr::Set(self.symbols.intern(name), self.add(value)),
+            Expression::Delay(e) => Expr::Delay(self.add(e)),
+            Expression::DelayForce(e) => Expr::DelayForce(self.add(e)),
+            Expression::DefineRecordType(r) => {
+                let mark = self.symbol_stack.len();
+                for f in &r.constructor.1 {
+                    let f = self.symbols.intern(f);
+                    self.symbol_stack.push(f);
+                }
+                let constructor = (self.symbols.intern(&r.constructor.0), self.symbol_list(mark));
+                let fields: Vec<Field> = r.fields.iter().map(|f| Field {
+                    name: self.symbols.intern(&f.name),
+                    accessor: self.symbols.intern(&f.accessor),
+                    modifier: f.modifier.as_ref().map(|m| self.symbols.intern(m)),
+                }).collect();
+                Expr::DefineRecordType(RecordType {
+                    name: self.symbols.intern(&r.name),
+                    constructor,
+                    predicate: self.symbols.intern(&r.predicate),
+                    fields: self.list(fields),
+                })
+            },
+            Expression::Parameterize(bindings, body) => {
+                let bindings: Vec<(ExprId, ExprId)> = bindings.iter()
+                    .map(|(p, v)| (self.add(p), self.add(v)))
+                    .collect();
+                let bindings = self.list(bindings);
+                Expr::Parameterize(bindings, self.add_all(body))
+            },
+            Expression::Guard(variable, clauses, else_body, body) => {
+                let variable = self.symbols.intern(variable);
+                let (clauses, else_body) = self.add_cond_clauses(clauses, else_body);
+                Expr::Guard(variable, clauses, else_body, self.add_all(body))
+            },
+            Expression::Application(operator, operands) => {
+                let operator = self.add(operator);
+                Expr::Application(operator, self.add_all(operands))
+            },
+        };
+        self.push_expr(e)
+    }
+
+    fn add_all(&mut self, es: &[Expression]) -> List<ExprId> {
+        let mark = self.expr_stack.len();
+        for e in es {
+            let id = self.add(e);
+            self.expr_stack.push(id);
+        }
+        self.expr_list(mark)
+    }
+
+    fn add_quotation(&mut self, q: &Quotation) -> DatumId {
+        match q {
+            Quotation::Literal(l) => self.push_datum(Datum::Literal(l.clone())),
+            Quotation::Symbol(s) => {
+                let s = self.symbols.intern(s);
+                self.push_datum(Datum::Symbol(s))
+            },
+            Quotation::Nil => self.push_datum(Datum::Nil),
+            Quotation::Cons(..) => {
+                // Walk along the list, then build it from the end.
+                let mut items = Vec::new();
+                let mut tail = q;
+                while let Quotation::Cons(hd, tl) = tail {
+                    items.push(self.add_quotation(hd));
+                    tail = tl;
+                }
+                let mut res = self.add_quotation(tail);
+                for hd in items.into_iter().rev() {
+                    res = self.push_datum(Datum::Pair(hd, res));
+                }
+                res
+            },
+        }
+    }
+
+    fn add_formals(&mut self, formals: &syntax::Formals) -> Formals {
+        let mark = self.symbol_stack.len();
+        for p in &formals.required {
+            let p = self.symbols.intern(p);
+            self.symbol_stack.push(p);
+        }
+        let rest = formals.rest.as_ref().map(|r| self.symbols.intern(r));
+        Formals { required: self.symbol_list(mark), rest }
+    }
+
+    fn add_clause_body(&mut self, body: &syntax::ClauseBody) -> ClauseBody {
+        match body {
+            syntax::ClauseBody::Sequence(es) => ClauseBody::Sequence(self.add_all(es)),
+            syntax::ClauseBody::Receiver(e) => ClauseBody::Receiver(self.add(e)),
+        }
+    }
+
+    fn add_cond_clauses(&mut self, clauses: &[syntax::CondClause], else_body: &Option<Vec<Expression>>)
+                        -> (List<CondClause>, Option<List<ExprId>>) {
+        let clauses: Vec<CondClause> = clauses.iter().map(|c| match c {
+            syntax::CondClause::Simple(test, body) => {
+                let test = self.add(test);
+                CondClause::Simple(test, self.add_all(body))
+            },
+            syntax::CondClause::Binding(test, receiver) => {
+                let test = self.add(test);
+                CondClause::Binding(test, self.add(receiver))
+            },
+            syntax::CondClause::Inconsequential(test) => CondClause::Inconsequential(self.add(test)),
+        }).collect();
+        let clauses = self.list(clauses);
+        (clauses, else_body.as_ref().map(|b| self.add_all(b)))
+    }
+
+    fn add_values_bindings(&mut self, bindings: &[syntax::ValuesBinding]) -> List<ValuesBinding> {
+        let bindings: Vec<ValuesBinding> = bindings.iter()
+            .map(|(formals, e)| (self.add_formals(formals), self.add(e)))
+            .collect();
+        self.list(bindings)
+    }
+
+    // Copying out expressions.
+
+    /// The `syntax::Expression` that `id` stands for.
+    pub fn expression(&self, id: ExprId) -> Expression {
+        let all = |list: List<ExprId>| -> Vec<Expression> {
+            self[list].iter().map(|&e| self.expression(e)).collect()
+        };
+        let boxed = |id: ExprId| Box::new(self.expression(id));
+        let name = |s: Symbol| self.name(s).to_string();
+        match self[id] {
+            Expr::Literal(ref l) => Expression::Literal(l.clone()),
+            Expr::Variable(s) => Expression::Variable(name(s)),
+            Expr::Quote(d) => Expression::Quote(self.quotation(d)),
+            Expr::Time(e) => Expression::Time(boxed(e)),
+            Expr::If(test, consequent, alternative) =>
+                Expression::If(boxed(test), boxed(consequent), alternative.map(boxed)),
+            Expr::And(es) => Expression::And(all(es)),
+            Expr::Or(es) => Expression::Or(all(es)),
+            Expr::Begin(es) => Expression::Begin(all(es)),
+            Expr::When(test, body) => Expression::When(boxed(test), all(body)),
+            Expr::Unless(test, body) => Expression::Unless(boxed(test), all(body)),
+            Expr::Cond(clauses, else_body) =>
+                Expression::Cond(self.cond_clauses_out(clauses), else_body.map(all)),
+            Expr::Case(key, clauses, else_body) => Expression::Case(
+                boxed(key),
+                self[clauses].iter().map(|&(data, body)| {
+                    (self[data].iter().map(|&d| self.quotation(d)).collect(), self.clause_body(body))
+                }).collect(),
+                else_body.map(|b| self.clause_body(b))),
+            Expr::Lambda(formals, body) => Expression::Lambda(self.formals_out(formals), all(body)),
+            Expr::Define(n, value) => Expression::Define(name(n), boxed(value)),
+            Expr::Let(n, bindings, body) => Expression::Let(
+                n.map(name),
+                self[bindings].iter().map(|&(n, e)| (name(n), self.expression(e))).collect(),
+                all(body)),
+            Expr::LetValues(bindings, body) =>
+                Expression::LetValues(self.values_bindings_out(bindings), all(body)),
+            Expr::LetStarValues(bindings, body) =>
+                Expression::LetStarValues(self.values_bindings_out(bindings), all(body)),
+            Expr::DefineValues(formals, value) =>
+                Expression::DefineValues(self.formals_out(formals), boxed(value)),
+            Expr::Receive(formals, value, body) =>
+                Expression::Receive(self.formals_out(formals), boxed(value), all(body)),
+            Expr::Do(bindings, test, result, commands) => Expression::Do(
+                self[bindings].iter().map(|&(n, init, step)| {
+                    (name(n), self.expression(init), step.map(|s| self.expression(s)))
+                }).collect(),
+                boxed(test), all(result), all(commands)),
+            Expr::Set(n, value) => Expression::Set(name(n), boxed(value)),
+            Expr::Delay(e) => Expression::Delay(boxed(e)),
+            Expr::DelayForce(e) => Expression::DelayForce(boxed(e)),
+            Expr::DefineRecordType(r) => Expression::DefineRecordType(syntax::RecordType {
+                name: name(r.name),
+                constructor: (name(r.constructor.0),
+                              self[r.constructor.1].iter().map(|&f| name(f)).collect()),
+                predicate: name(r.predicate),
+                fields: self[r.fields].iter().map(|f| syntax::Field {
+                    name: name(f.name),
+                    accessor: name(f.accessor),
+                    modifier: f.modifier.map(name),
+                }).collect(),
+            }),
+            Expr::Parameterize(bindings, body) => Expression::Parameterize(
+                self[bindings].iter().map(|&(p, v)| (self.expression(p), self.expression(v))).collect(),
+                all(body)),
+            Expr::Guard(variable, clauses, else_body, body) => Expression::Guard(
+                name(variable), self.cond_clauses_out(clauses), else_body.map(all), all(body)),
+            Expr::Application(operator, operands) => Expression::Application(boxed(operator), all(operands)),
+        }
+    }
+
+    /// The `syntax::Quotation` that `id` stands for.
+    pub fn quotation(&self, id: DatumId) -> Quotation {
+        match self[id] {
+            Datum::Literal(ref l) => Quotation::Literal(l.clone()),
+            Datum::Symbol(s) => Quotation::Symbol(self.name(s).to_string()),
+            Datum::Nil => Quotation::Nil,
+            Datum::Pair(..) => {
+                let mut items = Vec::new();
+                let mut tail = id;
+                while let Datum::Pair(hd, tl) = self[tail] {
+                    items.push(self.quotation(hd));
+                    tail = tl;
+                }
+                let mut res = self.quotation(tail);
+                for hd in items.into_iter().rev() {
+                    res = Quotation::Cons(Box::new(hd), Box::new(res));
+                }
+                res
+            },
+        }
+    }
+
+    fn formals_out(&self, formals: Formals) -> syntax::Formals {
+        syntax::Formals {
+            required: self[formals.required].iter().map(|&s| self.name(s).to_string()).collect(),
+            rest: formals.rest.map(|s| self.name(s).to_string()),
+        }
+    }
+
+    fn clause_body(&self, body: ClauseBody) -> syntax::ClauseBody {
+        match body {
+            ClauseBody::Sequence(es) =>
+                syntax::ClauseBody::Sequence(self[es].iter().map(|&e| self.expression(e)).collect()),
+            ClauseBody::Receiver(e) => syntax::ClauseBody::Receiver(Box::new(self.expression(e))),
+        }
+    }
+
+    fn cond_clauses_out(&self, clauses: List<CondClause>) -> Vec<syntax::CondClause> {
+        self[clauses].iter().map(|c| match *c {
+            CondClause::Simple(test, body) => syntax::CondClause::Simple(
+                self.expression(test), self[body].iter().map(|&e| self.expression(e)).collect()),
+            CondClause::Binding(test, receiver) =>
+                syntax::CondClause::Binding(self.expression(test), self.expression(receiver)),
+            CondClause::Inconsequential(test) => syntax::CondClause::Inconsequential(self.expression(test)),
+        }).collect()
+    }
+
+    fn values_bindings_out(&self, bindings: List<ValuesBinding>) -> Vec<syntax::ValuesBinding> {
+        self[bindings].iter().map(|&(formals, e)| (self.formals_out(formals), self.expression(e))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use lexer::RegexLexer;
+    use parser::Parser;
+    use scheme::parse_expression;
+    use serialize::Serialize;
+    use super::{Ast, Datum, Expr, Interner, Symbol, KEYWORDS};
+
+    #[test]
+    fn interning() {
+        let mut symbols = Interner::new();
+        for (i, name) in KEYWORDS.iter().enumerate() {
+            assert_eq!(symbols.intern(name), Symbol(i as u32));
+        }
+        assert_eq!(symbols.intern("quote"), Symbol::QUOTE);
+        assert_eq!(symbols.name(Symbol::DELAY_FORCE), "delay-force");
+        let x = symbols.intern("x");
+        assert_eq!(symbols.intern("x"), x);
+        assert_eq!(symbols.get("x"), Some(x));
+        assert_eq!(symbols.get("y"), None);
+        assert_eq!(symbols.len(), KEYWORDS.len() + 1);
+    }
+
+    /// The arena accepts what `parse_expression` accepts, with the same
+    /// expressions and the same errors.
+    #[test]
+    fn same_as_parse_expression() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut text = String::new();
+        for dir in &["conformance", "benches/programs"] {
+            let mut paths: Vec<_> = fs::read_dir(root.join(dir)).unwrap().map(|e| e.unwrap().path()).collect();
+            paths.sort();
+            for path in paths {
+                text.push_str(&fs::read_to_string(path).unwrap());
+            }
+        }
+        // Forms that the conformance tests only quote, and errors.
+        text.push_str("
+            (if) (if 1 2 3 4) (quote) (quote 1 2) (begin) (when 1) (set! 1 2) (set! x)
+            (cond) (cond ()) (cond 1) (cond (a => b c)) (cond (else)) (cond (else 1) (a 2))
+            (cond (a => b) (b) (c d e) (else f)) (cond (else => 1))
+            (case) (case x) (case x (1 2)) (case x ((1))) (case x ((1) => f g))
+            (case x ((1) => f) (else => g)) (case x (else => g h)) (case x (else 1) ((2) 3))
+            (case x ((a (b . c) . d) 1) (() 2))
+            (lambda) (lambda (x)) (lambda (x x) x) (lambda (x . x) x) (lambda (x . y z) x)
+            (lambda (1) x) (lambda 1 x) (lambda x x) (lambda (x . y) x) (lambda (. y) y)
+            (define) (define x) (define x 1 2) (define 1 2) (define () 1) (define (f)) (define (1) 2)
+            (define (f x) x) (define (f . x) x) (define (f x x) x)
+            (let) (let x) (let ()) (let x ()) (let (x) 1) (let ((x)) 1) (let ((1 2)) 1)
+            (let ((x 1) (x 2)) x) (let loop ((i 0)) (loop i)) (let 1 2)
+            (do ((i 0 (+ i 1))) ((= i 3) (if))) (guard (e (#t (if))) 1) (f (do))
+            (1 2) () (() 1) ((f) (g)) (else 1) (=> 1) (. 1) (time) (delay 1 2) (delay-force)
+            (quote (a . b . c)) (quote (. a)) (quote (a .)) (quote (a . b))
+            (quote (a . b c)) (quote (a . . b)) (quote (a . .)) (quote ((a . b c) d))
+            (case x ((a . b c) 1))
+        ");
+        let mut ast = Ast::new();
+        let mut count = 0;
+        for node in Parser::new(RegexLexer::new(&text)) {
+            let node = node.unwrap();
+            match (ast.parse(&node), parse_expression(node.clone())) {
+                (Ok(id), Ok(e)) => assert_eq!(ast.expression(id), e, "{:?}", node),
+                (Err(a), Err(b)) => assert_eq!((a.code, a.message), (b.code, b.message)),
+                (a, b) => panic!("{:?}: {:?} and {:?}", node, a.map(|id| ast.expression(id)), b),
+            }
+            count += 1;
+        }
+        assert!(count > 400);
+    }
+
+    #[test]
+    fn arena() {
+        let mut ast = Ast::new();
+        let text = format!("(case x ((a b) 1) (else 2)) (quote ({}))",
+                           (0..100000).map(|i| i.to_string()).collect::<Vec<_>>().join(" "));
+        let nodes: Vec<_> = Parser::new(RegexLexer::new(&text)).map(|n| n.unwrap()).collect();
+        let case = ast.parse(&nodes[0]).unwrap();
+        let clauses = match ast[case] {
+            Expr::Case(_, clauses, Some(_)) => clauses,
+            ref e => panic!("unexpected {:?}", e),
+        };
+        let data = ast[clauses][0].0;
+        let a = ast.symbols().get("a").unwrap();
+        assert_eq!(ast[ast[data][0]], Datum::Symbol(a));
+        // Long lists neither recurse nor change when copied out and in.
+        let list = ast.parse(&nodes[1]).unwrap();
+        let e = ast.expression(list);
+        let copy = ast.add(&e);
+        assert_eq!(ast.expression(copy).to_sexpr(), e.to_sexpr());
+        ast.clear();
+        assert_eq!(ast.parse(&nodes[0]).unwrap(), case);
+    }
+}
@@ -0,0 +1,294 @@
+//! Tokens and data that borrow their text from the source instead of
+//! copying it, for tools that only need the structure of a file, such as
+//! matching parentheses or listing definitions. Reading with these
+//! allocates only for the items of lists, and string escapes are replaced
+//! only when `Str::value` is asked for a string that has them.
+//!
+//! ```
+//! use scheme_syntax::borrowed::{Node, Parser};
+//!
+//! let text = "(define (f x) \"plain\")";
+//! let node = Parser::new(text).next().unwrap().unwrap();
+//! match node {
+//!     Node::List(ref items) => assert_eq!(items[0], Node::Identifier("define")),
+//!     _ => unreachable!(),
+//! }
+//! ```
+
+use std::borrow::Cow;
+
+use lexer::{self, RegexLexer, Span};
+use parser::{self, read_next, Read, ReadLimits, ReadToken, Size};
+
+/// The text of a string literal between its quotes, with its escapes,
+/// which the lexer has checked, not yet replaced.
+#[derive(Debug, Clone, Copy)]
+pub struct Str<'t> {
+    raw: &'t str,
+}
+
+impl <'t> Str<'t> {
+    /// Checks the escapes in `raw`, the text between the quotes.
+    pub(crate) fn new(raw: &'t str) -> lexer::Result<Self> {
+        let mut chars = raw.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some('\\') | Some('"') | Some('n') | Some('t') => (),
+                    _ => return Err("Bad string escape"),
+                }
+            }
+        }
+        Ok(Str { raw })
+    }
+
+    /// The text as written, escapes and all.
+    pub fn raw(&self) -> &'t str {
+        self.raw
+    }
+
+    /// The string, borrowed from the source unless it has escapes.
+    pub fn value(&self) -> Cow<'t, str> {
+        if !self.raw.contains('\\') {
+            return Cow::Borrowed(self.raw);
+        }
+        let mut r = String::with_capacity(self.raw.len());
+        let mut chars = self.raw.chars();
+        while let Some(c) = chars.next() {
+            r.push(if c != '\\' {
+                c
+            } else {
+                match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some(c) => c,
+                    None => unreachable!("checked by Str::new"),
+                }
+            });
+        }
+        Cow::Owned(r)
+    }
+
+    /// The length of `value` in bytes, without building it. Each escape
+    /// is two bytes that stand for one.
+    pub fn len(&self) -> usize {
+        let mut escapes = 0;
+        let mut bytes = self.raw.bytes();
+        while let Some(b) = bytes.next() {
+            if b == b'\\' {
+                escapes += 1;
+                bytes.next();
+            }
+        }
+        self.raw.len() - escapes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+}
+
+/// Strings are equal if their values are, however they are written.
+impl <'t> PartialEq for Str<'t> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw || self.value() == other.value()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Literal<'t> {
+    Number(i32),
+    Real(f64),
+    Boolean(bool),
+    Character(char),
+    String(Str<'t>),
+}
+
+impl <'t> Literal<'t> {
+    pub fn into_owned(self) -> lexer::Literal {
+        match self {
+            Literal::Number(n) => lexer::Literal::Number(n),
+            Literal::Real(x) => lexer::Literal::Real(x),
+            Literal::Boolean(b) => lexer::Literal::Boolean(b),
+            Literal::Character(c) => lexer::Literal::Character(c),
+            Literal::String(s) => lexer::Literal::String(s.value().into_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token<'t> {
+    Identifier(&'t str),
+    LParen,
+    RParen,
+    Literal(Literal<'t>),
+}
+
+impl <'t> Token<'t> {
+    pub fn into_owned(self) -> lexer::Token {
+        match self {
+            Token::Identifier(s) => lexer::Token::Identifier(s.to_string()),
+            Token::LParen => lexer::Token::LParen,
+            Token::RParen => lexer::Token::RParen,
+            Token::Literal(l) => lexer::Token::Literal(l.into_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node<'t> {
+    Identifier(&'t str),
+    Literal(Literal<'t>),
+    List(Vec<Node<'t>>),
+}
+
+impl <'t> Node<'t> {
+    pub fn into_owned(self) -> parser::Node {
+        match self {
+            Node::Identifier(s) => parser::Node::Identifier(s.to_string()),
+            Node::Literal(l) => parser::Node::Literal(l.into_owned()),
+            Node::List(items) => parser::Node::List(items.into_iter().map(Node::into_owned).collect()),
+        }
+    }
+}
+
+impl <'t> ReadToken for Token<'t> {
+    type Node = Node<'t>;
+
+    fn size(&self) -> Option<Size> {
+        Some(match self {
+            Token::LParen => Size::List,
+            Token::RParen => return None,
+            Token::Identifier(s) => Size::Identifier(s.len()),
+            Token::Literal(Literal::String(s)) => Size::String(s.len()),
+            Token::Literal(_) => Size::Atom,
+        })
+    }
+
+    fn read(self) -> Read<Node<'t>> {
+        match self {
+            Token::LParen => Read::Open,
+            Token::RParen => Read::Close,
+            Token::Identifier(s) => Read::Atom(Node::Identifier(s)),
+            Token::Literal(l) => Read::Atom(Node::Literal(l)),
+        }
+    }
+
+    fn list(items: Vec<Node<'t>>) -> Node<'t> {
+        Node::List(items)
+    }
+}
+
+/// The tokens of a text, borrowed from it.
+pub struct Tokens<'t> {
+    lexer: RegexLexer<'t>,
+}
+
+impl <'t> Tokens<'t> {
+    pub fn new(text: &'t str) -> Self {
+        Tokens { lexer: RegexLexer::new(text) }
+    }
+
+    /// The span of the token last returned, or of the rest of the text if
+    /// it failed to lex.
+    pub fn span(&self) -> Span {
+        self.lexer.span()
+    }
+}
+
+impl <'t> Iterator for Tokens<'t> {
+    type Item = lexer::Result<Token<'t>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lexer.next_borrowed()
+    }
+}
+
+/// A parser that reads data borrowed from the text, with the same errors
+/// and limits as `parser::Parser`.
+pub struct Parser<'t> {
+    tokens: Tokens<'t>,
+    limits: ReadLimits,
+    nodes: usize,
+}
+
+impl <'t> Parser<'t> {
+    pub fn new(text: &'t str) -> Self {
+        Parser::with_limits(text, ReadLimits::default())
+    }
+
+    pub fn with_limits(text: &'t str, limits: ReadLimits) -> Self {
+        Parser { tokens: Tokens::new(text), limits, nodes: 0 }
+    }
+
+    /// The span of the token last read, which is where an error is.
+    pub fn span(&self) -> Span {
+        self.tokens.span()
+    }
+}
+
+impl <'t> Iterator for Parser<'t> {
+    type Item = Result<Node<'t>, &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        read_next(&mut self.tokens, &self.limits, &mut self.nodes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use lexer::RegexLexer;
+    use parser::{self, ReadLimits};
+    use super::{Literal, Node, Parser, Str, Token, Tokens};
+
+    #[test]
+    fn strings() {
+        let plain = Str::new("a b").unwrap();
+        assert!(matches!(plain.value(), Cow::Borrowed("a b")));
+        let escaped = Str::new("a\\n\\\"b\\\\").unwrap();
+        assert_eq!(escaped.value(), Cow::Owned::<str>("a\n\"b\\".to_string()));
+        assert_eq!(escaped.len(), escaped.value().len());
+        assert_eq!(Str::new("\t").unwrap(), Str::new("\\t").unwrap());
+        assert_eq!(Str::new("a\\x").unwrap_err(), "Bad string escape");
+    }
+
+    #[test]
+    fn borrows() {
+        let text = "(f \"s\" x) 1.5 #\\a";
+        let tokens: Vec<_> = Tokens::new(text).map(|t| t.unwrap()).collect();
+        assert_eq!(tokens[1], Token::Identifier("f"));
+        match tokens[1] {
+            Token::Identifier(s) => assert_eq!(s.as_ptr(), text[1..].as_ptr()),
+            _ => unreachable!(),
+        }
+        match tokens[2] {
+            Token::Literal(Literal::String(s)) => assert_eq!(s.raw().as_ptr(), text[4..].as_ptr()),
+            _ => unreachable!(),
+        }
+        let owned: Vec<_> = RegexLexer::new(text).map(|t| t.unwrap()).collect();
+        assert_eq!(tokens.into_iter().map(Token::into_owned).collect::<Vec<_>>(), owned);
+    }
+
+    #[test]
+    fn same_as_parser() {
+        let text = "(define (f x) (g \"a\\tb\" #t -3 . x)) y ((()))";
+        let owned: Vec<_> = parser::Parser::new(RegexLexer::new(text)).map(|n| n.unwrap()).collect();
+        let borrowed: Vec<_> = Parser::new(text).map(|n| n.unwrap().into_owned()).collect();
+        assert_eq!(borrowed, owned);
+        assert_eq!(Parser::new("y").next(), Some(Ok(Node::Identifier("y"))));
+
+        for text in &["(a", ")", "\"\\q\"", "(((a)))", "\"a\\nbc\"", "(1 2 3 4 5)"] {
+            let limits = ReadLimits { depth: Some(2), string_length: Some(3), nodes: Some(4),
+                                      ..ReadLimits::default() };
+            let owned: Vec<_> = parser::Parser::with_limits(RegexLexer::new(text), limits).collect();
+            let borrowed: Vec<_> = Parser::with_limits(text, limits)
+                .map(|n| n.map(Node::into_owned))
+                .collect();
+            assert_eq!(borrowed, owned, "{}", text);
+        }
+        let mut p = Parser::new("(a \"\\q\")");
+        assert_eq!(p.next(), Some(Err("lexer error")));
+        assert_eq!((p.span().start, p.span().end), (3, 7));
+    }
+}
@@ -0,0 +1,1533 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use heap::{Heap, Object, Ref};
+use value::{Value, Primitive, PrimitiveFn, Arity, RuntimeError, Result};
+use vm::Vm;
+
+pub fn expected(heap: &Heap, what: &str, v: &Value) -> RuntimeError {
+    RuntimeError::Basic(
+        format!("Expected {}, got {} {}", what, v.type_name(), heap.write(v)))
+}
+
+fn out_of_range(heap: &Heap, what: &str, k: &Value) -> RuntimeError {
+    RuntimeError::Basic(format!("{} index out of range: {}", what, heap.write(k)))
+}
+
+/// A number taken apart for arithmetic. Exact integers are 32 bits and
+/// there are no bignums or rationals: an exact result that does not fit in
+/// an `i32` is an error, and dividing exact integers that do not divide
+/// evenly gives an inexact result.
+#[derive(Debug, Clone, Copy)]
+enum Num {
+    Exact(i32),
+    Inexact(f64),
+}
+
+impl Num {
+    fn real(self) -> f64 {
+        match self {
+            Num::Exact(n) => f64::from(n),
+            Num::Inexact(x) => x,
+        }
+    }
+
+    fn value(self) -> Value {
+        match self {
+            Num::Exact(n) => Value::Number(n),
+            Num::Inexact(x) => Value::Real(x),
+        }
+    }
+}
+
+fn number(heap: &Heap, v: &Value) -> Result<Num> {
+    match v {
+        Value::Number(n) => Ok(Num::Exact(*n)),
+        Value::Real(x) => Ok(Num::Inexact(*x)),
+        v => Err(expected(heap, "number", v)),
+    }
+}
+
+fn integer(heap: &Heap, v: &Value) -> Result<Num> {
+    match number(heap, v)? {
+        Num::Inexact(x) if x.fract() != 0.0 || !x.is_finite() =>
+            Err(expected(heap, "integer", v)),
+        n => Ok(n),
+    }
+}
+
+/// An exact non-negative integer below `len`, for indexing.
+fn index(heap: &Heap, what: &str, k: &Value, len: usize) -> Result<usize> {
+    match k {
+        Value::Number(n) if *n >= 0 && (*n as usize) < len => Ok(*n as usize),
+        Value::Number(_) => Err(out_of_range(heap, what, k)),
+        k => Err(expected(heap, "exact integer", k)),
+    }
+}
+
+/// The optional `start` and `end` arguments at `args[i..]` of a procedure
+/// working on a sequence of length `len`.
+fn range(heap: &Heap, what: &str, args: &[Value], i: usize, len: usize) -> Result<(usize, usize)> {
+    let start = match args.get(i) {
+        Some(k) => index(heap, what, k, len + 1)?,
+        None => 0,
+    };
+    let end = match args.get(i + 1) {
+        Some(k) => index(heap, what, k, len + 1)?,
+        None => len,
+    };
+    if start > end {
+        return Err(out_of_range(heap, what, &args[i]));
+    }
+    Ok((start, end))
+}
+
+fn overflow() -> RuntimeError {
+    RuntimeError::Basic("Exact integer overflow".to_string())
+}
+
+/// Applies `exact` if both numbers are exact, where `None` means the
+/// result does not fit, and `inexact` otherwise.
+fn arith(a: Num, b: Num, exact: fn(i32, i32) -> Option<i32>,
+         inexact: fn(f64, f64) -> f64) -> Result<Num> {
+    match (a, b) {
+        (Num::Exact(a), Num::Exact(b)) => exact(a, b).map(Num::Exact).ok_or_else(overflow),
+        (a, b) => Ok(Num::Inexact(inexact(a.real(), b.real()))),
+    }
+}
+
+fn add(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let mut acc = Num::Exact(0);
+    for a in args {
+        acc = arith(acc, number(vm.heap(), a)?, i32::checked_add, |a, b| a + b)?;
+    }
+    Ok(acc.value())
+}
+
+fn mul(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let mut acc = Num::Exact(1);
+    for a in args {
+        acc = arith(acc, number(vm.heap(), a)?, i32::checked_mul, |a, b| a * b)?;
+    }
+    Ok(acc.value())
+}
+
+fn sub(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let first = number(vm.heap(), &args[0])?;
+    if args.len() == 1 {
+        return Ok(arith(Num::Exact(0), first, i32::checked_sub, |a, b| a - b)?.value());
+    }
+    let mut acc = first;
+    for a in &args[1..] {
+        acc = arith(acc, number(vm.heap(), a)?, i32::checked_sub, |a, b| a - b)?;
+    }
+    Ok(acc.value())
+}
+
+fn divide(a: Num, b: Num) -> Result<Num> {
+    match (a, b) {
+        (Num::Exact(_), Num::Exact(0)) => Err(RuntimeError::Basic("Division by zero".to_string())),
+        (Num::Exact(a), Num::Exact(b)) if a.checked_rem(b).unwrap_or(0) == 0 =>
+            a.checked_div(b).map(Num::Exact).ok_or_else(overflow),
+        (a, b) => Ok(Num::Inexact(a.real() / b.real())),
+    }
+}
+
+fn div(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let first = number(vm.heap(), &args[0])?;
+    if args.len() == 1 {
+        return Ok(divide(Num::Exact(1), first)?.value());
+    }
+    let mut acc = first;
+    for a in &args[1..] {
+        acc = divide(acc, number(vm.heap(), a)?)?;
+    }
+    Ok(acc.value())
+}
+
+fn num_cmp(a: Num, b: Num) -> Option<Ordering> {
+    match (a, b) {
+        (Num::Exact(a), Num::Exact(b)) => Some(a.cmp(&b)),
+        (a, b) => a.real().partial_cmp(&b.real()),
+    }
+}
+
+fn compare(vm: &Vm, args: &[Value], ok: fn(Ordering) -> bool) -> Result<Value> {
+    let mut prev = number(vm.heap(), &args[0])?;
+    let mut res = true;
+    for a in &args[1..] {
+        let n = number(vm.heap(), a)?;
+        res = res && num_cmp(prev, n).is_some_and(ok);
+        prev = n;
+    }
+    Ok(Value::Boolean(res))
+}
+
+fn num_eq(vm: &mut Vm, args: &[Value]) -> Result<Value> { compare(vm, args, |o| o == Ordering::Equal) }
+fn lt(vm: &mut Vm, args: &[Value]) -> Result<Value> { compare(vm, args, |o| o == Ordering::Less) }
+fn gt(vm: &mut Vm, args: &[Value]) -> Result<Value> { compare(vm, args, |o| o == Ordering::Greater) }
+fn le(vm: &mut Vm, args: &[Value]) -> Result<Value> { compare(vm, args, |o| o != Ordering::Greater) }
+fn ge(vm: &mut Vm, args: &[Value]) -> Result<Value> { compare(vm, args, |o| o != Ordering::Less) }
+
+fn is_number(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(args[0], Value::Number(_) | Value::Real(_))))
+}
+
+fn is_integer(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(match args[0] {
+        Value::Number(_) => true,
+        Value::Real(x) => x.is_finite() && x.fract() == 0.0,
+        _ => false,
+    }))
+}
+
+fn is_exact(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(number(vm.heap(), &args[0])?, Num::Exact(_))))
+}
+
+fn is_inexact(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(number(vm.heap(), &args[0])?, Num::Inexact(_))))
+}
+
+fn is_zero(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(number(vm.heap(), &args[0])?.real() == 0.0))
+}
+
+fn is_positive(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(number(vm.heap(), &args[0])?.real() > 0.0))
+}
+
+fn is_negative(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(number(vm.heap(), &args[0])?.real() < 0.0))
+}
+
+fn is_odd(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(match integer(vm.heap(), &args[0])? {
+        Num::Exact(n) => n % 2 != 0,
+        Num::Inexact(x) => x % 2.0 != 0.0,
+    }))
+}
+
+fn is_even(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(match integer(vm.heap(), &args[0])? {
+        Num::Exact(n) => n % 2 == 0,
+        Num::Inexact(x) => x % 2.0 == 0.0,
+    }))
+}
+
+fn abs(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(match number(vm.heap(), &args[0])? {
+        Num::Exact(n) => Value::Number(n.checked_abs().ok_or_else(overflow)?),
+        Num::Inexact(x) => Value::Real(x.abs()),
+    })
+}
+
+fn extremum(vm: &Vm, args: &[Value], keep: Ordering) -> Result<Value> {
+    let mut acc = number(vm.heap(), &args[0])?;
+    let mut inexact = matches!(acc, Num::Inexact(_));
+    for a in &args[1..] {
+        let n = number(vm.heap(), a)?;
+        inexact = inexact || matches!(n, Num::Inexact(_));
+        if num_cmp(n, acc) == Some(keep) {
+            acc = n;
+        }
+    }
+    Ok(if inexact { Num::Inexact(acc.real()) } else { acc }.value())
+}
+
+fn min(vm: &mut Vm, args: &[Value]) -> Result<Value> { extremum(vm, args, Ordering::Less) }
+fn max(vm: &mut Vm, args: &[Value]) -> Result<Value> { extremum(vm, args, Ordering::Greater) }
+
+/// Integer division. `exact` returns `None` only on overflow.
+fn int_div(vm: &Vm, args: &[Value], exact: fn(i32, i32) -> Option<i32>,
+           inexact: fn(f64, f64) -> f64) -> Result<Value> {
+    let a = integer(vm.heap(), &args[0])?;
+    let b = integer(vm.heap(), &args[1])?;
+    if b.real() == 0.0 {
+        return Err(RuntimeError::Basic("Division by zero".to_string()));
+    }
+    Ok(arith(a, b, exact, inexact)?.value())
+}
+
+fn quotient(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    int_div(vm, args, i32::checked_div, |a, b| (a / b).trunc())
+}
+
+fn remainder(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    int_div(vm, args, |a, b| Some(a.wrapping_rem(b)), |a, b| a % b)
+}
+
+fn modulo(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    int_div(vm, args, |a, b| {
+        let r = a.wrapping_rem(b);
+        Some(if r != 0 && (r < 0) != (b < 0) { r + b } else { r })
+    }, |a, b| {
+        let r = a % b;
+        if r != 0.0 && (r < 0.0) != (b < 0.0) { r + b } else { r }
+    })
+}
+
+fn rounding(vm: &Vm, args: &[Value], f: fn(f64) -> f64) -> Result<Value> {
+    Ok(match number(vm.heap(), &args[0])? {
+        Num::Exact(n) => Value::Number(n),
+        Num::Inexact(x) => Value::Real(f(x)),
+    })
+}
+
+fn floor(vm: &mut Vm, args: &[Value]) -> Result<Value> { rounding(vm, args, f64::floor) }
+fn ceiling(vm: &mut Vm, args: &[Value]) -> Result<Value> { rounding(vm, args, f64::ceil) }
+fn round(vm: &mut Vm, args: &[Value]) -> Result<Value> { rounding(vm, args, f64::round_ties_even) }
+fn truncate(vm: &mut Vm, args: &[Value]) -> Result<Value> { rounding(vm, args, f64::trunc) }
+
+fn exact(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    match number(vm.heap(), &args[0])? {
+        Num::Exact(n) => Ok(Value::Number(n)),
+        Num::Inexact(x) if x.fract() == 0.0 &&
+            x >= f64::from(i32::MIN) && x <= f64::from(i32::MAX) => Ok(Value::Number(x as i32)),
+        Num::Inexact(_) => Err(RuntimeError::Basic(
+            format!("No exact representation of {}", vm.write(&args[0])))),
+    }
+}
+
+fn inexact(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Real(number(vm.heap(), &args[0])?.real()))
+}
+
+fn sqrt(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let n = number(vm.heap(), &args[0])?;
+    let root = n.real().sqrt();
+    if let Num::Exact(n) = n {
+        let r = root.round() as i32;
+        if r.checked_mul(r) == Some(n) {
+            return Ok(Value::Number(r));
+        }
+    }
+    Ok(Value::Real(root))
+}
+
+fn expt(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let base = number(vm.heap(), &args[0])?;
+    let power = number(vm.heap(), &args[1])?;
+    if let (Num::Exact(_), Num::Exact(b)) = (base, power) {
+        if b < 0 {
+            return Ok(Value::Real(base.real().powi(b)));
+        }
+    }
+    Ok(arith(base, power, |a, b| a.checked_pow(b as u32), f64::powf)?.value())
+}
+
+fn radix(vm: &Vm, args: &[Value], i: usize) -> Result<u32> {
+    match args.get(i) {
+        None => Ok(10),
+        Some(Value::Number(r)) if [2, 8, 10, 16].contains(r) => Ok(*r as u32),
+        Some(v) => Err(RuntimeError::Basic(
+            format!("Unsupported radix {}", vm.write(v)))),
+    }
+}
+
+fn format_radix(n: i32, radix: u32) -> String {
+    let digits = "0123456789abcdef".as_bytes();
+    let mut m = i64::from(n).abs();
+    let mut s = Vec::new();
+    loop {
+        s.push(digits[(m % i64::from(radix)) as usize]);
+        m /= i64::from(radix);
+        if m == 0 {
+            break;
+        }
+    }
+    if n < 0 {
+        s.push(b'-');
+    }
+    s.reverse();
+    String::from_utf8(s).unwrap()
+}
+
+fn number_to_string(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let radix = radix(vm, args, 1)?;
+    let s = match number(vm.heap(), &args[0])? {
+        Num::Exact(n) => format_radix(n, radix),
+        Num::Inexact(_) if radix == 10 => vm.write(&args[0]),
+        Num::Inexact(_) => return Err(RuntimeError::Basic(
+            "Inexact numbers can only be written in radix 10".to_string())),
+    };
+    Ok(vm.heap_mut().string(s))
+}
+
+fn parse_number(s: &str, radix: u32) -> Option<Value> {
+    if let Ok(n) = i32::from_str_radix(s, radix) {
+        return Some(Value::Number(n));
+    }
+    if radix != 10 {
+        return None;
+    }
+    match s {
+        "+inf.0" => return Some(Value::Real(f64::INFINITY)),
+        "-inf.0" => return Some(Value::Real(f64::NEG_INFINITY)),
+        "+nan.0" | "-nan.0" => return Some(Value::Real(f64::NAN)),
+        _ => (),
+    }
+    // Rust also accepts spellings such as "inf" that are not Scheme numbers.
+    let numeric = s.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+    if !numeric || !s.chars().any(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    s.parse::<f64>().ok().map(Value::Real)
+}
+
+fn string_to_number(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = string(vm.heap(), &args[0])?;
+    let radix = radix(vm, args, 1)?;
+    Ok(parse_number(vm.heap().str(r), radix).unwrap_or(Value::Boolean(false)))
+}
+
+fn not(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(!args[0].is_true()))
+}
+
+fn is_boolean(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(args[0], Value::Boolean(_))))
+}
+
+fn eqv(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(args[0].eqv(&args[1])))
+}
+
+/// Structural equality. Pairs of objects already being compared are
+/// assumed equal, so circular structures terminate.
+fn equal_values(heap: &Heap, a: &Value, b: &Value) -> bool {
+    let mut seen = HashSet::new();
+    let mut todo = vec![(a.clone(), b.clone())];
+    while let Some((a, b)) = todo.pop() {
+        match (&a, &b) {
+            (Value::Pair(x), Value::Pair(y)) => {
+                if x == y || !seen.insert((*x, *y)) {
+                    continue;
+                }
+                let (car1, cdr1) = heap.pair(*x);
+                let (car2, cdr2) = heap.pair(*y);
+                todo.push((cdr1.clone(), cdr2.clone()));
+                todo.push((car1.clone(), car2.clone()));
+            },
+            (Value::Vector(x), Value::Vector(y)) => {
+                if x == y || !seen.insert((*x, *y)) {
+                    continue;
+                }
+                let (xs, ys) = (heap.items(*x), heap.items(*y));
+                if xs.len() != ys.len() {
+                    return false;
+                }
+                todo.extend(xs.iter().cloned().zip(ys.iter().cloned()).rev());
+            },
+            (Value::String(x), Value::String(y)) =>
+                if heap.str(*x) != heap.str(*y) {
+                    return false;
+                },
+            (a, b) =>
+                if !a.eqv(b) {
+                    return false;
+                },
+        }
+    }
+    true
+}
+
+fn equal(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(equal_values(vm.heap(), &args[0], &args[1])))
+}
+
+fn is_procedure(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(args[0].type_name() == "procedure"))
+}
+
+fn pair(heap: &Heap, v: &Value) -> Result<Ref> {
+    match v {
+        Value::Pair(r) => Ok(*r),
+        v => Err(expected(heap, "pair", v)),
+    }
+}
+
+/// Length of a proper list, or `None` for improper and circular lists.
+fn list_length(heap: &Heap, v: &Value) -> Option<usize> {
+    let mut n = 0;
+    let mut fast = v;
+    let mut slow = v;
+    loop {
+        fast = match fast {
+            Value::Nil => return Some(n),
+            Value::Pair(r) => heap.pair(*r).1,
+            _ => return None,
+        };
+        n += 1;
+        if n % 2 == 0 {
+            if let Value::Pair(r) = slow {
+                slow = heap.pair(*r).1;
+            }
+            if let (Value::Pair(a), Value::Pair(b)) = (fast, slow) {
+                if a == b {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+pub fn list_items(heap: &Heap, v: &Value) -> Result<Vec<Value>> {
+    let n = list_length(heap, v).ok_or_else(|| expected(heap, "list", v))?;
+    let mut items = Vec::with_capacity(n);
+    let mut v = v;
+    while let Value::Pair(r) = v {
+        let (car, cdr) = heap.pair(*r);
+        items.push(car.clone());
+        v = cdr;
+    }
+    Ok(items)
+}
+
+fn is_pair(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(args[0], Value::Pair(_))))
+}
+
+fn is_null(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(args[0], Value::Nil)))
+}
+
+fn is_list(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(list_length(vm.heap(), &args[0]).is_some()))
+}
+
+fn cons(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(vm.heap_mut().cons(args[0].clone(), args[1].clone()))
+}
+
+fn car(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = pair(vm.heap(), &args[0])?;
+    Ok(vm.heap().pair(r).0.clone())
+}
+
+fn cdr(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = pair(vm.heap(), &args[0])?;
+    Ok(vm.heap().pair(r).1.clone())
+}
+
+/// Follows `path` from right to left, taking the car for each `a` and the
+/// cdr for each `d`.
+fn cxr(vm: &Vm, v: &Value, path: &str) -> Result<Value> {
+    let mut v = v.clone();
+    for step in path.chars().rev() {
+        let r = pair(vm.heap(), &v)?;
+        let (car, cdr) = vm.heap().pair(r);
+        v = if step == 'a' { car.clone() } else { cdr.clone() };
+    }
+    Ok(v)
+}
+
+fn caar(vm: &mut Vm, args: &[Value]) -> Result<Value> { cxr(vm, &args[0], "aa") }
+fn cadr(vm: &mut Vm, args: &[Value]) -> Result<Value> { cxr(vm, &args[0], "ad") }
+fn cdar(vm: &mut Vm, args: &[Value]) -> Result<Value> { cxr(vm, &args[0], "da") }
+fn cddr(vm: &mut Vm, args: &[Value]) -> Result<Value> { cxr(vm, &args[0], "dd") }
+
+fn set_car(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = pair(vm.heap(), &args[0])?;
+    if let Object::Pair(ref mut car, _) = *vm.heap_mut().get_mut(r) {
+        *car = args[1].clone();
+    }
+    Ok(Value::Unspecified)
+}
+
+fn set_cdr(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = pair(vm.heap(), &args[0])?;
+    if let Object::Pair(_, ref mut cdr) = *vm.heap_mut().get_mut(r) {
+        *cdr = args[1].clone();
+    }
+    Ok(Value::Unspecified)
+}
+
+fn list(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    vm.reserve(args.len())?;
+    Ok(vm.heap_mut().list(args.to_vec()))
+}
+
+fn length(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    match list_length(vm.heap(), &args[0]) {
+        Some(n) => Ok(Value::Number(n as i32)),
+        None => Err(expected(vm.heap(), "list", &args[0])),
+    }
+}
+
+fn append(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let (last, init) = match args.split_last() {
+        Some(split) => split,
+        None => return Ok(Value::Nil),
+    };
+    let mut items = Vec::new();
+    for l in init {
+        items.extend(list_items(vm.heap(), l)?);
+    }
+    vm.reserve(items.len())?;
+    let heap = vm.heap_mut();
+    Ok(items.into_iter().rev().fold(last.clone(), |tl, hd| heap.cons(hd, tl)))
+}
+
+fn reverse(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let items = list_items(vm.heap(), &args[0])?;
+    vm.reserve(items.len())?;
+    let heap = vm.heap_mut();
+    Ok(items.into_iter().fold(Value::Nil, |tl, hd| heap.cons(hd, tl)))
+}
+
+fn list_tail_of(vm: &Vm, l: &Value, k: &Value) -> Result<Value> {
+    let n = match k {
+        Value::Number(n) if *n >= 0 => *n,
+        k => return Err(expected(vm.heap(), "exact non-negative integer", k)),
+    };
+    let mut v = l.clone();
+    for _ in 0..n {
+        v = match v {
+            Value::Pair(r) => vm.heap().pair(r).1.clone(),
+            _ => return Err(out_of_range(vm.heap(), "List", k)),
+        };
+    }
+    Ok(v)
+}
+
+fn list_tail(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    list_tail_of(vm, &args[0], &args[1])
+}
+
+fn list_ref(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    match list_tail_of(vm, &args[0], &args[1])? {
+        Value::Pair(r) => Ok(vm.heap().pair(r).0.clone()),
+        _ => Err(out_of_range(vm.heap(), "List", &args[1])),
+    }
+}
+
+fn list_copy(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let mut items = Vec::new();
+    let mut v = args[0].clone();
+    // The tail half as far along, which the tail meets only in a cycle.
+    let mut slow = args[0].clone();
+    while let Value::Pair(r) = v {
+        let (car, cdr) = vm.heap().pair(r);
+        items.push(car.clone());
+        v = cdr.clone();
+        if items.len() % 2 == 0 {
+            if let Value::Pair(s) = slow {
+                slow = vm.heap().pair(s).1.clone();
+            }
+            if let (Value::Pair(a), Value::Pair(b)) = (&v, &slow) {
+                if a == b {
+                    return Err(RuntimeError::Basic("Circular list".to_string()));
+                }
+            }
+        }
+    }
+    vm.reserve(items.len())?;
+    let heap = vm.heap_mut();
+    Ok(items.into_iter().rev().fold(v, |tl, hd| heap.cons(hd, tl)))
+}
+
+/// The first tail of `l` whose car satisfies `found`.
+fn member_by(vm: &Vm, x: &Value, l: &Value, found: fn(&Heap, &Value, &Value) -> bool) -> Result<Value> {
+    list_items(vm.heap(), l)?;
+    let mut v = l;
+    while let Value::Pair(r) = v {
+        let (car, cdr) = vm.heap().pair(*r);
+        if found(vm.heap(), x, car) {
+            return Ok(v.clone());
+        }
+        v = cdr;
+    }
+    Ok(Value::Boolean(false))
+}
+
+/// The first element of the association list `l` whose key satisfies `found`.
+fn assoc_by(vm: &Vm, x: &Value, l: &Value, found: fn(&Heap, &Value, &Value) -> bool) -> Result<Value> {
+    for entry in list_items(vm.heap(), l)? {
+        let r = pair(vm.heap(), &entry)?;
+        if found(vm.heap(), x, vm.heap().pair(r).0) {
+            return Ok(entry);
+        }
+    }
+    Ok(Value::Boolean(false))
+}
+
+fn memv(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    member_by(vm, &args[0], &args[1], |_, a, b| a.eqv(b))
+}
+
+fn assv(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    assoc_by(vm, &args[0], &args[1], |_, a, b| a.eqv(b))
+}
+
+fn member_equal(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    member_by(vm, &args[0], &args[1], equal_values)
+}
+
+fn assoc_equal(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    assoc_by(vm, &args[0], &args[1], equal_values)
+}
+
+fn is_symbol(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(args[0], Value::Symbol(_))))
+}
+
+fn symbol_to_string(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    match args[0] {
+        Value::Symbol(ref s) => Ok(vm.heap_mut().string(s.to_string())),
+        ref v => Err(expected(vm.heap(), "symbol", v)),
+    }
+}
+
+fn string_to_symbol(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = string(vm.heap(), &args[0])?;
+    Ok(Value::Symbol(Rc::from(vm.heap().str(r))))
+}
+
+fn character(heap: &Heap, v: &Value) -> Result<char> {
+    match v {
+        Value::Character(c) => Ok(*c),
+        v => Err(expected(heap, "character", v)),
+    }
+}
+
+fn is_char(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(args[0], Value::Character(_))))
+}
+
+fn char_to_integer(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Number(character(vm.heap(), &args[0])? as i32))
+}
+
+fn integer_to_char(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    match args[0] {
+        Value::Number(n) if n >= 0 => match ::std::char::from_u32(n as u32) {
+            Some(c) => Ok(Value::Character(c)),
+            None => Err(expected(vm.heap(), "Unicode scalar value", &args[0])),
+        },
+        ref v => Err(expected(vm.heap(), "Unicode scalar value", v)),
+    }
+}
+
+fn char_compare(vm: &Vm, args: &[Value], ok: fn(Ordering) -> bool) -> Result<Value> {
+    let mut prev = character(vm.heap(), &args[0])?;
+    let mut res = true;
+    for a in &args[1..] {
+        let c = character(vm.heap(), a)?;
+        res = res && ok(prev.cmp(&c));
+        prev = c;
+    }
+    Ok(Value::Boolean(res))
+}
+
+fn char_eq(vm: &mut Vm, args: &[Value]) -> Result<Value> { char_compare(vm, args, |o| o == Ordering::Equal) }
+fn char_lt(vm: &mut Vm, args: &[Value]) -> Result<Value> { char_compare(vm, args, |o| o == Ordering::Less) }
+fn char_gt(vm: &mut Vm, args: &[Value]) -> Result<Value> { char_compare(vm, args, |o| o == Ordering::Greater) }
+fn char_le(vm: &mut Vm, args: &[Value]) -> Result<Value> { char_compare(vm, args, |o| o != Ordering::Greater) }
+fn char_ge(vm: &mut Vm, args: &[Value]) -> Result<Value> { char_compare(vm, args, |o| o != Ordering::Less) }
+
+fn char_test(vm: &Vm, args: &[Value], test: fn(char) -> bool) -> Result<Value> {
+    Ok(Value::Boolean(test(character(vm.heap(), &args[0])?)))
+}
+
+fn is_alphabetic(vm: &mut Vm, args: &[Value]) -> Result<Value> { char_test(vm, args, char::is_alphabetic) }
+fn is_numeric(vm: &mut Vm, args: &[Value]) -> Result<Value> { char_test(vm, args, char::is_numeric) }
+fn is_whitespace(vm: &mut Vm, args: &[Value]) -> Result<Value> { char_test(vm, args, char::is_whitespace) }
+fn is_upper_case(vm: &mut Vm, args: &[Value]) -> Result<Value> { char_test(vm, args, char::is_uppercase) }
+fn is_lower_case(vm: &mut Vm, args: &[Value]) -> Result<Value> { char_test(vm, args, char::is_lowercase) }
+
+fn char_upcase(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let c = character(vm.heap(), &args[0])?;
+    Ok(Value::Character(c.to_uppercase().next().unwrap_or(c)))
+}
+
+fn char_downcase(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let c = character(vm.heap(), &args[0])?;
+    Ok(Value::Character(c.to_lowercase().next().unwrap_or(c)))
+}
+
+fn string(heap: &Heap, v: &Value) -> Result<Ref> {
+    match v {
+        Value::String(r) => Ok(*r),
+        v => Err(expected(heap, "string", v)),
+    }
+}
+
+fn chars(heap: &Heap, v: &Value) -> Result<Vec<char>> {
+    let r = string(heap, v)?;
+    Ok(heap.str(r).chars().collect())
+}
+
+fn is_string(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(args[0], Value::String(_))))
+}
+
+fn make_string(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let n = match args[0] {
+        Value::Number(n) if n >= 0 => n as usize,
+        ref k => return Err(expected(vm.heap(), "exact non-negative integer", k)),
+    };
+    let c = match args.get(1) {
+        Some(c) => character(vm.heap(), c)?,
+        None => ' ',
+    };
+    vm.reserve(1 + n)?;
+    Ok(vm.heap_mut().string(::std::iter::repeat_n(c, n).collect()))
+}
+
+fn string_from_chars(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let mut s = String::new();
+    for c in args {
+        s.push(character(vm.heap(), c)?);
+    }
+    Ok(vm.heap_mut().string(s))
+}
+
+fn string_length(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = string(vm.heap(), &args[0])?;
+    Ok(Value::Number(vm.heap().str(r).chars().count() as i32))
+}
+
+fn string_ref(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let s = chars(vm.heap(), &args[0])?;
+    let k = index(vm.heap(), "String", &args[1], s.len())?;
+    Ok(Value::Character(s[k]))
+}
+
+fn string_set(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = string(vm.heap(), &args[0])?;
+    let mut s = chars(vm.heap(), &args[0])?;
+    let k = index(vm.heap(), "String", &args[1], s.len())?;
+    s[k] = character(vm.heap(), &args[2])?;
+    if let Object::String(ref mut old) = *vm.heap_mut().get_mut(r) {
+        *old = s.into_iter().collect();
+    }
+    Ok(Value::Unspecified)
+}
+
+fn substring(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let s = chars(vm.heap(), &args[0])?;
+    let (start, end) = range(vm.heap(), "String", args, 1, s.len())?;
+    Ok(vm.heap_mut().string(s[start..end].iter().collect()))
+}
+
+fn string_append(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let mut s = String::new();
+    for a in args {
+        let r = string(vm.heap(), a)?;
+        s.push_str(vm.heap().str(r));
+    }
+    vm.reserve(1 + s.chars().count())?;
+    Ok(vm.heap_mut().string(s))
+}
+
+fn string_to_list(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let s = chars(vm.heap(), &args[0])?;
+    let (start, end) = range(vm.heap(), "String", args, 1, s.len())?;
+    let items: Vec<_> = s[start..end].iter().map(|c| Value::Character(*c)).collect();
+    vm.reserve(items.len())?;
+    Ok(vm.heap_mut().list(items))
+}
+
+fn list_to_string(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let items = list_items(vm.heap(), &args[0])?;
+    string_from_chars(vm, &items)
+}
+
+fn string_compare(vm: &Vm, args: &[Value], ok: fn(Ordering) -> bool) -> Result<Value> {
+    let mut res = true;
+    for w in args.windows(2) {
+        let (a, b) = (string(vm.heap(), &w[0])?, string(vm.heap(), &w[1])?);
+        res = res && ok(vm.heap().str(a).cmp(vm.heap().str(b)));
+    }
+    string(vm.heap(), &args[0])?;
+    Ok(Value::Boolean(res))
+}
+
+fn string_eq(vm: &mut Vm, args: &[Value]) -> Result<Value> { string_compare(vm, args, |o| o == Ordering::Equal) }
+fn string_lt(vm: &mut Vm, args: &[Value]) -> Result<Value> { string_compare(vm, args, |o| o == Ordering::Less) }
+fn string_gt(vm: &mut Vm, args: &[Value]) -> Result<Value> { string_compare(vm, args, |o| o == Ordering::Greater) }
+fn string_le(vm: &mut Vm, args: &[Value]) -> Result<Value> { string_compare(vm, args, |o| o != Ordering::Greater) }
+fn string_ge(vm: &mut Vm, args: &[Value]) -> Result<Value> { string_compare(vm, args, |o| o != Ordering::Less) }
+
+fn string_upcase(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = string(vm.heap(), &args[0])?;
+    let s = vm.heap().str(r).to_uppercase();
+    Ok(vm.heap_mut().string(s))
+}
+
+fn string_downcase(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = string(vm.heap(), &args[0])?;
+    let s = vm.heap().str(r).to_lowercase();
+    Ok(vm.heap_mut().string(s))
+}
+
+fn vector_ref_of(heap: &Heap, v: &Value) -> Result<Ref> {
+    match v {
+        Value::Vector(r) => Ok(*r),
+        v => Err(expected(heap, "vector", v)),
+    }
+}
+
+fn is_vector(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(args[0], Value::Vector(_))))
+}
+
+fn make_vector(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let n = match args[0] {
+        Value::Number(n) if n >= 0 => n as usize,
+        ref k => return Err(expected(vm.heap(), "exact non-negative integer", k)),
+    };
+    let fill = args.get(1).cloned().unwrap_or(Value::Unspecified);
+    vm.reserve(1 + n)?;
+    Ok(vm.heap_mut().vector(vec![fill; n]))
+}
+
+fn vector(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(vm.heap_mut().vector(args.to_vec()))
+}
+
+fn vector_length(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = vector_ref_of(vm.heap(), &args[0])?;
+    Ok(Value::Number(vm.heap().items(r).len() as i32))
+}
+
+fn vector_ref(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = vector_ref_of(vm.heap(), &args[0])?;
+    let items = vm.heap().items(r);
+    let k = index(vm.heap(), "Vector", &args[1], items.len())?;
+    Ok(items[k].clone())
+}
+
+fn vector_set(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = vector_ref_of(vm.heap(), &args[0])?;
+    let k = index(vm.heap(), "Vector", &args[1], vm.heap().items(r).len())?;
+    if let Object::Vector(ref mut items) = *vm.heap_mut().get_mut(r) {
+        items[k] = args[2].clone();
+    }
+    Ok(Value::Unspecified)
+}
+
+fn vector_to_list(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = vector_ref_of(vm.heap(), &args[0])?;
+    let items = vm.heap().items(r);
+    let (start, end) = range(vm.heap(), "Vector", args, 1, items.len())?;
+    let items = items[start..end].to_vec();
+    vm.reserve(items.len())?;
+    Ok(vm.heap_mut().list(items))
+}
+
+fn list_to_vector(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let items = list_items(vm.heap(), &args[0])?;
+    vm.reserve(1 + items.len())?;
+    Ok(vm.heap_mut().vector(items))
+}
+
+fn vector_fill(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = vector_ref_of(vm.heap(), &args[0])?;
+    let (start, end) = range(vm.heap(), "Vector", args, 2, vm.heap().items(r).len())?;
+    if let Object::Vector(ref mut items) = *vm.heap_mut().get_mut(r) {
+        for v in &mut items[start..end] {
+            *v = args[1].clone();
+        }
+    }
+    Ok(Value::Unspecified)
+}
+
+fn emit(vm: &mut Vm, s: &str) -> Result<Value> {
+    vm.output().write_all(s.as_bytes())
+        .and_then(|()| vm.output().flush())
+        .map_err(|e| RuntimeError::Basic(format!("Output error: {}", e)))?;
+    Ok(Value::Unspecified)
+}
+
+fn promise_ref_of(heap: &Heap, v: &Value) -> Result<Ref> {
+    match v {
+        Value::Promise(r) => Ok(*r),
+        v => Err(expected(heap, "promise", v)),
+    }
+}
+
+/// The state of a promise: whether it is forced, and its value or thunk.
+fn promise_state(heap: &Heap, r: Ref) -> (bool, Value) {
+    match heap.get(r) {
+        Object::Promise { done, value } => (*done, value.clone()),
+        _ => unreachable!(),
+    }
+}
+
+fn is_promise(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(args[0], Value::Promise(_))))
+}
+
+fn make_promise(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    if let Value::Promise(_) = args[0] {
+        return Ok(args[0].clone());
+    }
+    let r = vm.heap_mut().alloc(Object::Promise { done: true, value: args[0].clone() });
+    Ok(Value::Promise(r))
+}
+
+fn promise_is_done(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = promise_ref_of(vm.heap(), &args[0])?;
+    Ok(Value::Boolean(promise_state(vm.heap(), r).0))
+}
+
+fn promise_value(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = promise_ref_of(vm.heap(), &args[0])?;
+    Ok(promise_state(vm.heap(), r).1)
+}
+
+/// `(%promise-update! new old)` makes `old` a copy of `new`, as `force`
+/// does when the thunk of `old` returns `new`.
+fn promise_update(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let new = promise_ref_of(vm.heap(), &args[0])?;
+    let old = promise_ref_of(vm.heap(), &args[1])?;
+    let state = promise_state(vm.heap(), new);
+    if let Object::Promise { ref mut done, ref mut value } = *vm.heap_mut().get_mut(old) {
+        *done = state.0;
+        *value = state.1;
+    }
+    Ok(Value::Unspecified)
+}
+
+/// `(%record-type name fields)` makes a record type descriptor; `fields`
+/// is a list of symbols.
+fn record_type(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let name = match args[0] {
+        Value::Symbol(ref s) => s.clone(),
+        ref v => return Err(expected(vm.heap(), "symbol", v)),
+    };
+    let mut fields = Vec::new();
+    let mut l = args[1].clone();
+    while let Value::Pair(r) = l {
+        let (car, cdr) = vm.heap().pair(r);
+        match car {
+            Value::Symbol(s) => fields.push(s.clone()),
+            v => return Err(expected(vm.heap(), "symbol", v)),
+        }
+        l = cdr.clone();
+    }
+    Ok(Value::RecordType(vm.heap_mut().alloc(Object::RecordType { name, fields })))
+}
+
+fn record_type_of(heap: &Heap, v: &Value) -> Result<Ref> {
+    match v {
+        Value::RecordType(r) => Ok(*r),
+        v => Err(expected(heap, "record type", v)),
+    }
+}
+
+/// The record `v`, which must be of the type `t`.
+fn record_of(heap: &Heap, t: Ref, v: &Value) -> Result<Ref> {
+    if let Value::Record(r) = v {
+        if let Object::Record { record_type, .. } = heap.get(*r) {
+            if *record_type == t {
+                return Ok(*r);
+            }
+        }
+    }
+    let name = match heap.get(t) {
+        Object::RecordType { name, .. } => name.clone(),
+        _ => unreachable!(),
+    };
+    Err(expected(heap, &format!("record of type {}", name), v))
+}
+
+fn record_field(heap: &Heap, t: Ref, k: &Value) -> Result<usize> {
+    let n = match heap.get(t) {
+        Object::RecordType { fields, .. } => fields.len(),
+        _ => unreachable!(),
+    };
+    index(heap, "Record", k, n)
+}
+
+fn make_record(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let t = record_type_of(vm.heap(), &args[0])?;
+    let fields = args[1..].to_vec();
+    match vm.heap().get(t) {
+        Object::RecordType { fields: names, .. } if names.len() == fields.len() => (),
+        _ => return Err(RuntimeError::Basic(
+            format!("Wrong number of record fields: got {}", fields.len()))),
+    }
+    Ok(Value::Record(vm.heap_mut().alloc(Object::Record { record_type: t, fields })))
+}
+
+fn is_record(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let t = record_type_of(vm.heap(), &args[0])?;
+    Ok(Value::Boolean(record_of(vm.heap(), t, &args[1]).is_ok()))
+}
+
+fn record_ref(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let t = record_type_of(vm.heap(), &args[0])?;
+    let r = record_of(vm.heap(), t, &args[1])?;
+    let k = record_field(vm.heap(), t, &args[2])?;
+    match vm.heap().get(r) {
+        Object::Record { fields, .. } => Ok(fields[k].clone()),
+        _ => unreachable!(),
+    }
+}
+
+fn record_set(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let t = record_type_of(vm.heap(), &args[0])?;
+    let r = record_of(vm.heap(), t, &args[1])?;
+    let k = record_field(vm.heap(), t, &args[2])?;
+    if let Object::Record { ref mut fields, .. } = *vm.heap_mut().get_mut(r) {
+        fields[k] = args[3].clone();
+    }
+    Ok(Value::Unspecified)
+}
+
+fn parameter_of(heap: &Heap, v: &Value) -> Result<Ref> {
+    match v {
+        Value::Parameter(r) => Ok(*r),
+        v => Err(expected(heap, "parameter", v)),
+    }
+}
+
+/// `(%make-parameter value converter)`, where `value` is already converted.
+fn make_parameter(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let p = Object::Parameter { value: args[0].clone(), converter: args[1].clone() };
+    Ok(Value::Parameter(vm.heap_mut().alloc(p)))
+}
+
+fn parameter_converter(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = parameter_of(vm.heap(), &args[0])?;
+    match vm.heap().get(r) {
+        Object::Parameter { converter, .. } => Ok(converter.clone()),
+        _ => unreachable!(),
+    }
+}
+
+fn parameter_set(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let r = parameter_of(vm.heap(), &args[0])?;
+    if let Object::Parameter { ref mut value, .. } = *vm.heap_mut().get_mut(r) {
+        *value = args[1].clone();
+    }
+    Ok(Value::Unspecified)
+}
+
+fn values(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    if args.len() == 1 {
+        return Ok(args[0].clone());
+    }
+    Ok(Value::Values(vm.heap_mut().alloc(Object::Values(args.to_vec()))))
+}
+
+/// The values that `v` stands for: its items if it is multiple values,
+/// and `v` itself otherwise.
+fn values_of(heap: &Heap, v: &Value) -> Vec<Value> {
+    match v {
+        Value::Values(r) => heap.items(*r).to_vec(),
+        v => vec![v.clone()],
+    }
+}
+
+fn values_to_list(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let items = values_of(vm.heap(), &args[0]);
+    vm.reserve(items.len())?;
+    Ok(vm.heap_mut().list(items))
+}
+
+/// `(%bind-values values required rest?)` checks that there are enough
+/// values for formals with `required` variables and an optional rest
+/// variable, and lists the value of each variable.
+fn bind_values(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let mut items = values_of(vm.heap(), &args[0]);
+    let required = match args[1] {
+        Value::Number(n) if n >= 0 => n as usize,
+        ref v => return Err(expected(vm.heap(), "exact non-negative integer", v)),
+    };
+    let arity = if args[2].is_true() { Arity::at_least(required) } else { Arity::exactly(required) };
+    arity.check_count("values", items.len())?;
+    vm.reserve(items.len() + 1)?;
+    if arity.max.is_none() {
+        let rest = items.split_off(required);
+        let rest = vm.heap_mut().list(rest);
+        items.push(rest);
+    }
+    Ok(vm.heap_mut().list(items))
+}
+
+fn error_of(heap: &Heap, v: &Value) -> Result<(Value, Value)> {
+    match v {
+        Value::Error(r) => match heap.get(*r) {
+            Object::Error { message, irritants } => Ok((message.clone(), irritants.clone())),
+            _ => unreachable!(),
+        },
+        v => Err(expected(heap, "error object", v)),
+    }
+}
+
+/// `(%make-error message irritants)`, where `irritants` is a list.
+fn make_error(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    if let Value::String(_) = args[0] {} else {
+        return Err(expected(vm.heap(), "string", &args[0]));
+    }
+    let e = Object::Error { message: args[0].clone(), irritants: args[1].clone() };
+    Ok(Value::Error(vm.heap_mut().alloc(e)))
+}
+
+fn is_error_object(_: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(Value::Boolean(matches!(args[0], Value::Error(_))))
+}
+
+fn error_object_message(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(error_of(vm.heap(), &args[0])?.0)
+}
+
+fn error_object_irritants(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    Ok(error_of(vm.heap(), &args[0])?.1)
+}
+
+/// `(%raise obj)` ends the program with `obj` when no handler is left.
+fn raise(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let heap = vm.heap();
+    let message = match error_of(heap, &args[0]) {
+        Ok((message, mut irritants)) => {
+            let mut s = heap.display(&message).to_string();
+            while let Value::Pair(r) = irritants {
+                let (car, cdr) = heap.pair(r);
+                s.push(' ');
+                s.push_str(&heap.write(car).to_string());
+                irritants = cdr.clone();
+            }
+            s
+        },
+        Err(_) => format!("Uncaught exception: {}", heap.write(&args[0])),
+    };
+    Err(RuntimeError::Basic(message))
+}
+
+fn handlers(vm: &mut Vm, _: &[Value]) -> Result<Value> {
+    Ok(vm.handlers().clone())
+}
+
+fn set_handlers(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    vm.set_handlers(args[0].clone());
+    Ok(Value::Unspecified)
+}
+
+fn display(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let s = vm.heap().display(&args[0]).to_string();
+    emit(vm, &s)
+}
+
+fn write(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let s = vm.write(&args[0]);
+    emit(vm, &s)
+}
+
+fn write_char(vm: &mut Vm, args: &[Value]) -> Result<Value> {
+    let c = character(vm.heap(), &args[0])?;
+    emit(vm, &c.to_string())
+}
+
+fn newline(vm: &mut Vm, _: &[Value]) -> Result<Value> {
+    emit(vm, "\n")
+}
+
+const PRIMITIVES: &[(&str, usize, Option<usize>, PrimitiveFn)] = &[
+    ("+", 0, None, add),
+    ("*", 0, None, mul),
+    ("-", 1, None, sub),
+    ("/", 1, None, div),
+    ("=", 1, None, num_eq),
+    ("<", 1, None, lt),
+    (">", 1, None, gt),
+    ("<=", 1, None, le),
+    (">=", 1, None, ge),
+    ("number?", 1, Some(1), is_number),
+    ("integer?", 1, Some(1), is_integer),
+    ("exact?", 1, Some(1), is_exact),
+    ("inexact?", 1, Some(1), is_inexact),
+    ("zero?", 1, Some(1), is_zero),
+    ("positive?", 1, Some(1), is_positive),
+    ("negative?", 1, Some(1), is_negative),
+    ("odd?", 1, Some(1), is_odd),
+    ("even?", 1, Some(1), is_even),
+    ("abs", 1, Some(1), abs),
+    ("min", 1, None, min),
+    ("max", 1, None, max),
+    ("quotient", 2, Some(2), quotient),
+    ("remainder", 2, Some(2), remainder),
+    ("modulo", 2, Some(2), modulo),
+    ("floor", 1, Some(1), floor),
+    ("ceiling", 1, Some(1), ceiling),
+    ("round", 1, Some(1), round),
+    ("truncate", 1, Some(1), truncate),
+    ("exact", 1, Some(1), exact),
+    ("inexact", 1, Some(1), inexact),
+    ("inexact->exact", 1, Some(1), exact),
+    ("exact->inexact", 1, Some(1), inexact),
+    ("sqrt", 1, Some(1), sqrt),
+    ("expt", 2, Some(2), expt),
+    ("number->string", 1, Some(2), number_to_string),
+    ("string->number", 1, Some(2), string_to_number),
+    ("not", 1, Some(1), not),
+    ("boolean?", 1, Some(1), is_boolean),
+    ("eq?", 2, Some(2), eqv),
+    ("eqv?", 2, Some(2), eqv),
+    ("equal?", 2, Some(2), equal),
+    ("procedure?", 1, Some(1), is_procedure),
+    ("pair?", 1, Some(1), is_pair),
+    ("null?", 1, Some(1), is_null),
+    ("list?", 1, Some(1), is_list),
+    ("cons", 2, Some(2), cons),
+    ("car", 1, Some(1), car),
+    ("cdr", 1, Some(1), cdr),
+    ("caar", 1, Some(1), caar),
+    ("cadr", 1, Some(1), cadr),
+    ("cdar", 1, Some(1), cdar),
+    ("cddr", 1, Some(1), cddr),
+    ("set-car!", 2, Some(2), set_car),
+    ("set-cdr!", 2, Some(2), set_cdr),
+    ("list", 0, None, list),
+    ("length", 1, Some(1), length),
+    ("append", 0, None, append),
+    ("reverse", 1, Some(1), reverse),
+    ("list-tail", 2, Some(2), list_tail),
+    ("list-ref", 2, Some(2), list_ref),
+    ("list-copy", 1, Some(1), list_copy),
+    ("memq", 2, Some(2), memv),
+    ("memv", 2, Some(2), memv),
+    ("assq", 2, Some(2), assv),
+    ("assv", 2, Some(2), assv),
+    ("%member", 2, Some(2), member_equal),
+    ("%assoc", 2, Some(2), assoc_equal),
+    ("symbol?", 1, Some(1), is_symbol),
+    ("symbol->string", 1, Some(1), symbol_to_string),
+    ("string->symbol", 1, Some(1), string_to_symbol),
+    ("char?", 1, Some(1), is_char),
+    ("char->integer", 1, Some(1), char_to_integer),
+    ("integer->char", 1, Some(1), integer_to_char),
+    ("char=?", 1, None, char_eq),
+    ("char<?", 1, None, char_lt),
+    ("char>?", 1, None, char_gt),
+    ("char<=?", 1, None, char_le),
+    ("char>=?", 1, None, char_ge),
+    ("char-alphabetic?", 1, Some(1), is_alphabetic),
+    ("char-numeric?", 1, Some(1), is_numeric),
+    ("char-whitespace?", 1, Some(1), is_whitespace),
+    ("char-upper-case?", 1, Some(1), is_upper_case),
+    ("char-lower-case?", 1, Some(1), is_lower_case),
+    ("char-upcase", 1, Some(1), char_upcase),
+    ("char-downcase", 1, Some(1), char_downcase),
+    ("string?", 1, Some(1), is_string),
+    ("make-string", 1, Some(2), make_string),
+    ("string", 0, None, string_from_chars),
+    ("string-length", 1, Some(1), string_length),
+    ("string-ref", 2, Some(2), string_ref),
+    ("string-set!", 3, Some(3), string_set),
+    ("substring", 3, Some(3), substring),
+    ("string-copy", 1, Some(3), substring),
+    ("string-append", 0, None, string_append),
+    ("string->list", 1, Some(3), string_to_list),
+    ("list->string", 1, Some(1), list_to_string),
+    ("string=?", 1, None, string_eq),
+    ("string<?", 1, None, string_lt),
+    ("string>?", 1, None, string_gt),
+    ("string<=?", 1, None, string_le),
+    ("string>=?", 1, None, string_ge),
+    ("string-upcase", 1, Some(1), string_upcase),
+    ("string-downcase", 1, Some(1), string_downcase),
+    ("vector?", 1, Some(1), is_vector),
+    ("make-vector", 1, Some(2), make_vector),
+    ("vector", 0, None, vector),
+    ("vector-length", 1, Some(1), vector_length),
+    ("vector-ref", 2, Some(2), vector_ref),
+    ("vector-set!", 3, Some(3), vector_set),
+    ("vector->list", 1, Some(3), vector_to_list),
+    ("list->vector", 1, Some(1), list_to_vector),
+    ("vector-fill!", 2, Some(4), vector_fill),
+    ("promise?", 1, Some(1), is_promise),
+    ("make-promise", 1, Some(1), make_promise),
+    ("%promise-done?", 1, Some(1), promise_is_done),
+    ("%promise-value", 1, Some(1), promise_value),
+    ("%promise-update!", 2, Some(2), promise_update),
+    ("%record-type", 2, Some(2), record_type),
+    ("%make-record", 1, None, make_record),
+    ("%record?", 2, Some(2), is_record),
+    ("%record-ref", 3, Some(3), record_ref),
+    ("%record-set!", 4, Some(4), record_set),
+    ("%make-parameter", 2, Some(2), make_parameter),
+    ("%parameter-converter", 1, Some(1), parameter_converter),
+    ("%parameter-set!", 2, Some(2), parameter_set),
+    ("values", 0, None, values),
+    ("%values->list", 1, Some(1), values_to_list),
+    ("%bind-values", 3, Some(3), bind_values),
+    ("%make-error", 2, Some(2), make_error),
+    ("error-object?", 1, Some(1), is_error_object),
+    ("error-object-message", 1, Some(1), error_object_message),
+    ("error-object-irritants", 1, Some(1), error_object_irritants),
+    ("%raise", 1, Some(1), raise),
+    ("%handlers", 0, Some(0), handlers),
+    ("%set-handlers!", 1, Some(1), set_handlers),
+    ("display", 1, Some(1), display),
+    ("write", 1, Some(1), write),
+    ("write-char", 1, Some(1), write_char),
+    ("newline", 0, Some(0), newline),
+];
+
+/// Primitives that reach outside the interpreter, which a sandbox leaves
+/// out.
+pub const SYSTEM: &[&str] = &["display", "write", "write-char", "newline"];
+
+pub fn install(vm: &mut Vm) {
+    for &(name, min, max, func) in PRIMITIVES {
+        vm.define(name, Value::Primitive(Rc::new(Primitive {
+            name,
+            arity: Arity { min, max },
+            func,
+        })));
+    }
+    // The names that compiled code uses, which programs cannot redefine.
+    for &name in &["car", "cdr", "append"] {
+        let v = vm.lookup(name).unwrap();
+        vm.define(&format!("%{}", name), v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use lexer::RegexLexer;
+    use parser::Parser;
+    use scheme::parse_expression;
+    use vm::Vm;
+
+    struct Capture(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run_in(vm: &mut Vm, s: &str) -> Result<String, String> {
+        let mut res = String::new();
+        for node in Parser::new(RegexLexer::new(s)) {
+            let e = parse_expression(node.unwrap()).unwrap();
+            let v = vm.eval(&e).map_err(|e| e.to_string())?;
+            res = vm.write(&v);
+        }
+        Ok(res)
+    }
+
+    fn run(s: &str) -> String {
+        run_in(&mut Vm::new(), s).unwrap()
+    }
+
+    fn fail(s: &str) -> String {
+        run_in(&mut Vm::new(), s).unwrap_err()
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(run("(+ 1 2.5)"), "3.5");
+        assert_eq!(run("(/ 6 3)"), "2");
+        assert_eq!(run("(/ 1 4)"), "0.25");
+        assert_eq!(run("(list (expt 2 -2) (+ 2147483647 1.0))"), "(0.25 2147483648.0)");
+        for overflow in &["(+ 2147483647 1)", "(* 100000 100000)", "(- -2147483648 1)",
+                          "(- -2147483648)", "(abs -2147483648)", "(/ -2147483648 -1)",
+                          "(quotient -2147483648 -1)", "(expt 2 31)"] {
+            assert_eq!(fail(overflow), "RuntimeError: Exact integer overflow", "{}", overflow);
+        }
+        assert_eq!(run("(list (quotient -7 2) (remainder -7 2) (modulo -7 2))"), "(-3 -1 1)");
+        assert_eq!(run("(list (round 2.5) (round 3.5) (floor -1.5) (truncate -1.5))"),
+                   "(2.0 4.0 -2.0 -1.0)");
+        assert_eq!(run("(list (exact 3.0) (inexact 1) (sqrt 16) (sqrt 2.25) (expt 2 10))"),
+                   "(3 1.0 4 1.5 1024)");
+        assert_eq!(run("(list (max 1 2.0) (min 1 2) (abs -5))"), "(2.0 1 5)");
+        assert_eq!(run("(list (< 1 2 3) (< 1 3 2) (= 1 1.0))"), "(#t #f #t)");
+        assert_eq!(run("(list (number->string 255 16) (string->number \"1e2\") \
+                              (string->number \"abc\"))"), "(\"ff\" 100.0 #f)");
+        assert_eq!(run("(list (/ 1.0 0) (- (/ 1.0 0)))"), "(+inf.0 -inf.0)");
+        assert_eq!(fail("(/ 1 0)"), "RuntimeError: Division by zero");
+        assert_eq!(fail("(+ 1 #t)"), "RuntimeError: Expected number, got boolean #t");
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(run("(append (list 1 2) (list 3) 4)"), "(1 2 3 . 4)");
+        assert_eq!(run("(reverse (list 1 2 3))"), "(3 2 1)");
+        assert_eq!(run("(list (length (list 1 2)) (list-ref (list 1 2 3) 2) (cadr (list 1 2)))"),
+                   "(2 3 2)");
+        assert_eq!(run("(memv 2 (list 1 2 3))"), "(2 3)");
+        assert_eq!(run("(assq (quote b) (list (cons (quote a) 1) (cons (quote b) 2)))"), "(b . 2)");
+        assert_eq!(run("(member (list 1) (list 0 (list 1) 2))"), "((1) 2)");
+        assert_eq!(run("(assoc 2.0 (list (list 1) (list 2)) =)"), "(2)");
+        assert_eq!(run("(map + (list 1 2 3) (list 10 20))"), "(11 22)");
+        assert_eq!(run("(map (lambda (x) (* x x)) (list 1 2 3))"), "(1 4 9)");
+        assert_eq!(run("(apply + 1 2 (list 3 4))"), "10");
+        assert_eq!(run("(vector-map + (vector 1 2) (vector 10 20))"), "#(11 22)");
+        assert_eq!(run("(define l (list 1 2)) (set-cdr! (cdr l) l) (list? l)"), "#f");
+        assert_eq!(run("(list-copy (cons 1 (cons 2 3)))"), "(1 2 . 3)");
+        assert_eq!(fail("(define l (list 1)) (set-cdr! l l) (list-copy l)"),
+                   "RuntimeError: Circular list");
+        assert_eq!(fail("(define l (list 1 2 3)) (set-cdr! (cddr l) (cdr l)) (list-copy l)"),
+                   "RuntimeError: Circular list");
+        assert_eq!(fail("(length 5)"), "RuntimeError: Expected list, got number 5");
+    }
+
+    #[test]
+    fn strings_and_chars() {
+        assert_eq!(run("(string-append \"ab\" (string #\\c) (substring \"xdefx\" 1 4))"),
+                   "\"abcdef\"");
+        assert_eq!(run("(list (string-length \"h\u{e9}llo\") (string-ref \"h\u{e9}llo\" 1))"),
+                   "(5 #\\\u{e9})");
+        assert_eq!(run("(define s (make-string 3 #\\a)) (string-set! s 1 #\\b) s"), "\"aba\"");
+        assert_eq!(run("(list (string<? \"a\" \"b\") (string=? \"a\" \"a\" \"b\"))"), "(#t #f)");
+        assert_eq!(run("(string->list \"abc\")"), "(#\\a #\\b #\\c)");
+        assert_eq!(run("(list->string (list #\\a #\\space))"), "\"a \"");
+        assert_eq!(run("(symbol->string (string->symbol \"foo\"))"), "\"foo\"");
+        assert_eq!(run("(map string->symbol (list \"hello world\" \"\" \"1\" \".\" \"a|b\"))"),
+                   "(|hello world| || |1| |.| |a\\|b|)");
+        assert_eq!(run("(list (quote ...) (quote +) (quote ->x) (quote Ab?))"), "(... + ->x Ab?)");
+        assert_eq!(run("(list (char->integer #\\A) (char-upcase #\\a) (char<? #\\a #\\b))"),
+                   "(65 #\\A #t)");
+        assert_eq!(fail("(string-ref \"abc\" 3)"), "RuntimeError: String index out of range: 3");
+    }
+
+    #[test]
+    fn equivalence() {
+        assert_eq!(run("(list (eqv? 2 2) (eqv? 2 2.0) (eq? (list 1) (list 1)))"), "(#t #f #f)");
+        assert_eq!(run("(equal? (list 1 (vector \"a\" 2)) (list 1 (vector \"a\" 2)))"), "#t");
+        assert_eq!(run("(equal? (list 1 2) (list 1 3))"), "#f");
+        assert_eq!(run("(define a (list 1)) (set-cdr! a a) \
+                        (define b (list 1)) (set-cdr! b b) (equal? a b)"), "#t");
+        assert_eq!(run("(list (not 1) (not #f) (procedure? car) (procedure? 1))"),
+                   "(#f #t #t #f)");
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(fail("(car)"),
+                   "RuntimeError: Wrong number of arguments: expected 1, got 0");
+        assert_eq!(fail("(substring \"a\")"),
+                   "RuntimeError: Wrong number of arguments: expected 3, got 1");
+        assert_eq!(fail("(number->string 1 2 3)"),
+                   "RuntimeError: Wrong number of arguments: expected 1 to 2, got 3");
+        assert_eq!(fail("(apply car)"),
+                   "RuntimeError: Wrong number of arguments: expected at least 2, got 1");
+    }
+
+    #[test]
+    fn output() {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = Vm::new();
+        vm.set_output(Box::new(Capture(out.clone())));
+        run_in(&mut vm, "(display \"a\\\"b\") (write \"a\\\"b\") (newline) \
+                         (for-each display (list 1 #\\x 2.5))").unwrap();
+        assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(),
+                   "a\"b\"a\\\"b\"\n1x2.5");
+    }
+}
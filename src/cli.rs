@@ -0,0 +1,831 @@
+use std::fmt::Debug;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+
+use codes;
+use conformance;
+use diagnostic::{self, Diagnostic};
+use glob;
+use json::Json;
+use lexer::{RegexLexer, Span};
+use library::{self, Loader, LibraryError};
+use parallel;
+use parser::{Node, Parser, ReadError, ReadLimits, Spans};
+use scheme::{parse_expression, SchemeError};
+use scheme::syntax::Expression;
+use serialize::{self, Serialize, Spanned};
+use vm::Vm;
+
+pub const USAGE: &str = "\
+Usage: scheme-syntax [COMMAND] [OPTIONS] [FILE...]
+
+Commands:
+  tokens       print the tokens of each file
+  read         print the data read from each file
+  expand       print each top-level form after syntax analysis (default)
+  check        read, expand and compile each file and the libraries it
+               imports, without running anything
+  run          run each file as a program; with no files, evaluate stdin
+               line by line and print each value
+  disassemble  print the bytecode compiled for each top-level form
+  test         run the conformance tests in each file, such as those in
+               conformance/, and print a pass/fail report per chapter
+
+FILE may be a glob pattern (`*`, `?`, `[...]`, `**`), a directory, which
+stands for the .scm and .sld files below it, or `-` for stdin. With no
+files, stdin is read.
+
+Options:
+  --format FORMAT     output format: debug (default), sexpr, json or ast;
+                      json and ast print one versioned document per line
+  --input FORMAT      read data as scheme source (default), or as the json
+                      or ast documents printed by `read`
+  --spans             include source spans in json and ast output
+  --diagnostics MODE  how to print errors: human (default), with the source
+                      lines they are about, or machine, as
+                      file:line:col: error: message lines, or json, as
+                      one JSON object per line on stdout. `check` reports
+                      the syntax errors of every top-level form
+  --color WHEN        colour human diagnostics: auto (default, when stderr
+                      is a terminal and NO_COLOR is unset), always or never
+  -j, --jobs N        read and expand each file on N threads, and check N
+                      files at a time (default: one per processor)
+  --read-limit NAME=N bound what is read: depth (lists open at once),
+                      token (bytes in an identifier), string (bytes in a
+                      string) or data (data in a file); N may be none
+  -L, --library-path DIR
+                      also look for libraries in DIR; the directories in
+                      SCHEME_SYNTAX_PATH and the current directory are
+                      always searched
+  --explain CODE      describe the error with code CODE, such as S0002,
+                      with examples
+  -h, --help          print this help
+
+Exit status: 0 on success, 1 if there was a syntax error, 2 for usage or
+I/O errors, 3 if a program raised a runtime error, 4 if a conformance
+test failed.
+";
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_SYNTAX: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_RUNTIME: i32 = 3;
+pub const EXIT_TEST: i32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Tokens,
+    Read,
+    Expand,
+    Check,
+    Run,
+    Disassemble,
+    Test,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Debug,
+    Sexpr,
+    Json,
+    Ast,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Diagnostics {
+    Human,
+    Machine,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Colour {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    Scheme,
+    Json,
+    Ast,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    pub format: Format,
+    pub input: InputFormat,
+    pub spans: bool,
+    pub diagnostics: Diagnostics,
+    pub colour: Colour,
+    pub library_path: Vec<PathBuf>,
+    /// Threads to use, or 0 for one per processor.
+    pub jobs: usize,
+    pub read_limits: ReadLimits,
+    pub files: Vec<String>,
+    pub explain: Option<String>,
+    pub help: bool,
+}
+
+fn command(s: &str) -> Option<Command> {
+    Some(match s {
+        "tokens" => Command::Tokens,
+        "read" => Command::Read,
+        "expand" => Command::Expand,
+        "check" => Command::Check,
+        "run" => Command::Run,
+        "disassemble" => Command::Disassemble,
+        "test" => Command::Test,
+        _ => return None,
+    })
+}
+
+pub fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        command: Command::Expand,
+        format: Format::Debug,
+        input: InputFormat::Scheme,
+        spans: false,
+        diagnostics: Diagnostics::Human,
+        colour: Colour::Auto,
+        library_path: Vec::new(),
+        jobs: 0,
+        read_limits: ReadLimits::default(),
+        files: Vec::new(),
+        explain: None,
+        help: false,
+    };
+    let mut args = args.into_iter().peekable();
+    if let Some(c) = args.peek().and_then(|a| command(a)) {
+        options.command = c;
+        args.next();
+    }
+    let mut only_files = false;
+    while let Some(arg) = args.next() {
+        if only_files || arg == "-" || !arg.starts_with('-') {
+            options.files.push(arg);
+            continue;
+        }
+        let (flag, value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            _ => (arg, None),
+        };
+        let value = |args: &mut ::std::iter::Peekable<::std::vec::IntoIter<String>>| {
+            value.clone().or_else(|| args.next())
+                .ok_or_else(|| format!("{} requires an argument", flag))
+        };
+        match flag.as_str() {
+            "--" => only_files = true,
+            "-h" | "--help" => options.help = true,
+            "--explain" => options.explain = Some(value(&mut args)?),
+            // The flags of earlier versions.
+            "--run" => options.command = Command::Run,
+            "--disassemble" => options.command = Command::Disassemble,
+            "--format" => options.format = match value(&mut args)?.as_str() {
+                "debug" => Format::Debug,
+                "sexpr" => Format::Sexpr,
+                "json" => Format::Json,
+                "ast" => Format::Ast,
+                f => return Err(format!("Unknown format {}", f)),
+            },
+            "--input" => options.input = match value(&mut args)?.as_str() {
+                "scheme" => InputFormat::Scheme,
+                "json" => InputFormat::Json,
+                "ast" => InputFormat::Ast,
+                f => return Err(format!("Unknown input format {}", f)),
+            },
+            "--spans" => options.spans = true,
+            "--diagnostics" => options.diagnostics = match value(&mut args)?.as_str() {
+                "human" => Diagnostics::Human,
+                "machine" => Diagnostics::Machine,
+                "json" => Diagnostics::Json,
+                m => return Err(format!("Unknown diagnostics mode {}", m)),
+            },
+            "--color" | "--colour" => options.colour = match value(&mut args)?.as_str() {
+                "auto" => Colour::Auto,
+                "always" => Colour::Always,
+                "never" => Colour::Never,
+                c => return Err(format!("Unknown colour setting {}", c)),
+            },
+            "-j" | "--jobs" => {
+                let n = value(&mut args)?;
+                options.jobs = match n.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("Invalid number of jobs {}", n)),
+                };
+            },
+            "--read-limit" => {
+                let limit = value(&mut args)?;
+                let (name, n) = limit.split_at(limit.find('=').unwrap_or(limit.len()));
+                let n = match &n.get(1..) {
+                    Some("none") => None,
+                    Some(n) => Some(n.parse().map_err(|_| format!("Invalid read limit {}", limit))?),
+                    None => return Err(format!("Invalid read limit {}", limit)),
+                };
+                let limits = &mut options.read_limits;
+                match name {
+                    "depth" => limits.depth = n,
+                    "token" => limits.token_length = n,
+                    "string" => limits.string_length = n,
+                    "data" => limits.nodes = n,
+                    _ => return Err(format!("Unknown read limit {}", name)),
+                }
+            },
+            "-L" | "--library-path" => options.library_path.push(PathBuf::from(value(&mut args)?)),
+            _ => return Err(format!("Unknown option {}", flag)),
+        }
+    }
+    Ok(options)
+}
+
+enum Input {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Input {
+    fn name(&self) -> String {
+        match self {
+            Input::Stdin => "<stdin>".to_string(),
+            Input::File(path) => path.display().to_string(),
+        }
+    }
+
+    fn dir(&self) -> PathBuf {
+        match self {
+            Input::Stdin => PathBuf::from("."),
+            Input::File(path) => library::parent(path),
+        }
+    }
+
+    fn read(&self) -> io::Result<String> {
+        match self {
+            Input::Stdin => {
+                let mut s = String::new();
+                io::stdin().read_to_string(&mut s)?;
+                Ok(s)
+            },
+            Input::File(path) => fs::read_to_string(path),
+        }
+    }
+}
+
+fn inputs(files: &[String]) -> Result<Vec<Input>, String> {
+    let mut res = Vec::new();
+    for f in files {
+        if f == "-" {
+            res.push(Input::Stdin);
+        } else if glob::is_pattern(f) {
+            let paths = glob::glob(f).map_err(|e| format!("{}: {}", f, e))?;
+            if paths.is_empty() {
+                return Err(format!("No files match {}", f));
+            }
+            res.extend(paths.into_iter().map(Input::File));
+        } else if Path::new(f).is_dir() {
+            let mut paths = Vec::new();
+            for ext in &["scm", "sld"] {
+                let pattern = Path::new(f).join("**").join(format!("*.{}", ext));
+                paths.extend(glob::glob(&pattern.to_string_lossy())
+                             .map_err(|e| format!("{}: {}", f, e))?);
+            }
+            paths.sort();
+            res.extend(paths.into_iter().map(Input::File));
+        } else {
+            res.push(Input::File(PathBuf::from(f)));
+        }
+    }
+    Ok(res)
+}
+
+fn print<T: Serialize + Debug>(format: Format, item: &T, span: Option<Span>) {
+    let document = || serialize::document(Spanned { value: item, span }.to_json());
+    match format {
+        Format::Debug => println!("{:?}", item),
+        Format::Sexpr => println!("{}", item.to_sexpr()),
+        Format::Json => println!("{}", document()),
+        Format::Ast => {
+            let mut out = String::new();
+            serialize::write_ast(&document(), &mut out);
+            println!("{}", out);
+        },
+    }
+}
+
+/// A top-level datum of an input, and its spans if they are known.
+type Datum = (Node, Option<Spans>);
+
+/// Reads the data of an input, along with their spans if they are known.
+fn read_nodes(text: &str, input: InputFormat, threads: usize, limits: ReadLimits)
+              -> Result<Vec<(Node, Option<Spans>)>, Diagnostic> {
+    let mut nodes = Vec::new();
+    let mut document = |json: Json| -> Result<(), Diagnostic> {
+        let node: Spanned<Node> = serialize::from_document(&json)
+            .map_err(|e| Diagnostic::new(e.to_string()))?;
+        nodes.push((node.value, node.span.map(|span| Spans { span, children: Vec::new() })));
+        Ok(())
+    };
+    match input {
+        InputFormat::Scheme => return parallel::read(text, threads, limits)
+            .map(|data| data.into_iter().map(|(node, spans)| (node, Some(spans))).collect())
+            .map_err(|e| Diagnostic::from_read_error(&e)),
+        InputFormat::Json =>
+            for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                document(Json::parse(line).map_err(Diagnostic::new)?)?;
+            },
+        InputFormat::Ast =>
+            for json in serialize::parse_ast(text).map_err(|e| Diagnostic::new(e.to_string()))? {
+                document(json)?;
+            },
+    }
+    Ok(nodes)
+}
+
+/// The exit status and diagnostic for a syntax error in `nodes`. Labels
+/// are placed at the first datum equal to the one they are about, so
+/// `nodes` should be only the form being expanded when that is known.
+fn syntax_error(e: &SchemeError, nodes: &[(Node, Option<Spans>)]) -> (i32, Diagnostic) {
+    let data: Vec<_> = nodes.iter()
+        .filter_map(|(node, spans)| spans.as_ref().map(|spans| (node, spans)))
+        .collect();
+    (EXIT_SYNTAX, Diagnostic::from_scheme_error(e, &data))
+}
+
+fn library_error(e: &LibraryError, nodes: &[(Node, Option<Spans>)]) -> (i32, Diagnostic) {
+    match e {
+        LibraryError::Runtime(_) => (EXIT_RUNTIME, Diagnostic::new(e.to_string())),
+        LibraryError::Basic(_) => (EXIT_SYNTAX, Diagnostic::new(e.to_string())),
+        LibraryError::Syntax(e) => syntax_error(e, nodes),
+    }
+}
+
+/// Prints rendered diagnostics on stderr, or on stdout if they are JSON.
+fn print_diagnostics(mode: Diagnostics, out: &str) {
+    if mode == Diagnostics::Json {
+        print!("{}", out);
+        let _ = io::stdout().flush();
+    } else {
+        eprint!("{}", out);
+    }
+}
+
+/// What reading and expanding inputs needs, without the VM that loading
+/// them needs, so that files can be checked on threads of their own.
+struct Checker {
+    options: Options,
+    colour: bool,
+    /// Threads to read and expand each input on.
+    threads: usize,
+    /// Diagnostics kept to be printed later, for inputs checked on other
+    /// threads, or `None` to print them as they come.
+    buffer: Option<String>,
+}
+
+/// The text of a checked input, its data, and the expressions they expand
+/// to, or `None` for the forms left to the loader.
+type Checked = (String, Vec<Datum>, Vec<Option<Expression>>);
+
+struct Session {
+    checker: Checker,
+    vm: Vm,
+    loader: Loader,
+}
+
+impl Checker {
+    fn new(options: Options, colour: bool, threads: usize) -> Self {
+        Checker { options, colour, threads, buffer: None }
+    }
+
+    fn report(&mut self, name: &str, text: &str, d: &Diagnostic) {
+        let out = match self.options.diagnostics {
+            Diagnostics::Human => diagnostic::render(d, name, text, self.colour),
+            Diagnostics::Machine => diagnostic::render_machine(d, name, text),
+            Diagnostics::Json => format!("{}\n", diagnostic::to_json(d, name, text)),
+        };
+        match self.buffer {
+            Some(ref mut buffer) => buffer.push_str(&out),
+            None => print_diagnostics(self.options.diagnostics, &out),
+        }
+    }
+
+    fn read_nodes(&self, text: &str) -> Result<Vec<Datum>, (i32, Diagnostic)> {
+        read_nodes(text, self.options.input, self.threads, self.options.read_limits)
+            .map_err(|d| (EXIT_SYNTAX, d))
+    }
+
+    /// Reads `input` and reports the syntax errors in its forms, the part
+    /// of `check` that does not depend on the inputs before it. Returns
+    /// the text, its data and their expansions, or the exit status if
+    /// there are errors.
+    fn check_syntax(&mut self, input: &Input) -> Result<Checked, i32> {
+        let name = input.name();
+        let text = match input.read() {
+            Ok(text) => text,
+            Err(e) => {
+                self.report(&name, "", &Diagnostic::new(e.to_string()));
+                return Err(EXIT_USAGE);
+            },
+        };
+        let checked = self.read_nodes(&text).and_then(|nodes| {
+            self.check_forms(input, &text, &nodes).map(|expanded| (nodes, expanded))
+        });
+        match checked {
+            Ok((nodes, expanded)) => Ok((text, nodes, expanded)),
+            Err((code, d)) => {
+                self.report(&name, &text, &d);
+                Err(code)
+            },
+        }
+    }
+
+    /// Expands all the top-level forms and reports the syntax errors in
+    /// them, rather than stopping at the first. Library declarations are
+    /// left to the loader, and expand to `None`.
+    fn check_forms(&mut self, input: &Input, text: &str, nodes: &[Datum])
+                   -> Result<Vec<Option<Expression>>, (i32, Diagnostic)> {
+        let expand = |n: &Datum| match n.0 {
+            Node::List(ref items) => match items.first() {
+                Some(Node::Identifier(ref s))
+                    if ["import", "define-library", "include", "include-ci", "cond-expand"]
+                    .contains(&s.as_str()) => None,
+                _ => Some(parse_expression(n.0.clone())),
+            },
+            _ => Some(parse_expression(n.0.clone())),
+        };
+        // Forms are small, so each thread takes a run of them at a time.
+        let size = nodes.len() / (self.threads * 4) + 1;
+        let runs: Vec<_> = nodes.chunks(size).collect();
+        let runs = parallel::map(&runs, self.threads, || (), |_, run| {
+            run.iter().map(expand).collect::<Vec<_>>()
+        });
+        let mut expanded = Vec::with_capacity(nodes.len());
+        let mut errors = Vec::new();
+        for (i, e) in runs.into_iter().flatten().enumerate() {
+            match e {
+                Some(Ok(e)) => expanded.push(Some(e)),
+                Some(Err(e)) => errors.push(syntax_error(&e, &nodes[i..i + 1])),
+                None => expanded.push(None),
+            }
+        }
+        let last = match errors.pop() {
+            Some(last) => last,
+            None => return Ok(expanded),
+        };
+        for error in &errors {
+            self.report(&input.name(), text, &error.1);
+        }
+        Err(last)
+    }
+}
+
+impl Session {
+    fn new(options: Options, colour: bool, threads: usize) -> Self {
+        let vm = Vm::new();
+        let mut loader = Loader::new(&vm, options.command == Command::Run);
+        for dir in &options.library_path {
+            loader.add_search_path(dir.clone());
+        }
+        if let Some(dirs) = ::std::env::var_os("SCHEME_SYNTAX_PATH") {
+            for dir in ::std::env::split_paths(&dirs) {
+                loader.add_search_path(dir);
+            }
+        }
+        loader.add_search_path(".");
+        Session { checker: Checker::new(options, colour, threads), vm, loader }
+    }
+
+    /// Processes one input, reporting errors on stderr. Returns the exit
+    /// status for the input.
+    fn process(&mut self, input: &Input) -> i32 {
+        let name = input.name();
+        let text = match input.read() {
+            Ok(text) => text,
+            Err(e) => {
+                self.checker.report(&name, "", &Diagnostic::new(e.to_string()));
+                return EXIT_USAGE;
+            },
+        };
+        match self.process_text(input, &text) {
+            Ok(()) => EXIT_OK,
+            Err((code, d)) => {
+                self.checker.report(&name, &text, &d);
+                code
+            },
+        }
+    }
+
+    fn process_text(&mut self, input: &Input, text: &str) -> Result<(), (i32, Diagnostic)> {
+        let options = &self.checker.options;
+        let (format, spans, command) = (options.format, options.spans, options.command);
+        if command == Command::Tokens {
+            let mut lexer = RegexLexer::new(text);
+            while let Some(token) = lexer.next() {
+                let token = token.map_err(|message| {
+                    let e = ReadError { code: "S0103", message, span: lexer.span() };
+                    (EXIT_SYNTAX, Diagnostic::from_read_error(&e))
+                })?;
+                print(format, &token, if spans { Some(lexer.span()) } else { None });
+            }
+            return Ok(());
+        }
+        let nodes = self.checker.read_nodes(text)?;
+        let span = |s: &Option<Spans>| if spans { s.as_ref().map(|s| s.span) } else { None };
+        match command {
+            Command::Tokens | Command::Test => unreachable!(),
+            Command::Read =>
+                for (node, s) in &nodes {
+                    print(format, node, span(s));
+                },
+            Command::Expand =>
+                for (i, (node, s)) in nodes.iter().enumerate() {
+                    let e = parse_expression(node.clone())
+                        .map_err(|e| syntax_error(&e, &nodes[i..i + 1]))?;
+                    print(format, &e, span(s));
+                },
+            Command::Disassemble =>
+                for (i, (node, _)) in nodes.iter().enumerate() {
+                    let e = parse_expression(node.clone())
+                        .map_err(|e| syntax_error(&e, &nodes[i..i + 1]))?;
+                    let chunk = self.vm.compile(&e)
+                        .map_err(|e| (EXIT_SYNTAX, Diagnostic::new(e.to_string())))?;
+                    print!("{}", self.vm.disassemble(&chunk));
+                },
+            Command::Check => {
+                let expanded = self.checker.check_forms(input, text, &nodes)?;
+                self.load(input, &nodes, expanded)?;
+            },
+            Command::Run => {
+                let expanded = nodes.iter().map(|_| None).collect();
+                self.load(input, &nodes, expanded)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Loads and runs the program in `nodes`, with the libraries that
+    /// earlier inputs defined. The forms `expanded` has an expression for
+    /// are not expanded again.
+    fn load(&mut self, input: &Input, nodes: &[Datum], expanded: Vec<Option<Expression>>)
+            -> Result<(), (i32, Diagnostic)> {
+        let program = nodes.iter().map(|n| n.0.clone()).zip(expanded).collect();
+        self.loader.load_expanded(&mut self.vm, program, &input.dir())
+            .map(|_| ())
+            .map_err(|e| library_error(&e, nodes))
+    }
+
+    /// Evaluates stdin line by line, printing each value.
+    fn repl(&mut self) -> i32 {
+        let mut status = EXIT_OK;
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("<stdin>: error: {}", e);
+                    return EXIT_USAGE;
+                },
+            };
+            let limits = self.checker.options.read_limits;
+            for node in Parser::with_limits(RegexLexer::new(&line), limits) {
+                let res = match node {
+                    Err(e) => Err((EXIT_SYNTAX, e.to_string())),
+                    Ok(node) => self.loader.eval(&mut self.vm, node, Path::new("."))
+                        .map_err(|e| (library_error(&e, &[]).0, e.to_string())),
+                };
+                match res {
+                    Ok(v) => println!("{}", self.vm.write(&v)),
+                    Err((code, e)) => {
+                        println!("error: {}", e);
+                        status = status.max(code);
+                    },
+                }
+            }
+        }
+        status
+    }
+}
+
+/// Runs the conformance tests in `inputs`, printing each failure on stderr
+/// and the report on stdout.
+fn test(inputs: &[Input]) -> i32 {
+    let mut outcomes = Vec::new();
+    let mut status = EXIT_OK;
+    for input in inputs {
+        let text = match input.read() {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("{}: error: {}", input.name(), e);
+                status = status.max(EXIT_USAGE);
+                continue;
+            },
+        };
+        match conformance::run(&text, &input.dir()) {
+            Ok(res) => {
+                for o in &res {
+                    if let Some(f) = o.unexpected() {
+                        eprintln!("{}:{}: {}", input.name(), o.line, f);
+                        status = status.max(EXIT_TEST);
+                    }
+                }
+                outcomes.extend(res);
+            },
+            Err(e) => {
+                eprintln!("{}: error: {}", input.name(), e);
+                status = status.max(EXIT_USAGE);
+            },
+        }
+    }
+    print!("{}", conformance::report(&outcomes));
+    status
+}
+
+/// Runs the command line `args` (without the program name) and returns
+/// the exit status.
+pub fn main(args: Vec<String>) -> i32 {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return EXIT_USAGE;
+        },
+    };
+    if options.help {
+        print!("{}", USAGE);
+        return EXIT_OK;
+    }
+    if let Some(ref code) = options.explain {
+        return match codes::explanation(code) {
+            Some(e) => {
+                print!("{}", codes::explain(e));
+                EXIT_OK
+            },
+            None => {
+                eprintln!("error: Unknown error code {}", code);
+                EXIT_USAGE
+            },
+        };
+    }
+    let inputs = match inputs(&options.files) {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return EXIT_USAGE;
+        },
+    };
+    if options.command == Command::Test {
+        return test(if options.files.is_empty() { &[Input::Stdin] } else { &inputs });
+    }
+    let no_files = options.files.is_empty();
+    let colour = match options.colour {
+        Colour::Auto => diagnostic::detect_colour(),
+        Colour::Always => true,
+        Colour::Never => false,
+    };
+    let jobs = if options.jobs == 0 { parallel::threads() } else { options.jobs };
+    if options.command == Command::Check && inputs.len() > 1 && jobs > 1 {
+        // Each thread reads and expands files with a checker of its own,
+        // reading each on one thread. The files are then loaded in order
+        // in one session, so that each sees the libraries defined before
+        // it, and the diagnostics of each are printed as soon as it and
+        // the files before it are done.
+        let mut session = Session::new(options.clone(), colour, 1);
+        let mut status = EXIT_OK;
+        parallel::map_ordered(&inputs, jobs, || {
+            let mut checker = Checker::new(options.clone(), colour, 1);
+            checker.buffer = Some(String::new());
+            checker
+        }, |checker, input| {
+            let checked = checker.check_syntax(input);
+            (checked, checker.buffer.replace(String::new()).unwrap())
+        }, |input, (checked, out)| {
+            print_diagnostics(options.diagnostics, &out);
+            let s = match checked {
+                Ok((text, nodes, expanded)) => match session.load(input, &nodes, expanded) {
+                    Ok(()) => EXIT_OK,
+                    Err((code, d)) => {
+                        session.checker.report(&input.name(), &text, &d);
+                        code
+                    },
+                },
+                Err(code) => code,
+            };
+            status = status.max(s);
+        });
+        return status;
+    }
+    let mut session = Session::new(options, colour, jobs);
+    if no_files {
+        if session.checker.options.command == Command::Run {
+            return session.repl();
+        }
+        return session.process(&Input::Stdin);
+    }
+    let mut status = EXIT_OK;
+    for input in &inputs {
+        status = status.max(session.process(input));
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use json::Json;
+    use super::{main, parse_args, Colour, Command, Diagnostics, Format, Input, InputFormat,
+                Options, Session, EXIT_OK, EXIT_SYNTAX};
+
+    fn args(s: &str) -> Result<Options, String> {
+        parse_args(s.split_whitespace().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn arguments() {
+        let o = args("check --format=json -L lib a.scm - -- --b.scm").unwrap();
+        assert_eq!(o.command, Command::Check);
+        assert_eq!(o.format, Format::Json);
+        assert_eq!(o.library_path, vec![PathBuf::from("lib")]);
+        assert_eq!(o.files, vec!["a.scm", "-", "--b.scm"]);
+        let o = args("--format sexpr x").unwrap();
+        assert_eq!((o.command, o.format), (Command::Expand, Format::Sexpr));
+        let o = args("read --input ast --format=ast --spans").unwrap();
+        assert_eq!((o.input, o.format, o.spans), (InputFormat::Ast, Format::Ast, true));
+        let o = args("check --diagnostics machine --color=never").unwrap();
+        assert_eq!((o.diagnostics, o.colour), (Diagnostics::Machine, Colour::Never));
+        assert_eq!(args("check --diagnostics=json").unwrap().diagnostics, Diagnostics::Json);
+        assert_eq!(args("--explain S0002").unwrap().explain, Some("S0002".to_string()));
+        assert_eq!(args("--run").unwrap().command, Command::Run);
+        assert_eq!(args("check -j 4 a.scm").unwrap().jobs, 4);
+        let limits = args("--read-limit depth=10 --read-limit=data=none").unwrap().read_limits;
+        assert_eq!((limits.depth, limits.nodes), (Some(10), None));
+        assert_eq!(args("--read-limit depth").unwrap_err(), "Invalid read limit depth");
+        assert_eq!(args("--read-limit size=1").unwrap_err(), "Unknown read limit size");
+        assert_eq!(args("check --jobs=0").unwrap_err(), "Invalid number of jobs 0");
+        assert_eq!(args("run --format").unwrap_err(), "--format requires an argument");
+        assert_eq!(args("--format yaml").unwrap_err(), "Unknown format yaml");
+        assert_eq!(args("-x").unwrap_err(), "Unknown option -x");
+    }
+
+    #[test]
+    fn lexer_errors() {
+        for command in &["tokens", "read", "expand"] {
+            let mut session = Session::new(args(command).unwrap(), false, 1);
+            let (status, d) = session.process_text(&Input::Stdin, "(a \"\\q\")").unwrap_err();
+            assert_eq!((status, d.code), (EXIT_SYNTAX, Some("S0103")), "{}", command);
+        }
+    }
+
+    #[test]
+    fn read_limits() {
+        let text = "(a (b (c)))";
+        let mut session = Session::new(args("read --read-limit depth=2").unwrap(), false, 1);
+        let (status, d) = session.process_text(&Input::Stdin, text).unwrap_err();
+        assert_eq!((status, d.message.as_str()), (EXIT_SYNTAX, "lists nested too deeply"));
+        let mut session = Session::new(args("read --read-limit depth=3").unwrap(), false, 1);
+        assert!(session.process_text(&Input::Stdin, text).is_ok());
+    }
+
+    #[test]
+    fn check_libraries_in_parallel() {
+        let dir = env::temp_dir().join(format!("scheme-syntax-check-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.scm"),
+                  "(define-library (foo) (export x) (import (scheme base)) (begin (define x 1)))")
+            .unwrap();
+        for name in &["b.scm", "c.scm"] {
+            fs::write(dir.join(name), "(import (scheme base) (foo)) (+ x 1)").unwrap();
+        }
+        let file = |name: &str| dir.join(name).display().to_string();
+        for jobs in &["1", "4"] {
+            let args = vec!["check".to_string(), "-j".to_string(), jobs.to_string(),
+                            file("a.scm"), file("b.scm"), file("c.scm")];
+            assert_eq!(main(args), EXIT_OK, "-j {}", jobs);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_repeated_forms() {
+        let path = env::temp_dir()
+            .join(format!("scheme-syntax-repeated-{}.scm", ::std::process::id()));
+        fs::write(&path, "(quote (if x))\n(display (if x))\n(define c (if x))\n(if x)\n").unwrap();
+        for jobs in &[1, 4] {
+            let mut session = Session::new(args("check --diagnostics json").unwrap(), false, *jobs);
+            session.checker.buffer = Some(String::new());
+            assert_eq!(session.process(&Input::File(path.clone())), EXIT_SYNTAX);
+            let found: Vec<_> = session.checker.buffer.unwrap().lines().map(|line| {
+                let span = Json::parse(line).unwrap().get("span").unwrap().clone();
+                let number = |key| match span.get(key) {
+                    Some(&Json::Number(n)) => n as usize,
+                    _ => panic!("no {} in {}", key, line),
+                };
+                (number("line"), number("column"))
+            }).collect();
+            assert_eq!(found, vec![(2, 10), (3, 11), (4, 1)], "-j {}", jobs);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
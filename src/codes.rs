@@ -0,0 +1,302 @@
+/// A longer description of an error code, for `--explain`. The examples
+/// are an incorrect program that causes the error and a corrected one.
+pub struct Explanation {
+    pub code: &'static str,
+    pub title: &'static str,
+    pub description: &'static str,
+    pub incorrect: &'static str,
+    pub correct: &'static str,
+}
+
+/// Every error code. Codes are never reused for a different error; S00xx
+/// are errors in the syntax of expressions and S01xx errors in reading
+/// data. S0001, a `cond` or `case` without an else clause, is no longer an
+/// error.
+pub const EXPLANATIONS: &[Explanation] = &[
+    Explanation {
+        code: "S0002",
+        title: "wrong number of operands to a special form",
+        description: "\
+Special forms take a fixed number of operands: `if` takes a test, a
+consequent and an optional alternative, `quote`, `time`, `delay` and
+`delay-force` take one operand, `set!` takes a variable and an expression,
+`when` and `unless` take a test and a body, `begin` takes at least one
+expression, `lambda`, `define` and `do` take at least two operands,
+`define-values` takes formals and an expression, `receive` takes formals,
+an expression and a body, and `case` takes a key and any number of clauses.",
+        incorrect: "(if (< x 0))",
+        correct: "(if (< x 0) (- x) x)",
+    },
+    Explanation {
+        code: "S0003",
+        title: "malformed cond clause",
+        description: "\
+A `cond` clause is a list of the form `(test)`, whose value is the test,
+`(test expression...)`, or `(test => receiver)`, where receiver is called
+with the value of the test.",
+        incorrect: "(cond ((assv x lst) => cdr car) (else #f))",
+        correct: "(cond ((assv x lst) => cdr) (else #f))",
+    },
+    Explanation {
+        code: "S0004",
+        title: "malformed else clause",
+        description: "\
+An else clause has the form `(else expression...)`, with at least one
+expression, and must be the last clause of its `cond` or `case`. In a
+`case` it may also be `(else => receiver)`.",
+        incorrect: "(cond ((f x) 1) (else))",
+        correct: "(cond ((f x) 1) (else 0))",
+    },
+    Explanation {
+        code: "S0005",
+        title: "malformed case clause",
+        description: "\
+A `case` clause has the form `((datum...) expression...)`: a list of the
+data to compare the key with, and at least one expression. Instead of the
+expressions, it may have `=> receiver`, where receiver is called with the
+key.",
+        incorrect: "(case x (1 \"one\") (else \"many\"))",
+        correct: "(case x ((1) \"one\") (else \"many\"))",
+    },
+    Explanation {
+        code: "S0006",
+        title: "expected an identifier",
+        description: "\
+Parameters, the names of definitions and the variables of `let` bindings
+must be identifiers.",
+        incorrect: "(let ((\"x\" 1)) x)",
+        correct: "(let ((x 1)) x)",
+    },
+    Explanation {
+        code: "S0007",
+        title: "malformed parameter list",
+        description: "\
+The parameters of a `lambda` are an identifier, which receives all the
+arguments as a list, or a list of identifiers, which may end with a dot
+and one identifier that receives the remaining arguments.",
+        incorrect: "(lambda (x . y z) x)",
+        correct: "(lambda (x y . z) x)",
+    },
+    Explanation {
+        code: "S0008",
+        title: "duplicate variable",
+        description: "\
+The parameters of a procedure, the variables bound by a `let`, `let-values`
+or `do`, and the fields of a record type and of its constructor must be
+distinct.",
+        incorrect: "(lambda (x y x) (+ x y))",
+        correct: "(lambda (x y z) (+ x y z))",
+    },
+    Explanation {
+        code: "S0009",
+        title: "empty body",
+        description: "\
+The body of a `lambda`, a procedure definition, a `let` or `let-values`, a
+`receive`, a `parameterize` or a `guard` must contain at least one
+expression.",
+        incorrect: "(define (f x))",
+        correct: "(define (f x) x)",
+    },
+    Explanation {
+        code: "S0010",
+        title: "malformed definition",
+        description: "\
+A definition has the form `(define name expression)`, or
+`(define (name parameter...) body...)` to define a procedure.",
+        incorrect: "(define () 1)",
+        correct: "(define (f) 1)",
+    },
+    Explanation {
+        code: "S0011",
+        title: "malformed let bindings",
+        description: "\
+A `let` has a list of bindings, each of the form `(variable init)`,
+followed by a body. A named `let` has a name before the bindings. The
+bindings of a `let-values` have the form `(formals init)`, and those of a
+`parameterize` the form `(parameter value)`.",
+        incorrect: "(let (x 1) x)",
+        correct: "(let ((x 1)) x)",
+    },
+    Explanation {
+        code: "S0012",
+        title: "literal in operator position",
+        description: "\
+The first element of a procedure call is the procedure to call, so it
+cannot be a number, string, character or boolean. To write a list of
+data, quote it.",
+        incorrect: "(1 2 3)",
+        correct: "(quote (1 2 3))",
+    },
+    Explanation {
+        code: "S0013",
+        title: "empty combination",
+        description: "\
+`()` is not an expression. To write the empty list, quote it.",
+        incorrect: "(define x ())",
+        correct: "(define x (quote ()))",
+    },
+    Explanation {
+        code: "S0014",
+        title: "malformed do loop",
+        description: "\
+A `do` loop has the form `(do ((variable init step)...) (test result...)
+command...)`. Each binding is a list of a variable, its initial value and
+an optional step, and the test clause is a list that starts with the test
+that ends the loop.",
+        incorrect: "(do (i 0 (+ i 1)) ((= i 3)) (display i))",
+        correct: "(do ((i 0 (+ i 1))) ((= i 3)) (display i))",
+    },
+    Explanation {
+        code: "S0015",
+        title: "malformed record type",
+        description: "\
+A record type definition has the form `(define-record-type name
+(constructor field...) predicate (field accessor modifier)...)`. Each field
+spec is a list of the field name, its accessor and an optional modifier, and
+the constructor may only initialise fields of the record type.",
+        incorrect: "(define-record-type point (make-point x y) point? (x point-x))",
+        correct: "(define-record-type point (make-point x y) point? (x point-x) (y point-y))",
+    },
+    Explanation {
+        code: "S0016",
+        title: "malformed guard",
+        description: "\
+A `guard` has the form `(guard (variable clause...) body...)`. The variable
+is bound to the raised object, and the clauses are like those of `cond`.",
+        incorrect: "(guard e (raise 1))",
+        correct: "(guard (e ((error-object? e) #f)) (raise 1))",
+    },
+    Explanation {
+        code: "S0017",
+        title: "misplaced dot in a datum",
+        description: "\
+In a quoted list a dot may only come before its last element, as in
+`(a b . c)`, which is the list of `a` and `b` ending in `c` rather than the
+empty list. A dot anywhere else, including first, or more than one dot is
+an error.",
+        incorrect: "(quote (a . b c))",
+        correct: "(quote (a b . c))",
+    },
+    Explanation {
+        code: "S0101",
+        title: "unmatched right parenthesis",
+        description: "\
+A right parenthesis appears where no list is open, usually because of one
+right parenthesis too many.",
+        incorrect: "(define (f x) (* x x)))",
+        correct: "(define (f x) (* x x))",
+    },
+    Explanation {
+        code: "S0102",
+        title: "unclosed parenthesis",
+        description: "\
+The input ends inside a list, usually because a right parenthesis is
+missing.",
+        incorrect: "(define (f x) (* x x)",
+        correct: "(define (f x) (* x x))",
+    },
+    Explanation {
+        code: "S0103",
+        title: "unreadable token",
+        description: "\
+The input contains text that is not a token: an unknown character such as
+`[`, a bad escape in a string, or a number too large to represent exactly.",
+        incorrect: "(display \"a\\qb\")",
+        correct: "(display \"a\\\\qb\")",
+    },
+    Explanation {
+        code: "S0104",
+        title: "reader limit exceeded",
+        description: "\
+The input exceeds a limit of the reader: lists nested more than 200 deep,
+an identifier longer than 1024 bytes, a string longer than 1048576 bytes
+or more than 1048576 data in all. The limits keep hostile input from
+exhausting the stack or memory; programs that hit them should be split up
+or keep large data in files of their own.",
+        incorrect: "(((((((((( ... ))))))))))",
+        correct: "(define nested (quote (((1)))))",
+    },
+];
+
+pub fn explanation(code: &str) -> Option<&'static Explanation> {
+    EXPLANATIONS.iter().find(|e| e.code.eq_ignore_ascii_case(code))
+}
+
+fn indent(s: &str) -> String {
+    s.lines().map(|l| format!("    {}\n", l)).collect()
+}
+
+/// The text that `--explain` prints.
+pub fn explain(e: &Explanation) -> String {
+    format!("{}: {}\n\n{}\n\nIncorrect:\n\n{}\nCorrect:\n\n{}",
+            e.code, e.title, e.description, indent(e.incorrect), indent(e.correct))
+}
+
+#[cfg(test)]
+mod tests {
+    use diagnostic::Diagnostic;
+    use parser::{ReadLimits, SpannedParser};
+    use scheme::parse_expression;
+    use super::{explain, explanation, EXPLANATIONS};
+
+    /// The codes of the errors in reading and expanding `text`, which stop
+    /// at the first error in reading.
+    fn codes(text: &str) -> Vec<&'static str> {
+        let mut res = Vec::new();
+        for datum in SpannedParser::new(text) {
+            match datum {
+                Ok((node, _)) => res.extend(parse_expression(node).err().map(|e| e.code)),
+                Err(e) => {
+                    res.push(Diagnostic::from_read_error(&e).code.unwrap());
+                    break;
+                },
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn examples() {
+        for e in EXPLANATIONS {
+            // Input that exceeds a reader limit is too long to show.
+            if e.code != "S0104" {
+                assert_eq!(codes(e.incorrect), vec![e.code], "{}", e.code);
+            }
+            assert_eq!(codes(e.correct), Vec::<&str>::new(), "{}", e.code);
+        }
+        assert!(EXPLANATIONS.windows(2).all(|w| w[0].code < w[1].code));
+    }
+
+    /// The limits that the explanation of S0104 gives are the defaults.
+    #[test]
+    fn read_limits() {
+        let limits = ReadLimits::default();
+        let text = format!(
+            "lists nested more than {} deep, an identifier longer than {} bytes, a string longer \
+             than {} bytes or more than {} data in all",
+            limits.depth.unwrap(), limits.token_length.unwrap(), limits.string_length.unwrap(),
+            limits.nodes.unwrap());
+        let description = explanation("S0104").unwrap().description.replace('\n', " ");
+        assert!(description.contains(&text), "{}", description);
+    }
+
+    #[test]
+    fn explaining() {
+        assert_eq!(explain(explanation("s0004").unwrap()), "\
+S0004: malformed else clause
+
+An else clause has the form `(else expression...)`, with at least one
+expression, and must be the last clause of its `cond` or `case`. In a
+`case` it may also be `(else => receiver)`.
+
+Incorrect:
+
+    (cond ((f x) 1) (else))
+
+Correct:
+
+    (cond ((f x) 1) (else 0))
+");
+        assert!(explanation("S9999").is_none());
+    }
+}
@@ -0,0 +1,966 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use scheme::syntax::{Expression, CondClause, CaseClause, ClauseBody, Quotation, Literal, Binding,
+                     DoBinding, Formals, RecordType};
+use heap::Heap;
+use library::Namespace;
+use value::{Value, Arity, Winders, RuntimeError, Result};
+use vm::Globals;
+
+/// A single VM instruction. Jump targets are absolute offsets into the
+/// enclosing chunk's code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Const(u32),
+    Unspecified,
+    Global(u32),
+    DefineGlobal(u32),
+    /// Assigns to a global, which must already be bound.
+    SetGlobal(u32),
+    Local(u16, u16),
+    SetLocal(u16, u16),
+    Pop,
+    Dup,
+    Swap,
+    Jump(u32),
+    JumpIfFalse(u32),
+    /// Jump if the top of the stack is false, otherwise pop it.
+    AndJump(u32),
+    /// Jump if the top of the stack is true, otherwise pop it.
+    OrJump(u32),
+    CaseJump(u32),
+    Closure(u32),
+    Call(u32),
+    TailCall(u32),
+    Return,
+    TimeStart,
+    TimeEnd,
+    /// Pops an after and a before thunk and enters their extent.
+    PushWinder,
+    PopWinder,
+    SetWinders(u32),
+    /// Pops a value and pushes a promise of it: a forced one if the flag is
+    /// set, otherwise one whose value is the promise that the popped thunk
+    /// returns.
+    MakePromise(bool),
+}
+
+/// Datums that `case` can dispatch on in constant time. Other quoted data
+/// (strings, lists) are never `eqv?` to a computed key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CaseKey {
+    Number(i32),
+    /// Reals are keyed by their bit pattern, matching `eqv?`.
+    Real(u64),
+    Boolean(bool),
+    Character(char),
+    Symbol(Rc<str>),
+    Nil,
+}
+
+impl CaseKey {
+    pub fn from_value(v: &Value) -> Option<CaseKey> {
+        match v {
+            Value::Number(n) => Some(CaseKey::Number(*n)),
+            Value::Real(x) => Some(CaseKey::Real(x.to_bits())),
+            Value::Boolean(b) => Some(CaseKey::Boolean(*b)),
+            Value::Character(c) => Some(CaseKey::Character(*c)),
+            Value::Symbol(s) => Some(CaseKey::Symbol(s.clone())),
+            Value::Nil => Some(CaseKey::Nil),
+            _ => None,
+        }
+    }
+
+    pub fn from_quotation(q: &Quotation) -> Option<CaseKey> {
+        match q {
+            Quotation::Literal(Literal::Number(n)) => Some(CaseKey::Number(*n)),
+            Quotation::Literal(Literal::Real(x)) => Some(CaseKey::Real(x.to_bits())),
+            Quotation::Literal(Literal::Boolean(b)) => Some(CaseKey::Boolean(*b)),
+            Quotation::Literal(Literal::Character(c)) => Some(CaseKey::Character(*c)),
+            Quotation::Symbol(s) => Some(CaseKey::Symbol(Rc::from(s.as_str()))),
+            Quotation::Nil => Some(CaseKey::Nil),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CaseTable {
+    pub targets: HashMap<CaseKey, u32>,
+    pub default: u32,
+}
+
+impl CaseTable {
+    pub fn lookup(&self, key: &Value) -> u32 {
+        CaseKey::from_value(key)
+            .and_then(|k| self.targets.get(&k).cloned())
+            .unwrap_or(self.default)
+    }
+}
+
+/// Compiled code for a lambda body or a top-level expression.
+pub struct Chunk {
+    pub name: Option<String>,
+    pub arity: Arity,
+    /// Number of variable slots in the frame created when this chunk is
+    /// entered: parameters first, then internal definitions.
+    pub frame_size: usize,
+    pub code: Vec<Op>,
+    pub constants: Vec<Value>,
+    pub case_tables: Vec<CaseTable>,
+    pub lambdas: Vec<Rc<Chunk>>,
+    /// Wind lists restored by `SetWinders`, which the VM only emits in the
+    /// code it generates for unwinding to a continuation.
+    pub winders: Vec<Winders>,
+}
+
+impl Chunk {
+    pub fn new(name: Option<String>, arity: Arity, frame_size: usize) -> Self {
+        Chunk {
+            name,
+            arity,
+            frame_size,
+            code: Vec::new(),
+            constants: Vec::new(),
+            case_tables: Vec::new(),
+            lambdas: Vec::new(),
+            winders: Vec::new(),
+        }
+    }
+
+    pub fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    fn here(&self) -> u32 {
+        self.code.len() as u32
+    }
+
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        self.code[at] = match self.code[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::AndJump(_) => Op::AndJump(target),
+            Op::OrJump(_) => Op::OrJump(target),
+            op => panic!("cannot patch {:?}", op),
+        };
+    }
+
+    pub fn constant(&mut self, v: Value) -> u32 {
+        self.constants.push(v);
+        (self.constants.len() - 1) as u32
+    }
+}
+
+/// Allocates a quoted datum on the heap. Lists are built from their last
+/// pair back, so that long ones do not recurse.
+pub fn quotation_value(heap: &mut Heap, q: &Quotation) -> Value {
+    let mut items = Vec::new();
+    let mut q = q;
+    while let Quotation::Cons(hd, tl) = q {
+        items.push(quotation_value(heap, hd));
+        q = tl;
+    }
+    let mut res = match q {
+        Quotation::Literal(l) => literal_value(heap, l),
+        Quotation::Symbol(s) => Value::Symbol(Rc::from(s.as_str())),
+        Quotation::Nil | Quotation::Cons(..) => Value::Nil,
+    };
+    for hd in items.into_iter().rev() {
+        res = heap.cons(hd, res);
+    }
+    res
+}
+
+pub fn literal_value(heap: &mut Heap, l: &Literal) -> Value {
+    match l {
+        Literal::Number(n) => Value::Number(*n),
+        Literal::Real(x) => Value::Real(*x),
+        Literal::Boolean(b) => Value::Boolean(*b),
+        Literal::Character(c) => Value::Character(*c),
+        Literal::String(s) => heap.string(s.clone()),
+    }
+}
+
+/// The variables of `formals`, the rest variable last.
+fn formals_names(formals: &Formals) -> Vec<String> {
+    formals.required.iter().chain(&formals.rest).cloned().collect()
+}
+
+/// Collects the names introduced by definitions at the top of a body, so
+/// that they can be given slots in the body's frame.
+pub fn body_definitions(body: &[Expression], names: &mut Vec<String>) {
+    for e in body {
+        match e {
+            Expression::Define(name, _) if !names.contains(name) =>
+                names.push(name.clone()),
+            Expression::Begin(es) => body_definitions(es, names),
+            Expression::DefineValues(formals, _) =>
+                for name in formals_names(formals) {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                },
+            Expression::DefineRecordType(rt) => {
+                let fields = rt.fields.iter()
+                    .flat_map(|f| Some(&f.accessor).into_iter().chain(&f.modifier));
+                for name in vec![&rt.name, &rt.constructor.0, &rt.predicate].into_iter().chain(fields) {
+                    if !names.contains(name) {
+                        names.push(name.clone());
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+/// The operands of `Local` and `SetLocal` for the variable in slot `index`
+/// of the frame `depth` frames out.
+fn local(depth: usize, index: usize) -> Result<(u16, u16)> {
+    match (u16::try_from(depth), u16::try_from(index)) {
+        (Ok(depth), Ok(index)) => Ok((depth, index)),
+        _ => Err(RuntimeError::Basic(
+            "Too many variables or nested scopes to compile".to_string())),
+    }
+}
+
+/// Translates expressions to chunks. Quoted constants are allocated on the
+/// heap as they are compiled, so the resulting chunk must be kept reachable
+/// by the VM before the heap is next collected.
+pub struct Compiler<'g> {
+    globals: &'g mut Globals,
+    heap: &'g mut Heap,
+    namespace: &'g Namespace,
+    /// Names bound in each enclosing frame, innermost last.
+    scopes: Vec<Vec<String>>,
+}
+
+impl <'g> Compiler<'g> {
+    pub fn new(globals: &'g mut Globals, heap: &'g mut Heap, namespace: &'g Namespace) -> Self {
+        Compiler {
+            globals,
+            heap,
+            namespace,
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Compiles a top-level expression into a chunk taking no arguments.
+    pub fn compile_toplevel(&mut self, e: &Expression) -> Result<Rc<Chunk>> {
+        let mut chunk = Chunk::new(None, Arity::exactly(0), 0);
+        self.compile(&mut chunk, e, true)?;
+        chunk.emit(Op::Return);
+        Ok(Rc::new(chunk))
+    }
+
+    fn resolve(&self, name: &str) -> Result<Option<(u16, u16)>> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(index) = scope.iter().rposition(|n| n == name) {
+                return local(depth, index).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn compile(&mut self, chunk: &mut Chunk, e: &Expression, tail: bool) -> Result<()> {
+        match e {
+            Expression::Literal(l) => {
+                let c = chunk.constant(literal_value(self.heap, l));
+                chunk.emit(Op::Const(c));
+            },
+            Expression::Variable(name) => {
+                let op = match self.resolve(name)? {
+                    Some((depth, index)) => Op::Local(depth, index),
+                    None => Op::Global(self.globals.slot(&self.namespace.global(name))),
+                };
+                chunk.emit(op);
+            },
+            Expression::Quote(q) => {
+                let c = chunk.constant(quotation_value(self.heap, q));
+                chunk.emit(Op::Const(c));
+            },
+            Expression::Time(e) => {
+                chunk.emit(Op::TimeStart);
+                self.compile(chunk, e, false)?;
+                chunk.emit(Op::TimeEnd);
+            },
+            Expression::If(test, consequent, alternative) => {
+                self.compile(chunk, test, false)?;
+                let to_alternative = chunk.emit(Op::JumpIfFalse(0));
+                self.compile(chunk, consequent, tail)?;
+                let to_end = chunk.emit(Op::Jump(0));
+                chunk.patch(to_alternative);
+                match alternative {
+                    Some(alternative) => self.compile(chunk, alternative, tail)?,
+                    None => { chunk.emit(Op::Unspecified); },
+                }
+                chunk.patch(to_end);
+            },
+            Expression::And(es) => self.compile_junction(chunk, es, tail, true)?,
+            Expression::Or(es) => self.compile_junction(chunk, es, tail, false)?,
+            Expression::Begin(es) => self.compile_sequence(chunk, es, tail)?,
+            Expression::When(test, body) => {
+                self.compile(chunk, test, false)?;
+                let to_end_of_body = chunk.emit(Op::JumpIfFalse(0));
+                self.compile_sequence(chunk, body, tail)?;
+                let to_end = chunk.emit(Op::Jump(0));
+                chunk.patch(to_end_of_body);
+                chunk.emit(Op::Unspecified);
+                chunk.patch(to_end);
+            },
+            Expression::Unless(test, body) => {
+                self.compile(chunk, test, false)?;
+                let to_body = chunk.emit(Op::JumpIfFalse(0));
+                chunk.emit(Op::Unspecified);
+                let to_end = chunk.emit(Op::Jump(0));
+                chunk.patch(to_body);
+                self.compile_sequence(chunk, body, tail)?;
+                chunk.patch(to_end);
+            },
+            Expression::Cond(clauses, else_clause) =>
+                self.compile_cond(chunk, clauses, else_clause, tail)?,
+            Expression::Case(key, clauses, else_clause) =>
+                self.compile_case(chunk, key, clauses, else_clause, tail)?,
+            Expression::Lambda(formals, body) => {
+                let lambda = self.compile_lambda(
+                    None, &formals.required, formals.rest.as_ref(), body)?;
+                chunk.lambdas.push(lambda);
+                chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+            },
+            Expression::Define(name, value) => self.compile_define(chunk, name, value)?,
+            Expression::Let(None, bindings, body) => {
+                let names: Vec<String> = bindings.iter().map(|b| b.0.clone()).collect();
+                let lambda = self.compile_lambda(None, &names, None, body)?;
+                chunk.lambdas.push(lambda);
+                chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+                self.compile_operands(chunk, bindings.iter().map(|b| &b.1), tail)?;
+            },
+            Expression::Let(Some(name), bindings, body) =>
+                self.compile_named_let(chunk, name, bindings, body, tail)?,
+            Expression::LetValues(bindings, body) => {
+                let bindings: Vec<_> = bindings.iter().map(|b| (&b.0, &b.1)).collect();
+                self.compile_let_values(chunk, &bindings, false, body, tail)?;
+            },
+            Expression::LetStarValues(bindings, body) => {
+                let bindings: Vec<_> = bindings.iter().map(|b| (&b.0, &b.1)).collect();
+                self.compile_let_values(chunk, &bindings, true, body, tail)?;
+            },
+            Expression::Receive(formals, value, body) =>
+                self.compile_let_values(chunk, &[(formals, value)], false, body, tail)?,
+            Expression::DefineValues(formals, value) => {
+                self.compile_bind_values(chunk, formals, value)?;
+                for name in formals_names(formals) {
+                    chunk.emit(Op::Dup);
+                    self.builtin(chunk, "%car");
+                    chunk.emit(Op::Swap);
+                    chunk.emit(Op::Call(1));
+                    self.define_variable(chunk, &name)?;
+                    self.builtin(chunk, "%cdr");
+                    chunk.emit(Op::Swap);
+                    chunk.emit(Op::Call(1));
+                }
+                chunk.emit(Op::Pop);
+                chunk.emit(Op::Unspecified);
+            },
+            Expression::Do(bindings, test, result, commands) =>
+                self.compile_do(chunk, bindings, test, result, commands, tail)?,
+            Expression::Set(name, value) => {
+                self.compile(chunk, value, false)?;
+                let op = match self.resolve(name)? {
+                    Some((depth, index)) => Op::SetLocal(depth, index),
+                    None => Op::SetGlobal(self.globals.slot(&self.namespace.global(name))),
+                };
+                chunk.emit(op);
+                chunk.emit(Op::Unspecified);
+            },
+            Expression::Delay(e) => self.compile_promise(chunk, e, true)?,
+            Expression::DelayForce(e) => self.compile_promise(chunk, e, false)?,
+            Expression::DefineRecordType(rt) => self.compile_record_type(chunk, rt)?,
+            Expression::Parameterize(bindings, body) => {
+                self.builtin(chunk, "%parameterize");
+                let thunk = self.compile_lambda(None, &[], None, body)?;
+                chunk.lambdas.push(thunk);
+                chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+                for (parameter, value) in bindings {
+                    self.compile(chunk, parameter, false)?;
+                    self.compile(chunk, value, false)?;
+                }
+                let n = 1 + 2 * bindings.len() as u32;
+                chunk.emit(if tail { Op::TailCall(n) } else { Op::Call(n) });
+            },
+            Expression::Guard(variable, clauses, else_clause, body) =>
+                self.compile_guard(chunk, variable, clauses, else_clause, body, tail)?,
+            Expression::Application(operator, operands) => {
+                self.compile(chunk, operator, false)?;
+                self.compile_operands(chunk, operands.iter(), tail)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Compiles the arguments of a call whose operator is already on the
+    /// stack, followed by the call itself.
+    fn compile_operands<'e, I>(&mut self, chunk: &mut Chunk, operands: I, tail: bool) -> Result<()>
+    where I: Iterator<Item=&'e Expression> {
+        let mut n = 0;
+        for e in operands {
+            self.compile(chunk, e, false)?;
+            n += 1;
+        }
+        chunk.emit(if tail { Op::TailCall(n) } else { Op::Call(n) });
+        Ok(())
+    }
+
+    fn compile_sequence(&mut self, chunk: &mut Chunk, es: &[Expression], tail: bool) -> Result<()> {
+        let (last, init) = match es.split_last() {
+            Some(x) => x,
+            None => {
+                chunk.emit(Op::Unspecified);
+                return Ok(());
+            },
+        };
+        for e in init {
+            self.compile(chunk, e, false)?;
+            chunk.emit(Op::Pop);
+        }
+        self.compile(chunk, last, tail)
+    }
+
+    fn compile_junction(&mut self, chunk: &mut Chunk, es: &[Expression], tail: bool,
+                        conjunction: bool) -> Result<()> {
+        let (last, init) = match es.split_last() {
+            Some(x) => x,
+            None => {
+                let c = chunk.constant(Value::Boolean(conjunction));
+                chunk.emit(Op::Const(c));
+                return Ok(());
+            },
+        };
+        let mut to_end = Vec::new();
+        for e in init {
+            self.compile(chunk, e, false)?;
+            to_end.push(chunk.emit(
+                if conjunction { Op::AndJump(0) } else { Op::OrJump(0) }));
+        }
+        self.compile(chunk, last, tail)?;
+        for at in to_end {
+            chunk.patch(at);
+        }
+        Ok(())
+    }
+
+    fn compile_cond(&mut self, chunk: &mut Chunk, clauses: &[CondClause],
+                    else_clause: &Option<Vec<Expression>>, tail: bool) -> Result<()> {
+        let to_end = self.compile_cond_clauses(chunk, clauses, tail)?;
+        match else_clause {
+            Some(body) => self.compile_sequence(chunk, body, tail)?,
+            None => { chunk.emit(Op::Unspecified); },
+        }
+        for at in to_end {
+            chunk.patch(at);
+        }
+        Ok(())
+    }
+
+    /// Compiles the clauses of a `cond`, falling through to the code that
+    /// follows when none applies. Returns the jumps to patch to the end.
+    fn compile_cond_clauses(&mut self, chunk: &mut Chunk, clauses: &[CondClause],
+                            tail: bool) -> Result<Vec<usize>> {
+        let mut to_end = Vec::new();
+        for clause in clauses {
+            match clause {
+                CondClause::Simple(test, body) => {
+                    self.compile(chunk, test, false)?;
+                    let to_next = chunk.emit(Op::JumpIfFalse(0));
+                    self.compile_sequence(chunk, body, tail)?;
+                    to_end.push(chunk.emit(Op::Jump(0)));
+                    chunk.patch(to_next);
+                },
+                CondClause::Binding(test, receiver) => {
+                    self.compile(chunk, test, false)?;
+                    chunk.emit(Op::Dup);
+                    let to_next = chunk.emit(Op::JumpIfFalse(0));
+                    self.compile(chunk, receiver, false)?;
+                    chunk.emit(Op::Swap);
+                    chunk.emit(if tail { Op::TailCall(1) } else { Op::Call(1) });
+                    to_end.push(chunk.emit(Op::Jump(0)));
+                    chunk.patch(to_next);
+                    chunk.emit(Op::Pop);
+                },
+                CondClause::Inconsequential(test) => {
+                    self.compile(chunk, test, false)?;
+                    to_end.push(chunk.emit(Op::OrJump(0)));
+                },
+            }
+        }
+        Ok(to_end)
+    }
+
+    /// `(%guard body handler)` runs the body thunk, and calls the handler
+    /// with the condition if one is raised. The handler raises it again with
+    /// `raise-continuable` when no clause applies.
+    fn compile_guard(&mut self, chunk: &mut Chunk, variable: &str, clauses: &[CondClause],
+                     else_clause: &Option<Vec<Expression>>, body: &[Expression],
+                     tail: bool) -> Result<()> {
+        self.builtin(chunk, "%guard");
+        let thunk = self.compile_lambda(None, &[], None, body)?;
+        chunk.lambdas.push(thunk);
+        chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+        let mut handler = Chunk::new(None, Arity::exactly(1), 1);
+        self.scopes.push(vec![variable.to_string()]);
+        let res = self.compile_guard_handler(&mut handler, clauses, else_clause);
+        self.scopes.pop();
+        res?;
+        handler.emit(Op::Return);
+        chunk.lambdas.push(Rc::new(handler));
+        chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+        chunk.emit(if tail { Op::TailCall(2) } else { Op::Call(2) });
+        Ok(())
+    }
+
+    fn compile_guard_handler(&mut self, chunk: &mut Chunk, clauses: &[CondClause],
+                             else_clause: &Option<Vec<Expression>>) -> Result<()> {
+        let to_end = self.compile_cond_clauses(chunk, clauses, true)?;
+        match else_clause {
+            Some(body) => self.compile_sequence(chunk, body, true)?,
+            None => {
+                self.builtin(chunk, "%raise-continuable");
+                chunk.emit(Op::Local(0, 0));
+                chunk.emit(Op::TailCall(1));
+            },
+        }
+        for at in to_end {
+            chunk.patch(at);
+        }
+        Ok(())
+    }
+
+    fn compile_case(&mut self, chunk: &mut Chunk, key: &Expression, clauses: &[CaseClause],
+                    else_clause: &Option<ClauseBody>, tail: bool) -> Result<()> {
+        // A receiver is called with the key, so the key stays on the stack
+        // for every clause if any clause has one.
+        let keep = clauses.iter().map(|c| &c.1).chain(else_clause)
+            .any(|b| matches!(b, ClauseBody::Receiver(_)));
+        self.compile(chunk, key, false)?;
+        if keep {
+            chunk.emit(Op::Dup);
+        }
+        chunk.case_tables.push(CaseTable {
+            targets: HashMap::new(),
+            default: 0,
+        });
+        let table = chunk.case_tables.len() - 1;
+        chunk.emit(Op::CaseJump(table as u32));
+        let mut to_end = Vec::new();
+        for (data, body) in clauses {
+            let target = chunk.here();
+            for datum in data {
+                if let Some(k) = CaseKey::from_quotation(datum) {
+                    chunk.case_tables[table].targets.entry(k).or_insert(target);
+                }
+            }
+            self.compile_case_body(chunk, body, keep, tail)?;
+            to_end.push(chunk.emit(Op::Jump(0)));
+        }
+        chunk.case_tables[table].default = chunk.here();
+        match else_clause {
+            Some(body) => self.compile_case_body(chunk, body, keep, tail)?,
+            None => {
+                if keep {
+                    chunk.emit(Op::Pop);
+                }
+                chunk.emit(Op::Unspecified);
+            },
+        }
+        for at in to_end {
+            chunk.patch(at);
+        }
+        Ok(())
+    }
+
+    /// Compiles the body of a `case` clause, with the key on the stack if
+    /// `keep` is set.
+    fn compile_case_body(&mut self, chunk: &mut Chunk, body: &ClauseBody, keep: bool,
+                         tail: bool) -> Result<()> {
+        match body {
+            ClauseBody::Sequence(es) => {
+                if keep {
+                    chunk.emit(Op::Pop);
+                }
+                self.compile_sequence(chunk, es, tail)
+            },
+            ClauseBody::Receiver(receiver) => {
+                self.compile(chunk, receiver, false)?;
+                chunk.emit(Op::Swap);
+                chunk.emit(if tail { Op::TailCall(1) } else { Op::Call(1) });
+                Ok(())
+            },
+        }
+    }
+
+    fn compile_define(&mut self, chunk: &mut Chunk, name: &str, value: &Expression) -> Result<()> {
+        match value {
+            Expression::Lambda(formals, body) => {
+                let lambda = self.compile_lambda(
+                    Some(name.to_string()), &formals.required, formals.rest.as_ref(), body)?;
+                chunk.lambdas.push(lambda);
+                chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+            },
+            _ => self.compile(chunk, value, false)?,
+        }
+        self.define_variable(chunk, name)?;
+        chunk.emit(Op::Unspecified);
+        Ok(())
+    }
+
+    /// Pops the value on the stack into the variable `name` that is being
+    /// defined.
+    fn define_variable(&mut self, chunk: &mut Chunk, name: &str) -> Result<()> {
+        let op = self.definition(name, self.scopes.len())?;
+        chunk.emit(op);
+        Ok(())
+    }
+
+    /// The instruction that defines `name` in the body whose scope is the
+    /// first `depth` of the current scopes, from code in the innermost one.
+    fn definition(&mut self, name: &str, depth: usize) -> Result<Op> {
+        if depth == 0 {
+            return Ok(Op::DefineGlobal(self.globals.slot(&self.namespace.global(name))));
+        }
+        match self.scopes[depth - 1].iter().rposition(|n| n == name) {
+            Some(index) => {
+                let (depth, index) = local(self.scopes.len() - depth, index)?;
+                Ok(Op::SetLocal(depth, index))
+            },
+            None => Err(RuntimeError::Basic(
+                format!("define: {} is not at the start of a body", name))),
+        }
+    }
+
+    /// Pushes a builtin procedure that generated code relies on. It is
+    /// looked up by its global name rather than through the namespace, so
+    /// that it need not be imported.
+    fn builtin(&mut self, chunk: &mut Chunk, name: &str) {
+        chunk.emit(Op::Global(self.globals.slot(name)));
+    }
+
+    /// Makes the record type descriptor and passes it to a generated
+    /// procedure, which defines the type's name and procedures in the
+    /// enclosing body. The procedures refer to the descriptor in that
+    /// procedure's frame, so they keep working if the name is redefined.
+    fn compile_record_type(&mut self, chunk: &mut Chunk, rt: &RecordType) -> Result<()> {
+        let depth = self.scopes.len();
+        let mut definer = Chunk::new(None, Arity::exactly(1), 1);
+        // The descriptor is only referred to by its slot.
+        self.scopes.push(vec![String::new()]);
+        let res = self.compile_record_definitions(&mut definer, rt, depth);
+        self.scopes.pop();
+        res?;
+        definer.emit(Op::Unspecified);
+        definer.emit(Op::Return);
+        chunk.lambdas.push(Rc::new(definer));
+        chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+        self.builtin(chunk, "%record-type");
+        let name = chunk.constant(Value::Symbol(Rc::from(rt.name.as_str())));
+        chunk.emit(Op::Const(name));
+        let fields = rt.fields.iter().map(|f| Value::Symbol(Rc::from(f.name.as_str()))).collect();
+        let fields = chunk.constant(self.heap.list(fields));
+        chunk.emit(Op::Const(fields));
+        chunk.emit(Op::Call(2));
+        chunk.emit(Op::Call(1));
+        Ok(())
+    }
+
+    fn compile_record_definitions(&mut self, chunk: &mut Chunk, rt: &RecordType,
+                                  depth: usize) -> Result<()> {
+        chunk.emit(Op::Local(0, 0));
+        let op = self.definition(&rt.name, depth)?;
+        chunk.emit(op);
+        let (ref constructor, ref args) = rt.constructor;
+        let slots: Vec<Option<u16>> = rt.fields.iter()
+            .map(|f| args.iter().position(|a| *a == f.name).map(|i| local(0, i).map(|l| l.1)).transpose())
+            .collect::<Result<_>>()?;
+        self.record_procedure(chunk, depth, constructor, args.len(), "%make-record", |chunk| {
+            for slot in &slots {
+                chunk.emit(match slot {
+                    Some(i) => Op::Local(0, *i),
+                    None => Op::Unspecified,
+                });
+            }
+            slots.len()
+        })?;
+        self.record_procedure(chunk, depth, &rt.predicate, 1, "%record?", |chunk| {
+            chunk.emit(Op::Local(0, 0));
+            1
+        })?;
+        for (k, field) in rt.fields.iter().enumerate() {
+            let k = Value::Number(k as i32);
+            self.record_procedure(chunk, depth, &field.accessor, 1, "%record-ref", |chunk| {
+                chunk.emit(Op::Local(0, 0));
+                let k = chunk.constant(k.clone());
+                chunk.emit(Op::Const(k));
+                2
+            })?;
+            if let Some(ref modifier) = field.modifier {
+                self.record_procedure(chunk, depth, modifier, 2, "%record-set!", |chunk| {
+                    chunk.emit(Op::Local(0, 0));
+                    let k = chunk.constant(k.clone());
+                    chunk.emit(Op::Const(k));
+                    chunk.emit(Op::Local(0, 1));
+                    3
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Defines `name` as a procedure of `arity` arguments that calls the
+    /// builtin with the record type descriptor and the values that `args`
+    /// pushes into the procedure's chunk, returning how many.
+    fn record_procedure<F>(&mut self, chunk: &mut Chunk, depth: usize, name: &str, arity: usize,
+                           builtin: &str, args: F) -> Result<()>
+    where F: FnOnce(&mut Chunk) -> usize {
+        let mut procedure = Chunk::new(Some(name.to_string()), Arity::exactly(arity), arity);
+        self.builtin(&mut procedure, builtin);
+        procedure.emit(Op::Local(1, 0));
+        let n = args(&mut procedure);
+        procedure.emit(Op::TailCall(1 + n as u32));
+        procedure.emit(Op::Return);
+        chunk.lambdas.push(Rc::new(procedure));
+        chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+        let op = self.definition(name, depth)?;
+        chunk.emit(op);
+        Ok(())
+    }
+
+    fn compile_lambda(&mut self, name: Option<String>, required: &[String],
+                      rest: Option<&String>, body: &[Expression]) -> Result<Rc<Chunk>> {
+        let mut names = required.to_vec();
+        let arity = match rest {
+            Some(rest) => {
+                names.push(rest.clone());
+                Arity::at_least(required.len())
+            },
+            None => Arity::exactly(required.len()),
+        };
+        body_definitions(body, &mut names);
+        let mut chunk = Chunk::new(name, arity, names.len());
+        self.scopes.push(names);
+        let res = self.compile_sequence(&mut chunk, body, true);
+        self.scopes.pop();
+        res?;
+        chunk.emit(Op::Return);
+        Ok(Rc::new(chunk))
+    }
+
+    /// Applies a procedure whose parameters are the variables of the
+    /// bindings to their values. In a `let*-values` each binding but the
+    /// last gets a procedure of its own, nested in that of the one before.
+    fn compile_let_values(&mut self, chunk: &mut Chunk, bindings: &[(&Formals, &Expression)],
+                          sequential: bool, body: &[Expression], tail: bool) -> Result<()> {
+        let split = if sequential { bindings.len().min(1) } else { bindings.len() };
+        let (now, later) = bindings.split_at(split);
+        let mut names: Vec<String> = now.iter().flat_map(|b| formals_names(b.0)).collect();
+        let params = names.len();
+        if later.is_empty() {
+            body_definitions(body, &mut names);
+        }
+        let mut lambda = Chunk::new(None, Arity::exactly(params), names.len());
+        self.scopes.push(names);
+        let res = if later.is_empty() {
+            self.compile_sequence(&mut lambda, body, true)
+        } else {
+            self.compile_let_values(&mut lambda, later, true, body, true)
+        };
+        self.scopes.pop();
+        res?;
+        lambda.emit(Op::Return);
+        if now.is_empty() {
+            chunk.lambdas.push(Rc::new(lambda));
+            chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+            chunk.emit(if tail { Op::TailCall(0) } else { Op::Call(0) });
+            return Ok(());
+        }
+        self.builtin(chunk, "%apply");
+        chunk.lambdas.push(Rc::new(lambda));
+        chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+        if now.len() > 1 {
+            self.builtin(chunk, "%append");
+        }
+        for (formals, value) in now {
+            self.compile_bind_values(chunk, formals, value)?;
+        }
+        if now.len() > 1 {
+            chunk.emit(Op::Call(now.len() as u32));
+        }
+        chunk.emit(if tail { Op::TailCall(2) } else { Op::Call(2) });
+        Ok(())
+    }
+
+    /// Pushes the list of the values of the variables of `formals`, whose
+    /// rest variable gets a list of the remaining values.
+    fn compile_bind_values(&mut self, chunk: &mut Chunk, formals: &Formals,
+                           value: &Expression) -> Result<()> {
+        self.builtin(chunk, "%bind-values");
+        self.compile(chunk, value, false)?;
+        let required = chunk.constant(Value::Number(formals.required.len() as i32));
+        chunk.emit(Op::Const(required));
+        let rest = chunk.constant(Value::Boolean(formals.rest.is_some()));
+        chunk.emit(Op::Const(rest));
+        chunk.emit(Op::Call(3));
+        Ok(())
+    }
+
+    /// `(let name ((v e) ...) body)` binds `name` to the loop procedure in
+    /// a frame of its own, so the initialisers cannot see it.
+    fn compile_named_let(&mut self, chunk: &mut Chunk, name: &str, bindings: &[Binding],
+                         body: &[Expression], tail: bool) -> Result<()> {
+        let names: Vec<String> = bindings.iter().map(|b| b.0.clone()).collect();
+        let mut binder = Chunk::new(None, Arity::exactly(0), 1);
+        self.scopes.push(vec![name.to_string()]);
+        let res = self.compile_lambda(Some(name.to_string()), &names, None, body);
+        self.scopes.pop();
+        binder.lambdas.push(res?);
+        binder.emit(Op::Closure(0));
+        binder.emit(Op::SetLocal(0, 0));
+        binder.emit(Op::Local(0, 0));
+        binder.emit(Op::Return);
+        chunk.lambdas.push(Rc::new(binder));
+        chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+        chunk.emit(Op::Call(0));
+        self.compile_operands(chunk, bindings.iter().map(|b| &b.1), tail)
+    }
+
+    /// `(do ((v init step) ...) (test result ...) command ...)` is a loop
+    /// procedure bound in a frame of its own, as for a named `let`, which
+    /// takes the variables as arguments.
+    fn compile_do(&mut self, chunk: &mut Chunk, bindings: &[DoBinding], test: &Expression,
+                  result: &[Expression], commands: &[Expression], tail: bool) -> Result<()> {
+        let names: Vec<String> = bindings.iter().map(|b| b.0.clone()).collect();
+        let mut binder = Chunk::new(None, Arity::exactly(0), 1);
+        // The loop procedure is only referred to by its slot, so its frame
+        // has an empty name, which no identifier can resolve to.
+        self.scopes.push(vec![String::new()]);
+        self.scopes.push(names.clone());
+        let mut body = Chunk::new(Some("do".to_string()), Arity::exactly(names.len()), names.len());
+        let res = self.compile_do_body(&mut body, bindings, test, result, commands);
+        self.scopes.pop();
+        self.scopes.pop();
+        res?;
+        binder.lambdas.push(Rc::new(body));
+        binder.emit(Op::Closure(0));
+        binder.emit(Op::SetLocal(0, 0));
+        binder.emit(Op::Local(0, 0));
+        binder.emit(Op::Return);
+        chunk.lambdas.push(Rc::new(binder));
+        chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+        chunk.emit(Op::Call(0));
+        self.compile_operands(chunk, bindings.iter().map(|b| &b.1), tail)
+    }
+
+    fn compile_do_body(&mut self, chunk: &mut Chunk, bindings: &[DoBinding], test: &Expression,
+                       result: &[Expression], commands: &[Expression]) -> Result<()> {
+        self.compile(chunk, test, false)?;
+        let to_commands = chunk.emit(Op::JumpIfFalse(0));
+        self.compile_sequence(chunk, result, true)?;
+        chunk.emit(Op::Return);
+        chunk.patch(to_commands);
+        for e in commands {
+            self.compile(chunk, e, false)?;
+            chunk.emit(Op::Pop);
+        }
+        chunk.emit(Op::Local(1, 0));
+        for (i, b) in bindings.iter().enumerate() {
+            match b.2 {
+                Some(ref step) => self.compile(chunk, step, false)?,
+                None => {
+                    let (depth, index) = local(0, i)?;
+                    chunk.emit(Op::Local(depth, index));
+                },
+            }
+        }
+        chunk.emit(Op::TailCall(bindings.len() as u32));
+        chunk.emit(Op::Return);
+        Ok(())
+    }
+
+    /// `(delay e)` and `(delay-force e)` make a promise of a thunk. The
+    /// thunk of `delay` wraps the value of `e` in a forced promise, so that
+    /// `force` can treat both alike.
+    fn compile_promise(&mut self, chunk: &mut Chunk, e: &Expression, delay: bool) -> Result<()> {
+        let mut thunk = Chunk::new(None, Arity::exactly(0), 0);
+        self.scopes.push(Vec::new());
+        let res = self.compile(&mut thunk, e, !delay);
+        self.scopes.pop();
+        res?;
+        if delay {
+            thunk.emit(Op::MakePromise(true));
+        }
+        thunk.emit(Op::Return);
+        chunk.lambdas.push(Rc::new(thunk));
+        chunk.emit(Op::Closure((chunk.lambdas.len() - 1) as u32));
+        chunk.emit(Op::MakePromise(false));
+        Ok(())
+    }
+}
+
+/// `(dynamic-wind before thunk after)`, written directly in bytecode since
+/// it needs the winder instructions.
+pub fn dynamic_wind() -> Chunk {
+    let mut chunk = Chunk::new(Some("dynamic-wind".to_string()), Arity::exactly(3), 3);
+    for &op in &[
+        Op::Local(0, 0), Op::Call(0), Op::Pop,
+        Op::Local(0, 0), Op::Local(0, 2), Op::PushWinder,
+        Op::Local(0, 1), Op::Call(0),
+        Op::PopWinder,
+        Op::Local(0, 2), Op::Call(0), Op::Pop,
+        Op::Return,
+    ] {
+        chunk.emit(op);
+    }
+    chunk
+}
+
+/// Renders a chunk and the lambdas nested in it as annotated assembly.
+pub fn disassemble(chunk: &Chunk, globals: &Globals, heap: &Heap) -> String {
+    let mut out = String::new();
+    disassemble_into(&mut out, chunk, globals, heap, "toplevel");
+    out
+}
+
+fn disassemble_into(out: &mut String, chunk: &Chunk, globals: &Globals, heap: &Heap,
+                    path: &str) {
+    let _ = writeln!(out, "== {} {} (arity {}{}, frame {}) ==",
+                     path, chunk.name.as_ref().map_or("", |n| n.as_str()),
+                     chunk.arity.min, if chunk.arity.max.is_none() { "+" } else { "" },
+                     chunk.frame_size);
+    for (pc, op) in chunk.code.iter().enumerate() {
+        let _ = match op {
+            Op::Const(c) =>
+                writeln!(out, "{:04} {:?}\t; {}", pc, op,
+                         heap.write(&chunk.constants[*c as usize])),
+            Op::Global(g) | Op::DefineGlobal(g) | Op::SetGlobal(g) =>
+                writeln!(out, "{:04} {:?}\t; {}", pc, op, globals.name(*g)),
+            Op::CaseJump(t) => {
+                let table = &chunk.case_tables[*t as usize];
+                let mut entries: Vec<_> = table.targets.iter()
+                    .map(|(k, target)| format!("{:?} -> {}", k, target)).collect();
+                entries.sort();
+                writeln!(out, "{:04} {:?}\t; {} else -> {}", pc, op,
+                         entries.join(", "), table.default)
+            },
+            Op::Closure(l) =>
+                writeln!(out, "{:04} {:?}\t; {}/{}", pc, op, path, l),
+            _ => writeln!(out, "{:04} {:?}", pc, op),
+        };
+    }
+    for (i, lambda) in chunk.lambdas.iter().enumerate() {
+        disassemble_into(out, lambda, globals, heap, &format!("{}/{}", path, i));
+    }
+}
@@ -0,0 +1,324 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use lexer::{Literal, RegexLexer};
+use library::{Loader, LibraryError};
+use parser::{Node, SpannedParser};
+use scheme::parse_expression;
+use serialize::Serialize;
+use value::Value;
+use vm::Vm;
+
+/// The result of one test in a conformance file.
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub chapter: String,
+    pub line: usize,
+    /// Why the test failed, or `None` if it passed.
+    pub failure: Option<String>,
+    /// Whether the test was marked with `test-expect-fail`.
+    pub expected_failure: bool,
+}
+
+impl Outcome {
+    /// Why the outcome is not the one expected: the failure of a test
+    /// expected to pass, or the pass of one expected to fail.
+    pub fn unexpected(&self) -> Option<String> {
+        match (&self.failure, self.expected_failure) {
+            (Some(f), false) => Some(f.clone()),
+            (None, true) => Some("passed, but was expected to fail".to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Output that `display` and `write` send to a buffer the runner reads.
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn string(node: &Node) -> Option<&str> {
+    match node {
+        Node::Literal(Literal::String(s)) => Some(s),
+        _ => None,
+    }
+}
+
+/// The printed data that `text` reads as, separated by spaces, or the code
+/// of the error reading it.
+fn read(text: &str) -> Result<String, &'static str> {
+    let mut res = Vec::new();
+    for datum in SpannedParser::new(text) {
+        res.push(datum.map_err(|e| e.code)?.0.to_sexpr());
+    }
+    Ok(res.join(" "))
+}
+
+fn tokens(text: &str) -> String {
+    RegexLexer::new(text)
+        .map(|tok| tok.map_or_else(|e| format!("<{}>", e), |tok| tok.to_sexpr()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn check(expected: &str, got: &str) -> Option<String> {
+    if expected == got {
+        None
+    } else {
+        Some(format!("expected {}, got {}", expected, got))
+    }
+}
+
+fn error_code(e: &LibraryError) -> Option<&'static str> {
+    match e {
+        LibraryError::Syntax(e) => Some(e.code),
+        _ => None,
+    }
+}
+
+/// Runs the tests in a conformance file. Test forms are
+///
+/// - `(test-begin "chapter")` and `(test-end)`, which group the tests
+///   between them into a chapter of the report;
+/// - `(test-expect-fail n)`, which marks the next `n` tests as known
+///   failures;
+/// - `(test-tokens "tokens" "text")`, `(test-read "data" "text")` and
+///   `(test-read-error "code" "text")`, for the lexer and the reader;
+/// - `(test-expand "expression" form)` and `(test-syntax-error "code"
+///   form)`, for syntax analysis;
+/// - `(test expected expression)`, which compares values with `equal?`,
+///   `(test-output "text" expression)` and `(test-error expression)`.
+///
+/// Any other form is run, and is a failed test only if it fails.
+pub fn run(text: &str, dir: &Path) -> Result<Vec<Outcome>, String> {
+    let mut vm = Vm::new();
+    let output = Rc::new(RefCell::new(Vec::new()));
+    vm.set_output(Box::new(Capture(output.clone())));
+    let mut loader = Loader::new(&vm, true);
+    let mut chapter = String::new();
+    let mut expect_fail = 0;
+    let mut res = Vec::new();
+    for datum in SpannedParser::new(text) {
+        let (node, spans) = datum.map_err(|e| e.to_string())?;
+        let line = text[..spans.span.start].matches('\n').count() + 1;
+        let (keyword, args) = match node {
+            Node::List(ref items) => match items.split_first() {
+                Some((Node::Identifier(k), args)) if k == "test" || k.starts_with("test-") =>
+                    (k.as_str(), args),
+                _ => ("", &[][..]),
+            },
+            _ => ("", &[][..]),
+        };
+        let failure = match (keyword, args) {
+            ("test-begin", [name]) => {
+                chapter = string(name).unwrap_or_default().to_string();
+                continue;
+            },
+            ("test-end", []) => {
+                chapter = String::new();
+                continue;
+            },
+            ("test-expect-fail", [Node::Literal(Literal::Number(n))]) if *n > 0 => {
+                expect_fail = *n;
+                continue;
+            },
+            ("test-tokens", [expected, text]) => match (string(expected), string(text)) {
+                (Some(expected), Some(text)) => check(expected, &tokens(text)),
+                _ => Some("malformed test".to_string()),
+            },
+            ("test-read", [expected, text]) => match (string(expected), string(text)) {
+                (Some(expected), Some(text)) => match read(text) {
+                    Ok(got) => check(expected, &got),
+                    Err(code) => Some(format!("expected {}, got error {}", expected, code)),
+                },
+                _ => Some("malformed test".to_string()),
+            },
+            ("test-read-error", [code, text]) => match (string(code), string(text)) {
+                (Some(code), Some(text)) => match read(text) {
+                    Ok(got) => Some(format!("expected error {}, read {}", code, got)),
+                    Err(got) => check(code, got),
+                },
+                _ => Some("malformed test".to_string()),
+            },
+            ("test-expand", [expected, form]) => match string(expected) {
+                Some(expected) => match parse_expression(form.clone()) {
+                    Ok(e) => check(expected, &e.to_sexpr()),
+                    Err(e) => Some(format!("expected {}, got error {}", expected, e.code)),
+                },
+                None => Some("malformed test".to_string()),
+            },
+            ("test-syntax-error", [code, form]) => match string(code) {
+                Some(code) => match parse_expression(form.clone()) {
+                    Ok(e) => Some(format!("expected error {}, got {}", code, e.to_sexpr())),
+                    Err(e) => check(code, e.code),
+                },
+                None => Some("malformed test".to_string()),
+            },
+            ("test", [expected, expr]) => {
+                // Both values are compared in one form, so that neither is
+                // collected before the other is computed.
+                let compare = Node::List(vec![
+                    Node::Identifier("let".to_string()),
+                    Node::List(vec![
+                        Node::List(vec![Node::Identifier("%expected".to_string()), expected.clone()]),
+                        Node::List(vec![Node::Identifier("%got".to_string()), expr.clone()]),
+                    ]),
+                    Node::List(vec![
+                        Node::Identifier("if".to_string()),
+                        Node::List(vec![Node::Identifier("equal?".to_string()),
+                                        Node::Identifier("%expected".to_string()),
+                                        Node::Identifier("%got".to_string())]),
+                        Node::Literal(Literal::Boolean(true)),
+                        Node::List(vec![Node::Identifier("vector".to_string()),
+                                        Node::Identifier("%expected".to_string()),
+                                        Node::Identifier("%got".to_string())]),
+                    ]),
+                ]);
+                match loader.eval(&mut vm, compare, dir) {
+                    Ok(Value::Vector(r)) => {
+                        let items = vm.heap().items(r);
+                        Some(format!("expected {}, got {}", vm.write(&items[0]), vm.write(&items[1])))
+                    },
+                    Ok(_) => None,
+                    Err(e) => Some(e.to_string()),
+                }
+            },
+            ("test-output", [expected, expr]) => match string(expected) {
+                Some(expected) => {
+                    output.borrow_mut().clear();
+                    match loader.eval(&mut vm, expr.clone(), dir) {
+                        Ok(_) => check(expected, &String::from_utf8_lossy(&output.borrow())),
+                        Err(e) => Some(e.to_string()),
+                    }
+                },
+                None => Some("malformed test".to_string()),
+            },
+            ("test-error", [expr]) => match loader.eval(&mut vm, expr.clone(), dir) {
+                Ok(v) => Some(format!("expected an error, got {}", vm.write(&v))),
+                Err(ref e) if error_code(e).is_some() =>
+                    Some(format!("expected a runtime error, got {}", e)),
+                Err(_) => None,
+            },
+            ("", _) => match loader.eval(&mut vm, node.clone(), dir) {
+                Ok(_) if expect_fail == 0 => continue,
+                Ok(_) => None,
+                Err(e) => Some(e.to_string()),
+            },
+            _ => Some("malformed test".to_string()),
+        };
+        res.push(Outcome { chapter: chapter.clone(), line, failure, expected_failure: expect_fail > 0 });
+        expect_fail = (expect_fail - 1).max(0);
+    }
+    Ok(res)
+}
+
+/// A table of the tests passed, failed and failed as expected in each
+/// chapter, in the order the chapters first appear, with totals.
+pub fn report(outcomes: &[Outcome]) -> String {
+    let mut chapters: Vec<(&str, [usize; 3])> = Vec::new();
+    for o in outcomes {
+        let name = if o.chapter.is_empty() { "(no chapter)" } else { &o.chapter };
+        let i = match chapters.iter().position(|c| c.0 == name) {
+            Some(i) => i,
+            None => {
+                chapters.push((name, [0; 3]));
+                chapters.len() - 1
+            },
+        };
+        let column = match (&o.failure, o.expected_failure) {
+            (None, _) => 0,
+            (Some(_), false) => 1,
+            (Some(_), true) => 2,
+        };
+        chapters[i].1[column] += 1;
+    }
+    let mut total = [0; 3];
+    for c in &chapters {
+        for (t, n) in total.iter_mut().zip(&c.1) {
+            *t += n;
+        }
+    }
+    chapters.push(("total", total));
+    let width = chapters.iter().map(|c| c.0.chars().count()).max().unwrap_or(0);
+    let mut out = format!("{:width$}  {:>5}  {:>5}  {:>5}\n", "chapter", "pass", "fail", "xfail",
+                          width = width);
+    for (name, [passed, failed, expected]) in chapters {
+        out.push_str(&format!("{:width$}  {:>5}  {:>5}  {:>5}\n", name, passed, failed, expected,
+                              width = width));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use super::{report, run, Outcome};
+
+    #[test]
+    fn runner() {
+        let text = "\
+(test-begin \"numbers\")
+(test 3 (+ 1 2))
+(test 4 (+ 1 2))
+(define x 5)
+(test-output \"5\" (display x))
+(test-error (car 1))
+(test-end)
+(test-read \"(a . b) 1\" \"(a . b) ; c\\n 1\")
+(test-read-error \"S0102\" \"(a\")
+(test-syntax-error \"S0002\" (if))
+(test-expand \"(if a b)\" (when))
+(test-expect-fail 2)
+(test 1 (car (list 2)))
+(test 1 (car (list 1)))
+";
+        let outcomes = run(text, Path::new(".")).unwrap();
+        let unexpected: Vec<_> = outcomes.iter()
+            .filter_map(|o| o.unexpected().map(|f| (o.line, f)))
+            .collect();
+        assert_eq!(unexpected, vec![
+            (3, "expected 4, got 3".to_string()),
+            (11, "expected (if a b), got error S0002".to_string()),
+            (14, "passed, but was expected to fail".to_string()),
+        ]);
+        assert_eq!(outcomes[0],
+                   Outcome { chapter: "numbers".to_string(), line: 2, failure: None, expected_failure: false });
+        assert_eq!(report(&outcomes), "\
+chapter        pass   fail  xfail
+numbers           3      1      0
+(no chapter)      4      1      1
+total             7      2      1
+");
+    }
+
+    #[test]
+    fn suite() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("conformance");
+        let mut outcomes = Vec::new();
+        let mut failures = String::new();
+        let mut entries: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+        entries.sort();
+        for path in entries {
+            let text = fs::read_to_string(&path).unwrap();
+            for o in run(&text, &dir).unwrap() {
+                if let Some(f) = o.unexpected() {
+                    failures.push_str(&format!("{}:{}: {}\n", path.display(), o.line, f));
+                }
+                outcomes.push(o);
+            }
+        }
+        assert!(failures.is_empty(), "{}{}", failures, report(&outcomes));
+    }
+}
@@ -0,0 +1,318 @@
+use std::env;
+use std::io::{self, IsTerminal};
+
+use json::Json;
+use lexer::Span;
+use parser::{Node, ReadError, Spans};
+use scheme::SchemeError;
+
+/// An error to report to the user, with the spans of source text it is
+/// about. Each label is a span and what is wrong there; the label text may
+/// be empty. Syntax errors have a code that `--explain` describes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub primary: Option<(Span, String)>,
+    pub secondary: Vec<(Span, String)>,
+}
+
+impl Diagnostic {
+    /// A diagnostic without a location.
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        Diagnostic {
+            code: None,
+            message: message.into(),
+            primary: None,
+            secondary: Vec::new(),
+        }
+    }
+
+    pub fn at<S: Into<String>>(message: S, span: Span, label: &str) -> Self {
+        Diagnostic {
+            code: None,
+            message: message.into(),
+            primary: Some((span, label.to_string())),
+            secondary: Vec::new(),
+        }
+    }
+
+    pub fn from_read_error(e: &ReadError) -> Self {
+        let label = match e.code {
+            "S0101" => "no matching left parenthesis",
+            "S0102" => "this parenthesis is never closed",
+            _ => "",
+        };
+        Diagnostic {
+            code: Some(e.code),
+            ..Diagnostic::at(e.message, e.span, label)
+        }
+    }
+
+    /// Locates the labels of a syntax error in the data it was found in.
+    /// A datum that occurs more than once is taken to be its first
+    /// occurrence, except that the primary label prefers an occurrence
+    /// inside a secondary label and distinct from the secondary labels.
+    pub fn from_scheme_error(e: &SchemeError, data: &[(&Node, &Spans)]) -> Self {
+        let occurrences = |target: &Node| {
+            let mut out = Vec::new();
+            for &(node, spans) in data {
+                spans.find(node, target, &mut out);
+            }
+            out
+        };
+        let mut d = Diagnostic {
+            code: Some(e.code),
+            ..Diagnostic::new(e.message.clone())
+        };
+        let (primary, secondary) = match e.labels.split_first() {
+            Some(split) => split,
+            None => return d,
+        };
+        for label in secondary {
+            if let Some(&span) = occurrences(&label.node).first() {
+                d.secondary.push((span, label.message.clone()));
+            }
+        }
+        let candidates = occurrences(&primary.node);
+        let distinct = |s: &&Span| d.secondary.iter().all(|l| l.0 != **s);
+        let inside = |s: &&Span| d.secondary.iter().any(|l| l.0.start <= s.start && s.end <= l.0.end);
+        let span = candidates.iter().find(|s| distinct(s) && inside(s))
+            .or_else(|| candidates.iter().find(distinct))
+            .or_else(|| candidates.first());
+        d.primary = span.map(|&span| (span, primary.message.clone()));
+        d
+    }
+}
+
+/// The line and column, both counted from 1, of byte `offset` in `text`.
+/// Columns count characters.
+pub fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(text.len());
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+/// Whether to colour diagnostics written to stderr: only on a terminal,
+/// and not if NO_COLOR is set or TERM is dumb.
+pub fn detect_colour() -> bool {
+    io::stderr().is_terminal()
+        && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        && env::var("TERM").map(|t| t != "dumb").unwrap_or(true)
+}
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders `d` for a person to read, quoting the lines of `text` it is
+/// about and underlining the labelled spans: `^` for the primary label and
+/// `-` for secondary ones.
+pub fn render(d: &Diagnostic, file: &str, text: &str, colour: bool) -> String {
+    let paint = |code: &str, s: &str| if colour { format!("{}{}{}", code, s, RESET) } else { s.to_string() };
+    let error = match d.code {
+        Some(code) => format!("error[{}]", code),
+        None => "error".to_string(),
+    };
+    let mut out = format!("{}{}\n", paint(RED, &error), paint(BOLD, &format!(": {}", d.message)));
+    let mut labels: Vec<(usize, usize, usize, bool, &str)> = Vec::new();
+    for (i, &(span, ref label)) in d.primary.iter().chain(&d.secondary).enumerate() {
+        let (line, col) = line_col(text, span.start);
+        let (end_line, end_col) = line_col(text, span.end);
+        // Spans that run past the end of the line are cut off there.
+        let end = if end_line > line {
+            text.lines().nth(line - 1).unwrap_or("").chars().count() + 1
+        } else {
+            end_col
+        };
+        let width = end.saturating_sub(col).max(1);
+        labels.push((line, col, width, i == 0 && d.primary.is_some(), label));
+    }
+    let gutter = labels.iter().map(|l| l.0.to_string().len()).max().unwrap_or(1);
+    let pad = " ".repeat(gutter);
+    match labels.first() {
+        Some(&(line, col, _, _, _)) =>
+            out += &format!("{}{} {}:{}:{}\n", pad, paint(BLUE, "-->"), file, line, col),
+        None => {
+            out += &format!("{}{} {}\n", pad, paint(BLUE, "-->"), file);
+            return out;
+        },
+    }
+    out += &format!("{} {}\n", pad, paint(BLUE, "|"));
+    let mut lines: Vec<usize> = labels.iter().map(|l| l.0).collect();
+    lines.sort();
+    lines.dedup();
+    for line in lines {
+        let line_text = text.lines().nth(line - 1).unwrap_or("").replace('\t', " ");
+        out += &format!("{} {} {}\n", paint(BLUE, &format!("{:>w$}", line, w = gutter)),
+                        paint(BLUE, "|"), line_text);
+        let mut on_line: Vec<_> = labels.iter().filter(|l| l.0 == line).collect();
+        on_line.sort_by_key(|l| l.1);
+        for &&(_, col, width, primary, label) in &on_line {
+            let (code, mark) = if primary { (RED, "^") } else { (BLUE, "-") };
+            let mut underline = mark.repeat(width);
+            if !label.is_empty() {
+                underline = format!("{} {}", underline, label);
+            }
+            out += &format!("{} {} {}{}\n", pad, paint(BLUE, "|"), " ".repeat(col - 1),
+                            paint(code, &underline));
+        }
+    }
+    if let Some(code) = d.code {
+        out += &format!("{} {} for more information, run `scheme-syntax --explain {}`\n",
+                        pad, paint(BLUE, "="), code);
+    }
+    out
+}
+
+/// Renders `d` as lines of the form `file:line:col: error: message [code]`,
+/// which editors and other tools understand, followed by a `note` line for
+/// each secondary label.
+pub fn render_machine(d: &Diagnostic, file: &str, text: &str) -> String {
+    let location = |span: Span| {
+        let (line, col) = line_col(text, span.start);
+        format!("{}:{}:{}", file, line, col)
+    };
+    let mut message = d.message.clone();
+    match d.primary {
+        Some((_, ref label)) if !label.is_empty() => message += &format!(" ({})", label),
+        _ => (),
+    }
+    if let Some(code) = d.code {
+        message += &format!(" [{}]", code);
+    }
+    let mut out = match d.primary {
+        Some((span, _)) => format!("{}: error: {}\n", location(span), message),
+        None => format!("{}: error: {}\n", file, message),
+    };
+    for &(span, ref label) in &d.secondary {
+        out += &format!("{}: note: {}\n", location(span), label);
+    }
+    out
+}
+
+fn span_json(text: &str, span: Span) -> Json {
+    let (line, column) = line_col(text, span.start);
+    let (end_line, end_column) = line_col(text, span.end);
+    Json::object(vec![
+        ("start", Json::Number(span.start as f64)),
+        ("end", Json::Number(span.end as f64)),
+        ("line", Json::Number(line as f64)),
+        ("column", Json::Number(column as f64)),
+        ("end_line", Json::Number(end_line as f64)),
+        ("end_column", Json::Number(end_column as f64)),
+    ])
+}
+
+/// `d` as a JSON object with the fields file, span, severity, code,
+/// message, label and related. Spans have byte offsets as well as lines and
+/// columns counted from 1, and are null for diagnostics without a location.
+/// Related spans are the secondary labels, each with a span and a message.
+pub fn to_json(d: &Diagnostic, file: &str, text: &str) -> Json {
+    Json::object(vec![
+        ("file", Json::string(file)),
+        ("span", d.primary.as_ref().map_or(Json::Null, |p| span_json(text, p.0))),
+        ("severity", Json::string("error")),
+        ("code", d.code.map_or(Json::Null, Json::string)),
+        ("message", Json::string(d.message.clone())),
+        ("label", d.primary.as_ref().map_or(Json::Null, |p| Json::string(p.1.clone()))),
+        ("related", Json::Array(d.secondary.iter().map(|&(span, ref label)| Json::object(vec![
+            ("span", span_json(text, span)),
+            ("message", Json::string(label.clone())),
+        ])).collect())),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::SpannedParser;
+    use scheme::parse_expression;
+    use super::{line_col, render, render_machine, to_json, Diagnostic};
+
+    /// The diagnostics for the forms of `text` that have syntax errors.
+    fn diagnose_all(text: &str) -> Vec<Diagnostic> {
+        SpannedParser::new(text).map(Result::unwrap)
+            .filter_map(|(n, s)| parse_expression(n.clone()).err()
+                        .map(|e| Diagnostic::from_scheme_error(&e, &[(&n, &s)])))
+            .collect()
+    }
+
+    fn diagnose(text: &str) -> Diagnostic {
+        diagnose_all(text).remove(0)
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(line_col("ab\ncæd", 0), (1, 1));
+        assert_eq!(line_col("ab\ncæd", 3), (2, 1));
+        assert_eq!(line_col("ab\ncæd", 6), (2, 3));
+    }
+
+    #[test]
+    fn rendering() {
+        let text = "(define x 1)\n(case x\n  (else 1)\n  ((3) 2))\n";
+        assert_eq!(render(&diagnose(text), "a.scm", text, false), "\
+error[S0004]: case else clause: Expected else to be the last clause
+ --> a.scm:3:3
+  |
+3 |   (else 1)
+  |   ^^^^^^^^ this else clause is not the last
+4 |   ((3) 2))
+  |   ------- this clause follows it
+  = for more information, run `scheme-syntax --explain S0004`
+");
+        assert_eq!(render_machine(&diagnose(text), "a.scm", text), "\
+a.scm:3:3: error: case else clause: Expected else to be the last clause (this else clause is not the last) [S0004]
+a.scm:4:3: note: this clause follows it
+");
+        let text = "(define (f x y x) x)";
+        assert_eq!(render(&diagnose(text), "b.scm", text, false), "\
+error[S0008]: lambda formals: Duplicate parameter x
+ --> b.scm:1:16
+  |
+1 | (define (f x y x) x)
+  |            - first defined here
+  |                ^ parameter defined again here
+  = for more information, run `scheme-syntax --explain S0008`
+");
+        let text = "(if 1)";
+        assert_eq!(render(&diagnose(text), "c.scm", text, true),
+                   "\x1b[1;31merror[S0002]\x1b[0m\x1b[1m: Wrong number of arguments: expected 2 or 3, got 1\x1b[0m\n \
+                    \x1b[1;34m-->\x1b[0m c.scm:1:1\n  \x1b[1;34m|\x1b[0m\n\
+                    \x1b[1;34m1\x1b[0m \x1b[1;34m|\x1b[0m (if 1)\n  \
+                    \x1b[1;34m|\x1b[0m \x1b[1;31m^^^^^^ expected 2 or 3 operands\x1b[0m\n  \
+                    \x1b[1;34m=\x1b[0m for more information, run `scheme-syntax --explain S0002`\n");
+        assert_eq!(render(&Diagnostic::new("LibraryError: Unknown library (x)"), "d.scm", "", false),
+                   "error: LibraryError: Unknown library (x)\n --> d.scm\n");
+    }
+
+    #[test]
+    fn repeated_forms() {
+        let text = "(define a 1)\n(define b 2)\n(display (if x))\n(define c (if x))\n";
+        let found: Vec<_> = diagnose_all(text).iter()
+            .map(|d| line_col(text, d.primary.as_ref().unwrap().0.start))
+            .collect();
+        assert_eq!(found, vec![(3, 10), (4, 11)]);
+        let text = "(quote (if x))\n(if x)";
+        let d = diagnose(text);
+        assert_eq!(line_col(text, d.primary.unwrap().0.start), (2, 1));
+    }
+
+    #[test]
+    fn json() {
+        let text = "(let ((x 1)\n      (x 2)) x)";
+        assert_eq!(to_json(&diagnose(text), "a.scm", text).to_string(),
+                   "{\"file\":\"a.scm\",\
+                    \"span\":{\"start\":19,\"end\":20,\"line\":2,\"column\":8,\"end_line\":2,\"end_column\":9},\
+                    \"severity\":\"error\",\"code\":\"S0008\",\
+                    \"message\":\"let bindings: Duplicate variable x\",\
+                    \"label\":\"variable bound again here\",\
+                    \"related\":[{\"span\":{\"start\":7,\"end\":8,\"line\":1,\"column\":8,\"end_line\":1,\"end_column\":9},\
+                    \"message\":\"first bound here\"}]}");
+        assert_eq!(to_json(&Diagnostic::new("unreadable"), "b.scm", "").to_string(),
+                   "{\"file\":\"b.scm\",\"span\":null,\"severity\":\"error\",\"code\":null,\
+                    \"message\":\"unreadable\",\"label\":null,\"related\":[]}");
+    }
+}
@@ -0,0 +1,192 @@
+//! Entry points for the fuzz targets in `fuzz/`, which run with
+//! `cargo fuzz run <target>`. Each takes arbitrary bytes, runs them through
+//! one layer, and panics only if that layer panics or breaks one of the
+//! invariants checked here. Crashes go in `fuzz/regressions` once
+//! minimised with `cargo fuzz tmin`, and the tests replay them.
+
+use std::str;
+use std::time::Duration;
+
+use ast::Ast;
+use interpreter::Interpreter;
+use json::Json;
+use lexer::RegexLexer;
+use parser::{Parser, SpannedParser};
+use scheme::parse_expression;
+use scheme::syntax::Expression;
+use serialize::{Deserialize, Serialize};
+use value::Value;
+use vm::Limits;
+
+/// Tokens have spans that lie in the text, in order, on character
+/// boundaries.
+pub fn lex(data: &[u8]) {
+    let text = match str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    let mut lexer = RegexLexer::new(text);
+    let mut end = 0;
+    while lexer.next().is_some() {
+        let span = lexer.span();
+        assert!(end <= span.start && span.start <= span.end && span.end <= text.len());
+        assert!(text.is_char_boundary(span.start) && text.is_char_boundary(span.end));
+        end = span.end;
+    }
+}
+
+/// The text of a datum's span reads as the datum, and the datum prints as
+/// text that reads back as the same datum.
+pub fn read(data: &[u8]) {
+    let text = match str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    for datum in SpannedParser::new(text) {
+        let (node, spans) = match datum {
+            Ok(datum) => datum,
+            Err(_) => break,
+        };
+        let source = &text[spans.span.start..spans.span.end];
+        let reread = Parser::new(RegexLexer::new(source)).next().unwrap().unwrap();
+        // Compare printed forms, since NaN is not equal to itself.
+        assert_eq!(reread.to_sexpr(), node.to_sexpr());
+        let printed = node.to_sexpr();
+        let reread = Parser::new(RegexLexer::new(&printed)).next().unwrap().unwrap();
+        assert_eq!(reread.to_sexpr(), printed);
+    }
+}
+
+/// Expressions print as text that expands to the same expression, and
+/// convert to JSON and back unchanged. The arena accepts the same forms.
+pub fn expand(data: &[u8]) {
+    let text = match str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    let mut ast = Ast::new();
+    for node in Parser::new(RegexLexer::new(text)) {
+        let node = match node {
+            Ok(node) => node,
+            Err(_) => break,
+        };
+        let id = ast.parse(&node);
+        let e = match parse_expression(node) {
+            Ok(e) => e,
+            Err(_) => {
+                assert!(id.is_err());
+                continue;
+            },
+        };
+        assert_eq!(ast.expression(id.unwrap()).to_sexpr(), e.to_sexpr());
+        let printed = e.to_sexpr();
+        let node = Parser::new(RegexLexer::new(&printed)).next().unwrap().unwrap();
+        assert_eq!(parse_expression(node).unwrap().to_sexpr(), printed);
+        let json = Json::parse(&e.to_json().to_string()).unwrap();
+        assert_eq!(Expression::from_json(&json).unwrap().to_sexpr(), printed);
+    }
+}
+
+/// Running a program in a sandbox with limits ends, one way or another.
+pub fn eval(data: &[u8]) {
+    let text = match str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    let mut interp = Interpreter::sandboxed();
+    interp.set_limits(Limits {
+        steps: Some(100000),
+        depth: Some(200),
+        heap: Some(1 << 20),
+        time: Some(Duration::from_secs(1)),
+        fuel: None,
+    });
+    let _ = interp.eval::<Value>(text);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use lexer::Literal;
+    use parser::Node;
+    use serialize::Serialize;
+    use super::{eval, expand, lex, read};
+
+    /// A xorshift generator, so that the tests are the same on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+            &items[self.below(items.len())]
+        }
+    }
+
+    const IDENTIFIERS: &[&str] = &[
+        "x", "f", "...", "+", "-", ".", "->x", "a.b", "set!", "<=?", "else", "=>",
+    ];
+
+    const CHARACTERS: &[char] = &['a', ' ', '\n', '\t', '(', '"', '\\', ';', 'λ', 'N'];
+
+    fn literal(rng: &mut Rng) -> Literal {
+        match rng.below(5) {
+            0 => Literal::Number(*rng.pick(&[0, -1, 42, i32::MIN, i32::MAX])),
+            1 => Literal::Real(*rng.pick(&[0.5, -0.0, 1e300, 1e-7, 3.25, 1.0 / 0.0])),
+            2 => Literal::Boolean(rng.below(2) == 0),
+            3 => Literal::Character(*rng.pick(CHARACTERS)),
+            _ => Literal::String((0..rng.below(4)).map(|_| *rng.pick(CHARACTERS)).collect()),
+        }
+    }
+
+    fn node(rng: &mut Rng, depth: usize) -> Node {
+        match rng.below(if depth == 0 { 2 } else { 4 }) {
+            0 => Node::Identifier(rng.pick(IDENTIFIERS).to_string()),
+            1 => Node::Literal(literal(rng)),
+            _ => Node::List((0..rng.below(5)).map(|_| node(rng, depth - 1)).collect()),
+        }
+    }
+
+    const WORDS: &[&str] = &[
+        "(", "(", "(", ")", ")", ")", ".", "x", "y", "1", "1.5", "#t", "\"s\"", "else", "=>",
+        "lambda", "define", "let", "let*", "letrec", "if", "cond", "case", "and", "or", "when",
+        "do", "begin", "set!", "quote", "delay", "time", "define-record-type", "parameterize",
+        "guard", "let-values", "define-values", "receive", "...",
+    ];
+
+    #[test]
+    fn round_trips() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..300 {
+            let text = node(&mut rng, 4).to_sexpr();
+            lex(text.as_bytes());
+            read(text.as_bytes());
+        }
+        for _ in 0..500 {
+            let n = 1 + rng.below(16);
+            let words: Vec<&str> = (0..n).map(|_| *rng.pick(WORDS)).collect();
+            expand(words.join(" ").as_bytes());
+        }
+    }
+
+    #[test]
+    fn regressions() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz").join("regressions");
+        let mut count = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let data = fs::read(entry.unwrap().path()).unwrap();
+            lex(&data);
+            read(&data);
+            expand(&data);
+            eval(&data);
+            count += 1;
+        }
+        assert!(count > 0);
+    }
+}
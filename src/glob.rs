@@ -0,0 +1,179 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Whether `name` matches a single path component pattern, where `*`
+/// matches any run of characters, `?` any one character, and `[...]` one
+/// of a set of characters (`[!...]` negates, `a-z` is a range).
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    // Backtracking point for the last `*`: (pattern index, name index).
+    let mut star: Option<(usize, usize)> = None;
+    let (mut i, mut j) = (0, 0);
+    while j < n.len() {
+        if i < p.len() && p[i] == '*' {
+            star = Some((i, j));
+            i += 1;
+            continue;
+        }
+        if i < p.len() {
+            if let Some((ok, len)) = match_one(&p[i..], n[j]) {
+                if ok {
+                    i += len;
+                    j += 1;
+                    continue;
+                }
+            }
+        }
+        match star {
+            Some((si, sj)) => {
+                i = si + 1;
+                j = sj + 1;
+                star = Some((si, sj + 1));
+            },
+            None => return false,
+        }
+    }
+    p[i..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the pattern element at the start of `p`, returning
+/// whether it matched and the length of the element.
+fn match_one(p: &[char], c: char) -> Option<(bool, usize)> {
+    match p[0] {
+        '?' => Some((true, 1)),
+        '[' => {
+            let negated = p.get(1) == Some(&'!');
+            let start = if negated { 2 } else { 1 };
+            // A `]` right after the opening bracket is a literal.
+            let end = start + 1 + p.get(start + 1..)?.iter().position(|&c| c == ']')?;
+            let set = &p[start..end];
+            let mut found = false;
+            let mut k = 0;
+            while k < set.len() {
+                if k + 2 < set.len() && set[k + 1] == '-' {
+                    found = found || (set[k] <= c && c <= set[k + 2]);
+                    k += 3;
+                } else {
+                    found = found || set[k] == c;
+                    k += 1;
+                }
+            }
+            Some((found != negated, end + 1))
+        },
+        x => Some((x == c, 1)),
+    }
+}
+
+pub fn is_pattern(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// The paths matching `pattern`, in sorted order. A `**` component matches
+/// any number of directories. Hidden files only match patterns that start
+/// with a dot.
+pub fn glob(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let path = Path::new(pattern);
+    let mut base = PathBuf::new();
+    let mut parts = Vec::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(s) => parts.push(s.to_string_lossy().into_owned()),
+            c if parts.is_empty() => base.push(c.as_os_str()),
+            c => parts.push(c.as_os_str().to_string_lossy().into_owned()),
+        }
+    }
+    let mut res = Vec::new();
+    expand(&base, &parts, &mut res)?;
+    res.sort();
+    res.dedup();
+    Ok(res)
+}
+
+fn entries(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let mut res = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        res.push((entry.file_name().to_string_lossy().into_owned(), entry.path()));
+    }
+    Ok(res)
+}
+
+fn expand(base: &Path, parts: &[String], res: &mut Vec<PathBuf>) -> io::Result<()> {
+    let (part, rest) = match parts.split_first() {
+        Some(split) => split,
+        None => {
+            res.push(base.to_path_buf());
+            return Ok(());
+        },
+    };
+    if part == "**" {
+        expand(base, rest, res)?;
+        for (name, path) in entries(base)? {
+            if !name.starts_with('.') && path.is_dir() {
+                expand(&base.join(&name), parts, res)?;
+            }
+        }
+    } else if is_pattern(part) {
+        if !base.as_os_str().is_empty() && !base.is_dir() {
+            return Ok(());
+        }
+        for (name, _) in entries(base)? {
+            if name.starts_with('.') && !part.starts_with('.') {
+                continue;
+            }
+            if matches(part, &name) {
+                expand(&base.join(&name), rest, res)?;
+            }
+        }
+    } else {
+        let next = base.join(part);
+        if rest.is_empty() && !next.exists() {
+            return Ok(());
+        }
+        expand(&next, rest, res)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use super::{glob, matches};
+
+    #[test]
+    fn patterns() {
+        assert!(matches("*.scm", "a.scm"));
+        assert!(matches("*.scm", ".scm"));
+        assert!(!matches("*.scm", "a.sld"));
+        assert!(matches("a*b*c", "aXXbYbc"));
+        assert!(matches("?.s[cl][!x]", "a.scm"));
+        assert!(!matches("?.s[cl][!m]", "a.scm"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[a-c]x", "dx"));
+        assert!(matches("[]]", "]"));
+    }
+
+    #[test]
+    fn directories() {
+        let root = env::temp_dir().join(format!("scheme-syntax-glob-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for f in &["a.scm", "b.sld", "sub/c.scm", "sub/deep/d.scm", ".hidden/e.scm"] {
+            let path = root.join(f);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let pattern = |p: &str| root.join(p).to_string_lossy().into_owned();
+        let names = |p: &str| glob(&pattern(p)).unwrap().into_iter()
+            .map(|p| p.strip_prefix(&root).unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names("*.scm"), vec!["a.scm"]);
+        assert_eq!(names("**/*.scm"), vec!["a.scm", "sub/c.scm", "sub/deep/d.scm"]);
+        assert_eq!(names("*/c.*"), vec!["sub/c.scm"]);
+        assert_eq!(names("*.txt"), Vec::<String>::new());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,617 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+use compiler::Chunk;
+use value::{Value, Winders};
+use vm::Continuation;
+
+/// Handle to an object in a `Heap`. Handles are only meaningful for the
+/// heap that issued them, and only while the object is reachable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ref(u32);
+
+pub struct Closure {
+    pub code: Rc<Chunk>,
+    pub env: Option<Ref>,
+}
+
+pub enum Object {
+    Pair(Value, Value),
+    String(String),
+    Vector(Vec<Value>),
+    Closure(Closure),
+    /// Variable slots of a procedure activation. Slots are addressed by
+    /// (depth, index) pairs that the compiler resolves statically.
+    Frame {
+        slots: Vec<Value>,
+        parent: Option<Ref>,
+    },
+    Continuation(Continuation),
+    /// The value of a forced promise, or the thunk that computes it.
+    Promise {
+        done: bool,
+        value: Value,
+    },
+    RecordType {
+        name: Rc<str>,
+        fields: Vec<Rc<str>>,
+    },
+    Record {
+        record_type: Ref,
+        fields: Vec<Value>,
+    },
+    /// A parameter object's current value, and the procedure that converts
+    /// the values it is given.
+    Parameter {
+        value: Value,
+        converter: Value,
+    },
+    /// An error object, made by `error` or by a failing primitive. The
+    /// message is a string and the irritants a list.
+    Error {
+        message: Value,
+        irritants: Value,
+    },
+    Values(Vec<Value>),
+}
+
+/// Collects the objects reachable from a set of roots.
+pub struct Tracer<'h> {
+    marks: &'h mut Vec<bool>,
+    gray: Vec<Ref>,
+    chunks: HashSet<*const Chunk>,
+}
+
+impl <'h> Tracer<'h> {
+    pub fn mark(&mut self, r: Ref) {
+        let i = r.0 as usize;
+        if !self.marks[i] {
+            self.marks[i] = true;
+            self.gray.push(r);
+        }
+    }
+
+    pub fn value(&mut self, v: &Value) {
+        if let Some(r) = v.heap_ref() {
+            self.mark(r);
+        }
+    }
+
+    /// Marks the constants of a chunk and of the lambdas nested in it.
+    pub fn chunk(&mut self, c: &Rc<Chunk>) {
+        if !self.chunks.insert(&**c as *const Chunk) {
+            return;
+        }
+        for v in &c.constants {
+            self.value(v);
+        }
+        for w in &c.winders {
+            self.winders(w);
+        }
+        for l in &c.lambdas {
+            self.chunk(l);
+        }
+    }
+
+    pub fn winders(&mut self, w: &Winders) {
+        let mut w = w;
+        while let Some(winder) = w {
+            self.value(&winder.before);
+            self.value(&winder.after);
+            w = &winder.parent;
+        }
+    }
+
+    fn object(&mut self, o: &Object) {
+        match o {
+            Object::Pair(car, cdr) => {
+                self.value(car);
+                self.value(cdr);
+            },
+            Object::String(_) => (),
+            Object::Vector(items) =>
+                for v in items {
+                    self.value(v);
+                },
+            Object::Closure(c) => {
+                self.chunk(&c.code);
+                if let Some(env) = c.env {
+                    self.mark(env);
+                }
+            },
+            Object::Frame { slots, parent } => {
+                for v in slots {
+                    self.value(v);
+                }
+                if let Some(parent) = *parent {
+                    self.mark(parent);
+                }
+            },
+            Object::Continuation(k) => k.trace(self),
+            Object::Promise { value, .. } => self.value(value),
+            Object::RecordType { .. } => (),
+            Object::Record { record_type, fields } => {
+                self.mark(*record_type);
+                for v in fields {
+                    self.value(v);
+                }
+            },
+            Object::Parameter { value, converter } => {
+                self.value(value);
+                self.value(converter);
+            },
+            Object::Error { message, irritants } => {
+                self.value(message);
+                self.value(irritants);
+            },
+            Object::Values(items) => {
+                for v in items {
+                    self.value(v);
+                }
+            },
+        }
+    }
+}
+
+const MIN_THRESHOLD: usize = 4096;
+
+fn cells(o: &Object) -> usize {
+    1 + match o {
+        Object::String(s) => s.chars().count(),
+        Object::Vector(items) | Object::Values(items) => items.len(),
+        _ => 0,
+    }
+}
+
+/// Storage for every Scheme object with identity. Memory is reclaimed by a
+/// mark-and-sweep collection, which the VM triggers at points where all of
+/// its live values are visible as roots.
+pub struct Heap {
+    objects: Vec<Option<Object>>,
+    marks: Vec<bool>,
+    free: Vec<u32>,
+    live: usize,
+    size: usize,
+    threshold: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            marks: Vec::new(),
+            free: Vec::new(),
+            live: 0,
+            size: 0,
+            threshold: MIN_THRESHOLD,
+        }
+    }
+
+    pub fn alloc(&mut self, o: Object) -> Ref {
+        self.live += 1;
+        self.size += cells(&o);
+        match self.free.pop() {
+            Some(i) => {
+                self.objects[i as usize] = Some(o);
+                Ref(i)
+            },
+            None => {
+                self.objects.push(Some(o));
+                Ref((self.objects.len() - 1) as u32)
+            },
+        }
+    }
+
+    pub fn get(&self, r: Ref) -> &Object {
+        self.objects[r.0 as usize].as_ref().expect("dangling heap reference")
+    }
+
+    pub fn get_mut(&mut self, r: Ref) -> &mut Object {
+        self.objects[r.0 as usize].as_mut().expect("dangling heap reference")
+    }
+
+    /// Number of objects currently allocated.
+    #[cfg(test)]
+    pub fn live_objects(&self) -> usize {
+        self.live
+    }
+
+    /// Size of the allocated objects in cells: one for each object, and one
+    /// for each character of a string or element of a vector.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.live >= self.threshold
+    }
+
+    /// Frees every object not reachable from the roots that `roots` marks.
+    pub fn collect<F>(&mut self, roots: F) where F: FnOnce(&mut Tracer) {
+        self.marks.clear();
+        self.marks.resize(self.objects.len(), false);
+        {
+            let objects = &self.objects;
+            let mut tracer = Tracer {
+                marks: &mut self.marks,
+                gray: Vec::new(),
+                chunks: HashSet::new(),
+            };
+            roots(&mut tracer);
+            while let Some(r) = tracer.gray.pop() {
+                if let Some(ref o) = objects[r.0 as usize] {
+                    tracer.object(o);
+                }
+            }
+        }
+        for (i, o) in self.objects.iter_mut().enumerate() {
+            if o.is_some() && !self.marks[i] {
+                self.size -= o.take().map_or(0, |o| cells(&o));
+                self.free.push(i as u32);
+                self.live -= 1;
+            }
+        }
+        self.threshold = MIN_THRESHOLD.max(self.live * 2);
+    }
+
+    pub fn cons(&mut self, car: Value, cdr: Value) -> Value {
+        Value::Pair(self.alloc(Object::Pair(car, cdr)))
+    }
+
+    pub fn list(&mut self, items: Vec<Value>) -> Value {
+        items.into_iter().rev().fold(Value::Nil, |tl, hd| self.cons(hd, tl))
+    }
+
+    pub fn string(&mut self, s: String) -> Value {
+        Value::String(self.alloc(Object::String(s)))
+    }
+
+    pub fn vector(&mut self, items: Vec<Value>) -> Value {
+        Value::Vector(self.alloc(Object::Vector(items)))
+    }
+
+    pub fn pair(&self, r: Ref) -> (&Value, &Value) {
+        match self.get(r) {
+            Object::Pair(car, cdr) => (car, cdr),
+            _ => panic!("not a pair"),
+        }
+    }
+
+    /// Formats a value in the external representation used by `write`.
+    pub fn write<'h>(&'h self, v: &'h Value) -> Printer<'h> {
+        Printer {
+            heap: self,
+            value: v,
+            display: false,
+        }
+    }
+
+    /// Formats a value the way `display` shows it: strings and characters
+    /// stand for themselves.
+    pub fn display<'h>(&'h self, v: &'h Value) -> Printer<'h> {
+        Printer {
+            heap: self,
+            value: v,
+            display: true,
+        }
+    }
+
+    pub fn str(&self, r: Ref) -> &str {
+        match self.get(r) {
+            Object::String(s) => s,
+            _ => panic!("not a string"),
+        }
+    }
+
+    pub fn items(&self, r: Ref) -> &[Value] {
+        match self.get(r) {
+            Object::Vector(items) | Object::Values(items) => items,
+            _ => panic!("not a vector or values"),
+        }
+    }
+}
+
+pub struct Printer<'h> {
+    heap: &'h Heap,
+    value: &'h Value,
+    display: bool,
+}
+
+fn write_real(f: &mut fmt::Formatter, x: f64) -> fmt::Result {
+    if x.is_nan() {
+        write!(f, "+nan.0")
+    } else if x.is_infinite() {
+        write!(f, "{}inf.0", if x > 0.0 { "+" } else { "-" })
+    } else {
+        write!(f, "{:?}", x)
+    }
+}
+
+fn write_char(f: &mut fmt::Formatter, c: char) -> fmt::Result {
+    match c {
+        '\n' => write!(f, "#\\newline"),
+        ' ' => write!(f, "#\\space"),
+        c => write!(f, "#\\{}", c),
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '\\' => write!(f, "\\\\")?,
+            '"' => write!(f, "\\\"")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Whether the lexer reads `s` back as this identifier, so that it can be
+/// written without bars.
+fn plain_symbol(s: &str) -> bool {
+    let initial = |c: char| c.is_ascii_alphabetic() || "!$%&*/:<=>?~_^".contains(c);
+    let subsequent = |c: char| initial(c) || c.is_ascii_digit() || ".+-".contains(c);
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if initial(c) => chars.all(subsequent),
+        Some('+') | Some('-') => match chars.next() {
+            None => true,
+            Some(c) => (initial(c) || c == '+' || c == '-') && chars.all(subsequent),
+        },
+        _ => s == "...",
+    }
+}
+
+fn write_symbol(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    if plain_symbol(s) {
+        return write!(f, "{}", s);
+    }
+    write!(f, "|")?;
+    for c in s.chars() {
+        match c {
+            '\\' => write!(f, "\\\\")?,
+            '|' => write!(f, "\\|")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "|")
+}
+
+/// What is left to print of a value, kept on an explicit stack so that
+/// deeply nested data do not overflow the Rust stack.
+enum Print<'h> {
+    Value(&'h Value),
+    Text(&'static str),
+    /// The rest of a list after an element.
+    Tail(&'h Value),
+    /// The rest of the irritants of an error.
+    Irritants(&'h Value),
+    /// Leaves the objects entered since the path had this length.
+    Leave(usize),
+}
+
+/// The pairs, vectors and errors currently being printed, so that a cycle
+/// back into one of them is shown as `...` instead of looping.
+#[derive(Default)]
+struct Path {
+    refs: Vec<Ref>,
+    set: HashSet<Ref>,
+}
+
+impl Path {
+    fn contains(&self, r: Ref) -> bool {
+        self.set.contains(&r)
+    }
+
+    fn enter(&mut self, r: Ref) {
+        self.refs.push(r);
+        self.set.insert(r);
+    }
+
+    fn leave(&mut self, depth: usize) {
+        for r in self.refs.drain(depth..) {
+            self.set.remove(&r);
+        }
+    }
+}
+
+impl <'h> Printer<'h> {
+    /// Prints the items of a vector or of multiple values, separated by
+    /// spaces, after what is already on `stack`.
+    fn items(stack: &mut Vec<Print<'h>>, items: &'h [Value]) {
+        for (i, v) in items.iter().enumerate().rev() {
+            stack.push(Print::Value(v));
+            if i > 0 {
+                stack.push(Print::Text(" "));
+            }
+        }
+    }
+
+    fn value(&self, f: &mut fmt::Formatter, v: &'h Value, path: &mut Path,
+             stack: &mut Vec<Print<'h>>) -> fmt::Result {
+        if let Some(r) = v.heap_ref() {
+            if path.contains(r) {
+                return write!(f, "...");
+            }
+        }
+        match v {
+            Value::Unspecified => write!(f, "#<unspecified>"),
+            Value::Nil => write!(f, "()"),
+            Value::Boolean(true) => write!(f, "#t"),
+            Value::Boolean(false) => write!(f, "#f"),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Real(x) => write_real(f, *x),
+            Value::Character(c) if self.display => write!(f, "{}", c),
+            Value::Character(c) => write_char(f, *c),
+            Value::Symbol(s) if self.display => write!(f, "{}", s),
+            Value::Symbol(s) => write_symbol(f, s),
+            Value::String(r) if self.display => write!(f, "{}", self.heap.str(*r)),
+            Value::String(r) => write_string(f, self.heap.str(*r)),
+            Value::Pair(r) => {
+                stack.push(Print::Leave(path.refs.len()));
+                path.enter(*r);
+                let (car, cdr) = self.heap.pair(*r);
+                stack.push(Print::Tail(cdr));
+                stack.push(Print::Value(car));
+                write!(f, "(")
+            },
+            Value::Vector(r) => {
+                stack.push(Print::Leave(path.refs.len()));
+                stack.push(Print::Text(")"));
+                path.enter(*r);
+                Printer::items(stack, self.heap.items(*r));
+                write!(f, "#(")
+            },
+            Value::Closure(r) => match self.heap.get(*r) {
+                Object::Closure(Closure { code, .. }) => match code.name {
+                    Some(ref name) => write!(f, "#<procedure {}>", name),
+                    None => write!(f, "#<procedure>"),
+                },
+                _ => write!(f, "#<procedure>"),
+            },
+            Value::Continuation(_) => write!(f, "#<continuation>"),
+            Value::Promise(_) => write!(f, "#<promise>"),
+            Value::Record(r) => match self.heap.get(*r) {
+                Object::Record { record_type, .. } => match self.heap.get(*record_type) {
+                    Object::RecordType { name, .. } => write!(f, "#<record {}>", name),
+                    _ => write!(f, "#<record>"),
+                },
+                _ => write!(f, "#<record>"),
+            },
+            Value::RecordType(r) => match self.heap.get(*r) {
+                Object::RecordType { name, .. } => write!(f, "#<record-type {}>", name),
+                _ => write!(f, "#<record-type>"),
+            },
+            Value::Parameter(_) => write!(f, "#<parameter>"),
+            Value::Error(r) => match self.heap.get(*r) {
+                Object::Error { message, irritants } => {
+                    stack.push(Print::Leave(path.refs.len()));
+                    stack.push(Print::Text(">"));
+                    path.enter(*r);
+                    stack.push(Print::Irritants(irritants));
+                    stack.push(Print::Value(message));
+                    write!(f, "#<error ")
+                },
+                _ => write!(f, "#<error>"),
+            },
+            Value::Values(r) => {
+                stack.push(Print::Leave(path.refs.len()));
+                path.enter(*r);
+                Printer::items(stack, self.heap.items(*r));
+                Ok(())
+            },
+            Value::Primitive(p) => write!(f, "#<procedure {}>", p.name),
+            Value::Host(h) => write!(f, "#<procedure {}>", h.name),
+            Value::Control(c) => write!(f, "#<procedure {}>", c.name()),
+        }
+    }
+}
+
+impl <'h> fmt::Display for Printer<'h> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut path = Path::default();
+        let mut stack = vec![Print::Value(self.value)];
+        while let Some(next) = stack.pop() {
+            match next {
+                Print::Value(v) => self.value(f, v, &mut path, &mut stack)?,
+                Print::Text(s) => write!(f, "{}", s)?,
+                Print::Tail(tl) => match tl {
+                    Value::Nil => write!(f, ")")?,
+                    Value::Pair(r) if !path.contains(*r) => {
+                        path.enter(*r);
+                        let (car, cdr) = self.heap.pair(*r);
+                        stack.push(Print::Tail(cdr));
+                        stack.push(Print::Value(car));
+                        write!(f, " ")?;
+                    },
+                    v => {
+                        stack.push(Print::Text(")"));
+                        stack.push(Print::Value(v));
+                        write!(f, " . ")?;
+                    },
+                },
+                Print::Irritants(l) => match l {
+                    Value::Pair(r) if !path.contains(*r) => {
+                        path.enter(*r);
+                        let (car, cdr) = self.heap.pair(*r);
+                        stack.push(Print::Irritants(cdr));
+                        stack.push(Print::Value(car));
+                        write!(f, " ")?;
+                    },
+                    _ => (),
+                },
+                Print::Leave(depth) => path.leave(depth),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heap, Object};
+    use value::Value;
+
+    #[test]
+    fn collect_unreachable() {
+        let mut heap = Heap::new();
+        let kept = heap.list(vec![Value::Number(1), Value::Number(2)]);
+        heap.list(vec![Value::Number(3), Value::Number(4), Value::Number(5)]);
+        assert_eq!(heap.live_objects(), 5);
+        heap.collect(|t| t.value(&kept));
+        assert_eq!(heap.live_objects(), 2);
+        assert_eq!(heap.write(&kept).to_string(), "(1 2)");
+    }
+
+    #[test]
+    fn collect_cycle() {
+        let mut heap = Heap::new();
+        let a = heap.cons(Value::Number(1), Value::Nil);
+        let r = a.heap_ref().unwrap();
+        if let Object::Pair(_, ref mut cdr) = *heap.get_mut(r) {
+            *cdr = a.clone();
+        }
+        assert_eq!(heap.write(&a).to_string(), "(1 . ...)");
+        let v = heap.vector(vec![a.clone(), a.clone()]);
+        assert_eq!(heap.write(&v).to_string(), "#((1 . ...) (1 . ...))");
+        heap.collect(|t| t.value(&a));
+        assert_eq!(heap.live_objects(), 1);
+        heap.collect(|_| ());
+        assert_eq!(heap.live_objects(), 0);
+    }
+
+    #[test]
+    fn shared_structure() {
+        let mut heap = Heap::new();
+        let x = heap.list(vec![Value::Number(1)]);
+        let l = heap.list(vec![x.clone(), x]);
+        assert_eq!(heap.write(&l).to_string(), "((1) (1))");
+    }
+
+    #[test]
+    fn write_deep_and_long() {
+        let mut heap = Heap::new();
+        let mut v = Value::Number(1);
+        for _ in 0..200000 {
+            v = heap.list(vec![v]);
+        }
+        let expected = format!("{}1{}", "(".repeat(200000), ")".repeat(200000));
+        assert!(heap.write(&v).to_string() == expected);
+        let long = heap.list((0..200000).map(Value::Number).collect());
+        assert!(heap.write(&long).to_string().ends_with(" 199998 199999)"));
+        let v = heap.vector(vec![Value::Number(1), long, Value::Nil]);
+        assert!(heap.write(&v).to_string().starts_with("#(1 (0 1 2"));
+    }
+}
@@ -0,0 +1,615 @@
+use std;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use builtins::{self, expected, list_items};
+use heap::Heap;
+use library::{Loader, LibraryError};
+use parser::{ReadError, ReadLimits, SpannedParser};
+use scheme::SchemeError;
+use value::{self, Arity, Host, RuntimeError, Value};
+use vm::{Limits, Vm};
+
+/// An error from reading, expanding or running code in an `Interpreter`.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Read(ReadError),
+    Syntax(SchemeError),
+    Library(String),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "IoError: {}", e),
+            Error::Read(e) => write!(f, "{}", e),
+            Error::Syntax(e) => write!(f, "{}", e),
+            Error::Library(s) => write!(f, "LibraryError: {}", s),
+            Error::Runtime(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<ReadError> for Error {
+    fn from(e: ReadError) -> Self {
+        Error::Read(e)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Self {
+        Error::Runtime(e)
+    }
+}
+
+impl From<LibraryError> for Error {
+    fn from(e: LibraryError) -> Self {
+        match e {
+            LibraryError::Basic(s) => Error::Library(s),
+            LibraryError::Syntax(e) => Error::Syntax(e),
+            LibraryError::Runtime(e) => Error::Runtime(e),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Rust values that can be passed to Scheme.
+pub trait ToScheme {
+    fn to_scheme(self, heap: &mut Heap) -> value::Result<Value>;
+}
+
+/// Rust values that can be taken from Scheme. The conversion fails with a
+/// runtime error if the value has the wrong type.
+pub trait FromScheme: Sized {
+    fn from_scheme(heap: &Heap, v: &Value) -> value::Result<Self>;
+}
+
+/// A Scheme symbol, as opposed to a string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Symbol(pub String);
+
+/// A value is passed on as it is. It stays valid only until the
+/// interpreter next runs code, which may collect it.
+impl ToScheme for Value {
+    fn to_scheme(self, _: &mut Heap) -> value::Result<Value> {
+        Ok(self)
+    }
+}
+
+impl FromScheme for Value {
+    fn from_scheme(_: &Heap, v: &Value) -> value::Result<Self> {
+        Ok(v.clone())
+    }
+}
+
+impl ToScheme for () {
+    fn to_scheme(self, _: &mut Heap) -> value::Result<Value> {
+        Ok(Value::Unspecified)
+    }
+}
+
+/// Accepts any value and ignores it.
+impl FromScheme for () {
+    fn from_scheme(_: &Heap, _: &Value) -> value::Result<Self> {
+        Ok(())
+    }
+}
+
+impl ToScheme for bool {
+    fn to_scheme(self, _: &mut Heap) -> value::Result<Value> {
+        Ok(Value::Boolean(self))
+    }
+}
+
+impl FromScheme for bool {
+    fn from_scheme(heap: &Heap, v: &Value) -> value::Result<Self> {
+        match v {
+            Value::Boolean(b) => Ok(*b),
+            v => Err(expected(heap, "boolean", v)),
+        }
+    }
+}
+
+fn out_of_range<T: fmt::Display>(n: T) -> RuntimeError {
+    RuntimeError::Basic(format!("Integer out of range: {}", n))
+}
+
+macro_rules! integer {
+    ($($t:ty),*) => {$(
+        /// Exact integers are 32 bits; larger ones do not convert.
+        impl ToScheme for $t {
+            fn to_scheme(self, _: &mut Heap) -> value::Result<Value> {
+                i32::try_from(self).map(Value::Number).map_err(|_| out_of_range(self))
+            }
+        }
+
+        impl FromScheme for $t {
+            fn from_scheme(heap: &Heap, v: &Value) -> value::Result<Self> {
+                match v {
+                    Value::Number(n) => <$t>::try_from(*n).map_err(|_| out_of_range(n)),
+                    v => Err(expected(heap, "exact integer", v)),
+                }
+            }
+        }
+    )*}
+}
+
+integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl ToScheme for f64 {
+    fn to_scheme(self, _: &mut Heap) -> value::Result<Value> {
+        Ok(Value::Real(self))
+    }
+}
+
+/// Accepts exact integers as well as inexact numbers.
+impl FromScheme for f64 {
+    fn from_scheme(heap: &Heap, v: &Value) -> value::Result<Self> {
+        match v {
+            Value::Number(n) => Ok(*n as f64),
+            Value::Real(x) => Ok(*x),
+            v => Err(expected(heap, "number", v)),
+        }
+    }
+}
+
+impl ToScheme for char {
+    fn to_scheme(self, _: &mut Heap) -> value::Result<Value> {
+        Ok(Value::Character(self))
+    }
+}
+
+impl FromScheme for char {
+    fn from_scheme(heap: &Heap, v: &Value) -> value::Result<Self> {
+        match v {
+            Value::Character(c) => Ok(*c),
+            v => Err(expected(heap, "character", v)),
+        }
+    }
+}
+
+impl ToScheme for &str {
+    fn to_scheme(self, heap: &mut Heap) -> value::Result<Value> {
+        Ok(heap.string(self.to_string()))
+    }
+}
+
+impl ToScheme for String {
+    fn to_scheme(self, heap: &mut Heap) -> value::Result<Value> {
+        Ok(heap.string(self))
+    }
+}
+
+impl FromScheme for String {
+    fn from_scheme(heap: &Heap, v: &Value) -> value::Result<Self> {
+        match v {
+            Value::String(r) => Ok(heap.str(*r).to_string()),
+            v => Err(expected(heap, "string", v)),
+        }
+    }
+}
+
+impl ToScheme for Symbol {
+    fn to_scheme(self, _: &mut Heap) -> value::Result<Value> {
+        Ok(Value::Symbol(Rc::from(self.0)))
+    }
+}
+
+impl FromScheme for Symbol {
+    fn from_scheme(heap: &Heap, v: &Value) -> value::Result<Self> {
+        match v {
+            Value::Symbol(s) => Ok(Symbol(s.to_string())),
+            v => Err(expected(heap, "symbol", v)),
+        }
+    }
+}
+
+/// A vector becomes a list.
+impl<T: ToScheme> ToScheme for Vec<T> {
+    fn to_scheme(self, heap: &mut Heap) -> value::Result<Value> {
+        let mut items = Vec::new();
+        for x in self {
+            items.push(x.to_scheme(heap)?);
+        }
+        Ok(heap.list(items))
+    }
+}
+
+/// Accepts a proper list or a vector.
+impl<T: FromScheme> FromScheme for Vec<T> {
+    fn from_scheme(heap: &Heap, v: &Value) -> value::Result<Self> {
+        let items = match v {
+            Value::Vector(r) => heap.items(*r).to_vec(),
+            v => list_items(heap, v)?,
+        };
+        items.iter().map(|x| T::from_scheme(heap, x)).collect()
+    }
+}
+
+/// `None` is `#f`.
+impl<T: ToScheme> ToScheme for Option<T> {
+    fn to_scheme(self, heap: &mut Heap) -> value::Result<Value> {
+        match self {
+            Some(x) => x.to_scheme(heap),
+            None => Ok(Value::Boolean(false)),
+        }
+    }
+}
+
+impl<T: FromScheme> FromScheme for Option<T> {
+    fn from_scheme(heap: &Heap, v: &Value) -> value::Result<Self> {
+        match v {
+            Value::Boolean(false) => Ok(None),
+            v => Ok(Some(T::from_scheme(heap, v)?)),
+        }
+    }
+}
+
+/// An error is raised in Scheme with its message, so a procedure that
+/// returns one can be guarded against like any other.
+impl<T: ToScheme, E: fmt::Display> ToScheme for std::result::Result<T, E> {
+    fn to_scheme(self, heap: &mut Heap) -> value::Result<Value> {
+        match self {
+            Ok(x) => x.to_scheme(heap),
+            Err(e) => Err(RuntimeError::Basic(e.to_string())),
+        }
+    }
+}
+
+/// The arguments of a call from Rust into Scheme: a tuple of values, or a
+/// vector of values of one type.
+pub trait Arguments {
+    fn to_scheme_args(self, heap: &mut Heap) -> value::Result<Vec<Value>>;
+}
+
+impl<T: ToScheme> Arguments for Vec<T> {
+    fn to_scheme_args(self, heap: &mut Heap) -> value::Result<Vec<Value>> {
+        let mut res = Vec::new();
+        for x in self {
+            res.push(x.to_scheme(heap)?);
+        }
+        Ok(res)
+    }
+}
+
+/// Rust closures that can be called as Scheme procedures. `Args` is the
+/// tuple of their argument types.
+pub trait HostProcedure<Args>: 'static {
+    fn arity(&self) -> Arity;
+    fn call(&self, heap: &mut Heap, args: &[Value]) -> value::Result<Value>;
+}
+
+macro_rules! tuples {
+    ($($arg:ident),*) => {
+        impl<$($arg: ToScheme),*> Arguments for ($($arg,)*) {
+            #[allow(non_snake_case, unused_variables)]
+            fn to_scheme_args(self, heap: &mut Heap) -> value::Result<Vec<Value>> {
+                let ($($arg,)*) = self;
+                Ok(vec![$($arg.to_scheme(heap)?),*])
+            }
+        }
+
+        impl<F, R, $($arg),*> HostProcedure<($($arg,)*)> for F
+        where F: Fn($($arg),*) -> R + 'static, R: ToScheme, $($arg: FromScheme),* {
+            fn arity(&self) -> Arity {
+                Arity::exactly(<[&str]>::len(&[$(stringify!($arg)),*]))
+            }
+
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn call(&self, heap: &mut Heap, args: &[Value]) -> value::Result<Value> {
+                let mut args = args.iter();
+                $(let $arg = $arg::from_scheme(heap, args.next().unwrap())?;)*
+                self($($arg),*).to_scheme(heap)
+            }
+        }
+    }
+}
+
+tuples!();
+tuples!(A);
+tuples!(A, B);
+tuples!(A, B, C);
+tuples!(A, B, C, D);
+tuples!(A, B, C, D, E);
+
+/// A Scheme interpreter for programs that embed it. Each top-level form is
+/// run within the limits set with `set_limits`; exceeding one stops it with
+/// a `RuntimeError::Limit`, which `guard` cannot catch.
+pub struct Interpreter {
+    vm: Vm,
+    loader: Loader,
+    read_limits: ReadLimits,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let vm = Vm::new();
+        let loader = Loader::new(&vm, true);
+        Interpreter { vm, loader, read_limits: ReadLimits::default() }
+    }
+
+    /// An interpreter for untrusted code: it has no primitives that do
+    /// output, `time` does not report, and neither `include` nor imports
+    /// read files. Procedures the host registers are its only way out. It
+    /// runs within `Limits::sandbox` until `set_limits` replaces them.
+    pub fn sandboxed() -> Self {
+        let mut vm = Vm::new();
+        for name in builtins::SYSTEM {
+            vm.undefine(name);
+        }
+        vm.set_report_time(false);
+        vm.set_limits(Limits::sandbox());
+        let mut loader = Loader::new(&vm, true);
+        loader.deny_files();
+        Interpreter { vm, loader, read_limits: ReadLimits::default() }
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.vm.set_limits(limits);
+    }
+
+    /// Sets the limits on the source text that `eval` and `eval_file` read.
+    pub fn set_read_limits(&mut self, limits: ReadLimits) {
+        self.read_limits = limits;
+    }
+
+    /// Adds a directory in which to look for imported libraries.
+    pub fn add_search_path<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.loader.add_search_path(dir);
+    }
+
+    /// Evaluates the forms in `text` in turn and returns the value of the
+    /// last. Nothing is run if any form cannot be read. Imports stay in
+    /// effect for later calls.
+    pub fn eval<T: FromScheme>(&mut self, text: &str) -> Result<T> {
+        let mut nodes = Vec::new();
+        for datum in SpannedParser::with_limits(text, self.read_limits) {
+            nodes.push(datum?.0);
+        }
+        let mut res = Value::Unspecified;
+        for node in nodes {
+            res = self.loader.eval(&mut self.vm, node, Path::new("."))?;
+        }
+        self.convert(&res)
+    }
+
+    /// Runs the file at `path` as a program and returns the value of its
+    /// last form.
+    pub fn eval_file<T: FromScheme, P: AsRef<Path>>(&mut self, path: P) -> Result<T> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let mut nodes = Vec::new();
+        for datum in SpannedParser::with_limits(&text, self.read_limits) {
+            nodes.push(datum?.0);
+        }
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let res = self.loader.load_program(&mut self.vm, nodes, dir)?;
+        self.convert(&res)
+    }
+
+    /// Defines the global `name` as `value`.
+    pub fn define<T: ToScheme>(&mut self, name: &str, value: T) -> Result<()> {
+        let v = value.to_scheme(self.vm.heap_mut())?;
+        self.vm.define(name, v);
+        Ok(())
+    }
+
+    /// The value of the global `name`.
+    pub fn get<T: FromScheme>(&mut self, name: &str) -> Result<T> {
+        match self.vm.lookup(name) {
+            Some(v) => self.convert(&v),
+            None => Err(Error::Runtime(
+                RuntimeError::Basic(format!("Unbound variable {}", name)))),
+        }
+    }
+
+    /// Calls the procedure that the global `name` holds with `args`.
+    pub fn call<A: Arguments, T: FromScheme>(&mut self, name: &str, args: A) -> Result<T> {
+        let f: Value = self.get(name)?;
+        let args = args.to_scheme_args(self.vm.heap_mut())?;
+        let res = self.vm.call(f, args)?;
+        self.convert(&res)
+    }
+
+    /// Defines the global `name` as a procedure that calls `f`. Its
+    /// arguments are converted to the types `f` takes, and its result back.
+    pub fn register<Args, F: HostProcedure<Args>>(&mut self, name: &str, f: F) {
+        let arity = f.arity();
+        self.define_host(name, arity, Box::new(move |heap, args| f.call(heap, args)));
+    }
+
+    /// Like `register`, for a procedure that takes any number of arguments
+    /// of one type.
+    pub fn register_variadic<A, R, F>(&mut self, name: &str, f: F)
+    where A: FromScheme, R: ToScheme, F: Fn(Vec<A>) -> R + 'static {
+        self.define_host(name, Arity::at_least(0), Box::new(move |heap, args| {
+            let mut xs = Vec::new();
+            for v in args {
+                xs.push(A::from_scheme(heap, v)?);
+            }
+            f(xs).to_scheme(heap)
+        }));
+    }
+
+    fn define_host(&mut self, name: &str, arity: Arity, func: value::HostFn) {
+        let host = Host { name: name.to_string(), arity, func };
+        self.vm.define(name, Value::Host(Rc::new(host)));
+    }
+
+    fn convert<T: FromScheme>(&self, v: &Value) -> Result<T> {
+        Ok(T::from_scheme(self.vm.heap(), v)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::env;
+    use std::fs;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use vm::Limits;
+    use super::{Interpreter, Symbol};
+
+    #[test]
+    fn conversions() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval::<i64>("(+ 1 2)").unwrap(), 3);
+        assert_eq!(interp.eval::<f64>("(/ 1 2)").unwrap(), 0.5);
+        assert_eq!(interp.eval::<Vec<String>>("(list \"a\" \"b\")").unwrap(), vec!["a", "b"]);
+        assert_eq!(interp.eval::<Vec<u8>>("(vector 1 2)").unwrap(), vec![1, 2]);
+        assert_eq!(interp.eval::<Option<char>>("#f").unwrap(), None);
+        assert_eq!(interp.eval::<Symbol>("(quote a)").unwrap(), Symbol("a".to_string()));
+        assert_eq!(interp.eval::<u8>("300").unwrap_err().to_string(),
+                   "RuntimeError: Integer out of range: 300");
+        assert_eq!(interp.eval::<String>("1").unwrap_err().to_string(),
+                   "RuntimeError: Expected string, got number 1");
+        interp.define("config", vec![Some("x"), None]).unwrap();
+        assert_eq!(interp.eval::<String>("(car config)").unwrap(), "x");
+        assert_eq!(interp.get::<Vec<Option<String>>>("config").unwrap(),
+                   vec![Some("x".to_string()), None]);
+        interp.define("big", u64::MAX).unwrap_err();
+        assert!(interp.eval::<Vec<i32>>("(let ((l (list 1))) (set-cdr! l l) l)").is_err());
+    }
+
+    #[test]
+    fn host_procedures() {
+        let mut interp = Interpreter::new();
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        interp.register("greet", move |name: String| {
+            counter.set(counter.get() + 1);
+            format!("Hello, {}", name)
+        });
+        interp.register("checked-div", |a: i32, b: i32| {
+            if b == 0 { Err("Division by zero") } else { Ok(a / b) }
+        });
+        interp.register_variadic("sum", |xs: Vec<f64>| xs.iter().sum::<f64>());
+        assert_eq!(interp.eval::<String>("(greet \"you\")").unwrap(), "Hello, you");
+        assert_eq!(calls.get(), 1);
+        assert_eq!(interp.eval::<f64>("(sum 1 2.5)").unwrap(), 3.5);
+        assert_eq!(interp.eval::<String>("(guard (e (#t (error-object-message e))) \
+                                            (checked-div 1 0))").unwrap(), "Division by zero");
+        assert_eq!(interp.eval::<()>("(greet 1)").unwrap_err().to_string(),
+                   "RuntimeError: Expected string, got number 1");
+        assert_eq!(interp.eval::<()>("(greet)").unwrap_err().to_string(),
+                   "RuntimeError: Wrong number of arguments: expected 1, got 0");
+        assert_eq!(interp.call::<_, i32>("checked-div", (7, 2)).unwrap(), 3);
+        interp.eval::<()>("(define (add . xs) (apply + xs))").unwrap();
+        assert_eq!(interp.call::<_, i32>("add", vec![1, 2, 3]).unwrap(), 6);
+    }
+
+    #[test]
+    fn limits() {
+        let mut interp = Interpreter::new();
+        interp.set_limits(Limits { steps: Some(10000), depth: Some(100), ..Limits::default() });
+        assert_eq!(interp.eval::<String>("(guard (e (#t \"caught\")) (let loop () (loop)))")
+                   .unwrap_err().to_string(), "LimitError: Step limit of 10000 exceeded");
+        assert_eq!(interp.eval::<()>("(define (f n) (+ 1 (f n))) (f 1)")
+                   .unwrap_err().to_string(), "LimitError: Recursion depth limit of 100 exceeded");
+        assert_eq!(interp.eval::<i32>("(let loop ((i 0)) (if (= i 100) i (loop (+ i 1))))")
+                   .unwrap(), 100);
+
+        let max = interp.vm.heap().size() + 10000;
+        interp.set_limits(Limits { heap: Some(max), ..Limits::default() });
+        assert_eq!(interp.eval::<()>("(make-vector 20000)").unwrap_err().to_string(),
+                   format!("LimitError: Heap limit of {} cells exceeded", max));
+        assert!(interp.eval::<()>("(define l (let loop ((i 0) (l (quote ()))) \
+                                     (if (= i 20000) l (loop (+ i 1) (cons i l)))))")
+                .unwrap_err().to_string().starts_with("LimitError: Heap limit"));
+        assert_eq!(interp.eval::<i32>("(let loop ((i 0) (v #f)) \
+                                         (if (= i 1000) i (loop (+ i 1) (make-vector 100))))")
+                   .unwrap(), 1000);
+
+        // Primitives check the heap limit before they allocate, as well.
+        assert!(interp.eval::<()>("(define v (make-vector 6000 0)) (vector->list v) 1")
+                .unwrap_err().to_string().starts_with("LimitError: Heap limit"));
+        interp.eval::<()>("(set! v #f)").unwrap();
+        assert_eq!(interp.eval::<i32>("(length (vector->list (make-vector 4000 0)))").unwrap(),
+                   4000);
+
+        interp.set_limits(Limits { time: Some(Duration::from_millis(50)), ..Limits::default() });
+        assert_eq!(interp.eval::<()>("(let loop () (loop))").unwrap_err().to_string(),
+                   "LimitError: Time limit of 50ms exceeded");
+
+        interp.set_limits(Limits { fuel: Some(5000), ..Limits::default() });
+        interp.eval::<()>("(let loop ((i 0)) (if (< i 100) (loop (+ i 1))))").unwrap();
+        interp.eval::<()>("(let loop ((i 0)) (if (< i 100) (loop (+ i 1))))").unwrap();
+        assert_eq!(interp.eval::<()>("(let loop () (loop))").unwrap_err().to_string(),
+                   "LimitError: Fuel of 5000 steps exhausted");
+        assert!(interp.eval::<i32>("1").is_err());
+        interp.set_limits(Limits::default());
+        assert_eq!(interp.eval::<i32>("1").unwrap(), 1);
+    }
+
+    #[test]
+    fn sandbox() {
+        let dir = env::temp_dir().join(format!("scheme-syntax-sandbox-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("secret.scm"), "42").unwrap();
+        fs::write(dir.join("lib.sld"), "(define-library (lib) (export x) (begin (define x 1)))")
+            .unwrap();
+        let mut interp = Interpreter::sandboxed();
+        interp.add_search_path(&dir);
+        let include = format!("(include {:?})", dir.join("secret.scm").display().to_string());
+        assert_eq!(interp.eval::<()>(&include).unwrap_err().to_string(),
+                   "LibraryError: include: File access is not allowed");
+        assert_eq!(interp.eval::<()>("(import (lib))").unwrap_err().to_string(),
+                   "LibraryError: Library (lib) not found");
+        assert_eq!(interp.eval::<()>("(display 1)").unwrap_err().to_string(),
+                   "RuntimeError: Unbound variable display");
+        assert_eq!(interp.eval::<i32>("(import (scheme base)) (+ 1 2)").unwrap(), 3);
+        interp.register("log", |_: String| ());
+        interp.eval::<()>("(log \"allowed\")").unwrap();
+        assert_eq!(interp.eval::<i32>("(time (+ 1 2))").unwrap(), 3);
+        assert!(!interp.vm.reports_time());
+        assert_eq!(interp.eval::<()>("(let loop () (loop))").unwrap_err().to_string(),
+                   "LimitError: Time limit of 1s exceeded");
+        assert!(interp.eval::<()>("(define l (vector->list (make-vector 3000000 0))) \
+                                   (append l l)")
+                .unwrap_err().to_string().starts_with("LimitError: Heap limit"));
+        assert_eq!(interp.eval::<()>("(define l (list 1)) (set-cdr! l l) (list-copy l)")
+                   .unwrap_err().to_string(), "RuntimeError: Circular list");
+        assert_eq!(Interpreter::new().eval::<i32>(&include).unwrap(), 42);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files() {
+        let dir = env::temp_dir().join(format!("scheme-syntax-interpreter-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("util")).unwrap();
+        fs::write(dir.join("util").join("math.sld"),
+                  "(define-library (util math) (export square) (import (scheme base)) \
+                   (begin (define (square x) (* x x))))").unwrap();
+        fs::write(dir.join("main.scm"), "(import (scheme base) (util math)) (square 7)").unwrap();
+        let mut interp = Interpreter::new();
+        interp.add_search_path(&dir);
+        assert_eq!(interp.eval_file::<i32, _>(dir.join("main.scm")).unwrap(), 49);
+        assert_eq!(interp.eval::<i32>("(import (util math)) (square 3)").unwrap(), 9);
+        assert!(interp.eval_file::<(), _>(dir.join("missing.scm")).is_err());
+        assert_eq!(interp.eval::<()>("(f").unwrap_err().to_string(), "ReadError: unexpected EOF");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,14 @@
 use std;
+use std::sync::OnceLock;
 extern crate regex;
 use self::regex::Regex;
-use std::ascii::AsciiExt;
+
+use borrowed;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Literal {
     Number(i32),
+    Real(f64),
     Boolean(bool),
     Character(char),
     String(String),
@@ -19,80 +22,101 @@ pub enum Token {
     Literal(Literal),
 }
 
+/// A range of byte offsets into the source text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
 pub type Result<T> = std::result::Result<T, &'static str>;
 
 pub trait Lexer : Iterator<Item=Result<Token>> {}
 impl <I> Lexer for I where I: Iterator<Item=Result<Token>> {}
 
 pub struct RegexLexer<'t> {
-    lexer_re: Regex,
+    lexer_re: &'static Regex,
     rest: &'t str,
+    offset: usize,
+    span: Span,
+}
+
+/// The lexer's regex, compiled once and shared by all lexers on all
+/// threads.
+fn lexer_re() -> &'static Regex {
+    static LEXER_RE: OnceLock<Regex> = OnceLock::new();
+    LEXER_RE.get_or_init(|| Regex::new(
+        r#"(?xi)(?:\s|;[^\n]*(?:\n|$))*
+           (?:
+           (?P<lparen>\()|
+           (?P<rparen>\))|
+           (?P<real>[+-]?(?:[0-9]+\.[0-9]*|\.[0-9]+)(?:e[+-]?[0-9]+)?|
+                    [+-]?[0-9]+e[+-]?[0-9]+|[+-](?:inf|nan)\.0)|
+           (?P<number>[+-]?[0-9]+)|
+           (?P<identifier>[a-z!$%&*/:<=>?~_^]
+                [a-z!$%&*/:<=>?~_^0-9.+-]*|
+                [+-](?:[a-z!$%&*/:<=>?~_^+-][a-z!$%&*/:<=>?~_^0-9.+-]*)?|
+                \.\.\.|\.)|
+           (?P<boolean>\#[tf])|
+           (?P<character>\#\\(?:newline|space|.))|
+           (?P<string>"(?:[^\\"]|\\.)*")|
+           (?P<end>$))"#).unwrap())
 }
 
 impl <'t> RegexLexer<'t> {
     pub fn new(text: &'t str) -> Self {
-        let lexer_re = Regex::new(
-            r#"\s*(?xi)
-               (?:
-               (?P<lparen>\()|
-               (?P<rparen>\))|
-               (?P<identifier>[a-z!$%&*/:<=>?~_^]
-                    [a-z!$%&*/:<=>?~_^0-9.+-]*)|
-               (?P<boolean>\#[tf])|
-               (?P<number>[0-9]+)|
-               (?P<character>\#\\(?:newline|space|.))|
-               (?P<string>"(?:[^\\]|\\.)*"))"#).unwrap();
-
         RegexLexer {
-            lexer_re: lexer_re,
+            lexer_re: lexer_re(),
             rest: text,
+            offset: 0,
+            span: Span { start: 0, end: 0 },
         }
     }
-}
 
-impl <'t> Iterator for RegexLexer<'t> {
-    type Item = Result<Token>;
+    /// The span of the token last returned, or of the rest of the text if
+    /// it failed to lex.
+    pub fn span(&self) -> Span {
+        self.span
+    }
 
-    fn next(&mut self) -> Option<Result<Token>> {
-        let mo = match self.lexer_re.captures(self.rest) {
-            None => return None,
-            Some(mo) => mo,
-        };
+    /// The next token, with identifiers and strings borrowed from the text.
+    /// String escapes are checked but not replaced.
+    pub fn next_borrowed(&mut self) -> Option<Result<borrowed::Token<'t>>> {
+        let mo = self.lexer_re.captures(self.rest)?;
         let (begin, end) = mo.pos(0).unwrap();
         if begin != 0 {
+            self.span = Span { start: self.offset, end: self.offset + self.rest.len() };
             self.rest = "";
             return Some(Err("unmatched"));
         }
-        self.rest = &self.rest[end..];
-        let (groupname, value) = mo.iter_named().filter_map(
+        let text = self.rest;
+        self.rest = &text[end..];
+        let (groupname, len) = mo.iter_named().filter_map(
             |(key, value_opt)|
-            match value_opt {
-                Some(value) => Some((key, value)),
-                None => None,
-            }).next().unwrap();
-
-        fn weed_string(s: String) -> Result<String> {
-            let mut r = String::new();
-            let mut bs = false;
-            for c in s[1..s.len()-1].chars() {
-                if bs {
-                    if c == '\\' { r.push('\\'); }
-                    else if c == 'n' { r.push('\n'); }
-                    else if c == 't' { r.push('\t'); }
-                    else { return Err("Bad string escape"); }
-                    bs = false;
-                } else if c == '\\' {
-                    bs = true;
-                } else {
-                    r.push(c);
-                }
-            }
-            Ok(r)
+            value_opt.map(|value| (key, value.len()))).next().unwrap();
+        // The match is the token preceded by whitespace and comments.
+        let start = end - len;
+        let value = &text[start..end];
+        if groupname == "end" {
+            // Only whitespace and comments are left.
+            self.rest = "";
+            return None;
         }
+        self.span = Span { start: self.offset + start, end: self.offset + end };
+        self.offset += end;
 
-        fn parse_literal(groupname: &str, value: &str) -> Result<Literal> {
+        fn parse_literal<'t>(groupname: &str, value: &'t str) -> Result<borrowed::Literal<'t>> {
+            use borrowed::Literal;
             Ok(if groupname == "number" {
-                Literal::Number(value.parse::<i32>().unwrap())
+                Literal::Number(value.parse::<i32>().map_err(|_| "number out of range")?)
+            } else if groupname == "real" {
+                // Rust reads `+inf` and `-nan` without the `.0` of Scheme.
+                let value = if value.ends_with("inf.0") || value.ends_with("nan.0") {
+                    &value[..value.len() - 2]
+                } else {
+                    value
+                };
+                Literal::Real(value.parse::<f64>().map_err(|_| "bad real number")?)
             } else if groupname == "boolean" {
                 if value == "#t" { Literal::Boolean(true) }
                 else if value == "#f" { Literal::Boolean(false) }
@@ -102,24 +126,32 @@ impl <'t> Iterator for RegexLexer<'t> {
                 else if value.eq_ignore_ascii_case("#\\space") { Literal::Character(' ') }
                 else { Literal::Character(value.chars().nth(2).unwrap()) }
             } else if groupname == "string" {
-                Literal::String(try!(weed_string(value.to_string())))
+                Literal::String(borrowed::Str::new(&value[1..value.len()-1])?)
             } else { panic!("unknown match group {}", groupname) })
         }
 
         Some(Ok(
-            if groupname == "lparen" { Token::LParen }
-            else if groupname == "rparen" { Token::RParen }
+            if groupname == "lparen" { borrowed::Token::LParen }
+            else if groupname == "rparen" { borrowed::Token::RParen }
             else if groupname == "identifier" {
-                Token::Identifier(value.to_string()) }
+                borrowed::Token::Identifier(value) }
             else {
                 match parse_literal(groupname, value) {
-                    Ok(v) => Token::Literal(v),
+                    Ok(v) => borrowed::Token::Literal(v),
                     Err(e) => return Some(Err(e)),
                 }
             }))
     }
 }
 
+impl <'t> Iterator for RegexLexer<'t> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Result<Token>> {
+        self.next_borrowed().map(|tok| tok.map(borrowed::Token::into_owned))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,4 +164,14 @@ mod tests {
         assert_eq!(tokens[0], Ok(Token::LParen));
         assert_eq!(tokens[3], Ok(Token::RParen));
     }
+
+    #[test]
+    fn spans() {
+        let mut l = RegexLexer::new(" (foo ; x\n \"b\")");
+        let mut spans = vec![];
+        while let Some(_) = l.next() {
+            spans.push((l.span().start, l.span().end));
+        }
+        assert_eq!(spans, vec![(1, 2), (2, 5), (11, 14), (14, 15)]);
+    }
 }
@@ -0,0 +1,430 @@
+//! Reading, syntax analysis and evaluation of Scheme programs.
+//!
+//! `RegexLexer` splits source text into tokens, `Parser` reads them as
+//! data, and `parse_expression` turns each datum into an expression of the
+//! `syntax` module:
+//!
+//! ```
+//! use scheme_syntax::{parse_expression, Parser, RegexLexer};
+//! use scheme_syntax::syntax::Expression;
+//!
+//! for datum in Parser::new(RegexLexer::new("(f 1) x")) {
+//!     match parse_expression(datum.unwrap()).unwrap() {
+//!         Expression::Application(..) | Expression::Variable(_) => (),
+//!         e => panic!("unexpected {:?}", e),
+//!     }
+//! }
+//! ```
+//!
+//! `SpannedParser` also records where each datum came from, for error
+//! messages that point into the source. The `ast` module keeps expressions
+//! in an arena with interned symbols instead, for large files, and the
+//! `borrowed` module reads data that borrow their text from the source.
+//! The `parallel` module reads and expands large files on several threads.
+//!
+//! `Interpreter` runs programs, with Rust closures as procedures and values
+//! converted by `ToScheme` and `FromScheme`:
+//!
+//! ```
+//! use scheme_syntax::Interpreter;
+//!
+//! let mut interp = Interpreter::new();
+//! interp.register("scale", |x: f64| x * 1.5);
+//! assert_eq!(interp.eval::<f64>("(scale 4)").unwrap(), 6.0);
+//! ```
+
+mod lexer;
+mod parser;
+// mod io;
+// use io::CharsWrap;
+mod scheme;
+mod value;
+mod heap;
+mod compiler;
+mod builtins;
+mod vm;
+mod library;
+mod json;
+mod diagnostic;
+mod codes;
+mod serialize;
+mod glob;
+mod interpreter;
+mod conformance;
+pub mod ast;
+pub mod borrowed;
+pub mod parallel;
+/// The `scheme-syntax` command-line tool. Not part of the library's API.
+#[doc(hidden)]
+pub mod cli;
+#[doc(hidden)]
+pub mod fuzz;
+
+pub use lexer::{Lexer, Literal, RegexLexer, Span, Token};
+pub use parser::{Node, Parser, ReadError, ReadLimits, SpannedParser, Spans};
+pub use scheme::{parse_expression, Label, SchemeError};
+pub use scheme::syntax;
+pub use interpreter::{Arguments, Error, FromScheme, HostProcedure, Interpreter, Symbol, ToScheme};
+pub use heap::Heap;
+pub use value::{Arity, RuntimeError, Value};
+pub use vm::Limits;
+
+#[cfg(test)]
+mod tests {
+    use parser::{Node, Parser};
+    use lexer::RegexLexer;
+    use super::scheme::syntax::{Expression, Literal, Quotation, Formals, CondClause, ClauseBody,
+                                Field, RecordType};
+
+    fn parse(s: &str) -> Node {
+        let lexer = RegexLexer::new(s);
+        let mut parser = Parser::new(lexer);
+        parser.next().unwrap().unwrap()
+    }
+
+    fn expr_res(s: &str) -> super::scheme::Result<Expression> {
+        super::scheme::parse_expression(parse(s))
+    }
+
+    fn expr(s: &str) -> Expression {
+        expr_res(s).unwrap()
+    }
+
+    #[test]
+    fn parse_test() {
+        parse("(foo bar)");
+        parse("foo");
+        parse("foo )garbage");
+    }
+
+    #[test]
+    fn number() {
+        assert_eq!(expr("12"), Expression::Literal(Literal::Number(12)));
+    }
+
+    #[test]
+    fn bool_true() {
+        assert_eq!(expr("#t"), Expression::Literal(Literal::Boolean(true)));
+    }
+
+    #[test]
+    fn bool_false() {
+        assert_eq!(expr("#f"), Expression::Literal(Literal::Boolean(false)));
+    }
+
+    #[test]
+    fn char_normal() {
+        assert_eq!(expr("#\\a"), Expression::Literal(Literal::Character('a')));
+    }
+
+    #[test]
+    fn char_nl() {
+        assert_eq!(expr("#\\newline"), Expression::Literal(Literal::Character('\n')));
+    }
+
+    #[test]
+    fn char_nl_upper() {
+        assert_eq!(expr("#\\NewLine"), Expression::Literal(Literal::Character('\n')));
+    }
+
+    #[test]
+    fn char_space() {
+        assert_eq!(expr("#\\SPace"), Expression::Literal(Literal::Character(' ')));
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(expr("\"a\\nb\\tc\""), Expression::Literal(Literal::String("a\nb\tc".to_string())));
+    }
+
+    #[test]
+    fn var() {
+        assert_eq!(expr("foobar"), Expression::Variable("foobar".to_string()));
+    }
+
+    #[test]
+    fn time() {
+        assert_eq!(expr("(time 42)"),
+                   Expression::Time(Box::new(expr("42"))));
+    }
+
+    #[test]
+    fn parse_if() {
+        assert_eq!(expr("(if 1 2 3)"),
+                   Expression::If(
+                       Box::new(expr("1")),
+                       Box::new(expr("2")),
+                       Some(Box::new(expr("3")))));
+    }
+
+    #[test]
+    fn parse_and_0() {
+        assert_eq!(expr("(and)"),
+                   Expression::And(Vec::new()));
+    }
+
+    #[test]
+    fn parse_and_1() {
+        assert_eq!(expr("(and #t)"),
+                   Expression::And(vec![expr("#t")]));
+    }
+
+    #[test]
+    fn parse_and_3() {
+        assert_eq!(expr("(and #t #t #f)"),
+                   Expression::And(vec![
+                       expr("#t"),
+                       expr("#t"),
+                       expr("#f"),
+                   ]));
+    }
+
+    #[test]
+    fn parse_or_2() {
+        assert_eq!(expr("(or #t #f)"),
+                   Expression::Or(vec![
+                       expr("#t"),
+                       expr("#f"),
+                   ]));
+    }
+
+    #[test]
+    fn parse_begin_0() {
+        expr_res("(begin)").unwrap_err();
+    }
+
+    #[test]
+    fn begin_1() {
+        assert_eq!(expr("(begin 2)"),
+                   Expression::Begin(vec![expr("2")]));
+    }
+
+    #[test]
+    fn unless() {
+        assert_eq!(expr("(unless #t 2)"),
+                   Expression::Unless(
+                       Box::new(expr("#t")),
+                       vec![expr("2")]));
+        assert_eq!(expr("(when #t 2 3)"),
+                   Expression::When(
+                       Box::new(expr("#t")),
+                       vec![expr("2"), expr("3")]));
+        expr_res("(when #t)").unwrap_err();
+    }
+
+    #[test]
+    fn if_() {
+        assert_eq!(expr("(if #t 1)"),
+                   Expression::If(Box::new(expr("#t")), Box::new(expr("1")), None));
+        expr_res("(if #t)").unwrap_err();
+        expr_res("(if #t 1 2 3)").unwrap_err();
+    }
+
+    #[test]
+    fn set() {
+        assert_eq!(expr("(set! x 1)"), Expression::Set("x".to_string(), Box::new(expr("1"))));
+        expr_res("(set! 1 1)").unwrap_err();
+        expr_res("(set! x)").unwrap_err();
+    }
+
+    #[test]
+    fn do_() {
+        assert_eq!(expr("(do ((i 0 (+ i 1)) (v 1)) ((= i 3) v) (f i))"),
+                   Expression::Do(
+                       vec![("i".to_string(), expr("0"), Some(expr("(+ i 1)"))),
+                            ("v".to_string(), expr("1"), None)],
+                       Box::new(expr("(= i 3)")),
+                       vec![expr("v")],
+                       vec![expr("(f i)")]));
+        expr_res("(do ((i 0)))").unwrap_err();
+        expr_res("(do ((i 0) (i 1)) (#t))").unwrap_err();
+        expr_res("(do ((i)) (#t))").unwrap_err();
+        expr_res("(do () ())").unwrap_err();
+    }
+
+    #[test]
+    fn define_record_type() {
+        assert_eq!(expr("(define-record-type point (make-point y) point? (x px) (y py set-py!))"),
+                   Expression::DefineRecordType(RecordType {
+                       name: "point".to_string(),
+                       constructor: ("make-point".to_string(), vec!["y".to_string()]),
+                       predicate: "point?".to_string(),
+                       fields: vec![
+                           Field { name: "x".to_string(), accessor: "px".to_string(), modifier: None },
+                           Field {
+                               name: "y".to_string(),
+                               accessor: "py".to_string(),
+                               modifier: Some("set-py!".to_string()),
+                           },
+                       ],
+                   }));
+        expr_res("(define-record-type point (make-point) point?)").unwrap();
+        expr_res("(define-record-type point (make-point))").unwrap_err();
+        expr_res("(define-record-type point make-point point? (x px))").unwrap_err();
+        expr_res("(define-record-type point (make-point) point? x)").unwrap_err();
+        expr_res("(define-record-type point (make-point) point? (x))").unwrap_err();
+        expr_res("(define-record-type point (make-point) point? (x px 1))").unwrap_err();
+        expr_res("(define-record-type point (make-point) point? (x px) (x py))").unwrap_err();
+        expr_res("(define-record-type point (make-point z) point? (x px))").unwrap_err();
+        expr_res("(define-record-type point (make-point x x) point? (x px))").unwrap_err();
+    }
+
+    #[test]
+    fn parameterize() {
+        assert_eq!(expr("(parameterize ((p 1)) 2)"),
+                   Expression::Parameterize(vec![(expr("p"), expr("1"))], vec![expr("2")]));
+        expr_res("(parameterize ((p 1)))").unwrap_err();
+        expr_res("(parameterize (p 1) 2)").unwrap_err();
+        expr_res("(parameterize)").unwrap_err();
+    }
+
+    #[test]
+    fn values() {
+        let formals = |required: &[&str], rest: Option<&str>| Formals {
+            required: required.iter().map(|s| s.to_string()).collect(),
+            rest: rest.map(|s| s.to_string()),
+        };
+        assert_eq!(expr("(let-values (((a b) (f)) (c (g))) a)"),
+                   Expression::LetValues(vec![(formals(&["a", "b"], None), expr("(f)")),
+                                              (formals(&[], Some("c")), expr("(g)"))],
+                                         vec![expr("a")]));
+        assert_eq!(expr("(let*-values (((a) 1) ((a) 2)) a)"),
+                   Expression::LetStarValues(vec![(formals(&["a"], None), expr("1")),
+                                                  (formals(&["a"], None), expr("2"))],
+                                             vec![expr("a")]));
+        assert_eq!(expr("(define-values (q . r) (f))"),
+                   Expression::DefineValues(formals(&["q"], Some("r")), Box::new(expr("(f)"))));
+        assert_eq!(expr("(receive (a) (f) a)"),
+                   Expression::Receive(formals(&["a"], None), Box::new(expr("(f)")),
+                                       vec![expr("a")]));
+        assert_eq!(expr_res("(let-values (((a b) 1) ((b) 2)) a)").unwrap_err().code, "S0008");
+        assert_eq!(expr_res("(let-values (((a a) 1)) a)").unwrap_err().code, "S0008");
+        assert_eq!(expr_res("(let-values ((a)) a)").unwrap_err().code, "S0011");
+        assert_eq!(expr_res("(let-values (((1) 2)) 3)").unwrap_err().code, "S0006");
+        assert_eq!(expr_res("(let-values (((a) 1)))").unwrap_err().code, "S0009");
+        assert_eq!(expr_res("(define-values (a))").unwrap_err().code, "S0002");
+        assert_eq!(expr_res("(receive (a) (f))").unwrap_err().code, "S0009");
+    }
+
+    #[test]
+    fn guard() {
+        assert_eq!(expr("(guard (e ((f e) 1) (else 2)) 3)"),
+                   Expression::Guard(
+                       "e".to_string(),
+                       vec![CondClause::Simple(expr("(f e)"), vec![expr("1")])],
+                       Some(vec![expr("2")]),
+                       vec![expr("3")]));
+        expr_res("(guard (e))").unwrap_err();
+        expr_res("(guard () 1)").unwrap_err();
+        expr_res("(guard ((e) #t) 1)").unwrap_err();
+        expr_res("(guard (e (else 1) (#t 2)) 3)").unwrap_err();
+    }
+
+    #[test]
+    fn delay() {
+        assert_eq!(expr("(delay 1)"), Expression::Delay(Box::new(expr("1"))));
+        assert_eq!(expr("(delay-force 1)"), Expression::DelayForce(Box::new(expr("1"))));
+        expr_res("(delay)").unwrap_err();
+    }
+
+    #[test]
+    fn cond() {
+        expr("(cond (#f #t) (42) (23 => bla) (else 1))");
+        assert_eq!(expr("(cond (#f 1 2))"),
+                   Expression::Cond(
+                       vec![CondClause::Simple(expr("#f"), vec![expr("1"), expr("2")])],
+                       None));
+        assert_eq!(expr("(cond)"), Expression::Cond(vec![], None));
+        expr_res("(cond (else 1) (#f 2))").unwrap_err();
+        expr_res("(cond (#f 1) (else))").unwrap_err();
+        expr_res("(cond (#f => f g))").unwrap_err();
+    }
+
+    #[test]
+    fn case() {
+        expr("(case 42 ((23) #t) ((42) #f) (else 0))");
+        assert_eq!(expr("(case 42 ((23) 1 2) (else => f))"),
+                   Expression::Case(
+                       Box::new(expr("42")),
+                       vec![(vec![Quotation::Literal(Literal::Number(23))],
+                             ClauseBody::Sequence(vec![expr("1"), expr("2")]))],
+                       Some(ClauseBody::Receiver(Box::new(expr("f"))))));
+        assert_eq!(expr("(case 42)"), Expression::Case(Box::new(expr("42")), vec![], None));
+        expr_res("(case)").unwrap_err();
+        expr_res("(case 42 ((1) =>))").unwrap_err();
+        expr_res("(case 42 ((1)))").unwrap_err();
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(expr("(quote 42)"),
+                   Expression::Quote(Quotation::Literal(Literal::Number(42))));
+        expr_res("(quote 1 2)").unwrap_err();
+        let cons = |a, d| Quotation::Cons(Box::new(a), Box::new(d));
+        let sym = |s: &str| Quotation::Symbol(s.to_string());
+        assert_eq!(expr("(quote (a . b))"), Expression::Quote(cons(sym("a"), sym("b"))));
+        assert_eq!(expr("(quote (a b . c))"),
+                   Expression::Quote(cons(sym("a"), cons(sym("b"), sym("c")))));
+        assert_eq!(expr("(quote (a))"), Expression::Quote(cons(sym("a"), Quotation::Nil)));
+        assert_eq!(expr_res("(quote (. a))").unwrap_err().code, "S0017");
+        // Long lists neither recurse when parsed nor when dropped.
+        expr(&format!("(quote ({}))", "1 ".repeat(100000)));
+    }
+
+    #[test]
+    fn lambda() {
+        assert_eq!(expr("(lambda (x y) x)"),
+                   Expression::Lambda(
+                       Formals {
+                           required: vec!["x".to_string(), "y".to_string()],
+                           rest: None,
+                       },
+                       vec![expr("x")]));
+        assert_eq!(expr("(lambda args args)"),
+                   Expression::Lambda(
+                       Formals { required: vec![], rest: Some("args".to_string()) },
+                       vec![expr("args")]));
+        assert_eq!(expr("(lambda (x . y) y)"),
+                   Expression::Lambda(
+                       Formals {
+                           required: vec!["x".to_string()],
+                           rest: Some("y".to_string()),
+                       },
+                       vec![expr("y")]));
+        expr_res("(lambda (x x) x)").unwrap_err();
+        expr_res("(lambda (x . x) x)").unwrap_err();
+        expr_res("(lambda (x . y z) x)").unwrap_err();
+        expr_res("(lambda (x))").unwrap_err();
+    }
+
+    #[test]
+    fn define() {
+        assert_eq!(expr("(define x 1)"),
+                   Expression::Define("x".to_string(), Box::new(expr("1"))));
+        assert_eq!(expr("(define (f x) x)"),
+                   Expression::Define("f".to_string(), Box::new(expr("(lambda (x) x)"))));
+        expr_res("(define x 1 2)").unwrap_err();
+    }
+
+    #[test]
+    fn parse_let() {
+        assert_eq!(expr("(let ((x 1)) x)"),
+                   Expression::Let(None, vec![("x".to_string(), expr("1"))], vec![expr("x")]));
+        assert_eq!(expr("(let loop ((x 1)) (loop x))"),
+                   Expression::Let(Some("loop".to_string()),
+                                   vec![("x".to_string(), expr("1"))],
+                                   vec![expr("(loop x)")]));
+        expr_res("(let ((x 1) (x 2)) x)").unwrap_err();
+    }
+
+    #[test]
+    fn application() {
+        assert_eq!(expr("(+ 1 x)"),
+                   Expression::Application(
+                       Box::new(expr("+")),
+                       vec![expr("1"), expr("x")]));
+        assert_eq!(expr("((f) 1)"),
+                   Expression::Application(Box::new(expr("(f)")), vec![expr("1")]));
+        expr_res("(1 2)").unwrap_err();
+    }
+}
@@ -0,0 +1,698 @@
+use std;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use compiler::body_definitions;
+use lexer::RegexLexer;
+use parser::{Node, Parser};
+use scheme::{self, SchemeError};
+use scheme::syntax::Expression;
+use value::{RuntimeError, Value};
+use vm::Vm;
+
+#[derive(Debug)]
+pub enum LibraryError {
+    Basic(String),
+    Syntax(SchemeError),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LibraryError::Basic(s) => write!(f, "LibraryError: {}", s),
+            LibraryError::Syntax(e) => write!(f, "{}", e),
+            LibraryError::Runtime(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<SchemeError> for LibraryError {
+    fn from(e: SchemeError) -> Self {
+        LibraryError::Syntax(e)
+    }
+}
+
+impl From<RuntimeError> for LibraryError {
+    fn from(e: RuntimeError) -> Self {
+        LibraryError::Runtime(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, LibraryError>;
+
+/// Maps the identifiers visible in a program or library body to the globals
+/// they denote. Unmapped identifiers in a program refer to the global of the
+/// same name; in a library they refer to a global private to the library.
+#[derive(Debug, Default, Clone)]
+pub struct Namespace {
+    bindings: HashMap<String, String>,
+    library: Option<String>,
+}
+
+impl Namespace {
+    fn library(name: &[String]) -> Self {
+        Namespace {
+            bindings: HashMap::new(),
+            library: Some(library_display(name)),
+        }
+    }
+
+    pub fn global<'a>(&'a self, name: &'a str) -> Cow<'a, str> {
+        match self.bindings.get(name) {
+            Some(global) => Cow::Borrowed(global),
+            None => match self.library {
+                // Identifiers cannot contain parentheses, so this never
+                // collides with a name written in a program.
+                Some(ref library) => Cow::Owned(format!("{}@{}", name, library)),
+                None => Cow::Borrowed(name),
+            },
+        }
+    }
+
+    fn import(&mut self, names: HashMap<String, String>) {
+        self.bindings.extend(names);
+    }
+
+    fn define_private(&mut self, name: &str) {
+        let global = match self.library {
+            Some(ref library) => format!("{}@{}", name, library),
+            None => name.to_string(),
+        };
+        self.bindings.insert(name.to_string(), global);
+    }
+}
+
+/// Exports of the standard libraries other than `(scheme base)`, which
+/// exports every other builtin.
+const STANDARD_LIBRARIES: &[(&str, &[&str])] = &[
+    ("char", &["char-alphabetic?", "char-numeric?", "char-whitespace?",
+               "char-upper-case?", "char-lower-case?", "char-upcase",
+               "char-downcase", "string-upcase", "string-downcase"]),
+    ("inexact", &["sqrt"]),
+    ("lazy", &["force", "make-promise", "promise?"]),
+    ("write", &["display", "write"]),
+];
+
+/// Identifiers that `cond-expand` considers present.
+const FEATURES: &[&str] = &["r7rs", "full-unicode", "scheme-syntax"];
+
+type LibraryName = Vec<String>;
+
+/// Exported identifier to global name.
+type Exports = HashMap<String, String>;
+
+fn library_display(name: &[String]) -> String {
+    format!("({})", name.join(" "))
+}
+
+fn library_name(n: &Node) -> Result<LibraryName> {
+    let parts = match n {
+        Node::List(parts) if !parts.is_empty() => parts,
+        n => return Err(LibraryError::Basic(
+            format!("Expected library name, got {:?}", n))),
+    };
+    let mut name = Vec::new();
+    for p in parts {
+        name.push(match p {
+            Node::Identifier(s) => s.clone(),
+            Node::Literal(::lexer::Literal::Number(k)) if *k >= 0 => k.to_string(),
+            p => return Err(LibraryError::Basic(
+                format!("Expected identifier or exact integer in library name, got {:?}", p))),
+        });
+    }
+    Ok(name)
+}
+
+fn identifier(n: Node, context: &str) -> Result<String> {
+    match n {
+        Node::Identifier(s) => Ok(s),
+        n => Err(LibraryError::Basic(
+            format!("{}: Expected identifier, got {:?}", context, n))),
+    }
+}
+
+/// The keyword of a form such as `(import ...)`.
+fn keyword(n: &Node) -> Option<&str> {
+    match n {
+        Node::List(l) => match l.first() {
+            Some(Node::Identifier(k)) => Some(k),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn arguments(n: Node) -> Vec<Node> {
+    match n {
+        Node::List(mut l) => l.split_off(1),
+        _ => Vec::new(),
+    }
+}
+
+pub fn read_file(path: &Path) -> Result<Vec<Node>> {
+    let text = fs::read_to_string(path).map_err(|e| LibraryError::Basic(
+        format!("{}: {}", path.display(), e)))?;
+    let mut nodes = Vec::new();
+    for node in Parser::new(RegexLexer::new(&text)) {
+        nodes.push(node.map_err(|e| LibraryError::Basic(
+            format!("{}: {}", path.display(), e)))?);
+    }
+    Ok(nodes)
+}
+
+pub fn parent(path: &Path) -> PathBuf {
+    path.parent().map_or_else(PathBuf::new, Path::to_path_buf)
+}
+
+/// Loads programs and the libraries they import into a `Vm`. Libraries are
+/// found as `<dir>/<name parts joined by />.sld` (or `.scm`) under the
+/// directories of the search path, and each is instantiated once.
+pub struct Loader {
+    search_path: Vec<PathBuf>,
+    builtins: Vec<String>,
+    libraries: HashMap<LibraryName, Exports>,
+    /// Libraries whose definitions are being processed, to detect cycles.
+    loading: Vec<LibraryName>,
+    program: Namespace,
+    execute: bool,
+    /// Whether `include` and imports may read files.
+    files: bool,
+}
+
+impl Loader {
+    /// Creates a loader whose standard libraries export the builtins of
+    /// `vm`. If `execute` is false, code is compiled but not run.
+    pub fn new(vm: &Vm, execute: bool) -> Self {
+        let mut builtins: Vec<String> = vm.global_names().into_iter()
+            .filter(|name| !name.starts_with('%'))
+            .collect();
+        builtins.sort();
+        Loader {
+            search_path: Vec::new(),
+            builtins,
+            libraries: HashMap::new(),
+            loading: Vec::new(),
+            program: Namespace::default(),
+            execute,
+            files: true,
+        }
+    }
+
+    pub fn add_search_path<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.search_path.push(dir.into());
+    }
+
+    /// Makes `include` fail and imports find only the standard libraries
+    /// and those already defined.
+    pub fn deny_files(&mut self) {
+        self.files = false;
+    }
+
+    /// Forms named by the file names in an `include`, read relative to
+    /// `dir`.
+    fn include(&self, files: Vec<Node>, dir: &Path) -> Result<Vec<Node>> {
+        if !self.files {
+            return Err(LibraryError::Basic("include: File access is not allowed".to_string()));
+        }
+        let mut nodes = Vec::new();
+        for f in files {
+            match f {
+                Node::Literal(::lexer::Literal::String(f)) =>
+                    nodes.extend(read_file(&dir.join(f))?),
+                f => return Err(LibraryError::Basic(
+                    format!("include: Expected file name, got {:?}", f))),
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Runs the forms of a program and returns the value of the last. The
+    /// program sees the globals of the VM, but not what earlier programs
+    /// imported.
+    pub fn load_program(&mut self, vm: &mut Vm, nodes: Vec<Node>, dir: &Path) -> Result<Value> {
+        self.load_expanded(vm, nodes.into_iter().map(|node| (node, None)).collect(), dir)
+    }
+
+    /// Runs a program as `load_program` does, but runs the expression
+    /// given with a form, if any, instead of expanding the form again.
+    pub fn load_expanded(&mut self, vm: &mut Vm, forms: Vec<(Node, Option<Expression>)>,
+                         dir: &Path) -> Result<Value> {
+        self.program = Namespace::default();
+        let mut res = Value::Unspecified;
+        for (node, e) in forms {
+            res = match e {
+                Some(e) => {
+                    let namespace = self.program.clone();
+                    self.run(vm, &e, &namespace)?
+                },
+                None => self.eval(vm, node, dir)?,
+            };
+        }
+        Ok(res)
+    }
+
+    /// Runs a top-level program form. `dir` is where `include` looks for
+    /// files.
+    pub fn eval(&mut self, vm: &mut Vm, node: Node, dir: &Path) -> Result<Value> {
+        match keyword(&node) {
+            Some("import") => {
+                for set in arguments(node) {
+                    let names = self.import_set(vm, set)?;
+                    self.program.import(names);
+                }
+                Ok(Value::Unspecified)
+            },
+            Some("define-library") => {
+                self.define_library(vm, arguments(node), dir)?;
+                Ok(Value::Unspecified)
+            },
+            Some("include") => {
+                let nodes = self.include(arguments(node), dir)?;
+                self.eval_all(vm, nodes, dir)
+            },
+            Some("cond-expand") => {
+                let forms = self.cond_expand(arguments(node))?;
+                self.eval_all(vm, forms, dir)
+            },
+            _ => {
+                let e = scheme::parse_expression(node)?;
+                let namespace = self.program.clone();
+                self.run(vm, &e, &namespace)
+            },
+        }
+    }
+
+    fn eval_all(&mut self, vm: &mut Vm, nodes: Vec<Node>, dir: &Path) -> Result<Value> {
+        let mut res = Value::Unspecified;
+        for node in nodes {
+            res = self.eval(vm, node, dir)?;
+        }
+        Ok(res)
+    }
+
+    fn run(&mut self, vm: &mut Vm, e: &Expression, namespace: &Namespace) -> Result<Value> {
+        let chunk = vm.compile_in(e, namespace)?;
+        if !self.execute {
+            return Ok(Value::Unspecified);
+        }
+        Ok(vm.execute(chunk)?)
+    }
+
+    fn standard_library(&self, name: &[String]) -> Option<Exports> {
+        if name.len() != 2 || name[0] != "scheme" {
+            return None;
+        }
+        let names: Vec<&str> = if name[1] == "base" {
+            self.builtins.iter().map(|s| s.as_str())
+                .filter(|s| !STANDARD_LIBRARIES.iter().any(|l| l.1.contains(s)))
+                .collect()
+        } else {
+            STANDARD_LIBRARIES.iter().find(|l| l.0 == name[1])?.1.to_vec()
+        };
+        Some(names.into_iter().map(|s| (s.to_string(), s.to_string())).collect())
+    }
+
+    fn find_library_file(&self, name: &[String]) -> Option<PathBuf> {
+        if !self.files {
+            return None;
+        }
+        let relative: PathBuf = name.iter().collect();
+        for dir in &self.search_path {
+            for ext in &["sld", "scm"] {
+                let path = dir.join(&relative).with_extension(ext);
+                if path.is_file() {
+                    return Some(path);
+                }
+            }
+        }
+        None
+    }
+
+    fn library_available(&self, name: &[String]) -> bool {
+        self.libraries.contains_key(name) || self.standard_library(name).is_some() ||
+            self.find_library_file(name).is_some()
+    }
+
+    /// The exports of a library, loading it from the search path if needed.
+    fn library(&mut self, vm: &mut Vm, name: LibraryName) -> Result<Exports> {
+        if let Some(exports) = self.libraries.get(&name) {
+            return Ok(exports.clone());
+        }
+        if let Some(exports) = self.standard_library(&name) {
+            return Ok(exports);
+        }
+        if self.loading.contains(&name) {
+            return Err(LibraryError::Basic(
+                format!("Circular import of library {}", library_display(&name))));
+        }
+        let path = match self.find_library_file(&name) {
+            Some(path) => path,
+            None => return Err(LibraryError::Basic(
+                format!("Library {} not found", library_display(&name)))),
+        };
+        let dir = parent(&path);
+        for node in read_file(&path)? {
+            if keyword(&node) != Some("define-library") {
+                return Err(LibraryError::Basic(
+                    format!("{}: Expected define-library, got {:?}", path.display(), node)));
+            }
+            self.define_library(vm, arguments(node), &dir)?;
+        }
+        match self.libraries.get(&name) {
+            Some(exports) => Ok(exports.clone()),
+            None => Err(LibraryError::Basic(
+                format!("{}: Library {} is not defined", path.display(),
+                        library_display(&name)))),
+        }
+    }
+
+    fn import_set(&mut self, vm: &mut Vm, set: Node) -> Result<Exports> {
+        let modifier = match set {
+            Node::List(ref l) if l.len() >= 2 => match (&l[0], &l[1]) {
+                (Node::Identifier(k), Node::List(_)) if
+                    ["only", "except", "prefix", "rename"].contains(&k.as_str()) =>
+                    Some(k.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+        let modifier = match modifier {
+            Some(modifier) => modifier,
+            None => {
+                let name = library_name(&set)?;
+                return self.library(vm, name);
+            },
+        };
+        let mut args = arguments(set);
+        let specs = args.split_off(1);
+        let mut names = self.import_set(vm, args.pop().unwrap())?;
+        let missing = |id: &str| LibraryError::Basic(
+            format!("{}: {} is not in the import set", modifier, id));
+        match modifier.as_str() {
+            "only" => {
+                let mut res = HashMap::new();
+                for id in specs {
+                    let id = identifier(id, &modifier)?;
+                    let global = names.remove(&id).ok_or_else(|| missing(&id))?;
+                    res.insert(id, global);
+                }
+                Ok(res)
+            },
+            "except" => {
+                for id in specs {
+                    let id = identifier(id, &modifier)?;
+                    names.remove(&id).ok_or_else(|| missing(&id))?;
+                }
+                Ok(names)
+            },
+            "prefix" => {
+                if specs.len() != 1 {
+                    return Err(LibraryError::Basic(
+                        format!("prefix: Expected 1 prefix, got {}", specs.len())));
+                }
+                let prefix = identifier(specs.into_iter().next().unwrap(), &modifier)?;
+                Ok(names.into_iter().map(|(id, global)| (format!("{}{}", prefix, id), global))
+                   .collect())
+            },
+            _ => {
+                let mut renamed = Vec::new();
+                for spec in specs {
+                    let mut pair = match spec {
+                        Node::List(pair) if pair.len() == 2 => pair,
+                        spec => return Err(LibraryError::Basic(
+                            format!("rename: Expected (from to), got {:?}", spec))),
+                    };
+                    let to = identifier(pair.pop().unwrap(), &modifier)?;
+                    let from = identifier(pair.pop().unwrap(), &modifier)?;
+                    let global = names.remove(&from).ok_or_else(|| missing(&from))?;
+                    renamed.push((to, global));
+                }
+                names.extend(renamed);
+                Ok(names)
+            },
+        }
+    }
+
+    fn feature_requirement(&self, req: &Node) -> Result<bool> {
+        let l = match req {
+            Node::Identifier(f) => return Ok(f == "else" || FEATURES.contains(&f.as_str())),
+            Node::List(l) => l,
+            req => return Err(LibraryError::Basic(
+                format!("cond-expand: Expected feature requirement, got {:?}", req))),
+        };
+        let args = &l[1..];
+        match keyword(req) {
+            Some("and") => {
+                for r in args {
+                    if !self.feature_requirement(r)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            },
+            Some("or") => {
+                for r in args {
+                    if self.feature_requirement(r)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            },
+            Some("not") if args.len() == 1 => Ok(!self.feature_requirement(&args[0])?),
+            Some("library") if args.len() == 1 =>
+                Ok(self.library_available(&library_name(&args[0])?)),
+            _ => Err(LibraryError::Basic(
+                format!("cond-expand: Bad feature requirement {:?}", req))),
+        }
+    }
+
+    /// The forms of the first `cond-expand` clause whose requirement holds.
+    fn cond_expand(&self, clauses: Vec<Node>) -> Result<Vec<Node>> {
+        for clause in clauses {
+            let mut l = match clause {
+                Node::List(l) if !l.is_empty() => l,
+                clause => return Err(LibraryError::Basic(
+                    format!("cond-expand: Expected clause, got {:?}", clause))),
+            };
+            let forms = l.split_off(1);
+            if self.feature_requirement(&l[0])? {
+                return Ok(forms);
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    fn define_library(&mut self, vm: &mut Vm, mut decls: Vec<Node>, dir: &Path) -> Result<()> {
+        if decls.is_empty() {
+            return Err(LibraryError::Basic("define-library: Expected library name".to_string()));
+        }
+        let rest = decls.split_off(1);
+        let name = library_name(&decls[0])?;
+        if self.libraries.contains_key(&name) || self.standard_library(&name).is_some() {
+            return Err(LibraryError::Basic(
+                format!("Library {} is already defined", library_display(&name))));
+        }
+        self.loading.push(name.clone());
+        let res = self.library_body(vm, &name, rest, dir);
+        self.loading.pop();
+        let exports = res?;
+        self.libraries.insert(name, exports);
+        Ok(())
+    }
+
+    fn library_body(&mut self, vm: &mut Vm, name: &[String], decls: Vec<Node>,
+                    dir: &Path) -> Result<Exports> {
+        let mut namespace = Namespace::library(name);
+        let mut exports = Vec::new();
+        let mut body = Vec::new();
+        self.declarations(vm, decls, dir, &mut namespace, &mut exports, &mut body)?;
+        let mut expressions = Vec::new();
+        for node in body {
+            expressions.push(scheme::parse_expression(node)?);
+        }
+        let mut defined = Vec::new();
+        body_definitions(&expressions, &mut defined);
+        for name in &defined {
+            namespace.define_private(name);
+        }
+        for e in &expressions {
+            self.run(vm, e, &namespace)?;
+        }
+        let mut res = HashMap::new();
+        for (internal, external) in exports {
+            match namespace.bindings.get(&internal) {
+                Some(global) => res.insert(external, global.clone()),
+                None => return Err(LibraryError::Basic(
+                    format!("{}: Exported identifier {} is not defined",
+                            library_display(name), internal))),
+            };
+        }
+        Ok(res)
+    }
+
+    fn declarations(&mut self, vm: &mut Vm, decls: Vec<Node>, dir: &Path,
+                    namespace: &mut Namespace, exports: &mut Vec<(String, String)>,
+                    body: &mut Vec<Node>) -> Result<()> {
+        for decl in decls {
+            match keyword(&decl) {
+                Some("export") =>
+                    for spec in arguments(decl) {
+                        exports.push(match spec {
+                            Node::Identifier(id) => (id.clone(), id),
+                            spec => match keyword(&spec) {
+                                Some("rename") => {
+                                    let mut pair = arguments(spec);
+                                    if pair.len() != 2 {
+                                        return Err(LibraryError::Basic(
+                                            "export: Expected (rename from to)".to_string()));
+                                    }
+                                    let to = identifier(pair.pop().unwrap(), "export")?;
+                                    (identifier(pair.pop().unwrap(), "export")?, to)
+                                },
+                                _ => return Err(LibraryError::Basic(
+                                    format!("export: Expected export spec, got {:?}", spec))),
+                            },
+                        });
+                    },
+                Some("import") =>
+                    for set in arguments(decl) {
+                        let names = self.import_set(vm, set)?;
+                        namespace.import(names);
+                    },
+                Some("begin") => body.extend(arguments(decl)),
+                Some("include") => body.extend(self.include(arguments(decl), dir)?),
+                Some("include-library-declarations") => {
+                    let decls = self.include(arguments(decl), dir)?;
+                    self.declarations(vm, decls, dir, namespace, exports, body)?;
+                },
+                Some("cond-expand") => {
+                    let decls = self.cond_expand(arguments(decl))?;
+                    self.declarations(vm, decls, dir, namespace, exports, body)?;
+                },
+                _ => return Err(LibraryError::Basic(
+                    format!("define-library: Unknown declaration {:?}", decl))),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use lexer::RegexLexer;
+    use parser::Parser;
+    use vm::Vm;
+    use scheme;
+    use super::{Loader, read_file};
+
+    fn eval(loader: &mut Loader, vm: &mut Vm, s: &str) -> Result<String, String> {
+        let mut res = String::new();
+        for node in Parser::new(RegexLexer::new(s)) {
+            let v = loader.eval(vm, node.unwrap(), Path::new("."))
+                .map_err(|e| e.to_string())?;
+            res = vm.write(&v);
+        }
+        Ok(res)
+    }
+
+    fn run(s: &str) -> Result<String, String> {
+        let mut vm = Vm::new();
+        let mut loader = Loader::new(&vm, true);
+        eval(&mut loader, &mut vm, s)
+    }
+
+    /// A fresh directory holding the given files.
+    fn tree(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = env::temp_dir().join(format!("scheme-syntax-{}-{}", test, ::std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for &(path, text) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn define_library() {
+        let lib = "(define-library (counter) \
+                     (export make (rename get value)) \
+                     (import (scheme base)) \
+                     (begin (define (make) (list 0)) \
+                            (define (get c) (car c))))";
+        assert_eq!(run(&format!("{} (import (counter)) (value (make))", lib)).unwrap(), "0");
+        assert_eq!(run(&format!("{} (import (prefix (counter) c:)) (c:value (c:make))", lib))
+                   .unwrap(), "0");
+        assert_eq!(run(&format!("{} (import (only (counter) make)) (get (make))", lib))
+                   .unwrap_err(), "RuntimeError: Unbound variable get");
+        assert_eq!(run(&format!("{} (import (except (counter) value)) value", lib))
+                   .unwrap_err(), "RuntimeError: Unbound variable value");
+        assert_eq!(run(&format!("{} (import (rename (counter) (make new))) (value (new))", lib))
+                   .unwrap(), "0");
+    }
+
+    #[test]
+    fn private_definitions() {
+        // Library definitions do not leak, and the library only sees what
+        // it imports.
+        let lib = "(define-library (a) (export f) (import (scheme base)) \
+                     (begin (define x 1) (define (f) x)))";
+        assert_eq!(run(&format!("(define x 2) {} (import (a)) (list x (f))", lib)).unwrap(),
+                   "(2 1)");
+        assert_eq!(run("(define-library (b) (export f) (begin (define (f) (car 1)))) \
+                        (import (b)) (f)").unwrap_err(),
+                   "RuntimeError: Unbound variable car@(b)");
+        assert_eq!(run("(define-library (c) (export g))").unwrap_err(),
+                   "LibraryError: (c): Exported identifier g is not defined");
+        assert_eq!(run("(import (only (scheme base) nothing))").unwrap_err(),
+                   "LibraryError: only: nothing is not in the import set");
+    }
+
+    #[test]
+    fn cond_expand() {
+        assert_eq!(run("(cond-expand ((and r7rs (not foo)) 1) (else 2))").unwrap(), "1");
+        assert_eq!(run("(cond-expand ((library (scheme nope)) 1) (else 2))").unwrap(), "2");
+        assert_eq!(run("(define-library (d) (export v) \
+                          (cond-expand (scheme-syntax (begin (define v 1))) \
+                                       (else (begin (define v 2))))) \
+                        (import (d)) v").unwrap(), "1");
+    }
+
+    #[test]
+    fn search_path() {
+        let root = tree("search-path", &[
+            ("lib/util/math.sld",
+             "; Squares numbers.\n\
+              (define-library (util math) (export square) (import (scheme base))\n\
+                (include \"math-impl.scm\"))"),
+            ("lib/util/math-impl.scm", "(define (square x) (* x x))"),
+            ("lib/loop/a.sld", "(define-library (loop a) (import (loop b)))"),
+            ("lib/loop/b.sld", "(define-library (loop b) (import (loop a)))"),
+            ("main.scm", "(import (scheme base) (util math)) (square 7)"),
+        ]);
+        let mut vm = Vm::new();
+        let mut loader = Loader::new(&vm, true);
+        loader.add_search_path(root.join("lib"));
+        let main = read_file(&root.join("main.scm")).unwrap();
+        let v = loader.load_program(&mut vm, main.clone(), &root).unwrap();
+        assert_eq!(vm.write(&v), "49");
+        // A form given with an expression runs that instead.
+        let square = Parser::new(RegexLexer::new("(square 8)")).next().unwrap().unwrap();
+        let expanded = vec![None, Some(scheme::parse_expression(square).unwrap())];
+        let forms = main.into_iter().zip(expanded).collect();
+        let v = loader.load_expanded(&mut vm, forms, &root).unwrap();
+        assert_eq!(vm.write(&v), "64");
+        assert_eq!(eval(&mut loader, &mut vm, "(import (loop a))").unwrap_err(),
+                   "LibraryError: Circular import of library (loop a)");
+        assert_eq!(eval(&mut loader, &mut vm, "(import (missing))").unwrap_err(),
+                   "LibraryError: Library (missing) not found");
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,182 +1,5 @@
-use std::io::BufRead;
-mod lexer;
-use lexer::RegexLexer;
-mod parser;
-use parser::Parser;
-// mod io;
-// use io::CharsWrap;
-mod scheme;
-use scheme::parse_expression;
-
-// fn read_stdin() -> String {
-//     let mut s = String::new();
-//     std::io::stdin().read_to_string(&mut s).unwrap();
-//     s
-// }
+extern crate scheme_syntax;
 
 fn main() {
-    let stdin = std::io::stdin();
-    for line_result in stdin.lock().lines() {
-        let line = line_result.unwrap();
-        let lexer = RegexLexer::new(&line);
-        let parser = Parser::new(lexer);
-        for node_result in parser {
-            match node_result {
-                Err(e) => println!("error: {}", e),
-                Ok(node) =>
-                    match parse_expression(node) {
-                        Err(e) => println!("error: {}", e),
-                        Ok(expr) => println!("{:?}", expr),
-                    },
-            }
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use parser::{Node, Parser};
-    use lexer::RegexLexer;
-    use super::scheme::syntax::{Expression, Literal};
-
-    fn parse(s: &str) -> Node {
-        let lexer = RegexLexer::new(s);
-        let mut parser = Parser::new(lexer);
-        parser.next().unwrap().unwrap()
-    }
-
-    fn expr_res(s: &str) -> super::scheme::Result<Expression> {
-        super::scheme::parse_expression(parse(s))
-    }
-
-    fn expr(s: &str) -> Expression {
-        expr_res(s).unwrap()
-    }
-
-    #[test]
-    fn parse_test() {
-        parse("(foo bar)");
-        parse("foo");
-        parse("foo )garbage");
-    }
-
-    #[test]
-    fn number() {
-        assert_eq!(expr("12"), Expression::Literal(Literal::Number(12)));
-    }
-
-    #[test]
-    fn bool_true() {
-        assert_eq!(expr("#t"), Expression::Literal(Literal::Boolean(true)));
-    }
-
-    #[test]
-    fn bool_false() {
-        assert_eq!(expr("#f"), Expression::Literal(Literal::Boolean(false)));
-    }
-
-    #[test]
-    fn char_normal() {
-        assert_eq!(expr("#\\a"), Expression::Literal(Literal::Character('a')));
-    }
-
-    #[test]
-    fn char_nl() {
-        assert_eq!(expr("#\\newline"), Expression::Literal(Literal::Character('\n')));
-    }
-
-    #[test]
-    fn char_nl_upper() {
-        assert_eq!(expr("#\\NewLine"), Expression::Literal(Literal::Character('\n')));
-    }
-
-    #[test]
-    fn char_space() {
-        assert_eq!(expr("#\\SPace"), Expression::Literal(Literal::Character(' ')));
-    }
-
-    #[test]
-    fn strings() {
-        assert_eq!(expr("\"a\\nb\\tc\""), Expression::Literal(Literal::String("a\nb\tc".to_string())));
-    }
-
-    #[test]
-    fn var() {
-        assert_eq!(expr("foobar"), Expression::Variable("foobar".to_string()));
-    }
-
-    #[test]
-    fn time() {
-        assert_eq!(expr("(time 42)"),
-                   Expression::Time(Box::new(expr("42"))));
-    }
-
-    #[test]
-    fn parse_if() {
-        assert_eq!(expr("(if 1 2 3)"),
-                   Expression::If(
-                       Box::new(expr("1")),
-                       Box::new(expr("2")),
-                       Box::new(expr("3"))));
-    }
-
-    #[test]
-    fn parse_and_0() {
-        assert_eq!(expr("(and)"),
-                   Expression::And(Vec::new()));
-    }
-
-    #[test]
-    fn parse_and_1() {
-        assert_eq!(expr("(and #t)"),
-                   Expression::And(vec![expr("#t")]));
-    }
-
-    #[test]
-    fn parse_and_3() {
-        assert_eq!(expr("(and #t #t #f)"),
-                   Expression::And(vec![
-                       expr("#t"),
-                       expr("#t"),
-                       expr("#f"),
-                   ]));
-    }
-
-    #[test]
-    fn parse_or_2() {
-        assert_eq!(expr("(or #t #f)"),
-                   Expression::Or(vec![
-                       expr("#t"),
-                       expr("#f"),
-                   ]));
-    }
-
-    #[test]
-    fn parse_begin_0() {
-        expr_res("(begin)").unwrap_err();
-    }
-
-    #[test]
-    fn begin_1() {
-        assert_eq!(expr("(begin 2)"),
-                   Expression::Begin(vec![expr("2")]));
-    }
-
-    #[test]
-    fn unless() {
-        assert_eq!(expr("(unless #t 2)"),
-                   Expression::Unless(
-                       Box::new(expr("#t")),
-                       Box::new(expr("2"))));
-    }
-
-    #[test]
-    fn cond() {
-        expr("(cond (#f #t) (42) (23 => bla) (else 1))");
-    }
-
-    #[test]
-    fn case() {
-        expr("(case 42 ((23) #t) ((42) #f) (else 0))");
-    }
+    std::process::exit(scheme_syntax::cli::main(std::env::args().skip(1).collect()));
 }
@@ -0,0 +1,342 @@
+//! Reading and syntax analysis spread over threads. Top-level data do not
+//! depend on each other once their boundaries are known, so `ranges` finds
+//! those with a quick scan of the bytes, and `read` and `parse` lex, read
+//! and expand the ranges on several threads at once. The results are the
+//! same as those of `SpannedParser` and `parse_expression`, errors
+//! included.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use lexer::Span;
+use parser::{Node, ReadError, ReadLimits, SpannedParser, Spans};
+use scheme::{parse_expression, SchemeError};
+use scheme::syntax::Expression;
+
+/// Texts shorter than this are read on one thread.
+const MIN_BATCH: usize = 1 << 14;
+
+/// The number of threads to use by default: one per processor.
+pub fn threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Applies `f` to each of `items` on up to `threads` threads, each with a
+/// state made by `init`, and returns the results in the order of `items`.
+pub fn map<T, S, U, I, F>(items: &[T], threads: usize, init: I, f: F) -> Vec<U>
+where T: Sync, U: Send, I: Fn() -> S + Sync, F: Fn(&mut S, &T) -> U + Sync {
+    let mut results = Vec::with_capacity(items.len());
+    map_ordered(items, threads, init, f, |_, result| results.push(result));
+    results
+}
+
+/// Applies `f` as `map` does, and passes each item and its result to
+/// `done` on the calling thread, in the order of `items`, as soon as the
+/// results for it and the items before it are ready. Each thread takes
+/// the next item no thread has taken yet, so that slow items do not hold
+/// up the rest.
+pub fn map_ordered<T, S, U, I, F, D>(items: &[T], threads: usize, init: I, f: F, mut done: D)
+where T: Sync, U: Send, I: Fn() -> S + Sync, F: Fn(&mut S, &T) -> U + Sync, D: FnMut(&T, U) {
+    let threads = threads.min(items.len());
+    if threads <= 1 {
+        let mut state = init();
+        for item in items {
+            let result = f(&mut state, item);
+            done(item, result);
+        }
+        return;
+    }
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (next, init, f) = (&next, &init, &f);
+            scope.spawn(move || {
+                let mut state = init();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= items.len() {
+                        return;
+                    }
+                    if sender.send((i, f(&mut state, &items[i]))).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        // Results that came before those of earlier items wait here.
+        let mut pending: Vec<Option<U>> = items.iter().map(|_| None).collect();
+        let mut first = 0;
+        for (i, result) in receiver {
+            pending[i] = Some(result);
+            while let Some(result) = pending.get_mut(first).and_then(Option::take) {
+                done(&items[first], result);
+                first += 1;
+            }
+        }
+    });
+}
+
+/// The end of the string literal whose opening quote is at `start`, or the
+/// end of the text if it is not closed.
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// The end of the comment that starts at `start`.
+fn comment_end(bytes: &[u8], start: usize) -> usize {
+    bytes[start..].iter().position(|&b| b == b'\n').map_or(bytes.len(), |n| start + n)
+}
+
+/// Splits `text` into ranges that each hold whole top-level data, in
+/// order, skipping the whitespace and comments between them. A range is
+/// usually one datum, but atoms written without space between them, such
+/// as `1a`, share one. The scan knows strings, characters and comments,
+/// but not the rest of the syntax: a stray `)` is a range of its own, and
+/// a list or string that is not closed runs to the end of the text, where
+/// reading the range reports the error.
+pub fn ranges(text: &str) -> Vec<Span> {
+    let bytes = text.as_bytes();
+    let mut res = Vec::new();
+    // The start of the datum being scanned, if any, and the lists open in
+    // it.
+    let mut start = None;
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if depth == 0 {
+            let delimiter = b.is_ascii_whitespace() || b"();\"".contains(&b);
+            if let (true, Some(s)) = (delimiter, start) {
+                res.push(Span { start: s, end: i });
+                start = None;
+            }
+        }
+        match b {
+            b';' => {
+                i = comment_end(bytes, i);
+                continue;
+            },
+            b'"' => {
+                let end = string_end(bytes, i);
+                if depth == 0 {
+                    res.push(Span { start: i, end });
+                }
+                i = end;
+                continue;
+            },
+            b'(' => {
+                if depth == 0 {
+                    start = Some(i);
+                }
+                depth += 1;
+            },
+            b')' if depth == 0 => res.push(Span { start: i, end: i + 1 }),
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    res.push(Span { start: start.take().unwrap(), end: i + 1 });
+                }
+            },
+            _ if b.is_ascii_whitespace() => (),
+            _ => {
+                if start.is_none() {
+                    start = Some(i);
+                }
+                if b == b'#' && bytes.get(i + 1) == Some(&b'\\') {
+                    // A character, which may be a delimiter itself.
+                    i += 3;
+                    continue;
+                }
+            },
+        }
+        i += 1;
+    }
+    if let Some(s) = start {
+        res.push(Span { start: s, end: bytes.len() });
+    }
+    res
+}
+
+fn shift(spans: &mut Spans, offset: usize) {
+    spans.span.start += offset;
+    spans.span.end += offset;
+    for child in &mut spans.children {
+        shift(child, offset);
+    }
+}
+
+fn count(spans: &Spans) -> usize {
+    1 + spans.children.iter().map(count).sum::<usize>()
+}
+
+/// Reads `text` as `SpannedParser` does with `limits`, and applies `f` to
+/// each datum, on up to `threads` threads.
+fn read_map<U, F>(text: &str, threads: usize, limits: ReadLimits, f: F)
+                  -> Result<Vec<(Node, Spans, U)>, ReadError>
+where U: Send, F: Fn(&Node) -> U + Sync {
+    let sequential = || -> Result<Vec<_>, ReadError> {
+        SpannedParser::with_limits(text, limits)
+            .map(|datum| datum.map(|(node, spans)| {
+                let u = f(&node);
+                (node, spans, u)
+            }))
+            .collect()
+    };
+    let batches = (threads * 4).min(text.len() / MIN_BATCH);
+    if threads <= 1 || batches <= 1 {
+        return sequential();
+    }
+    // Runs of whole data of about the same size, one per item of work,
+    // each read by a parser of its own.
+    let size = text.len() / batches;
+    let mut work: Vec<Span> = Vec::new();
+    for range in ranges(text) {
+        match work.last_mut() {
+            Some(batch) if range.end - batch.start <= size => batch.end = range.end,
+            _ => work.push(range),
+        }
+    }
+    let results = map(&work, threads, || (), |_, batch| {
+        let mut res = Vec::new();
+        for datum in SpannedParser::with_limits(&text[batch.start..batch.end], limits) {
+            let (node, mut spans) = datum.ok()?;
+            shift(&mut spans, batch.start);
+            let u = f(&node);
+            res.push((node, spans, u));
+        }
+        Some(res)
+    });
+    // Errors and the limit on data in all are found by reading again on
+    // one thread, so that they are reported just as they would be.
+    let mut res = Vec::new();
+    for batch in results {
+        match batch {
+            Some(batch) => res.extend(batch),
+            None => return sequential(),
+        }
+    }
+    let limit = limits.nodes.unwrap_or(usize::MAX);
+    if res.iter().map(|datum| count(&datum.1)).sum::<usize>() > limit {
+        return sequential();
+    }
+    Ok(res)
+}
+
+/// Reads the data in `text` and their spans, as `SpannedParser` does with
+/// `limits`, on up to `threads` threads.
+pub fn read(text: &str, threads: usize, limits: ReadLimits)
+            -> Result<Vec<(Node, Spans)>, ReadError> {
+    let data = read_map(text, threads, limits, |_| ())?;
+    Ok(data.into_iter().map(|(node, spans, ())| (node, spans)).collect())
+}
+
+/// A top-level datum, its spans, and the expression it expands to.
+pub type Form = (Node, Spans, Result<Expression, SchemeError>);
+
+/// Reads the data in `text` and expands each, on up to `threads` threads.
+pub fn parse(text: &str, threads: usize, limits: ReadLimits) -> Result<Vec<Form>, ReadError> {
+    read_map(text, threads, limits, |node| parse_expression(node.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use parser::{ReadLimits, SpannedParser};
+    use scheme::{parse_expression, SchemeError};
+    use scheme::syntax::Expression;
+    use serialize::Serialize;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use super::{map, map_ordered, parse, ranges, read};
+
+    #[test]
+    fn scan() {
+        let text = "(a \"(\" #\\) b) x1 ; (\n\"s\\\"\"#\\( (c (d)) ) (e";
+        let found: Vec<&str> = ranges(text).into_iter().map(|s| &text[s.start..s.end]).collect();
+        assert_eq!(found, vec!["(a \"(\" #\\) b)", "x1", "\"s\\\"\"", "#\\(", "(c (d))", ")", "(e"]);
+        for range in ranges(text) {
+            assert!(SpannedParser::new(&text[range.start..range.end]).next().is_some());
+        }
+        assert!(ranges("  ; only a comment").is_empty());
+    }
+
+    #[test]
+    fn same_as_sequential() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let prelude = fs::read_to_string(root.join("src").join("prelude.scm")).unwrap();
+        let text = format!("{}(if)\n", prelude).repeat(20);
+        let expected: Vec<_> = SpannedParser::new(&text).map(|datum| datum.unwrap()).collect();
+        let limits = ReadLimits::default();
+        assert_eq!(read(&text, 4, limits).unwrap(), expected);
+        let forms = parse(&text, 4, limits).unwrap();
+        assert_eq!(forms.len(), expected.len());
+        for ((node, spans, e), (n, s)) in forms.iter().zip(&expected) {
+            assert_eq!((node, spans), (n, s));
+            let sexpr = |e: &Result<Expression, SchemeError>| e.as_ref()
+                .map(|e| e.to_sexpr())
+                .map_err(|e| e.code);
+            assert_eq!(sexpr(e), sexpr(&parse_expression(n.clone())));
+        }
+
+        for bad in &["(a", ")", "\"a", "#\\\n", "[a]"] {
+            let text = format!("{}{}\n{}", text, bad, text);
+            let expected = SpannedParser::new(&text).collect::<Result<Vec<_>, _>>().unwrap_err();
+            assert_eq!(read(&text, 4, limits).unwrap_err(), expected, "{}", bad);
+        }
+
+        // The limits hold in each batch and over the whole text.
+        for limits in &[ReadLimits { depth: Some(3), ..limits },
+                        ReadLimits { nodes: Some(expected.len() * 2), ..limits }] {
+            let sequential = SpannedParser::with_limits(&text, *limits)
+                .collect::<Result<Vec<_>, _>>().unwrap_err();
+            assert_eq!(read(&text, 4, *limits).unwrap_err(), sequential);
+        }
+    }
+
+    #[test]
+    fn ordered() {
+        let items: Vec<usize> = (0..1000).collect();
+        let squares = map(&items, 8, || 0, |calls, &i| {
+            *calls += 1;
+            i * i
+        });
+        assert_eq!(squares, items.iter().map(|i| i * i).collect::<Vec<_>>());
+        assert_eq!(map(&[] as &[usize], 8, || (), |_, &i| i), vec![]);
+    }
+
+    #[test]
+    fn streamed() {
+        // The second item waits until the first is done, which it never
+        // would if results were only passed on once all were ready.
+        let first_done = AtomicBool::new(false);
+        let mut order = Vec::new();
+        map_ordered(&[0, 1, 2], 2, || (), |_, &i| {
+            let start = Instant::now();
+            while i == 1 && !first_done.load(Ordering::SeqCst) {
+                if start.elapsed() > Duration::from_secs(10) {
+                    return false;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            true
+        }, |&i, streamed| {
+            first_done.store(true, Ordering::SeqCst);
+            order.push((i, streamed));
+        });
+        assert_eq!(order, vec![(0, true), (1, true), (2, true)]);
+    }
+}
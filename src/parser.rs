@@ -1,57 +1,179 @@
-use lexer::{Lexer, Token, Literal};
+use std::error;
+use std::fmt;
 
-#[derive(Debug)]
+use lexer::{self, Lexer, Token, Literal, RegexLexer, Span};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Node {
     Identifier(String),
     Literal(Literal),
     List(Vec<Node>),
 }
 
-pub fn parse_next<L>(lexer: &mut L) -> Option<Result<Node, &'static str>>
-where L: Lexer {
+/// Bounds on the input a parser accepts, so that hostile input fails with
+/// an error instead of exhausting the stack or memory. `None` means no
+/// bound. The default bounds are well above what programs need.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadLimits {
+    /// Lists open at once.
+    pub depth: Option<usize>,
+    /// Bytes in an identifier.
+    pub token_length: Option<usize>,
+    /// Bytes in a string literal, after escapes are replaced.
+    pub string_length: Option<usize>,
+    /// Data read by one parser in all, counting lists as well as atoms.
+    pub nodes: Option<usize>,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        ReadLimits {
+            depth: Some(200),
+            token_length: Some(1 << 10),
+            string_length: Some(1 << 20),
+            nodes: Some(1 << 20),
+        }
+    }
+}
+
+fn exceeds(limit: Option<usize>, n: usize) -> bool {
+    limit.is_some_and(|max| n > max)
+}
+
+/// What `ReadLimits` bounds in a token that starts a datum.
+pub(crate) enum Size {
+    List,
+    Identifier(usize),
+    /// A string, by its length after escapes are replaced.
+    String(usize),
+    Atom,
+}
+
+/// What a token does to the datum being read.
+pub(crate) enum Read<N> {
+    Open,
+    Close,
+    Atom(N),
+}
+
+/// A token the reader builds data from: `Token`s build `Node`s, and
+/// borrowed tokens build borrowed nodes.
+pub(crate) trait ReadToken {
+    type Node;
+
+    /// The size of the datum the token starts, or `None` for `)`.
+    fn size(&self) -> Option<Size>;
+    fn read(self) -> Read<Self::Node>;
+    fn list(items: Vec<Self::Node>) -> Self::Node;
+}
+
+impl ReadToken for Token {
+    type Node = Node;
+
+    fn size(&self) -> Option<Size> {
+        Some(match self {
+            Token::LParen => Size::List,
+            Token::RParen => return None,
+            Token::Identifier(s) => Size::Identifier(s.len()),
+            Token::Literal(Literal::String(s)) => Size::String(s.len()),
+            Token::Literal(_) => Size::Atom,
+        })
+    }
+
+    fn read(self) -> Read<Node> {
+        match self {
+            Token::LParen => Read::Open,
+            Token::RParen => Read::Close,
+            Token::Identifier(s) => Read::Atom(Node::Identifier(s)),
+            Token::Literal(l) => Read::Atom(Node::Literal(l)),
+        }
+    }
+
+    fn list(items: Vec<Node>) -> Node {
+        Node::List(items)
+    }
+}
+
+impl ReadLimits {
+    /// Checks a datum of `size`, read with `depth` lists open after `nodes`
+    /// data including this one.
+    fn check(&self, size: Size, depth: usize, nodes: usize) -> Result<(), &'static str> {
+        if exceeds(self.nodes, nodes) {
+            return Err("too many data");
+        }
+        match size {
+            Size::List if exceeds(self.depth, depth + 1) => Err("lists nested too deeply"),
+            Size::Identifier(n) if exceeds(self.token_length, n) => Err("identifier too long"),
+            Size::String(n) if exceeds(self.string_length, n) => Err("string too long"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reads the next datum from `tokens`. `nodes` counts the data read so far,
+/// for `limits`.
+pub(crate) fn read_next<T, I>(tokens: &mut I, limits: &ReadLimits, nodes: &mut usize)
+                              -> Option<Result<T::Node, &'static str>>
+where T: ReadToken, I: Iterator<Item=lexer::Result<T>> {
     let mut stack = vec![];
-    while let Some(token_result) = lexer.next() {
+    for token_result in tokens.by_ref() {
         let tok = match token_result {
             Ok(tok) => tok,
             Err(_) => return Some(Err("lexer error")),
         };
-        match tok {
-            Token::LParen => stack.push(vec![]),
-            Token::RParen => {
-                let c = match stack.pop() {
-                    Some(c) => c,
-                    None => return Some(Err("unmatched right parenthesis")),
-                };
-                match stack.last_mut() {
-                    None => return Some(Ok(Node::List(c))),
-                    Some(m) => m.push(Node::List(c)),
-                }
-            },
-            Token::Identifier(s) => match stack.last_mut() {
-                None => return Some(Ok(Node::Identifier(s))),
-                Some(m) => m.push(Node::Identifier(s)),
+        if let Some(size) = tok.size() {
+            *nodes += 1;
+            if let Err(e) = limits.check(size, stack.len(), *nodes) {
+                return Some(Err(e));
+            }
+        }
+        let node = match tok.read() {
+            Read::Open => {
+                stack.push(vec![]);
+                continue;
             },
-            Token::Literal(l) => match stack.last_mut() {
-                None => return Some(Ok(Node::Literal(l))),
-                Some(m) => m.push(Node::Literal(l)),
+            Read::Close => match stack.pop() {
+                Some(c) => T::list(c),
+                None => return Some(Err("unmatched right parenthesis")),
             },
+            Read::Atom(node) => node,
+        };
+        match stack.last_mut() {
+            None => return Some(Ok(node)),
+            Some(m) => m.push(node),
         }
     }
-    if stack.len() == 0 {
+    if stack.is_empty() {
         None
     } else {
         Some(Err("unexpected EOF"))
     }
 }
 
+/// Reads the next datum from `lexer`. `nodes` counts the data read so far,
+/// for `limits`.
+pub fn parse_next<L>(lexer: &mut L, limits: &ReadLimits, nodes: &mut usize)
+                     -> Option<Result<Node, &'static str>>
+where L: Lexer {
+    read_next(lexer, limits, nodes)
+}
+
 pub struct Parser<L> where L: Lexer {
     lexer: L,
+    limits: ReadLimits,
+    nodes: usize,
 }
 
 impl <L> Parser<L> where L: Lexer {
     pub fn new(lexer: L) -> Self {
+        Parser::with_limits(lexer, ReadLimits::default())
+    }
+
+    pub fn with_limits(lexer: L, limits: ReadLimits) -> Self {
         Parser {
-            lexer: lexer,
+            lexer,
+            limits,
+            nodes: 0,
         }
     }
 }
@@ -60,6 +182,162 @@ impl <L> Iterator for Parser<L> where L: Lexer {
     type Item = Result<Node, &'static str>;
 
     fn next(&mut self) -> Option<Result<Node, &'static str>> {
-        parse_next(&mut self.lexer)
+        parse_next(&mut self.lexer, &self.limits, &mut self.nodes)
+    }
+}
+
+/// The span of a datum, and the spans of the data inside it if it is a
+/// list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spans {
+    pub span: Span,
+    pub children: Vec<Spans>,
+}
+
+impl Spans {
+    /// Adds the spans of the data in `node` that equal `target` to `out`,
+    /// in the order they appear, where these are the spans of `node`.
+    pub fn find(&self, node: &Node, target: &Node, out: &mut Vec<Span>) {
+        if node == target {
+            out.push(self.span);
+        } else if let Node::List(items) = node {
+            for (item, spans) in items.iter().zip(&self.children) {
+                spans.find(item, target, out);
+            }
+        }
+    }
+}
+
+/// An error from reading, and the span of source text it is about. The
+/// code is one of those in `codes`.
+#[derive(Debug, PartialEq)]
+pub struct ReadError {
+    pub code: &'static str,
+    pub message: &'static str,
+    pub span: Span,
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ReadError: {}", self.message)
+    }
+}
+
+impl error::Error for ReadError {}
+
+/// A parser that also returns the spans of the data it reads.
+pub struct SpannedParser<'t> {
+    lexer: RegexLexer<'t>,
+    limits: ReadLimits,
+    nodes: usize,
+}
+
+impl <'t> SpannedParser<'t> {
+    pub fn new(text: &'t str) -> Self {
+        SpannedParser::with_limits(text, ReadLimits::default())
+    }
+
+    pub fn with_limits(text: &'t str, limits: ReadLimits) -> Self {
+        SpannedParser {
+            lexer: RegexLexer::new(text),
+            limits,
+            nodes: 0,
+        }
+    }
+}
+
+impl <'t> Iterator for SpannedParser<'t> {
+    type Item = Result<(Node, Spans), ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The lists being read, with the spans of their items and the span
+        // of their left parenthesis.
+        let mut stack: Vec<(Vec<Node>, Vec<Spans>, Span)> = vec![];
+        while let Some(token_result) = self.lexer.next() {
+            let span = self.lexer.span();
+            let error = |code, message| Some(Err(ReadError { code, message, span }));
+            if let Some(size) = token_result.as_ref().ok().and_then(Token::size) {
+                self.nodes += 1;
+                if let Err(e) = self.limits.check(size, stack.len(), self.nodes) {
+                    return error("S0104", e);
+                }
+            }
+            let (node, spans) = match token_result {
+                Err(e) => return error("S0103", e),
+                Ok(Token::LParen) => {
+                    stack.push((vec![], vec![], span));
+                    continue;
+                },
+                Ok(Token::RParen) => match stack.pop() {
+                    None => return error("S0101", "unmatched right parenthesis"),
+                    Some((items, children, start)) => (Node::List(items), Spans {
+                        span: Span { start: start.start, end: span.end },
+                        children,
+                    }),
+                },
+                Ok(Token::Identifier(s)) => (Node::Identifier(s), Spans { span, children: vec![] }),
+                Ok(Token::Literal(l)) => (Node::Literal(l), Spans { span, children: vec![] }),
+            };
+            match stack.last_mut() {
+                None => return Some(Ok((node, spans))),
+                Some(m) => {
+                    m.0.push(node);
+                    m.1.push(spans);
+                },
+            }
+        }
+        stack.first().map(|&(_, _, span)| Err(ReadError { code: "S0102", message: "unexpected EOF", span }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lexer::{RegexLexer, Span};
+    use super::{Node, Parser, ReadError, ReadLimits, SpannedParser};
+
+    #[test]
+    fn spans() {
+        let mut p = SpannedParser::new("(a (b 1) b)\n  (c");
+        let (node, spans) = p.next().unwrap().unwrap();
+        let find = |target: &Node| {
+            let mut out = vec![];
+            spans.find(&node, target, &mut out);
+            out.into_iter().map(|s| (s.start, s.end)).collect::<Vec<_>>()
+        };
+        assert_eq!(find(&Node::Identifier("b".to_string())), vec![(4, 5), (9, 10)]);
+        let list = match node {
+            Node::List(ref items) => items[1].clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(find(&list), vec![(3, 8)]);
+        assert_eq!(spans.span, Span { start: 0, end: 11 });
+        assert_eq!(p.next().unwrap().unwrap_err(),
+                   ReadError { code: "S0102", message: "unexpected EOF", span: Span { start: 14, end: 15 } });
+        assert!(p.next().is_none());
+    }
+
+    #[test]
+    fn limits() {
+        let limits = ReadLimits { depth: Some(2), token_length: Some(3), string_length: Some(3),
+                                  nodes: Some(6) };
+        let read = |text: &str| Parser::with_limits(RegexLexer::new(text), limits)
+            .collect::<Vec<_>>();
+        assert_eq!(read("((a)) abc \"abc\"").len(), 3);
+        assert_eq!(read("(((a)))")[0], Err("lists nested too deeply"));
+        assert_eq!(read("abcd")[0], Err("identifier too long"));
+        assert_eq!(read("\"a\\nbc\"")[0], Err("string too long"));
+        assert_eq!(read("(1 2 3) (4 5)")[1], Err("too many data"));
+        let mut p = SpannedParser::with_limits("(a (b (c)))", limits);
+        assert_eq!(p.next().unwrap().unwrap_err(),
+                   ReadError { code: "S0104", message: "lists nested too deeply",
+                               span: Span { start: 6, end: 7 } });
+
+        let deep = format!("{}{}", "(".repeat(100000), ")".repeat(100000));
+        assert_eq!(Parser::new(RegexLexer::new(&deep)).next().unwrap(),
+                   Err("lists nested too deeply"));
+        let unlimited = ReadLimits { depth: None, token_length: None, string_length: None,
+                                     nodes: None };
+        assert!(Parser::with_limits(RegexLexer::new(&"x".repeat(5000)), unlimited).next()
+                .unwrap().is_ok());
     }
 }
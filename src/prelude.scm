@@ -0,0 +1,119 @@
+(define (%any-null? ls)
+  (if (null? ls) #f (or (null? (car ls)) (%any-null? (cdr ls)))))
+
+(define (%cars ls)
+  (if (null? ls) (quote ()) (cons (car (car ls)) (%cars (cdr ls)))))
+
+(define (%cdrs ls)
+  (if (null? ls) (quote ()) (cons (cdr (car ls)) (%cdrs (cdr ls)))))
+
+(define (map f l . ls)
+  (if (null? ls)
+      (let loop ((l l))
+        (if (pair? l) (cons (f (car l)) (loop (cdr l))) (quote ())))
+      (let loop ((ls (cons l ls)))
+        (if (%any-null? ls)
+            (quote ())
+            (cons (apply f (%cars ls)) (loop (%cdrs ls)))))))
+
+(define (for-each f l . ls)
+  (if (null? ls)
+      (let loop ((l l))
+        (if (pair? l) (begin (f (car l)) (loop (cdr l))) #t))
+      (let loop ((ls (cons l ls)))
+        (if (%any-null? ls)
+            #t
+            (begin (apply f (%cars ls)) (loop (%cdrs ls)))))))
+
+(define (member x l . compare)
+  (if (null? compare)
+      (%member x l)
+      (let loop ((l l))
+        (cond ((not (pair? l)) #f)
+              (((car compare) x (car l)) l)
+              (else (loop (cdr l)))))))
+
+(define (assoc x l . compare)
+  (if (null? compare)
+      (%assoc x l)
+      (let loop ((l l))
+        (cond ((not (pair? l)) #f)
+              (((car compare) x (car (car l))) (car l))
+              (else (loop (cdr l)))))))
+
+(define (vector-map f v . vs)
+  (list->vector (apply map f (vector->list v) (map vector->list vs))))
+
+(define (vector-for-each f v . vs)
+  (apply for-each f (vector->list v) (map vector->list vs)))
+
+(define (force promise)
+  (if (promise? promise)
+      (let loop ()
+        (if (%promise-done? promise)
+            (%promise-value promise)
+            (let ((promise* ((%promise-value promise))))
+              (unless (%promise-done? promise)
+                (%promise-update! promise* promise))
+              (loop))))
+      promise))
+
+(define (make-parameter value . converter)
+  (let ((convert (if (null? converter) (lambda (x) x) (car converter))))
+    (%make-parameter (convert value) convert)))
+
+(define (%parameterize body . bindings)
+  (let loop ((bs bindings) (params (quote ())) (vals (quote ())))
+    (if (null? bs)
+        (let ((swap! (lambda ()
+                       (let ((old (map (lambda (p) (p)) params)))
+                         (for-each %parameter-set! params vals)
+                         (set! vals old)))))
+          (%dynamic-wind swap! body swap!))
+        (loop (cdr (cdr bs))
+              (cons (car bs) params)
+              (cons ((%parameter-converter (car bs)) (car (cdr bs))) vals)))))
+
+(define (%with-handlers handlers thunk)
+  (let ((outer (%handlers)))
+    (%dynamic-wind (lambda () (%set-handlers! handlers))
+                   thunk
+                   (lambda () (%set-handlers! outer)))))
+
+(define (with-exception-handler handler thunk)
+  (%with-handlers (cons handler (%handlers)) thunk))
+
+(define (raise-continuable obj)
+  (let ((handlers (%handlers)))
+    (if (null? handlers)
+        (%raise obj)
+        (%with-handlers (cdr handlers) (lambda () ((car handlers) obj))))))
+
+;; The names that compiled guards and errors raised by the VM use, which
+;; programs cannot redefine.
+(define %raise-continuable raise-continuable)
+
+(define (raise obj)
+  (let ((handlers (%handlers)))
+    (if (null? handlers)
+        (%raise obj)
+        (%with-handlers (cdr handlers)
+          (lambda ()
+            ((car handlers) obj)
+            (%raise-non-continuable
+             (%make-error "Handler returned from non-continuable raise" (list obj))))))))
+
+(define %raise-non-continuable raise)
+
+(define (error message . irritants)
+  (%raise-non-continuable (%make-error message irritants)))
+
+(define (%guard body handler)
+  ((call/cc
+    (lambda (k)
+      (with-exception-handler
+       (lambda (condition) (k (lambda () (handler condition))))
+       (lambda () (let ((v (body))) (lambda () v))))))))
+
+(define (call-with-values producer consumer)
+  (apply consumer (%values->list (producer))))
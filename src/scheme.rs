@@ -9,275 +9,957 @@ pub mod syntax {
         Nil,
         Cons(Box<Quotation>, Box<Quotation>),
     }
+    /// Lists are dropped a pair at a time, so that long ones do not
+    /// recurse.
+    impl Drop for Quotation {
+        fn drop(&mut self) {
+            if let Quotation::Cons(_, ref mut tl) = *self {
+                let mut tl = ::std::mem::replace(&mut **tl, Quotation::Nil);
+                while let Quotation::Cons(_, ref mut next) = tl {
+                    let next = ::std::mem::replace(&mut **next, Quotation::Nil);
+                    tl = next;
+                }
+            }
+        }
+    }
     #[derive(Debug, PartialEq)]
     pub enum CondClause {
-        Simple(Expression, Expression),
+        Simple(Expression, Vec<Expression>),
         Binding(Expression, Expression),
         Inconsequential(Expression),
     }
-    pub type CaseClause = (Vec<Quotation>, Expression);
+    /// The body of a `case` clause: expressions, or `=> receiver`, which
+    /// is called with the key.
+    #[derive(Debug, PartialEq)]
+    pub enum ClauseBody {
+        Sequence(Vec<Expression>),
+        Receiver(Box<Expression>),
+    }
+    pub type CaseClause = (Vec<Quotation>, ClauseBody);
+    #[derive(Debug, PartialEq)]
+    pub struct Formals {
+        pub required: Vec<String>,
+        pub rest: Option<String>,
+    }
+    pub type Binding = (String, Expression);
+    /// The formals of a `let-values` binding and the expression whose
+    /// values they receive.
+    pub type ValuesBinding = (Formals, Expression);
+    /// A `do` variable, its initial value and the optional step that
+    /// updates it.
+    pub type DoBinding = (String, Expression, Option<Expression>);
+    /// A field of a record type, with its accessor and optional modifier.
+    #[derive(Debug, PartialEq)]
+    pub struct Field {
+        pub name: String,
+        pub accessor: String,
+        pub modifier: Option<String>,
+    }
+    #[derive(Debug, PartialEq)]
+    pub struct RecordType {
+        pub name: String,
+        /// The constructor and the fields it initialises, in the order of
+        /// its arguments.
+        pub constructor: (String, Vec<String>),
+        pub predicate: String,
+        pub fields: Vec<Field>,
+    }
     #[derive(Debug, PartialEq)]
     pub enum Expression {
         Literal(Literal),
         Variable(String),
         Quote(Quotation),
         Time(Box<Expression>),
-        If(Box<Expression>, Box<Expression>, Box<Expression>),
+        If(Box<Expression>, Box<Expression>, Option<Box<Expression>>),
         And(Vec<Expression>),
         Or(Vec<Expression>),
         Begin(Vec<Expression>),
-        Unless(Box<Expression>, Box<Expression>),
-        Cond(Vec<CondClause>, Box<Expression>),
-        Case(Box<Expression>, Vec<CaseClause>, Box<Expression>),
+        When(Box<Expression>, Vec<Expression>),
+        Unless(Box<Expression>, Vec<Expression>),
+        Cond(Vec<CondClause>, Option<Vec<Expression>>),
+        Case(Box<Expression>, Vec<CaseClause>, Option<ClauseBody>),
+        Lambda(Formals, Vec<Expression>),
+        Define(String, Box<Expression>),
+        Let(Option<String>, Vec<Binding>, Vec<Expression>),
+        LetValues(Vec<ValuesBinding>, Vec<Expression>),
+        LetStarValues(Vec<ValuesBinding>, Vec<Expression>),
+        DefineValues(Formals, Box<Expression>),
+        /// `(receive formals expression body...)`.
+        Receive(Formals, Box<Expression>, Vec<Expression>),
+        /// Bindings, the exit test, the result expressions and the commands.
+        Do(Vec<DoBinding>, Box<Expression>, Vec<Expression>, Vec<Expression>),
+        Set(String, Box<Expression>),
+        Delay(Box<Expression>),
+        DelayForce(Box<Expression>),
+        DefineRecordType(RecordType),
+        /// Parameter and value pairs, and the body.
+        Parameterize(Vec<(Expression, Expression)>, Vec<Expression>),
+        /// The variable for the condition, the clauses and else clause that
+        /// handle it, and the body.
+        Guard(String, Vec<CondClause>, Option<Vec<Expression>>, Vec<Expression>),
+        Application(Box<Expression>, Vec<Expression>),
     }
 }
 
-use scheme::syntax::{Quotation, Expression, CondClause, CaseClause};
+use scheme::syntax::{Quotation, Expression, CondClause, CaseClause, ClauseBody, Formals, Binding,
+                     ValuesBinding, DoBinding, Field, RecordType};
+
+/// A part of the source that an error is about, and what is wrong there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub node: Node,
+    pub message: String,
+}
 
+/// A syntax error, with labels pointing at the forms it is about. The first
+/// label is the primary one. The code is one of those in `codes`.
 #[derive(Debug)]
-pub enum SchemeError {
-    Basic(String),
+pub struct SchemeError {
+    pub code: &'static str,
+    pub message: String,
+    pub labels: Vec<Label>,
+}
+
+impl SchemeError {
+    /// An error pointing at `node`, with `label` saying what is wrong there.
+    pub fn at<S: Into<String>>(code: &'static str, message: S, node: &Node, label: &str) -> SchemeError {
+        SchemeError {
+            code,
+            message: message.into(),
+            labels: Vec::new(),
+        }.also(node, label)
+    }
+
+    /// Adds a label pointing at `node`.
+    pub fn also(mut self, node: &Node, label: &str) -> SchemeError {
+        self.labels.push(Label {
+            node: node.clone(),
+            message: label.to_string(),
+        });
+        self
+    }
 }
 
 impl std::fmt::Display for SchemeError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            &SchemeError::Basic(ref s) => write!(f, "SchemeError: {}", s),
-        }
+        write!(f, "SchemeError: {}", self.message)
     }
 }
 
+impl std::error::Error for SchemeError {}
+
 use std;
 pub type Result<T> = std::result::Result<T, SchemeError>;
 
+/// Rebuilds the form `(keyword tl...)` for an error to point at.
+fn form(keyword: &str, tl: &[Node]) -> Node {
+    let mut items = vec![Node::Identifier(keyword.to_string())];
+    items.extend(tl.iter().cloned());
+    Node::List(items)
+}
+
+fn arity_error(keyword: &str, tl: &[Node], expected: &str) -> SchemeError {
+    SchemeError::at(
+        "S0002", format!("Wrong number of arguments: expected {}, got {}", expected, tl.len()),
+        &form(keyword, tl), &format!("expected {} operands", expected))
+}
+
 fn parse_quotation(e: Node) -> Result<Quotation> {
     match e {
-        Node::Identifier(s) => Ok(Quotation::Symbol(s.clone())),
-        Node::Literal(l) => Ok(Quotation::Literal(l.clone())),
+        Node::Identifier(s) => Ok(Quotation::Symbol(s)),
+        Node::Literal(l) => Ok(Quotation::Literal(l)),
         Node::List(s) => parse_quotation_list(s),
     }
 }
 
+/// Builds the list from its last element back, so that long lists do not
+/// recurse. `(a b . c)` ends in `c` rather than `()`; a dot anywhere but
+/// second to last, after at least one datum, is an error.
 fn parse_quotation_list(mut e: Vec<Node>) -> Result<Quotation> {
-    if e.len() == 0 { return Ok(Quotation::Nil); }
-    let tl = e.split_off(1);
-    let hd = e.pop().unwrap();
-    Ok(Quotation::Cons(
-        Box::new(try!(parse_quotation(hd))),
-        Box::new(try!(parse_quotation_list(tl)))))
+    let mut res = Quotation::Nil;
+    let n = e.len();
+    let dot = |n: &Node| matches!(n, Node::Identifier(s) if s == ".");
+    if let Some(i) = e.iter().position(dot) {
+        if i == 0 || i + 2 != n || dot(&e[n - 1]) {
+            return Err(SchemeError::at(
+                "S0017", "Misplaced . in list", &Node::List(e),
+                "a dot must be followed by exactly one datum"));
+        }
+        res = parse_quotation(e.pop().unwrap())?;
+        e.pop();
+    }
+    for hd in e.into_iter().rev() {
+        res = Quotation::Cons(Box::new(parse_quotation(hd)?), Box::new(res));
+    }
+    Ok(res)
 }
 
-fn unary_op<C>(ctor: C, mut tl: Vec<Node>) -> Result<Expression>
+fn unary_op<C>(keyword: &str, ctor: C, mut tl: Vec<Node>) -> Result<Expression>
 where C: FnOnce(Box<Expression>) -> Expression {
     if tl.len() != 1 {
-        return Err(SchemeError::Basic(
-            format!("Wrong number of arguments: expected 1, got {}", tl.len())));
+        return Err(arity_error(keyword, &tl, "1"));
     }
-    let arg1 = Box::new(try!(parse_expression(tl.pop().unwrap())));
+    let arg1 = Box::new(parse_expression(tl.pop().unwrap())?);
     Ok(ctor(arg1))
 }
 
-fn binary_op<C>(ctor: C, mut tl: Vec<Node>) -> Result<Expression>
-where C: FnOnce(Box<Expression>, Box<Expression>) -> Expression {
-    if tl.len() != 2 {
-        return Err(SchemeError::Basic(
-            format!("Wrong number of arguments: expected 2, got {}", tl.len())));
+/// Parses a form of a test followed by a body, such as `when`.
+fn test_body_op<C>(keyword: &str, ctor: C, mut tl: Vec<Node>) -> Result<Expression>
+where C: FnOnce(Box<Expression>, Vec<Expression>) -> Expression {
+    if tl.len() < 2 {
+        return Err(arity_error(keyword, &tl, "at least 2"));
+    }
+    let body = tl.split_off(1);
+    let test = Box::new(parse_expression(tl.pop().unwrap())?);
+    Ok(ctor(test, parse_body(body)?))
+}
+
+fn parse_if(mut tl: Vec<Node>) -> Result<Expression> {
+    if tl.len() != 2 && tl.len() != 3 {
+        return Err(arity_error("if", &tl, "2 or 3"));
     }
-    let arg2 = Box::new(try!(parse_expression(tl.pop().unwrap())));
-    let arg1 = Box::new(try!(parse_expression(tl.pop().unwrap())));
-    Ok(ctor(arg1, arg2))
+    let alternative = if tl.len() == 3 {
+        Some(Box::new(parse_expression(tl.pop().unwrap())?))
+    } else {
+        None
+    };
+    let consequent = Box::new(parse_expression(tl.pop().unwrap())?);
+    let test = Box::new(parse_expression(tl.pop().unwrap())?);
+    Ok(Expression::If(test, consequent, alternative))
 }
 
-fn ternary_op<C>(ctor: C, mut tl: Vec<Node>) -> Result<Expression>
-where C: FnOnce(Box<Expression>, Box<Expression>, Box<Expression>) -> Expression {
-    if tl.len() != 3 {
-        return Err(SchemeError::Basic(
-            format!("Wrong number of arguments: expected 3, got {}", tl.len())));
+fn parse_set(mut tl: Vec<Node>) -> Result<Expression> {
+    if tl.len() != 2 {
+        return Err(arity_error("set!", &tl, "2"));
     }
-    let arg3 = Box::new(try!(parse_expression(tl.pop().unwrap())));
-    let arg2 = Box::new(try!(parse_expression(tl.pop().unwrap())));
-    let arg1 = Box::new(try!(parse_expression(tl.pop().unwrap())));
-    Ok(ctor(arg1, arg2, arg3))
+    let value = parse_expression(tl.pop().unwrap())?;
+    let name = parse_identifier(tl.pop().unwrap(), "set!")?;
+    Ok(Expression::Set(name, Box::new(value)))
 }
 
 fn zero_or_more_op<C>(ctor: C, tl: Vec<Node>) -> Result<Expression>
 where C: FnOnce(Vec<Expression>) -> Expression {
     let mut args = Vec::new();
     for n in tl.into_iter() {
-        args.push(try!(parse_expression(n)));
+        args.push(parse_expression(n)?);
     }
     Ok(ctor(args))
 }
 
-fn one_or_more_op<C>(ctor: C, tl: Vec<Node>) -> Result<Expression>
+fn one_or_more_op<C>(keyword: &str, ctor: C, tl: Vec<Node>) -> Result<Expression>
 where C: FnOnce(Vec<Expression>) -> Expression {
-    if tl.len() == 0 {
-        return Err(SchemeError::Basic(
-            "Wrong number of arguments: expected at least 1, got 0".to_string()));
+    if tl.is_empty() {
+        return Err(arity_error(keyword, &tl, "at least 1"));
     }
     zero_or_more_op(ctor, tl)
 }
 
 fn parse_cond_clause_inconsequential(test: Node) -> Result<CondClause> {
-    Ok(CondClause::Inconsequential(try!(parse_expression(test))))
+    Ok(CondClause::Inconsequential(parse_expression(test)?))
 }
 
-fn parse_cond_clause_simple(test: Node, consequent: Node) -> Result<CondClause> {
-    Ok(CondClause::Simple(
-        try!(parse_expression(test)),
-        try!(parse_expression(consequent))))
+fn parse_cond_clause_simple(test: Node, body: Vec<Node>) -> Result<CondClause> {
+    Ok(CondClause::Simple(parse_expression(test)?, parse_body(body)?))
 }
 
-fn parse_cond_clause_binding(test: Node, arrow: Node, consequent: Node) -> Result<CondClause> {
-    match arrow {
-        Node::Identifier(a) =>
-            if a != "=>" {
-                return Err(SchemeError::Basic(
-                    "cond clause: middle argument must be =>".to_string()));
-            },
-        _ => return Err(SchemeError::Basic(
-            "cond clause: middle argument must be =>".to_string())),
-    };
+fn parse_cond_clause_binding(test: Node, receiver: Node) -> Result<CondClause> {
     Ok(CondClause::Binding(
-        try!(parse_expression(test)),
-        try!(parse_expression(consequent))))
+        parse_expression(test)?,
+        parse_expression(receiver)?))
+}
+
+fn is_arrow(n: &Node) -> bool {
+    match n {
+        Node::Identifier(a) => a == "=>",
+        _ => false,
+    }
+}
+
+/// Checks that a clause using `=>` has exactly one receiver after it.
+fn check_receiver(code: &'static str, keyword: &str, l: &[Node]) -> Result<()> {
+    if l.len() >= 2 && is_arrow(&l[1]) && l.len() != 3 {
+        return Err(SchemeError::at(
+            code, format!("{} clause: Expected one receiver after =>, got {}",
+                          keyword, l.len() - 2),
+            &Node::List(l.to_vec()), "expected one receiver after =>"));
+    }
+    Ok(())
 }
 
 fn parse_cond_clause(clause: Node) -> Result<CondClause> {
     let mut l = match clause {
         Node::List(l) => l,
-        _ => return Err(SchemeError::Basic(
-            "cond clause: Expected list".to_string())),
+        _ => return Err(SchemeError::at(
+            "S0003", "cond clause: Expected list", &clause, "expected a clause in parentheses")),
     };
-    if l.len() == 1 {
-        parse_cond_clause_inconsequential(l.pop().unwrap())
-    } else if l.len() == 2 {
-        let a2 = l.pop().unwrap();
-        let a1 = l.pop().unwrap();
-        parse_cond_clause_simple(a1, a2)
-    } else if l.len() == 3 {
-        let a3 = l.pop().unwrap();
-        let a2 = l.pop().unwrap();
-        let a1 = l.pop().unwrap();
-        parse_cond_clause_binding(a1, a2, a3)
+    if l.is_empty() {
+        return Err(SchemeError::at(
+            "S0003", "cond clause: Expected a test", &Node::List(l),
+            "expected (test), (test expression...) or (test => receiver)"));
+    }
+    check_receiver("S0003", "cond", &l)?;
+    let mut body = l.split_off(1);
+    let test = l.pop().unwrap();
+    match body.first() {
+        None => parse_cond_clause_inconsequential(test),
+        Some(arrow) if is_arrow(arrow) => parse_cond_clause_binding(test, body.pop().unwrap()),
+        Some(_) => parse_cond_clause_simple(test, body),
+    }
+}
+
+/// Parses the body of a `case` clause: expressions, or `=> receiver`, which
+/// `check_receiver` has checked.
+fn parse_case_body(mut body: Vec<Node>) -> Result<ClauseBody> {
+    if body.first().is_some_and(is_arrow) {
+        Ok(ClauseBody::Receiver(Box::new(parse_expression(body.pop().unwrap())?)))
     } else {
-        Err(SchemeError::Basic(
-            format!("cond clause: Expected 1 <= length <= 3, got {}", l.len())))
+        Ok(ClauseBody::Sequence(parse_body(body)?))
     }
 }
 
-fn get_cond_else(else_clause: Node) -> Result<Node> {
-    let mut l = match else_clause {
-        Node::List(l) => l,
-        _ => return Err(SchemeError::Basic(
-            "cond clause: Expected list".to_string())),
-    };
-    if l.len() != 2 {
-        return Err(SchemeError::Basic(
-            format!("cond else clause: Expected length 2, got {}", l.len())));
-    }
-    let a2 = l.pop().unwrap();
-    let a1 = l.pop().unwrap();
-    let else_id = match a1 {
-        Node::Identifier(id) => id,
-        _ => return Err(SchemeError::Basic(
-            "cond else clause: Expected else".to_string())),
-    };
-    if else_id != "else" {
-        return Err(SchemeError::Basic(
-            "cond else clause: Expected else".to_string()));
+fn is_else_clause(clause: &Node) -> bool {
+    match clause {
+        Node::List(l) => match l.first() {
+            Some(Node::Identifier(id)) => id == "else",
+            _ => false,
+        },
+        _ => false,
     }
-    Ok(a2)
 }
 
-fn parse_cond(mut clauses: Vec<Node>) -> Result<Expression> {
-    let n = clauses.len();
-    if n == 0 {
-        return Err(SchemeError::Basic(
-            "Wrong number of cond arguments: expected at least 1, got 0"
-            .to_string()));
+/// Splits off the else clause of a `cond`, `case` or `guard`, which is optional but
+/// must be the last clause, and returns its body.
+fn take_else(keyword: &str, clauses: &mut Vec<Node>) -> Result<Option<Vec<Node>>> {
+    if let Some(i) = clauses.iter().position(is_else_clause) {
+        if i + 1 != clauses.len() {
+            return Err(SchemeError::at(
+                "S0004", format!("{} else clause: Expected else to be the last clause", keyword),
+                &clauses[i], "this else clause is not the last")
+                .also(&clauses[i + 1], "this clause follows it"));
+        }
+    }
+    if !clauses.last().is_some_and(is_else_clause) {
+        return Ok(None);
+    }
+    let mut l = match clauses.pop() {
+        Some(Node::List(l)) => l,
+        _ => unreachable!(),
+    };
+    if l.len() < 2 {
+        return Err(SchemeError::at(
+            "S0004", format!("{} else clause: Expected at least one expression", keyword),
+            &Node::List(l), "expected (else expression...)"));
     }
-    let else_clause = clauses.split_off(n-1).into_iter().next().unwrap();
+    if keyword == "case" {
+        check_receiver("S0004", "case else", &l)?;
+    }
+    Ok(Some(l.split_off(1)))
+}
+
+fn parse_cond_clauses(keyword: &str, mut clauses: Vec<Node>)
+                      -> Result<(Vec<CondClause>, Option<Vec<Expression>>)> {
+    let else_clause = match take_else(keyword, &mut clauses)? {
+        Some(body) => Some(parse_body(body)?),
+        None => None,
+    };
     let mut res = Vec::new();
     for c in clauses.into_iter() {
-        res.push(try!(parse_cond_clause(c)));
+        res.push(parse_cond_clause(c)?);
     }
-    let else_clause = try!(parse_expression(
-        try!(get_cond_else(else_clause))));
-    Ok(Expression::Cond(res, Box::new(else_clause)))
+    Ok((res, else_clause))
+}
+
+fn parse_cond(clauses: Vec<Node>) -> Result<Expression> {
+    let (clauses, else_clause) = parse_cond_clauses("cond", clauses)?;
+    Ok(Expression::Cond(clauses, else_clause))
 }
 
 fn parse_case_clause(clause: Node) -> Result<CaseClause> {
     let mut l = match clause {
         Node::List(l) => l,
-        _ => return Err(SchemeError::Basic(
-            "case clause: Expected list".to_string())),
+        _ => return Err(SchemeError::at(
+            "S0005", "case clause: Expected list", &clause, "expected a clause in parentheses")),
     };
-    if l.len() != 2 {
-        return Err(SchemeError::Basic(
-            format!("case clause: Expected length = 2, got {}", l.len())))
+    if l.len() < 2 {
+        return Err(SchemeError::at(
+            "S0005", format!("case clause: Expected length >= 2, got {}", l.len()),
+            &Node::List(l), "expected ((datum...) expression...)"));
     }
-    let expr = l.pop().unwrap();
+    check_receiver("S0005", "case", &l)?;
+    let body = l.split_off(1);
     let cases = match l.pop().unwrap() {
         Node::List(l) => l,
-        v => return Err(SchemeError::Basic(
-            format!("case clause cases: Expected list, got {:?}",
-                    v))),
+        v => return Err(SchemeError::at(
+            "S0005", format!("case clause cases: Expected list, got {:?}", v),
+            &v, "expected a list of data")),
     };
     let mut res = Vec::new();
     for c in cases {
-        res.push(try!(parse_quotation(c)));
+        res.push(parse_quotation(c)?);
     }
-    Ok((res, try!(parse_expression(expr))))
+    Ok((res, parse_case_body(body)?))
 }
 
 fn parse_case(mut clauses: Vec<Node>) -> Result<Expression> {
-    let n = clauses.len();
-    if n < 2 {
-        return Err(SchemeError::Basic(
-            "Wrong number of case arguments: expected at least 2"
-            .to_string()));
-    }
-    let else_clause = clauses.split_off(n-1).into_iter().next().unwrap();
-    let case_clauses = clauses.split_off(1);
-    let expr = try!(parse_expression(clauses.into_iter().next().unwrap()));
+    if clauses.is_empty() {
+        return Err(SchemeError::at(
+            "S0002", "Wrong number of case arguments: expected at least 1",
+            &form("case", &clauses), "expected a key"));
+    }
+    let mut case_clauses = clauses.split_off(1);
+    let else_clause = match take_else("case", &mut case_clauses)? {
+        Some(body) => Some(parse_case_body(body)?),
+        None => None,
+    };
+    let expr = parse_expression(clauses.into_iter().next().unwrap())?;
     let mut res = Vec::new();
     for c in case_clauses.into_iter() {
-        res.push(try!(parse_case_clause(c)));
+        res.push(parse_case_clause(c)?);
+    }
+    Ok(Expression::Case(Box::new(expr), res, else_clause))
+}
+
+fn parse_quote(mut tl: Vec<Node>) -> Result<Expression> {
+    if tl.len() != 1 {
+        return Err(arity_error("quote", &tl, "1"));
+    }
+    Ok(Expression::Quote(parse_quotation(tl.pop().unwrap())?))
+}
+
+fn parse_identifier(n: Node, what: &str) -> Result<String> {
+    match n {
+        Node::Identifier(s) => Ok(s),
+        v => Err(SchemeError::at(
+            "S0006", format!("{}: Expected identifier, got {:?}", what, v),
+            &v, "expected an identifier")),
     }
-    let else_clause = try!(parse_expression(
-        try!(get_cond_else(else_clause))));
-    Ok(Expression::Case(Box::new(expr), res, Box::new(else_clause)))
+}
+
+fn parse_formals(n: Node) -> Result<Formals> {
+    let l = match n {
+        Node::Identifier(rest) => return Ok(Formals {
+            required: Vec::new(),
+            rest: Some(rest),
+        }),
+        Node::List(ref l) => l,
+        ref v => return Err(SchemeError::at(
+            "S0007", format!("lambda formals: Expected list or identifier, got {:?}", v),
+            v, "expected a parameter list or identifier")),
+    };
+    // The nodes of the parameters, required ones first.
+    let mut params: Vec<&Node> = Vec::new();
+    let mut rest = None;
+    for (i, p) in l.iter().enumerate() {
+        if let Node::Identifier(ref s) = *p {
+            if s == "." {
+                if i + 2 != l.len() {
+                    return Err(SchemeError::at(
+                        "S0007", "lambda formals: Expected exactly 1 parameter after .",
+                        &n, "expected one parameter after the dot"));
+                }
+                rest = Some(parse_identifier(l[i + 1].clone(), "lambda formals")?);
+                params.push(&l[i + 1]);
+                break;
+            }
+        }
+        parse_identifier(p.clone(), "lambda formals")?;
+        params.push(p);
+    }
+    let names: Vec<String> = params.iter().map(|p| match p {
+        Node::Identifier(s) => s.clone(),
+        _ => unreachable!(),
+    }).collect();
+    for (i, name) in names.iter().enumerate() {
+        if let Some(j) = names[..i].iter().position(|n| n == name) {
+            return Err(SchemeError::at(
+                "S0008", format!("lambda formals: Duplicate parameter {}", name),
+                params[i], "parameter defined again here")
+                .also(params[j], "first defined here"));
+        }
+    }
+    let n_required = names.len() - if rest.is_some() { 1 } else { 0 };
+    Ok(Formals {
+        required: names.into_iter().take(n_required).collect(),
+        rest,
+    })
+}
+
+/// The error for a `lambda`, `define` or `let` with no body after `header`.
+fn empty_body(keyword: &str, header: &[Node]) -> SchemeError {
+    SchemeError::at("S0009", "body: Expected at least 1 expression, got 0",
+                    &form(keyword, header), &format!("this `{}` has no body", keyword))
+}
+
+fn parse_body(tl: Vec<Node>) -> Result<Vec<Expression>> {
+    let mut body = Vec::new();
+    for n in tl {
+        body.push(parse_expression(n)?);
+    }
+    Ok(body)
+}
+
+fn parse_lambda(mut tl: Vec<Node>) -> Result<Expression> {
+    if tl.is_empty() {
+        return Err(arity_error("lambda", &tl, "at least 2"));
+    }
+    let body = tl.split_off(1);
+    if body.is_empty() {
+        return Err(empty_body("lambda", &tl));
+    }
+    let formals = parse_formals(tl.pop().unwrap())?;
+    Ok(Expression::Lambda(formals, parse_body(body)?))
+}
+
+fn parse_define(mut tl: Vec<Node>) -> Result<Expression> {
+    if tl.is_empty() {
+        return Err(arity_error("define", &tl, "at least 2"));
+    }
+    let body = tl.split_off(1);
+    match tl.pop().unwrap() {
+        Node::Identifier(name) => {
+            if body.len() != 1 {
+                let mut whole = vec![Node::Identifier(name)];
+                whole.extend(body);
+                return Err(arity_error("define", &whole, "2"));
+            }
+            let value = parse_expression(body.into_iter().next().unwrap())?;
+            Ok(Expression::Define(name, Box::new(value)))
+        },
+        Node::List(mut header) => {
+            if header.is_empty() {
+                return Err(SchemeError::at(
+                    "S0010", "define: Expected procedure name", &Node::List(header),
+                    "expected (name parameter...)"));
+            }
+            if body.is_empty() {
+                return Err(empty_body("define", &[Node::List(header)]));
+            }
+            let params = header.split_off(1);
+            let name = parse_identifier(header.pop().unwrap(), "define")?;
+            let formals = parse_formals(Node::List(params))?;
+            let lambda = Expression::Lambda(formals, parse_body(body)?);
+            Ok(Expression::Define(name, Box::new(lambda)))
+        },
+        v => Err(SchemeError::at(
+            "S0010", format!("define: Expected identifier or list, got {:?}", v),
+            &v, "expected a name or (name parameter...)")),
+    }
+}
+
+fn parse_binding(n: Node) -> Result<Binding> {
+    let mut l = match n {
+        Node::List(l) => l,
+        v => return Err(SchemeError::at(
+            "S0011", format!("let binding: Expected list, got {:?}", v),
+            &v, "expected (name expression)")),
+    };
+    if l.len() != 2 {
+        return Err(SchemeError::at(
+            "S0011", format!("let binding: Expected length 2, got {}", l.len()),
+            &Node::List(l), "expected (name expression)"));
+    }
+    let init = parse_expression(l.pop().unwrap())?;
+    let name = parse_identifier(l.pop().unwrap(), "let binding")?;
+    Ok((name, init))
+}
+
+fn parse_bindings(n: Node) -> Result<Vec<Binding>> {
+    let l = match n {
+        Node::List(l) => l,
+        v => return Err(SchemeError::at(
+            "S0011", format!("let bindings: Expected list, got {:?}", v),
+            &v, "expected a list of bindings")),
+    };
+    let mut res: Vec<Binding> = Vec::new();
+    let mut names: Vec<Node> = Vec::new();
+    for b in l {
+        if let Node::List(ref items) = b {
+            if let Some(Node::Identifier(name)) = items.first() {
+                if let Some(j) = res.iter().position(|b| b.0 == *name) {
+                    return Err(SchemeError::at(
+                        "S0008", format!("let bindings: Duplicate variable {}", name),
+                        &items[0], "variable bound again here")
+                        .also(&names[j], "first bound here"));
+                }
+                names.push(items[0].clone());
+            }
+        }
+        res.push(parse_binding(b)?);
+    }
+    Ok(res)
+}
+
+fn parse_let(mut tl: Vec<Node>) -> Result<Expression> {
+    let name = match tl.first() {
+        Some(&Node::Identifier(_)) => Some(parse_identifier(tl.remove(0), "let")?),
+        _ => None,
+    };
+    if tl.is_empty() {
+        let header: Vec<Node> = name.into_iter().map(Node::Identifier).collect();
+        return Err(SchemeError::at(
+            "S0011", "let: Expected bindings", &form("let", &header), "expected a list of bindings"));
+    }
+    let body = tl.split_off(1);
+    if body.is_empty() {
+        let mut header: Vec<Node> = name.into_iter().map(Node::Identifier).collect();
+        header.extend(tl);
+        return Err(empty_body("let", &header));
+    }
+    let bindings = parse_bindings(tl.pop().unwrap())?;
+    Ok(Expression::Let(name, bindings, parse_body(body)?))
+}
+
+/// The identifiers of a formals node, with their nodes.
+fn formals_names(n: &Node) -> Vec<(String, &Node)> {
+    match n {
+        Node::Identifier(s) => vec![(s.clone(), n)],
+        Node::List(l) => l.iter().filter_map(|p| match p {
+            Node::Identifier(s) if s != "." => Some((s.clone(), p)),
+            _ => None,
+        }).collect(),
+        Node::Literal(_) => Vec::new(),
+    }
+}
+
+fn parse_values_binding(keyword: &str, n: Node) -> Result<ValuesBinding> {
+    let mut l = match n {
+        Node::List(l) if l.len() == 2 => l,
+        v => return Err(SchemeError::at(
+            "S0011", format!("{} binding: Expected (formals expression)", keyword),
+            &v, "expected (formals expression)")),
+    };
+    let init = parse_expression(l.pop().unwrap())?;
+    Ok((parse_formals(l.pop().unwrap())?, init))
+}
+
+/// Parses the bindings of a `let-values`, whose variables must be
+/// distinct, or of a `let*-values`, where later ones may shadow earlier.
+fn parse_values_bindings(keyword: &str, n: Node, distinct: bool) -> Result<Vec<ValuesBinding>> {
+    let l = match n {
+        Node::List(l) => l,
+        v => return Err(SchemeError::at(
+            "S0011", format!("{} bindings: Expected list, got {:?}", keyword, v),
+            &v, "expected a list of bindings")),
+    };
+    let mut res = Vec::new();
+    for b in l.iter() {
+        res.push(parse_values_binding(keyword, b.clone())?);
+    }
+    if distinct {
+        let names: Vec<(String, &Node)> = l.iter().flat_map(|b| match b {
+            Node::List(items) => formals_names(&items[0]),
+            _ => unreachable!(),
+        }).collect();
+        if let Some((name, again, first)) = find_duplicate(&names) {
+            return Err(SchemeError::at(
+                "S0008", format!("{} bindings: Duplicate variable {}", keyword, name),
+                again, "variable bound again here")
+                .also(first, "first bound here"));
+        }
+    }
+    Ok(res)
+}
+
+fn parse_let_values<C>(keyword: &str, ctor: C, mut tl: Vec<Node>) -> Result<Expression>
+where C: FnOnce(Vec<ValuesBinding>, Vec<Expression>) -> Expression {
+    if tl.is_empty() {
+        return Err(SchemeError::at(
+            "S0011", format!("{}: Expected bindings", keyword), &form(keyword, &tl),
+            "expected a list of bindings"));
+    }
+    let body = tl.split_off(1);
+    if body.is_empty() {
+        return Err(empty_body(keyword, &tl));
+    }
+    let bindings = parse_values_bindings(keyword, tl.pop().unwrap(), keyword == "let-values")?;
+    Ok(ctor(bindings, parse_body(body)?))
+}
+
+fn parse_define_values(mut tl: Vec<Node>) -> Result<Expression> {
+    if tl.len() != 2 {
+        return Err(arity_error("define-values", &tl, "2"));
+    }
+    let value = Box::new(parse_expression(tl.pop().unwrap())?);
+    Ok(Expression::DefineValues(parse_formals(tl.pop().unwrap())?, value))
+}
+
+fn parse_receive(mut tl: Vec<Node>) -> Result<Expression> {
+    if tl.len() < 2 {
+        return Err(arity_error("receive", &tl, "at least 3"));
+    }
+    let body = tl.split_off(2);
+    if body.is_empty() {
+        return Err(empty_body("receive", &tl));
+    }
+    let value = Box::new(parse_expression(tl.pop().unwrap())?);
+    let formals = parse_formals(tl.pop().unwrap())?;
+    Ok(Expression::Receive(formals, value, parse_body(body)?))
+}
+
+fn parse_do_binding(n: Node) -> Result<DoBinding> {
+    let mut l = match n {
+        Node::List(l) => l,
+        v => return Err(SchemeError::at(
+            "S0014", format!("do binding: Expected list, got {:?}", v),
+            &v, "expected (name init step)")),
+    };
+    if l.len() != 2 && l.len() != 3 {
+        return Err(SchemeError::at(
+            "S0014", format!("do binding: Expected length 2 or 3, got {}", l.len()),
+            &Node::List(l), "expected (name init) or (name init step)"));
+    }
+    let step = if l.len() == 3 {
+        Some(parse_expression(l.pop().unwrap())?)
+    } else {
+        None
+    };
+    let init = parse_expression(l.pop().unwrap())?;
+    let name = parse_identifier(l.pop().unwrap(), "do binding")?;
+    Ok((name, init, step))
+}
+
+fn parse_do(mut tl: Vec<Node>) -> Result<Expression> {
+    if tl.len() < 2 {
+        return Err(arity_error("do", &tl, "at least 2"));
+    }
+    let commands = tl.split_off(2);
+    let mut exit = match tl.pop().unwrap() {
+        Node::List(l) if !l.is_empty() => l,
+        v => return Err(SchemeError::at(
+            "S0014", format!("do: Expected (test expression...), got {:?}", v),
+            &v, "expected (test expression...)")),
+    };
+    let bindings = match tl.pop().unwrap() {
+        Node::List(l) => l,
+        v => return Err(SchemeError::at(
+            "S0014", format!("do bindings: Expected list, got {:?}", v),
+            &v, "expected a list of bindings")),
+    };
+    let mut res: Vec<DoBinding> = Vec::new();
+    let mut names: Vec<Node> = Vec::new();
+    for b in bindings {
+        if let Node::List(ref items) = b {
+            if let Some(Node::Identifier(name)) = items.first() {
+                if let Some(j) = res.iter().position(|b| b.0 == *name) {
+                    return Err(SchemeError::at(
+                        "S0008", format!("do bindings: Duplicate variable {}", name),
+                        &items[0], "variable bound again here")
+                        .also(&names[j], "first bound here"));
+                }
+                names.push(items[0].clone());
+            }
+        }
+        res.push(parse_do_binding(b)?);
+    }
+    let result = exit.split_off(1);
+    let test = parse_expression(exit.pop().unwrap())?;
+    Ok(Expression::Do(res, Box::new(test), parse_body(result)?, parse_body(commands)?))
+}
+
+/// The first of `names` that appears twice, with the nodes of its second
+/// and first occurrences.
+fn find_duplicate<'a, 'n>(names: &'a [(String, &'n Node)])
+                          -> Option<(&'a str, &'n Node, &'n Node)> {
+    for (i, (name, node)) in names.iter().enumerate() {
+        if let Some(first) = names[..i].iter().find(|n| n.0 == *name) {
+            return Some((name, node, first.1));
+        }
+    }
+    None
+}
+
+fn parse_field(n: Node) -> Result<Field> {
+    let mut l = match n {
+        Node::List(l) if l.len() == 2 || l.len() == 3 => l,
+        v => return Err(SchemeError::at(
+            "S0015", "record field: Expected (field accessor) or (field accessor modifier)",
+            &v, "expected (field accessor) or (field accessor modifier)")),
+    };
+    let modifier = if l.len() == 3 {
+        Some(parse_identifier(l.pop().unwrap(), "record field")?)
+    } else {
+        None
+    };
+    let accessor = parse_identifier(l.pop().unwrap(), "record field")?;
+    let name = parse_identifier(l.pop().unwrap(), "record field")?;
+    Ok(Field { name, accessor, modifier })
+}
+
+fn parse_define_record_type(mut tl: Vec<Node>) -> Result<Expression> {
+    if tl.len() < 3 {
+        return Err(arity_error("define-record-type", &tl, "at least 3"));
+    }
+    let field_nodes = tl.split_off(3);
+    let predicate = parse_identifier(tl.pop().unwrap(), "define-record-type")?;
+    let constructor = tl.pop().unwrap();
+    let name = parse_identifier(tl.pop().unwrap(), "define-record-type")?;
+    let mut fields = Vec::new();
+    for f in field_nodes.iter() {
+        fields.push(parse_field(f.clone())?);
+    }
+    let field_names: Vec<(String, &Node)> = fields.iter().zip(&field_nodes).map(|(f, n)| match n {
+        Node::List(l) => (f.name.clone(), &l[0]),
+        _ => unreachable!(),
+    }).collect();
+    if let Some((name, again, first)) = find_duplicate(&field_names) {
+        return Err(SchemeError::at(
+            "S0008", format!("define-record-type: Duplicate field {}", name),
+            again, "field defined again here")
+            .also(first, "first defined here"));
+    }
+    let args = match constructor {
+        Node::List(ref l) if !l.is_empty() => l,
+        ref v => return Err(SchemeError::at(
+            "S0015", "define-record-type: Expected (constructor field...)",
+            v, "expected (constructor field...)")),
+    };
+    let mut arg_names = Vec::new();
+    for a in args.iter().skip(1) {
+        let arg = parse_identifier(a.clone(), "record constructor")?;
+        if !fields.iter().any(|f| f.name == arg) {
+            return Err(SchemeError::at(
+                "S0015", format!("define-record-type: {} is not a field", arg),
+                a, "not a field of this record type"));
+        }
+        arg_names.push((arg, a));
+    }
+    if let Some((name, again, first)) = find_duplicate(&arg_names) {
+        return Err(SchemeError::at(
+            "S0008", format!("record constructor: Duplicate field {}", name),
+            again, "field initialised again here")
+            .also(first, "first initialised here"));
+    }
+    let constructor_name = parse_identifier(args[0].clone(), "record constructor")?;
+    Ok(Expression::DefineRecordType(RecordType {
+        name,
+        constructor: (constructor_name, arg_names.into_iter().map(|a| a.0).collect()),
+        predicate,
+        fields,
+    }))
+}
+
+fn parse_parameterize(mut tl: Vec<Node>) -> Result<Expression> {
+    if tl.is_empty() {
+        return Err(SchemeError::at(
+            "S0011", "parameterize: Expected bindings", &form("parameterize", &tl),
+            "expected a list of bindings"));
+    }
+    let body = tl.split_off(1);
+    if body.is_empty() {
+        return Err(empty_body("parameterize", &tl));
+    }
+    let bindings = match tl.pop().unwrap() {
+        Node::List(l) => l,
+        v => return Err(SchemeError::at(
+            "S0011", format!("parameterize bindings: Expected list, got {:?}", v),
+            &v, "expected a list of bindings")),
+    };
+    let mut res = Vec::new();
+    for b in bindings {
+        let mut l = match b {
+            Node::List(l) if l.len() == 2 => l,
+            v => return Err(SchemeError::at(
+                "S0011", "parameterize binding: Expected (parameter expression)",
+                &v, "expected (parameter expression)")),
+        };
+        let value = parse_expression(l.pop().unwrap())?;
+        res.push((parse_expression(l.pop().unwrap())?, value));
+    }
+    Ok(Expression::Parameterize(res, parse_body(body)?))
+}
+
+fn parse_guard(mut tl: Vec<Node>) -> Result<Expression> {
+    if tl.is_empty() {
+        return Err(SchemeError::at(
+            "S0016", "guard: Expected (variable clause...)", &form("guard", &tl),
+            "expected (variable clause...)"));
+    }
+    let body = tl.split_off(1);
+    if body.is_empty() {
+        return Err(empty_body("guard", &tl));
+    }
+    let mut clauses = match tl.pop().unwrap() {
+        Node::List(l) if !l.is_empty() => l,
+        v => return Err(SchemeError::at(
+            "S0016", format!("guard: Expected (variable clause...), got {:?}", v),
+            &v, "expected (variable clause...)")),
+    };
+    let cond_clauses = clauses.split_off(1);
+    let variable = parse_identifier(clauses.pop().unwrap(), "guard")?;
+    let (cond_clauses, else_clause) = parse_cond_clauses("guard", cond_clauses)?;
+    Ok(Expression::Guard(variable, cond_clauses, else_clause, parse_body(body)?))
+}
+
+fn parse_application(hd: Node, tl: Vec<Node>) -> Result<Expression> {
+    let operator = parse_expression(hd)?;
+    let mut operands = Vec::new();
+    for n in tl {
+        operands.push(parse_expression(n)?);
+    }
+    Ok(Expression::Application(Box::new(operator), operands))
 }
 
 fn parse_expression_from_list(hd: Node, tl: Vec<Node>) -> Result<Expression> {
     match hd {
         Node::Identifier(ref keyword) =>
             if keyword == "quote" {
-                Ok(Expression::Quote(try!(parse_quotation_list(tl))))
+                parse_quote(tl)
             } else if keyword == "time" {
-                unary_op(Expression::Time, tl)
+                unary_op(keyword, Expression::Time, tl)
             } else if keyword == "if" {
-                ternary_op(Expression::If, tl)
+                parse_if(tl)
             } else if keyword == "and" {
                 zero_or_more_op(Expression::And, tl)
             } else if keyword == "or" {
                 zero_or_more_op(Expression::Or, tl)
             } else if keyword == "begin" {
-                one_or_more_op(Expression::Begin, tl)
+                one_or_more_op(keyword, Expression::Begin, tl)
+            } else if keyword == "when" {
+                test_body_op(keyword, Expression::When, tl)
             } else if keyword == "unless" {
-                binary_op(Expression::Unless, tl)
+                test_body_op(keyword, Expression::Unless, tl)
             } else if keyword == "cond" {
                 parse_cond(tl)
             } else if keyword == "case" {
                 parse_case(tl)
+            } else if keyword == "lambda" {
+                parse_lambda(tl)
+            } else if keyword == "define" {
+                parse_define(tl)
+            } else if keyword == "let" {
+                parse_let(tl)
+            } else if keyword == "let-values" {
+                parse_let_values(keyword, Expression::LetValues, tl)
+            } else if keyword == "let*-values" {
+                parse_let_values(keyword, Expression::LetStarValues, tl)
+            } else if keyword == "define-values" {
+                parse_define_values(tl)
+            } else if keyword == "receive" {
+                parse_receive(tl)
+            } else if keyword == "do" {
+                parse_do(tl)
+            } else if keyword == "set!" {
+                parse_set(tl)
+            } else if keyword == "delay" {
+                unary_op(keyword, Expression::Delay, tl)
+            } else if keyword == "delay-force" {
+                unary_op(keyword, Expression::DelayForce, tl)
+            } else if keyword == "define-record-type" {
+                parse_define_record_type(tl)
+            } else if keyword == "parameterize" {
+                parse_parameterize(tl)
+            } else if keyword == "guard" {
+                parse_guard(tl)
             } else {
-                Err(SchemeError::Basic(format!("unhandled keyword {}", keyword)))
+                parse_application(hd, tl)
             },
-        Node::Literal(_) =>
-            Err(SchemeError::Basic("Cannot apply to literal".to_string())),
-        Node::List(_) =>
-            Err(SchemeError::Basic("Application not implemented".to_string())),
+        Node::Literal(_) => {
+            let mut combination = vec![hd.clone()];
+            combination.extend(tl);
+            Err(SchemeError::at("S0012", "Cannot apply to literal", &hd, "not a procedure")
+                .also(&Node::List(combination), "in this procedure call"))
+        },
+        Node::List(_) => parse_application(hd, tl),
     }
 }
 
@@ -286,8 +968,9 @@ pub fn parse_expression(n: Node) -> Result<Expression> {
         Node::Literal(l) => Ok(Expression::Literal(l)),
         Node::Identifier(s) => Ok(Expression::Variable(s)), // TODO check reserved
         Node::List(mut s) => {
-            if s.len() == 0 {
-                return Err(SchemeError::Basic("Unexpected Nil".to_string()));
+            if s.is_empty() {
+                return Err(SchemeError::at(
+                    "S0013", "Unexpected Nil", &Node::List(s), "empty combination"));
             }
             let tl = s.split_off(1);
             let hd = s.pop().unwrap();
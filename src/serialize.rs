@@ -0,0 +1,1177 @@
+use std;
+use std::fmt;
+
+use json::Json;
+use lexer::{Literal, Token, RegexLexer, Span};
+use parser::{Node, Parser};
+use scheme::syntax::{Expression, Quotation, CondClause, ClauseBody, Formals, ValuesBinding, Field,
+                     RecordType};
+
+/// The version of the JSON schema and of the AST dump. It changes whenever
+/// a tree would be written differently.
+pub const SCHEMA_VERSION: u32 = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum DeserializeError {
+    Basic(String),
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeserializeError::Basic(s) => write!(f, "DeserializeError: {}", s),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, DeserializeError>;
+
+fn error<T, S: Into<String>>(msg: S) -> Result<T> {
+    Err(DeserializeError::Basic(msg.into()))
+}
+
+/// Conversion of syntax trees to external formats: JSON, the AST dump,
+/// which is the same tree as an S-expression, and S-expressions in the
+/// surface syntax that reads back as the same tree.
+pub trait Serialize {
+    fn to_json(&self) -> Json;
+
+    fn write_sexpr(&self, out: &mut String);
+
+    fn to_sexpr(&self) -> String {
+        let mut out = String::new();
+        self.write_sexpr(&mut out);
+        out
+    }
+}
+
+/// Conversion of syntax trees back from JSON, or from the AST dump by way
+/// of `parse_ast`.
+pub trait Deserialize: Sized {
+    fn from_json(json: &Json) -> Result<Self>;
+}
+
+/// Wraps a tree in the versioned envelope
+/// `{"type":"scheme-syntax","version":4,"tree":...}`.
+pub fn document(tree: Json) -> Json {
+    tagged("scheme-syntax", vec![
+        ("version", Json::Number(f64::from(SCHEMA_VERSION))),
+        ("tree", tree),
+    ])
+}
+
+/// Reads the tree of a document written by `document`.
+pub fn from_document<T: Deserialize>(json: &Json) -> Result<T> {
+    if tag(json)? != "scheme-syntax" {
+        return error("Expected a scheme-syntax document");
+    }
+    match field(json, "version")? {
+        Json::Number(v) if *v == f64::from(SCHEMA_VERSION) => (),
+        v => return error(format!("Unsupported schema version {}", v)),
+    }
+    T::from_json(field(json, "tree")?)
+}
+
+/// A tree along with the span of source text that it was read from.
+#[derive(Debug, PartialEq)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Option<Span>,
+}
+
+impl <T: Serialize> Serialize for Spanned<T> {
+    fn to_json(&self) -> Json {
+        let mut json = self.value.to_json();
+        if let (Some(span), &mut Json::Object(ref mut fields)) = (self.span, &mut json) {
+            fields.push(("span".to_string(), tagged("span", vec![
+                ("start", Json::Number(span.start as f64)),
+                ("end", Json::Number(span.end as f64)),
+            ])));
+        }
+        json
+    }
+
+    fn write_sexpr(&self, out: &mut String) {
+        self.value.write_sexpr(out)
+    }
+}
+
+impl <T: Deserialize> Deserialize for Spanned<T> {
+    fn from_json(json: &Json) -> Result<Self> {
+        let span = match json.get("span") {
+            None | Some(Json::Null) => None,
+            Some(span) => Some(Span {
+                start: index(field(span, "start")?)?,
+                end: index(field(span, "end")?)?,
+            }),
+        };
+        Ok(Spanned {
+            value: T::from_json(json)?,
+            span,
+        })
+    }
+}
+
+/// Writes JSON as an S-expression. An object becomes `(type (key value)...)`
+/// where `type` is its "type" field, or `object` if it has none; an array
+/// becomes a list, and null is the symbol `null`.
+pub fn write_ast(json: &Json, out: &mut String) {
+    match json {
+        Json::Null => out.push_str("null"),
+        Json::Bool(b) => out.push_str(if *b { "#t" } else { "#f" }),
+        Json::Number(x) if x.fract() == 0.0 && x.abs() <= f64::from(i32::MAX) =>
+            out.push_str(&(*x as i32).to_string()),
+        Json::Number(x) => out.push_str(&real_sexpr(*x)),
+        Json::String(s) => Literal::String(s.clone()).write_sexpr(out),
+        Json::Array(items) => {
+            out.push('(');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                write_ast(item, out);
+            }
+            out.push(')');
+        },
+        Json::Object(fields) => {
+            out.push('(');
+            out.push_str(json.get("type").and_then(Json::as_str).unwrap_or("object"));
+            for (k, v) in fields {
+                if k != "type" {
+                    out.push_str(" (");
+                    out.push_str(k);
+                    out.push(' ');
+                    write_ast(v, out);
+                    out.push(')');
+                }
+            }
+            out.push(')');
+        },
+    }
+}
+
+/// Reads the S-expressions written by `write_ast` back as JSON. A list that
+/// starts with a symbol is an object, and any other list an array.
+pub fn parse_ast(text: &str) -> Result<Vec<Json>> {
+    let mut res = Vec::new();
+    for node in Parser::new(RegexLexer::new(text)) {
+        match node {
+            Ok(node) => res.push(read_ast(node)?),
+            Err(e) => return error(e),
+        }
+    }
+    Ok(res)
+}
+
+fn read_ast(node: Node) -> Result<Json> {
+    match node {
+        Node::Identifier(ref s) if s == "null" => Ok(Json::Null),
+        Node::Identifier(s) => error(format!("Unexpected symbol {}", s)),
+        Node::Literal(Literal::Boolean(b)) => Ok(Json::Bool(b)),
+        Node::Literal(Literal::Number(n)) => Ok(Json::Number(f64::from(n))),
+        Node::Literal(Literal::Real(x)) => Ok(Json::Number(x)),
+        Node::Literal(Literal::String(s)) => Ok(Json::String(s)),
+        Node::Literal(Literal::Character(c)) => error(format!("Unexpected character {:?}", c)),
+        Node::List(items) => {
+            let tag = match items.first() {
+                Some(Node::Identifier(tag)) if tag != "null" => tag.clone(),
+                _ => return Ok(Json::Array(items.into_iter().map(read_ast).collect::<Result<_>>()?)),
+            };
+            let mut fields = Vec::new();
+            if tag != "object" {
+                fields.push(("type".to_string(), Json::String(tag)));
+            }
+            for item in items.into_iter().skip(1) {
+                match item {
+                    Node::List(mut kv) => {
+                        if kv.len() != 2 {
+                            return error("Expected (key value)");
+                        }
+                        let value = read_ast(kv.pop().unwrap())?;
+                        match kv.pop().unwrap() {
+                            Node::Identifier(key) => fields.push((key, value)),
+                            _ => return error("Expected (key value)"),
+                        }
+                    },
+                    _ => return error("Expected (key value)"),
+                }
+            }
+            Ok(Json::Object(fields))
+        },
+    }
+}
+
+fn field<'j>(json: &'j Json, key: &str) -> Result<&'j Json> {
+    match json.get(key) {
+        Some(v) => Ok(v),
+        None => error(format!("Missing field {}", key)),
+    }
+}
+
+fn tag(json: &Json) -> Result<&str> {
+    match json.get("type").and_then(Json::as_str) {
+        Some(tag) => Ok(tag),
+        None => error(format!("Expected an object with a type, got {}", json)),
+    }
+}
+
+fn string(json: &Json) -> Result<String> {
+    match json.as_str() {
+        Some(s) => Ok(s.to_string()),
+        None => error(format!("Expected a string, got {}", json)),
+    }
+}
+
+/// The string value of an optional field, which may be null or missing.
+fn optional_string(json: &Json, key: &str) -> Result<Option<String>> {
+    match json.get(key) {
+        None | Some(Json::Null) => Ok(None),
+        Some(v) => string(v).map(Some),
+    }
+}
+
+fn index(json: &Json) -> Result<usize> {
+    match json {
+        Json::Number(x) if x.fract() == 0.0 && *x >= 0.0 => Ok(*x as usize),
+        _ => error(format!("Expected an offset, got {}", json)),
+    }
+}
+
+fn items(json: &Json) -> Result<&[Json]> {
+    match json {
+        Json::Array(items) => Ok(items),
+        _ => error(format!("Expected an array, got {}", json)),
+    }
+}
+
+fn string_list(json: &Json, key: &str) -> Result<Vec<String>> {
+    items(field(json, key)?)?.iter().map(string).collect()
+}
+
+fn list<T: Deserialize>(json: &Json, key: &str) -> Result<Vec<T>> {
+    items(field(json, key)?)?.iter().map(T::from_json).collect()
+}
+
+/// The items of an optional array field, which may be null or missing.
+fn optional_list<T: Deserialize>(json: &Json, key: &str) -> Result<Option<Vec<T>>> {
+    match json.get(key) {
+        None | Some(Json::Null) => Ok(None),
+        Some(_) => list(json, key).map(Some),
+    }
+}
+
+fn boxed<T: Deserialize>(json: &Json, key: &str) -> Result<Box<T>> {
+    Ok(Box::new(T::from_json(field(json, key)?)?))
+}
+
+/// The value of an optional field, which may be null or missing.
+fn optional_boxed<T: Deserialize>(json: &Json, key: &str) -> Result<Option<Box<T>>> {
+    match json.get(key) {
+        None | Some(Json::Null) => Ok(None),
+        Some(_) => boxed(json, key).map(Some),
+    }
+}
+
+fn formals(json: &Json) -> Result<Formals> {
+    Ok(Formals {
+        required: string_list(json, "required")?,
+        rest: optional_string(json, "rest")?,
+    })
+}
+
+fn values_bindings(json: &Json) -> Result<Vec<ValuesBinding>> {
+    let mut bindings = Vec::new();
+    for b in items(field(json, "bindings")?)? {
+        bindings.push((formals(b)?, Expression::from_json(field(b, "init")?)?));
+    }
+    Ok(bindings)
+}
+
+fn unknown<T>(what: &str, tag: &str) -> Result<T> {
+    error(format!("Unknown {} type {}", what, tag))
+}
+
+fn tagged(tag: &str, mut fields: Vec<(&str, Json)>) -> Json {
+    fields.insert(0, ("type", Json::string(tag)));
+    Json::object(fields)
+}
+
+fn array<T: Serialize>(items: &[T]) -> Json {
+    Json::Array(items.iter().map(Serialize::to_json).collect())
+}
+
+/// Writes `(head item...)`, omitting `head` if it is empty.
+fn write_list<T: Serialize>(out: &mut String, head: &str, items: &[T]) {
+    out.push('(');
+    out.push_str(head);
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 || !head.is_empty() {
+            out.push(' ');
+        }
+        item.write_sexpr(out);
+    }
+    out.push(')');
+}
+
+fn real_sexpr(x: f64) -> String {
+    if x.is_nan() {
+        "+nan.0".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "+inf.0" } else { "-inf.0" }.to_string()
+    } else {
+        format!("{:?}", x)
+    }
+}
+
+impl Serialize for Literal {
+    fn to_json(&self) -> Json {
+        match self {
+            Literal::Number(n) => tagged("number", vec![("value", Json::Number(f64::from(*n)))]),
+            Literal::Real(x) if x.is_finite() => tagged("real", vec![("value", Json::Number(*x))]),
+            Literal::Real(x) => tagged("real", vec![("value", Json::String(real_sexpr(*x)))]),
+            Literal::Boolean(b) => tagged("boolean", vec![("value", Json::Bool(*b))]),
+            Literal::Character(c) => tagged("character", vec![("value", Json::string(c.to_string()))]),
+            Literal::String(s) => tagged("string", vec![("value", Json::string(s.clone()))]),
+        }
+    }
+
+    fn write_sexpr(&self, out: &mut String) {
+        match self {
+            Literal::Number(n) => out.push_str(&n.to_string()),
+            Literal::Real(x) => out.push_str(&real_sexpr(*x)),
+            Literal::Boolean(b) => out.push_str(if *b { "#t" } else { "#f" }),
+            Literal::Character('\n') => out.push_str("#\\newline"),
+            Literal::Character(' ') => out.push_str("#\\space"),
+            Literal::Character(c) => {
+                out.push_str("#\\");
+                out.push(*c);
+            },
+            Literal::String(s) => {
+                out.push('"');
+                for c in s.chars() {
+                    match c {
+                        '\\' => out.push_str("\\\\"),
+                        '"' => out.push_str("\\\""),
+                        '\n' => out.push_str("\\n"),
+                        '\t' => out.push_str("\\t"),
+                        c => out.push(c),
+                    }
+                }
+                out.push('"');
+            },
+        }
+    }
+}
+
+impl Serialize for Token {
+    fn to_json(&self) -> Json {
+        match self {
+            Token::Identifier(s) => tagged("identifier", vec![("name", Json::string(s.clone()))]),
+            Token::LParen => tagged("lparen", vec![]),
+            Token::RParen => tagged("rparen", vec![]),
+            Token::Literal(l) => tagged("literal", vec![("literal", l.to_json())]),
+        }
+    }
+
+    fn write_sexpr(&self, out: &mut String) {
+        match self {
+            Token::Identifier(s) => out.push_str(s),
+            Token::LParen => out.push('('),
+            Token::RParen => out.push(')'),
+            Token::Literal(l) => l.write_sexpr(out),
+        }
+    }
+}
+
+impl Serialize for Node {
+    fn to_json(&self) -> Json {
+        match self {
+            Node::Identifier(s) => tagged("identifier", vec![("name", Json::string(s.clone()))]),
+            Node::Literal(l) => tagged("literal", vec![("literal", l.to_json())]),
+            Node::List(items) => tagged("list", vec![("items", array(items))]),
+        }
+    }
+
+    fn write_sexpr(&self, out: &mut String) {
+        match self {
+            Node::Identifier(s) => out.push_str(s),
+            Node::Literal(l) => l.write_sexpr(out),
+            Node::List(items) => write_list(out, "", items),
+        }
+    }
+}
+
+impl Serialize for Quotation {
+    fn to_json(&self) -> Json {
+        match self {
+            Quotation::Literal(l) => tagged("literal", vec![("literal", l.to_json())]),
+            Quotation::Symbol(s) => tagged("symbol", vec![("name", Json::string(s.clone()))]),
+            Quotation::Nil => tagged("nil", vec![]),
+            // A list is flat rather than nested pairs, so that long ones do
+            // not nest deeply.
+            Quotation::Cons(..) => {
+                let mut items = Vec::new();
+                let mut tl = self;
+                while let Quotation::Cons(car, cdr) = tl {
+                    items.push(car.to_json());
+                    tl = cdr;
+                }
+                tagged("list", vec![("items", Json::Array(items)), ("tail", tl.to_json())])
+            },
+        }
+    }
+
+    fn write_sexpr(&self, out: &mut String) {
+        match self {
+            Quotation::Literal(l) => l.write_sexpr(out),
+            Quotation::Symbol(s) => out.push_str(s),
+            Quotation::Nil => out.push_str("()"),
+            Quotation::Cons(car, cdr) => {
+                out.push('(');
+                car.write_sexpr(out);
+                let mut tl: &Quotation = cdr;
+                while let Quotation::Cons(car, cdr) = tl {
+                    out.push(' ');
+                    car.write_sexpr(out);
+                    tl = cdr;
+                }
+                if *tl != Quotation::Nil {
+                    out.push_str(" . ");
+                    tl.write_sexpr(out);
+                }
+                out.push(')');
+            },
+        }
+    }
+}
+
+impl Serialize for CondClause {
+    fn to_json(&self) -> Json {
+        match self {
+            CondClause::Simple(test, body) =>
+                tagged("simple", vec![("test", test.to_json()), ("body", array(body))]),
+            CondClause::Binding(test, receiver) =>
+                tagged("binding", vec![("test", test.to_json()), ("receiver", receiver.to_json())]),
+            CondClause::Inconsequential(test) =>
+                tagged("test", vec![("test", test.to_json())]),
+        }
+    }
+
+    fn write_sexpr(&self, out: &mut String) {
+        out.push('(');
+        match self {
+            CondClause::Simple(test, body) => {
+                test.write_sexpr(out);
+                write_body(out, body);
+            },
+            CondClause::Binding(test, receiver) => {
+                test.write_sexpr(out);
+                out.push_str(" => ");
+                receiver.write_sexpr(out);
+            },
+            CondClause::Inconsequential(test) => test.write_sexpr(out),
+        }
+        out.push(')');
+    }
+}
+
+impl Serialize for ClauseBody {
+    fn to_json(&self) -> Json {
+        match self {
+            ClauseBody::Sequence(body) => tagged("sequence", vec![("body", array(body))]),
+            ClauseBody::Receiver(receiver) =>
+                tagged("receiver", vec![("receiver", receiver.to_json())]),
+        }
+    }
+
+    /// Writes the body with a space before each expression, to follow the
+    /// data or `else` of the clause.
+    fn write_sexpr(&self, out: &mut String) {
+        match self {
+            ClauseBody::Sequence(body) => write_body(out, body),
+            ClauseBody::Receiver(receiver) => {
+                out.push_str(" => ");
+                receiver.write_sexpr(out);
+            },
+        }
+    }
+}
+
+fn strings(names: &[String]) -> Json {
+    Json::Array(names.iter().cloned().map(Json::String).collect())
+}
+
+fn formals_json(formals: &Formals) -> Vec<(&'static str, Json)> {
+    vec![
+        ("required", strings(&formals.required)),
+        ("rest", formals.rest.clone().map_or(Json::Null, Json::String)),
+    ]
+}
+
+fn write_formals(out: &mut String, formals: &Formals) {
+    if formals.required.is_empty() {
+        if let Some(ref rest) = formals.rest {
+            out.push_str(rest);
+            return;
+        }
+    }
+    out.push('(');
+    out.push_str(&formals.required.join(" "));
+    if let Some(ref rest) = formals.rest {
+        out.push_str(" . ");
+        out.push_str(rest);
+    }
+    out.push(')');
+}
+
+fn values_bindings_json(bindings: &[ValuesBinding]) -> Json {
+    Json::Array(bindings.iter().map(|b| {
+        let mut fields = formals_json(&b.0);
+        fields.push(("init", b.1.to_json()));
+        tagged("values-binding", fields)
+    }).collect())
+}
+
+fn write_values_bindings(out: &mut String, head: &str, bindings: &[ValuesBinding],
+                         body: &[Expression]) {
+    out.push('(');
+    out.push_str(head);
+    out.push_str(" (");
+    for (i, b) in bindings.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        out.push('(');
+        write_formals(out, &b.0);
+        out.push(' ');
+        b.1.write_sexpr(out);
+        out.push(')');
+    }
+    out.push(')');
+    write_body(out, body);
+    out.push(')');
+}
+
+fn write_body(out: &mut String, body: &[Expression]) {
+    for e in body {
+        out.push(' ');
+        e.write_sexpr(out);
+    }
+}
+
+/// Writes `(head test body...)`, omitting `head` if it is empty.
+fn write_cond_clauses(out: &mut String, clauses: &[CondClause],
+                      else_clause: &Option<Vec<Expression>>) {
+    for c in clauses {
+        out.push(' ');
+        c.write_sexpr(out);
+    }
+    if let Some(body) = else_clause {
+        out.push_str(" (else");
+        write_body(out, body);
+        out.push(')');
+    }
+}
+
+fn write_test_body(out: &mut String, head: &str, test: &Expression, body: &[Expression]) {
+    out.push('(');
+    if !head.is_empty() {
+        out.push_str(head);
+        out.push(' ');
+    }
+    test.write_sexpr(out);
+    write_body(out, body);
+    out.push(')');
+}
+
+impl Serialize for Expression {
+    fn to_json(&self) -> Json {
+        match self {
+            Expression::Literal(l) => tagged("literal", vec![("literal", l.to_json())]),
+            Expression::Variable(s) => tagged("variable", vec![("name", Json::string(s.clone()))]),
+            Expression::Quote(q) => tagged("quote", vec![("datum", q.to_json())]),
+            Expression::Time(e) => tagged("time", vec![("body", e.to_json())]),
+            Expression::If(test, consequent, alternative) => tagged("if", vec![
+                ("test", test.to_json()),
+                ("consequent", consequent.to_json()),
+                ("alternative", alternative.as_ref().map_or(Json::Null, Serialize::to_json)),
+            ]),
+            Expression::And(es) => tagged("and", vec![("operands", array(es))]),
+            Expression::Or(es) => tagged("or", vec![("operands", array(es))]),
+            Expression::Begin(es) => tagged("begin", vec![("body", array(es))]),
+            Expression::When(test, body) =>
+                tagged("when", vec![("test", test.to_json()), ("body", array(body))]),
+            Expression::Unless(test, body) =>
+                tagged("unless", vec![("test", test.to_json()), ("body", array(body))]),
+            Expression::Cond(clauses, else_clause) => tagged("cond", vec![
+                ("clauses", array(clauses)),
+                ("else", else_clause.as_ref().map_or(Json::Null, |body| array(body))),
+            ]),
+            Expression::Case(key, clauses, else_clause) => tagged("case", vec![
+                ("key", key.to_json()),
+                ("clauses", Json::Array(clauses.iter().map(|c| tagged("case-clause", vec![
+                    ("data", array(&c.0)),
+                    ("body", c.1.to_json()),
+                ])).collect())),
+                ("else", else_clause.as_ref().map_or(Json::Null, Serialize::to_json)),
+            ]),
+            Expression::Lambda(formals, body) => {
+                let mut fields = formals_json(formals);
+                fields.push(("body", array(body)));
+                tagged("lambda", fields)
+            },
+            Expression::Define(name, value) => tagged("define", vec![
+                ("name", Json::string(name.clone())),
+                ("value", value.to_json()),
+            ]),
+            Expression::Let(name, bindings, body) => tagged("let", vec![
+                ("name", name.clone().map_or(Json::Null, Json::String)),
+                ("bindings", Json::Array(bindings.iter().map(|b| tagged("let-binding", vec![
+                    ("name", Json::string(b.0.clone())),
+                    ("init", b.1.to_json()),
+                ])).collect())),
+                ("body", array(body)),
+            ]),
+            Expression::Do(bindings, test, result, commands) => tagged("do", vec![
+                ("bindings", Json::Array(bindings.iter().map(|b| tagged("do-binding", vec![
+                    ("name", Json::string(b.0.clone())),
+                    ("init", b.1.to_json()),
+                    ("step", b.2.as_ref().map_or(Json::Null, Serialize::to_json)),
+                ])).collect())),
+                ("test", test.to_json()),
+                ("result", array(result)),
+                ("commands", array(commands)),
+            ]),
+            Expression::Set(name, value) => tagged("set", vec![
+                ("name", Json::string(name.clone())),
+                ("value", value.to_json()),
+            ]),
+            Expression::Delay(e) => tagged("delay", vec![("body", e.to_json())]),
+            Expression::DelayForce(e) => tagged("delay-force", vec![("body", e.to_json())]),
+            Expression::DefineRecordType(rt) => tagged("define-record-type", vec![
+                ("name", Json::string(rt.name.clone())),
+                ("constructor", tagged("constructor", vec![
+                    ("name", Json::string(rt.constructor.0.clone())),
+                    ("fields", strings(&rt.constructor.1)),
+                ])),
+                ("predicate", Json::string(rt.predicate.clone())),
+                ("fields", Json::Array(rt.fields.iter().map(|f| tagged("field", vec![
+                    ("name", Json::string(f.name.clone())),
+                    ("accessor", Json::string(f.accessor.clone())),
+                    ("modifier", f.modifier.clone().map_or(Json::Null, Json::String)),
+                ])).collect())),
+            ]),
+            Expression::Parameterize(bindings, body) => tagged("parameterize", vec![
+                ("bindings", Json::Array(bindings.iter().map(|b| tagged("parameterize-binding", vec![
+                    ("parameter", b.0.to_json()),
+                    ("value", b.1.to_json()),
+                ])).collect())),
+                ("body", array(body)),
+            ]),
+            Expression::LetValues(bindings, body) => tagged("let-values", vec![
+                ("bindings", values_bindings_json(bindings)),
+                ("body", array(body)),
+            ]),
+            Expression::LetStarValues(bindings, body) => tagged("let*-values", vec![
+                ("bindings", values_bindings_json(bindings)),
+                ("body", array(body)),
+            ]),
+            Expression::DefineValues(formals, value) => {
+                let mut fields = formals_json(formals);
+                fields.push(("value", value.to_json()));
+                tagged("define-values", fields)
+            },
+            Expression::Receive(formals, value, body) => {
+                let mut fields = formals_json(formals);
+                fields.push(("value", value.to_json()));
+                fields.push(("body", array(body)));
+                tagged("receive", fields)
+            },
+            Expression::Guard(variable, clauses, else_clause, body) => tagged("guard", vec![
+                ("variable", Json::string(variable.clone())),
+                ("clauses", array(clauses)),
+                ("else", else_clause.as_ref().map_or(Json::Null, |body| array(body))),
+                ("body", array(body)),
+            ]),
+            Expression::Application(operator, operands) => tagged("application", vec![
+                ("operator", operator.to_json()),
+                ("operands", array(operands)),
+            ]),
+        }
+    }
+
+    fn write_sexpr(&self, out: &mut String) {
+        match self {
+            Expression::Literal(l) => l.write_sexpr(out),
+            Expression::Variable(s) => out.push_str(s),
+            Expression::Quote(q) => {
+                out.push_str("(quote ");
+                q.write_sexpr(out);
+                out.push(')');
+            },
+            Expression::Time(e) => write_list(out, "time", &[e]),
+            Expression::If(test, consequent, Some(alternative)) =>
+                write_list(out, "if", &[test, consequent, alternative]),
+            Expression::If(test, consequent, None) => write_list(out, "if", &[test, consequent]),
+            Expression::And(es) => write_list(out, "and", es),
+            Expression::Or(es) => write_list(out, "or", es),
+            Expression::Begin(es) => write_list(out, "begin", es),
+            Expression::When(test, body) => write_test_body(out, "when", test, body),
+            Expression::Unless(test, body) => write_test_body(out, "unless", test, body),
+            Expression::Cond(clauses, else_clause) => {
+                out.push_str("(cond");
+                write_cond_clauses(out, clauses, else_clause);
+                out.push(')');
+            },
+            Expression::Case(key, clauses, else_clause) => {
+                out.push_str("(case ");
+                key.write_sexpr(out);
+                for c in clauses {
+                    out.push_str(" (");
+                    write_list(out, "", &c.0);
+                    c.1.write_sexpr(out);
+                    out.push(')');
+                }
+                if let Some(body) = else_clause {
+                    out.push_str(" (else");
+                    body.write_sexpr(out);
+                    out.push(')');
+                }
+                out.push(')');
+            },
+            Expression::Lambda(formals, body) => {
+                out.push_str("(lambda ");
+                write_formals(out, formals);
+                write_body(out, body);
+                out.push(')');
+            },
+            Expression::Define(name, value) => {
+                out.push_str("(define ");
+                out.push_str(name);
+                out.push(' ');
+                value.write_sexpr(out);
+                out.push(')');
+            },
+            Expression::Let(name, bindings, body) => {
+                out.push_str("(let ");
+                if let Some(name) = name {
+                    out.push_str(name);
+                    out.push(' ');
+                }
+                out.push('(');
+                for (i, b) in bindings.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    out.push('(');
+                    out.push_str(&b.0);
+                    out.push(' ');
+                    b.1.write_sexpr(out);
+                    out.push(')');
+                }
+                out.push(')');
+                write_body(out, body);
+                out.push(')');
+            },
+            Expression::Do(bindings, test, result, commands) => {
+                out.push_str("(do (");
+                for (i, b) in bindings.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    out.push('(');
+                    out.push_str(&b.0);
+                    out.push(' ');
+                    b.1.write_sexpr(out);
+                    if let Some(ref step) = b.2 {
+                        out.push(' ');
+                        step.write_sexpr(out);
+                    }
+                    out.push(')');
+                }
+                out.push_str(") ");
+                write_test_body(out, "", test, result);
+                write_body(out, commands);
+                out.push(')');
+            },
+            Expression::Set(name, value) => {
+                out.push_str("(set! ");
+                out.push_str(name);
+                out.push(' ');
+                value.write_sexpr(out);
+                out.push(')');
+            },
+            Expression::Delay(e) => write_list(out, "delay", &[e]),
+            Expression::DelayForce(e) => write_list(out, "delay-force", &[e]),
+            Expression::DefineRecordType(rt) => {
+                out.push_str("(define-record-type ");
+                out.push_str(&rt.name);
+                out.push_str(" (");
+                out.push_str(&rt.constructor.0);
+                for f in &rt.constructor.1 {
+                    out.push(' ');
+                    out.push_str(f);
+                }
+                out.push_str(") ");
+                out.push_str(&rt.predicate);
+                for f in &rt.fields {
+                    out.push_str(" (");
+                    out.push_str(&f.name);
+                    out.push(' ');
+                    out.push_str(&f.accessor);
+                    if let Some(ref modifier) = f.modifier {
+                        out.push(' ');
+                        out.push_str(modifier);
+                    }
+                    out.push(')');
+                }
+                out.push(')');
+            },
+            Expression::Parameterize(bindings, body) => {
+                out.push_str("(parameterize (");
+                for (i, b) in bindings.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    write_list(out, "", &[&b.0, &b.1]);
+                }
+                out.push(')');
+                write_body(out, body);
+                out.push(')');
+            },
+            Expression::LetValues(bindings, body) =>
+                write_values_bindings(out, "let-values", bindings, body),
+            Expression::LetStarValues(bindings, body) =>
+                write_values_bindings(out, "let*-values", bindings, body),
+            Expression::DefineValues(formals, value) => {
+                out.push_str("(define-values ");
+                write_formals(out, formals);
+                out.push(' ');
+                value.write_sexpr(out);
+                out.push(')');
+            },
+            Expression::Receive(formals, value, body) => {
+                out.push_str("(receive ");
+                write_formals(out, formals);
+                out.push(' ');
+                value.write_sexpr(out);
+                write_body(out, body);
+                out.push(')');
+            },
+            Expression::Guard(variable, clauses, else_clause, body) => {
+                out.push_str("(guard (");
+                out.push_str(variable);
+                write_cond_clauses(out, clauses, else_clause);
+                out.push(')');
+                write_body(out, body);
+                out.push(')');
+            },
+            Expression::Application(operator, operands) => {
+                out.push('(');
+                operator.write_sexpr(out);
+                write_body(out, operands);
+                out.push(')');
+            },
+        }
+    }
+}
+
+impl Deserialize for Literal {
+    fn from_json(json: &Json) -> Result<Self> {
+        let value = field(json, "value")?;
+        Ok(match (tag(json)?, value) {
+            ("number", Json::Number(x)) if x.fract() == 0.0 && x.abs() <= f64::from(i32::MAX) =>
+                Literal::Number(*x as i32),
+            ("real", Json::Number(x)) => Literal::Real(*x),
+            ("real", Json::String(s)) => Literal::Real(match s.as_str() {
+                "+inf.0" => f64::INFINITY,
+                "-inf.0" => f64::NEG_INFINITY,
+                "+nan.0" => f64::NAN,
+                _ => return error(format!("Bad real {}", s)),
+            }),
+            ("boolean", Json::Bool(b)) => Literal::Boolean(*b),
+            ("character", Json::String(s)) if s.chars().count() == 1 =>
+                Literal::Character(s.chars().next().unwrap()),
+            ("string", Json::String(s)) => Literal::String(s.clone()),
+            ("number", _) | ("real", _) | ("boolean", _) | ("character", _) | ("string", _) =>
+                return error(format!("Bad {} {}", tag(json)?, value)),
+            (t, _) => return unknown("literal", t),
+        })
+    }
+}
+
+impl Deserialize for Token {
+    fn from_json(json: &Json) -> Result<Self> {
+        Ok(match tag(json)? {
+            "identifier" => Token::Identifier(string(field(json, "name")?)?),
+            "lparen" => Token::LParen,
+            "rparen" => Token::RParen,
+            "literal" => Token::Literal(Literal::from_json(field(json, "literal")?)?),
+            t => return unknown("token", t),
+        })
+    }
+}
+
+impl Deserialize for Node {
+    fn from_json(json: &Json) -> Result<Self> {
+        Ok(match tag(json)? {
+            "identifier" => Node::Identifier(string(field(json, "name")?)?),
+            "literal" => Node::Literal(Literal::from_json(field(json, "literal")?)?),
+            "list" => Node::List(list(json, "items")?),
+            t => return unknown("node", t),
+        })
+    }
+}
+
+impl Deserialize for Quotation {
+    fn from_json(json: &Json) -> Result<Self> {
+        Ok(match tag(json)? {
+            "literal" => Quotation::Literal(Literal::from_json(field(json, "literal")?)?),
+            "symbol" => Quotation::Symbol(string(field(json, "name")?)?),
+            "nil" => Quotation::Nil,
+            "list" => {
+                let mut res = Quotation::from_json(field(json, "tail")?)?;
+                for car in list::<Quotation>(json, "items")?.into_iter().rev() {
+                    res = Quotation::Cons(Box::new(car), Box::new(res));
+                }
+                res
+            },
+            t => return unknown("quotation", t),
+        })
+    }
+}
+
+impl Deserialize for CondClause {
+    fn from_json(json: &Json) -> Result<Self> {
+        let test = Expression::from_json(field(json, "test")?)?;
+        Ok(match tag(json)? {
+            "simple" => CondClause::Simple(test, list(json, "body")?),
+            "binding" => CondClause::Binding(test, Expression::from_json(field(json, "receiver")?)?),
+            "test" => CondClause::Inconsequential(test),
+            t => return unknown("cond clause", t),
+        })
+    }
+}
+
+impl Deserialize for ClauseBody {
+    fn from_json(json: &Json) -> Result<Self> {
+        Ok(match tag(json)? {
+            "sequence" => ClauseBody::Sequence(list(json, "body")?),
+            "receiver" => ClauseBody::Receiver(boxed(json, "receiver")?),
+            t => return unknown("clause body", t),
+        })
+    }
+}
+
+impl Deserialize for Expression {
+    fn from_json(json: &Json) -> Result<Self> {
+        Ok(match tag(json)? {
+            "literal" => Expression::Literal(Literal::from_json(field(json, "literal")?)?),
+            "variable" => Expression::Variable(string(field(json, "name")?)?),
+            "quote" => Expression::Quote(Quotation::from_json(field(json, "datum")?)?),
+            "time" => Expression::Time(boxed(json, "body")?),
+            "if" => Expression::If(boxed(json, "test")?, boxed(json, "consequent")?,
+                                   optional_boxed(json, "alternative")?),
+            "and" => Expression::And(list(json, "operands")?),
+            "or" => Expression::Or(list(json, "operands")?),
+            "begin" => Expression::Begin(list(json, "body")?),
+            "when" => Expression::When(boxed(json, "test")?, list(json, "body")?),
+            "unless" => Expression::Unless(boxed(json, "test")?, list(json, "body")?),
+            "cond" => Expression::Cond(list(json, "clauses")?, optional_list(json, "else")?),
+            "case" => {
+                let mut clauses = Vec::new();
+                for c in items(field(json, "clauses")?)? {
+                    clauses.push((list(c, "data")?, ClauseBody::from_json(field(c, "body")?)?));
+                }
+                let else_clause = match json.get("else") {
+                    None | Some(Json::Null) => None,
+                    Some(body) => Some(ClauseBody::from_json(body)?),
+                };
+                Expression::Case(boxed(json, "key")?, clauses, else_clause)
+            },
+            "lambda" => Expression::Lambda(formals(json)?, list(json, "body")?),
+            "define" => Expression::Define(string(field(json, "name")?)?, boxed(json, "value")?),
+            "let" => {
+                let mut bindings = Vec::new();
+                for b in items(field(json, "bindings")?)? {
+                    bindings.push((string(field(b, "name")?)?, Expression::from_json(field(b, "init")?)?));
+                }
+                Expression::Let(optional_string(json, "name")?, bindings, list(json, "body")?)
+            },
+            "do" => {
+                let mut bindings = Vec::new();
+                for b in items(field(json, "bindings")?)? {
+                    bindings.push((string(field(b, "name")?)?, Expression::from_json(field(b, "init")?)?,
+                                   optional_boxed(b, "step")?.map(|step| *step)));
+                }
+                Expression::Do(bindings, boxed(json, "test")?, list(json, "result")?,
+                               list(json, "commands")?)
+            },
+            "set" => Expression::Set(string(field(json, "name")?)?, boxed(json, "value")?),
+            "delay" => Expression::Delay(boxed(json, "body")?),
+            "delay-force" => Expression::DelayForce(boxed(json, "body")?),
+            "define-record-type" => {
+                let constructor = field(json, "constructor")?;
+                let mut fields = Vec::new();
+                for f in items(field(json, "fields")?)? {
+                    fields.push(Field {
+                        name: string(field(f, "name")?)?,
+                        accessor: string(field(f, "accessor")?)?,
+                        modifier: optional_string(f, "modifier")?,
+                    });
+                }
+                Expression::DefineRecordType(RecordType {
+                    name: string(field(json, "name")?)?,
+                    constructor: (string(field(constructor, "name")?)?, string_list(constructor, "fields")?),
+                    predicate: string(field(json, "predicate")?)?,
+                    fields,
+                })
+            },
+            "parameterize" => {
+                let mut bindings = Vec::new();
+                for b in items(field(json, "bindings")?)? {
+                    bindings.push((Expression::from_json(field(b, "parameter")?)?,
+                                   Expression::from_json(field(b, "value")?)?));
+                }
+                Expression::Parameterize(bindings, list(json, "body")?)
+            },
+            "let-values" => Expression::LetValues(values_bindings(json)?, list(json, "body")?),
+            "let*-values" =>
+                Expression::LetStarValues(values_bindings(json)?, list(json, "body")?),
+            "define-values" => Expression::DefineValues(formals(json)?, boxed(json, "value")?),
+            "receive" =>
+                Expression::Receive(formals(json)?, boxed(json, "value")?, list(json, "body")?),
+            "guard" => Expression::Guard(string(field(json, "variable")?)?, list(json, "clauses")?,
+                                         optional_list(json, "else")?, list(json, "body")?),
+            "application" => Expression::Application(boxed(json, "operator")?, list(json, "operands")?),
+            t => return unknown("expression", t),
+        })
+    }
+}
+
+impl <T: Serialize> Serialize for &T {
+    fn to_json(&self) -> Json {
+        (*self).to_json()
+    }
+
+    fn write_sexpr(&self, out: &mut String) {
+        (*self).write_sexpr(out)
+    }
+}
+
+impl <T: Serialize> Serialize for Box<T> {
+    fn to_json(&self) -> Json {
+        (**self).to_json()
+    }
+
+    fn write_sexpr(&self, out: &mut String) {
+        (**self).write_sexpr(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use json::Json;
+    use lexer::{RegexLexer, Span};
+    use parser::{Node, Parser, SpannedParser};
+    use scheme::parse_expression;
+    use scheme::syntax::Expression;
+    use super::{document, from_document, parse_ast, write_ast, Deserialize, Serialize, Spanned};
+
+    fn expand(s: &str) -> String {
+        let node = Parser::new(RegexLexer::new(s)).next().unwrap().unwrap();
+        parse_expression(node).unwrap().to_sexpr()
+    }
+
+    #[test]
+    fn sexpr() {
+        assert_eq!(expand("(define (f x . r) (g x \"a\\nb\") #\\space)"),
+                   "(define f (lambda (x . r) (g x \"a\\nb\") #\\space))");
+        assert_eq!(expand("(cond ((f) => g) (x) (else (quote (1 . (2)))))"),
+                   "(cond ((f) => g) (x) (else (quote (1 2))))");
+        assert_eq!(expand("(case x ((1 a) 2.5) (else (let loop ((i 0)) i)))"),
+                   "(case x ((1 a) 2.5) (else (let loop ((i 0)) i)))");
+        assert_eq!(expand("(lambda args (if a b c))"), "(lambda args (if a b c))");
+        assert_eq!(expand("(cond ((f) (g) 1) (else (h) 2))"), "(cond ((f) (g) 1) (else (h) 2))");
+        assert_eq!(expand("(case x ((1) => f) ((2) (g) 3))"), "(case x ((1) => f) ((2) (g) 3))");
+        assert_eq!(expand("(case x (else => f))"), "(case x (else => f))");
+        assert_eq!(expand("(cond)"), "(cond)");
+        assert_eq!(expand("(do ((i 0 (+ i 1)) (v x)) ((= i 3) v) (set! v (f v)))"),
+                   "(do ((i 0 (+ i 1)) (v x)) ((= i 3) v) (set! v (f v)))");
+        assert_eq!(expand("(when (if a b) (delay c) (delay-force d))"),
+                   "(when (if a b) (delay c) (delay-force d))");
+        assert_eq!(expand("(define-record-type point (make-point y) point? (x px) (y py set-py!))"),
+                   "(define-record-type point (make-point y) point? (x px) (y py set-py!))");
+        assert_eq!(expand("(parameterize ((p 1) ((f) 2)) a b)"), "(parameterize ((p 1) ((f) 2)) a b)");
+        assert_eq!(expand("(guard (e ((f e) => g) (else 1)) a b)"), "(guard (e ((f e) => g) (else 1)) a b)");
+        assert_eq!(expand("(let-values (((a . b) x) (c y) (() z)) a)"),
+                   "(let-values (((a . b) x) (c y) (() z)) a)");
+        assert_eq!(expand("(define-values (a b) (receive c x c))"),
+                   "(define-values (a b) (receive c x c))");
+    }
+
+    #[test]
+    fn json() {
+        let node = Parser::new(RegexLexer::new("(f 1 #t)")).next().unwrap().unwrap();
+        assert_eq!(node.to_json().to_string(),
+                   "{\"type\":\"list\",\"items\":[\
+                    {\"type\":\"identifier\",\"name\":\"f\"},\
+                    {\"type\":\"literal\",\"literal\":{\"type\":\"number\",\"value\":1}},\
+                    {\"type\":\"literal\",\"literal\":{\"type\":\"boolean\",\"value\":true}}]}");
+        assert_eq!(parse_expression(node).unwrap().to_json().to_string(),
+                   "{\"type\":\"application\",\"operator\":{\"type\":\"variable\",\"name\":\"f\"},\
+                    \"operands\":[\
+                    {\"type\":\"literal\",\"literal\":{\"type\":\"number\",\"value\":1}},\
+                    {\"type\":\"literal\",\"literal\":{\"type\":\"boolean\",\"value\":true}}]}");
+        let node = Parser::new(RegexLexer::new("(quote (a . b))")).next().unwrap().unwrap();
+        assert_eq!(parse_expression(node).unwrap().to_json().to_string(),
+                   "{\"type\":\"quote\",\"datum\":{\"type\":\"list\",\"items\":[\
+                    {\"type\":\"symbol\",\"name\":\"a\"}],\
+                    \"tail\":{\"type\":\"symbol\",\"name\":\"b\"}}}");
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = "(define (f x . r) (g x \"a\\\"b\" #\\x)) \
+                    (let loop ((i 0.5)) (cond ((h) => k) (i) (else (quote (1 #t . s))))) \
+                    (case (time x) ((1 a) (and)) (else (or (begin y) (unless z (lambda () w))))) \
+                    (cond (a b c)) (case x ((1) => f) ((2) 3 4)) (case x ((1) 2) (else => g)) \
+                    (do ((i 0 (+ i 1)) (v x)) ((= i 3)) (set! v (f v))) (if a (delay b)) \
+                    (when a (delay-force b) c) (unless a b c) \
+                    (define-record-type point (make-point y x) point? (x point-x) (y point-y set-y!)) \
+                    (parameterize ((p 1) ((f) 2)) a b) (guard (e (a)) b) (guard (e (else 1)) b) \
+                    (let*-values (((a b) x) (c y)) a) (define-values (a . b) (receive (c) x c))";
+        for (node, spans) in SpannedParser::new(text).map(Result::unwrap) {
+            let tree = Spanned { value: parse_expression(node).unwrap(), span: Some(spans.span) };
+            let json = Json::parse(&document(tree.to_json()).to_string()).unwrap();
+            assert_eq!(from_document::<Spanned<Expression>>(&json).unwrap(), tree);
+            let mut ast = String::new();
+            write_ast(&json, &mut ast);
+            let json = parse_ast(&ast).unwrap().pop().unwrap();
+            assert_eq!(from_document::<Spanned<Expression>>(&json).unwrap(), tree);
+        }
+        let node = Node::List(vec![Node::Identifier("f".to_string())]);
+        let mut ast = String::new();
+        write_ast(&document(node.to_json()), &mut ast);
+        assert_eq!(ast, "(scheme-syntax (version 4) (tree (list (items ((identifier (name \"f\")))))))");
+        let json = document(Spanned { value: &node, span: Some(Span { start: 1, end: 4 }) }.to_json());
+        assert_eq!(json.get("tree").unwrap().get("span").unwrap().to_string(),
+                   "{\"type\":\"span\",\"start\":1,\"end\":4}");
+    }
+
+    #[test]
+    fn versions() {
+        let json = |s: &str| Json::parse(s).unwrap();
+        let tree = r#"{"type":"variable","name":"x"}"#;
+        assert_eq!(from_document::<Expression>(&json(&format!(
+            r#"{{"type":"scheme-syntax","version":4,"tree":{}}}"#, tree))).unwrap(),
+                   Expression::Variable("x".to_string()));
+        assert_eq!(from_document::<Expression>(&json(&format!(
+            r#"{{"type":"scheme-syntax","version":1,"tree":{}}}"#, tree))).unwrap_err().to_string(),
+                   "DeserializeError: Unsupported schema version 1");
+        assert_eq!(Expression::from_json(&json(r#"{"type":"while"}"#)).unwrap_err().to_string(),
+                   "DeserializeError: Unknown expression type while");
+        assert_eq!(Expression::from_json(&json(r#"{"type":"define","name":"x"}"#)).unwrap_err().to_string(),
+                   "DeserializeError: Missing field value");
+    }
+}
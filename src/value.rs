@@ -0,0 +1,229 @@
+use std;
+use std::fmt;
+use std::rc::Rc;
+
+use heap::{Heap, Ref};
+use vm::Vm;
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    Basic(String),
+    /// A resource limit was exceeded. Unlike other errors, this cannot be
+    /// handled by the program.
+    Limit(String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::Basic(s) => write!(f, "RuntimeError: {}", s),
+            RuntimeError::Limit(s) => write!(f, "LimitError: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+pub type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// Number of arguments a procedure accepts; `max` is `None` for variadic
+/// procedures.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn exactly(n: usize) -> Self {
+        Arity { min: n, max: Some(n) }
+    }
+
+    pub fn at_least(n: usize) -> Self {
+        Arity { min: n, max: None }
+    }
+
+    pub fn check(&self, got: usize) -> Result<()> {
+        self.check_count("arguments", got)
+    }
+
+    /// Checks the number of `what`, such as values received by formals.
+    pub fn check_count(&self, what: &str, got: usize) -> Result<()> {
+        let ok = got >= self.min && self.max.is_none_or(|max| got <= max);
+        if ok {
+            return Ok(());
+        }
+        Err(RuntimeError::Basic(match self.max {
+            Some(max) if max == self.min =>
+                format!("Wrong number of {}: expected {}, got {}", what, max, got),
+            Some(max) =>
+                format!("Wrong number of {}: expected {} to {}, got {}",
+                        what, self.min, max, got),
+            None =>
+                format!("Wrong number of {}: expected at least {}, got {}",
+                        what, self.min, got),
+        }))
+    }
+}
+
+pub type PrimitiveFn = fn(&mut Vm, &[Value]) -> Result<Value>;
+
+#[derive(Debug)]
+pub struct Primitive {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub func: PrimitiveFn,
+}
+
+pub type HostFn = Box<dyn Fn(&mut Heap, &[Value]) -> Result<Value>>;
+
+/// A procedure that the program embedding the interpreter provides.
+pub struct Host {
+    pub name: String,
+    pub arity: Arity,
+    pub func: HostFn,
+}
+
+impl fmt::Debug for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Host({})", self.name)
+    }
+}
+
+/// Procedures that manipulate the VM's control state instead of just
+/// computing a result from their arguments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    CallCC,
+    CallEC,
+    Apply,
+}
+
+impl Control {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Control::CallCC => "call-with-current-continuation",
+            Control::CallEC => "call-with-escape-continuation",
+            Control::Apply => "apply",
+        }
+    }
+}
+
+/// An entry in the list of active `dynamic-wind` extents.
+pub struct Winder {
+    pub before: Value,
+    pub after: Value,
+    pub parent: Winders,
+    pub depth: usize,
+}
+
+pub type Winders = Option<Rc<Winder>>;
+
+pub fn winders_depth(w: &Winders) -> usize {
+    w.as_ref().map_or(0, |w| w.depth)
+}
+
+pub fn same_winders(a: &Winders, b: &Winders) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// A Scheme value. Immediate values are stored inline; pairs, strings,
+/// vectors, procedures other than primitives, promises, records and error
+/// objects live in
+/// a `Heap` and are referred to by handle.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Unspecified,
+    Nil,
+    Boolean(bool),
+    Number(i32),
+    Real(f64),
+    Character(char),
+    Symbol(Rc<str>),
+    String(Ref),
+    Pair(Ref),
+    Vector(Ref),
+    Closure(Ref),
+    Continuation(Ref),
+    Promise(Ref),
+    Record(Ref),
+    RecordType(Ref),
+    Parameter(Ref),
+    Error(Ref),
+    /// Any number of values other than one, as returned by `values`.
+    Values(Ref),
+    Primitive(Rc<Primitive>),
+    Host(Rc<Host>),
+    Control(Control),
+}
+
+impl Value {
+    pub fn is_true(&self) -> bool {
+        !matches!(self, Value::Boolean(false))
+    }
+
+    /// The `eqv?` relation: identity for heap objects, value equality for
+    /// everything else.
+    pub fn eqv(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Unspecified, Value::Unspecified) => true,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Real(a), Value::Real(b)) => a.to_bits() == b.to_bits(),
+            (Value::Character(a), Value::Character(b)) => a == b,
+            (Value::Symbol(a), Value::Symbol(b)) => a == b,
+            (Value::String(a), Value::String(b)) |
+            (Value::Pair(a), Value::Pair(b)) |
+            (Value::Vector(a), Value::Vector(b)) |
+            (Value::Closure(a), Value::Closure(b)) |
+            (Value::Continuation(a), Value::Continuation(b)) |
+            (Value::Promise(a), Value::Promise(b)) |
+            (Value::Record(a), Value::Record(b)) |
+            (Value::RecordType(a), Value::RecordType(b)) |
+            (Value::Parameter(a), Value::Parameter(b)) |
+            (Value::Error(a), Value::Error(b)) |
+            (Value::Values(a), Value::Values(b)) => a == b,
+            (Value::Primitive(a), Value::Primitive(b)) => Rc::ptr_eq(a, b),
+            (Value::Host(a), Value::Host(b)) => Rc::ptr_eq(a, b),
+            (Value::Control(a), Value::Control(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// The heap object this value refers to, if any.
+    pub fn heap_ref(&self) -> Option<Ref> {
+        match self {
+            Value::String(r) | Value::Pair(r) | Value::Vector(r) |
+            Value::Closure(r) | Value::Continuation(r) | Value::Promise(r) |
+            Value::Record(r) | Value::RecordType(r) | Value::Parameter(r) |
+            Value::Error(r) | Value::Values(r) => Some(*r),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Unspecified => "unspecified",
+            Value::Nil => "empty list",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) | Value::Real(_) => "number",
+            Value::Character(_) => "character",
+            Value::String(_) => "string",
+            Value::Symbol(_) => "symbol",
+            Value::Pair(_) => "pair",
+            Value::Vector(_) => "vector",
+            Value::Promise(_) => "promise",
+            Value::Record(_) => "record",
+            Value::RecordType(_) => "record type",
+            Value::Error(_) => "error object",
+            Value::Values(_) => "multiple values",
+            Value::Closure(_) | Value::Primitive(_) | Value::Host(_) | Value::Control(_) |
+            Value::Continuation(_) | Value::Parameter(_) => "procedure",
+        }
+    }
+}
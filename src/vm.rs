@@ -0,0 +1,1198 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+use builtins;
+use compiler::{self, Chunk, Compiler, Op};
+use heap::{Heap, Object, Closure, Ref, Tracer};
+use lexer::RegexLexer;
+use library::Namespace;
+use parser::Parser;
+use scheme::{self, syntax::Expression};
+use value::{Value, Control, Winder, Winders, winders_depth, same_winders,
+            Arity, RuntimeError, Result};
+
+/// Top-level bindings. Names are resolved to slots at compile time, so a
+/// slot may exist before anything has been defined in it.
+#[derive(Default)]
+pub struct Globals {
+    index: HashMap<String, u32>,
+    names: Vec<Rc<str>>,
+    values: Vec<Option<Value>>,
+}
+
+impl Globals {
+    pub fn slot(&mut self, name: &str) -> u32 {
+        if let Some(&slot) = self.index.get(name) {
+            return slot;
+        }
+        let slot = self.values.len() as u32;
+        self.index.insert(name.to_string(), slot);
+        self.names.push(Rc::from(name));
+        self.values.push(None);
+        slot
+    }
+
+    pub fn name(&self, slot: u32) -> &str {
+        &self.names[slot as usize]
+    }
+
+    pub fn get(&self, slot: u32) -> Option<&Value> {
+        self.values[slot as usize].as_ref()
+    }
+
+    pub fn set(&mut self, slot: u32, v: Value) {
+        self.values[slot as usize] = Some(v);
+    }
+
+    /// The value of the global `name`, if it is defined.
+    pub fn lookup(&self, name: &str) -> Option<&Value> {
+        self.index.get(name).and_then(|&slot| self.get(slot))
+    }
+}
+
+/// A procedure activation: the code being run, the variable frame it runs
+/// in, and the stack index where its callee (and later its result) lives.
+#[derive(Clone)]
+struct Activation {
+    id: u64,
+    code: Rc<Chunk>,
+    pc: usize,
+    env: Option<Ref>,
+    base: usize,
+}
+
+impl Activation {
+    fn trace(&self, t: &mut Tracer) {
+        t.chunk(&self.code);
+        if let Some(env) = self.env {
+            t.mark(env);
+        }
+    }
+}
+
+enum Resumption {
+    /// A copy of the whole control state, which can be resumed any number
+    /// of times.
+    Full {
+        stack: Vec<Value>,
+        frames: Vec<Activation>,
+    },
+    /// Unwinds to the caller of `call/ec`, which is only possible while the
+    /// receiver's activation, identified by `id`, is still live.
+    Escape {
+        frames: usize,
+        id: u64,
+        height: usize,
+    },
+}
+
+pub struct Continuation {
+    resumption: Resumption,
+    winders: Winders,
+}
+
+impl Continuation {
+    pub fn trace(&self, t: &mut Tracer) {
+        if let Resumption::Full { ref stack, ref frames } = self.resumption {
+            for v in stack {
+                t.value(v);
+            }
+            for f in frames {
+                f.trace(t);
+            }
+        }
+        t.winders(&self.winders);
+    }
+}
+
+/// Bounds on what running one top-level form may use; `None` means no
+/// bound. Exceeding one is a `RuntimeError::Limit`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Instructions executed.
+    pub steps: Option<u64>,
+    /// Procedure activations live at once, not counting tail calls.
+    pub depth: Option<usize>,
+    /// Heap cells in use after collecting garbage; see `Heap::size`.
+    pub heap: Option<usize>,
+    /// Wall-clock time.
+    pub time: Option<Duration>,
+    /// Instructions executed by all forms run since the limits were set,
+    /// unlike `steps`, which is per form.
+    pub fuel: Option<u64>,
+}
+
+impl Limits {
+    /// The limits that `Interpreter::sandboxed` starts with, so that
+    /// untrusted code cannot run forever or exhaust memory if the host sets
+    /// none: a second, four million heap cells and ten thousand activations
+    /// for each top-level form.
+    pub fn sandbox() -> Self {
+        Limits {
+            depth: Some(10_000),
+            heap: Some(1 << 22),
+            time: Some(Duration::from_secs(1)),
+            ..Limits::default()
+        }
+    }
+}
+
+/// How often, in instructions, the time limit is checked.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// Values returned by `eval` and `execute` stay valid until the next call
+/// that runs code, since only then can the heap be collected.
+pub struct Vm {
+    globals: Globals,
+    heap: Heap,
+    /// Top-level chunks that may still be executed. Their constants live on
+    /// the heap, so they are roots for as long as someone holds them.
+    compiled: Vec<Weak<Chunk>>,
+    stack: Vec<Value>,
+    frames: Vec<Activation>,
+    winders: Winders,
+    /// The installed exception handlers, innermost first, as a list.
+    handlers: Value,
+    next_id: u64,
+    limits: Limits,
+    /// Instructions executed by the current top-level form.
+    steps: u64,
+    /// Instructions executed since the limits were set.
+    fuel: u64,
+    /// When the current top-level form started running.
+    started: Instant,
+    timers: Vec<Instant>,
+    /// Whether `time` reports how long its expression took on stderr.
+    report_time: bool,
+    /// Where `display`, `write` and `newline` send their output.
+    output: Box<dyn Write>,
+}
+
+fn heap_limit(max: usize) -> RuntimeError {
+    RuntimeError::Limit(format!("Heap limit of {} cells exceeded", max))
+}
+
+/// Library procedures that are written in Scheme on top of the primitives.
+const PRELUDE: &str = include_str!("prelude.scm");
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut vm = Vm {
+            globals: Globals::default(),
+            heap: Heap::new(),
+            compiled: Vec::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            winders: None,
+            handlers: Value::Nil,
+            next_id: 0,
+            limits: Limits::default(),
+            steps: 0,
+            fuel: 0,
+            started: Instant::now(),
+            timers: Vec::new(),
+            report_time: true,
+            output: Box::new(io::stdout()),
+        };
+        builtins::install(&mut vm);
+        vm.define("call-with-current-continuation", Value::Control(Control::CallCC));
+        vm.define("call/cc", Value::Control(Control::CallCC));
+        vm.define("call-with-escape-continuation", Value::Control(Control::CallEC));
+        vm.define("call/ec", Value::Control(Control::CallEC));
+        vm.define("apply", Value::Control(Control::Apply));
+        vm.define("%apply", Value::Control(Control::Apply));
+        let dynamic_wind = Rc::new(compiler::dynamic_wind());
+        let dynamic_wind = vm.heap.alloc(Object::Closure(Closure {
+            code: dynamic_wind,
+            env: None,
+        }));
+        vm.define("dynamic-wind", Value::Closure(dynamic_wind));
+        // For the prelude, which must not see programs redefine it.
+        vm.define("%dynamic-wind", Value::Closure(dynamic_wind));
+        for node in Parser::new(RegexLexer::new(PRELUDE)) {
+            let e = scheme::parse_expression(node.expect("prelude"))
+                .expect("prelude");
+            vm.eval(&e).expect("prelude");
+        }
+        vm
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut *self.output
+    }
+
+    /// Sets the limits on running code and refills its fuel.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.fuel = 0;
+    }
+
+    /// Fails if `cells` more would not fit in the heap limit, even after
+    /// collecting garbage, since the limit is otherwise only checked between
+    /// instructions. Primitives that allocate in proportion to their input
+    /// call this before they allocate anything, while all they hold is
+    /// still reachable from their arguments on the stack.
+    pub fn reserve(&mut self, cells: usize) -> Result<()> {
+        let max = match self.limits.heap {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        if self.heap.size().saturating_add(cells) > max {
+            self.collect();
+            if self.heap.size().saturating_add(cells) > max {
+                return Err(heap_limit(max));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handlers(&self) -> &Value {
+        &self.handlers
+    }
+
+    pub fn set_handlers(&mut self, handlers: Value) {
+        self.handlers = handlers;
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Makes `time` only evaluate its expression, without reporting.
+    pub fn set_report_time(&mut self, on: bool) {
+        self.report_time = on;
+    }
+
+    pub fn reports_time(&self) -> bool {
+        self.report_time
+    }
+
+    /// Formats a value the way `write` would.
+    pub fn write(&self, v: &Value) -> String {
+        self.heap.write(v).to_string()
+    }
+
+    /// Collects garbage, treating everything the VM can still reach as live.
+    pub fn collect(&mut self) {
+        self.compiled.retain(|c| c.upgrade().is_some());
+        let heap = &mut self.heap;
+        let globals = &self.globals;
+        let stack = &self.stack;
+        let frames = &self.frames;
+        let winders = &self.winders;
+        let handlers = &self.handlers;
+        let compiled = &self.compiled;
+        heap.collect(|t| {
+            for v in globals.values.iter().flatten() {
+                t.value(v);
+            }
+            for v in stack {
+                t.value(v);
+            }
+            for f in frames {
+                f.trace(t);
+            }
+            t.winders(winders);
+            t.value(handlers);
+            for c in compiled {
+                if let Some(c) = c.upgrade() {
+                    t.chunk(&c);
+                }
+            }
+        });
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<Value> {
+        self.globals.lookup(name).cloned()
+    }
+
+    pub fn define(&mut self, name: &str, v: Value) {
+        let slot = self.globals.slot(name);
+        self.globals.set(slot, v);
+    }
+
+    /// Leaves the global `name` without a value.
+    pub fn undefine(&mut self, name: &str) {
+        let slot = self.globals.slot(name);
+        self.globals.values[slot as usize] = None;
+    }
+
+    /// Names of the globals that currently have a value.
+    pub fn global_names(&self) -> Vec<String> {
+        self.globals.names.iter().zip(&self.globals.values)
+            .filter(|&(_, v)| v.is_some())
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    pub fn compile(&mut self, e: &Expression) -> Result<Rc<Chunk>> {
+        self.compile_in(e, &Namespace::default())
+    }
+
+    /// Compiles `e` with its free identifiers resolved through `namespace`.
+    pub fn compile_in(&mut self, e: &Expression, namespace: &Namespace) -> Result<Rc<Chunk>> {
+        let chunk = Compiler::new(&mut self.globals, &mut self.heap, namespace)
+            .compile_toplevel(e)?;
+        self.compiled.push(Rc::downgrade(&chunk));
+        Ok(chunk)
+    }
+
+    pub fn disassemble(&self, chunk: &Chunk) -> String {
+        compiler::disassemble(chunk, &self.globals, &self.heap)
+    }
+
+    pub fn eval(&mut self, e: &Expression) -> Result<Value> {
+        let chunk = self.compile(e)?;
+        self.execute(chunk)
+    }
+
+    /// Applies the procedure `f` to `args` as a top-level form would.
+    pub fn call(&mut self, f: Value, args: Vec<Value>) -> Result<Value> {
+        let mut chunk = Chunk::new(None, Arity::exactly(0), 0);
+        let argc = args.len();
+        for v in Some(f).into_iter().chain(args) {
+            let c = chunk.constant(v);
+            chunk.emit(Op::Const(c));
+        }
+        chunk.emit(Op::Call(argc as u32));
+        chunk.emit(Op::Return);
+        self.execute(Rc::new(chunk))
+    }
+
+    pub fn execute(&mut self, chunk: Rc<Chunk>) -> Result<Value> {
+        let depth = self.frames.len();
+        if depth == 0 {
+            self.steps = 0;
+            self.started = Instant::now();
+        }
+        let height = self.stack.len();
+        let winders = self.winders.clone();
+        let handlers = self.handlers.clone();
+        self.push_frame(chunk, None, height);
+        let res = self.run(depth);
+        if res.is_err() {
+            self.frames.truncate(depth);
+            self.stack.truncate(height);
+            self.winders = winders;
+            self.handlers = handlers;
+        }
+        res
+    }
+
+    fn push_frame(&mut self, code: Rc<Chunk>, env: Option<Ref>, base: usize) {
+        self.next_id += 1;
+        self.frames.push(Activation {
+            id: self.next_id,
+            code,
+            pc: 0,
+            env,
+            base,
+        });
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("VM stack underflow")
+    }
+
+    fn frame_slots(&mut self, depth: u16) -> &mut Vec<Value> {
+        let mut env = self.frames.last().unwrap().env.expect("unresolved local");
+        for _ in 0..depth {
+            env = match self.heap.get(env) {
+                Object::Frame { parent, .. } => parent.expect("unresolved local"),
+                _ => panic!("not a frame"),
+            };
+        }
+        match self.heap.get_mut(env) {
+            Object::Frame { slots, .. } => slots,
+            _ => panic!("not a frame"),
+        }
+    }
+
+
+    /// Applies the procedure at `stack[callee]` to the values above it. A
+    /// closure gets a new activation whose result will replace the callee;
+    /// a primitive's result replaces it immediately.
+    fn apply(&mut self, callee: usize) -> Result<()> {
+        let argc = self.stack.len() - callee - 1;
+        match self.stack[callee].clone() {
+            Value::Closure(r) => {
+                let (code, parent) = match self.heap.get(r) {
+                    Object::Closure(c) => (c.code.clone(), c.env),
+                    _ => panic!("not a closure"),
+                };
+                code.arity.check(argc)?;
+                if let Some(max) = self.limits.depth {
+                    if self.frames.len() >= max {
+                        return Err(RuntimeError::Limit(
+                            format!("Recursion depth limit of {} exceeded", max)));
+                    }
+                }
+                let mut slots = self.stack.split_off(callee + 1);
+                if code.arity.max.is_none() {
+                    let rest = slots.split_off(code.arity.min);
+                    slots.push(self.heap.list(rest));
+                }
+                slots.resize(code.frame_size, Value::Unspecified);
+                let env = self.heap.alloc(Object::Frame {
+                    slots,
+                    parent,
+                });
+                self.push_frame(code, Some(env), callee);
+                Ok(())
+            },
+            Value::Parameter(r) => {
+                Arity::exactly(0).check(argc)?;
+                let v = match self.heap.get(r) {
+                    Object::Parameter { value, .. } => value.clone(),
+                    _ => panic!("not a parameter"),
+                };
+                self.stack.truncate(callee);
+                self.stack.push(v);
+                Ok(())
+            },
+            Value::Primitive(p) => {
+                p.arity.check(argc)?;
+                // The arguments stay on the stack, where `reserve` can see
+                // them if it collects.
+                let args = self.stack[callee + 1..].to_vec();
+                let v = (p.func)(self, &args)?;
+                self.stack.truncate(callee);
+                self.stack.push(v);
+                Ok(())
+            },
+            Value::Host(h) => {
+                h.arity.check(argc)?;
+                let args = self.stack.split_off(callee + 1);
+                let v = (h.func)(&mut self.heap, &args)?;
+                self.stack.truncate(callee);
+                self.stack.push(v);
+                Ok(())
+            },
+            Value::Control(Control::Apply) => {
+                Arity::at_least(2).check(argc)?;
+                let mut list = self.pop();
+                while let Value::Pair(r) = list {
+                    let (car, cdr) = self.heap.pair(r);
+                    self.stack.push(car.clone());
+                    list = cdr.clone();
+                }
+                if let Value::Nil = list {} else {
+                    return Err(RuntimeError::Basic(
+                        format!("apply: Expected list, got {}", self.write(&list))));
+                }
+                self.stack.remove(callee);
+                self.apply(callee)
+            },
+            Value::Control(control) => {
+                Arity::exactly(1).check(argc)?;
+                let receiver = self.pop();
+                // The receiver's activation gets the next id. If there is no
+                // activation, burn the id so the continuation is never live.
+                let receiver_id = self.next_id + 1;
+                if let Value::Closure(_) = receiver {} else {
+                    self.next_id += 1;
+                }
+                let resumption = match control {
+                    Control::CallCC => Resumption::Full {
+                        stack: self.stack[..callee].to_vec(),
+                        frames: self.frames.clone(),
+                    },
+                    Control::CallEC => Resumption::Escape {
+                        frames: self.frames.len(),
+                        id: receiver_id,
+                        height: callee,
+                    },
+                    Control::Apply => unreachable!(),
+                };
+                let k = self.heap.alloc(Object::Continuation(Continuation {
+                    resumption,
+                    winders: self.winders.clone(),
+                }));
+                self.stack[callee] = receiver;
+                self.stack.push(Value::Continuation(k));
+                self.apply(callee)
+            },
+            Value::Continuation(r) => {
+                let k = match self.heap.get(r) {
+                    Object::Continuation(k) => k,
+                    _ => panic!("not a continuation"),
+                };
+                if !same_winders(&self.winders, &k.winders) {
+                    let target = k.winders.clone();
+                    return self.unwind(callee, r, target);
+                }
+                let args = self.stack.split_off(callee + 1);
+                match k.resumption {
+                    Resumption::Full { ref stack, ref frames } => {
+                        self.stack.clone_from(stack);
+                        self.frames.clone_from(frames);
+                    },
+                    Resumption::Escape { frames, id, height } => {
+                        let live = self.frames.len() > frames &&
+                            self.frames[frames].id == id;
+                        if !live {
+                            return Err(RuntimeError::Basic(
+                                "Escape continuation invoked outside its extent".to_string()));
+                        }
+                        self.frames.truncate(frames);
+                        self.stack.truncate(height);
+                    },
+                }
+                let v = match args.len() {
+                    1 => args.into_iter().next().unwrap(),
+                    _ => Value::Values(self.heap.alloc(Object::Values(args))),
+                };
+                self.stack.push(v);
+                Ok(())
+            },
+            v => Err(RuntimeError::Basic(
+                format!("Attempt to apply non-procedure {}", self.write(&v)))),
+        }
+    }
+
+    /// Runs the `after` and `before` thunks needed to move from the current
+    /// dynamic extent to that of `k`, then invokes `k` with the arguments
+    /// at `stack[callee + 1..]`. The thunks run in a generated activation
+    /// so they may themselves escape.
+    fn unwind(&mut self, callee: usize, k: Ref, target: Winders) -> Result<()> {
+        let mut from = self.winders.clone();
+        let mut to = target.clone();
+        let mut afters = Vec::new();
+        let mut befores = Vec::new();
+        while winders_depth(&from) > winders_depth(&to) {
+            let w = from.unwrap();
+            afters.push((w.after.clone(), w.parent.clone()));
+            from = w.parent.clone();
+        }
+        while winders_depth(&to) > winders_depth(&from) {
+            let w = to.unwrap();
+            befores.push((w.before.clone(), w.parent.clone()));
+            to = w.parent.clone();
+        }
+        while !same_winders(&from, &to) {
+            let f = from.unwrap();
+            let t = to.unwrap();
+            afters.push((f.after.clone(), f.parent.clone()));
+            befores.push((t.before.clone(), t.parent.clone()));
+            from = f.parent.clone();
+            to = t.parent.clone();
+        }
+        let mut chunk = Chunk::new(None, Arity::exactly(0), 0);
+        for (thunk, winders) in afters.into_iter().chain(befores.into_iter().rev())
+            .chain(Some((Value::Continuation(k), target))) {
+            chunk.winders.push(winders);
+            chunk.emit(Op::SetWinders((chunk.winders.len() - 1) as u32));
+            let c = chunk.constant(thunk);
+            chunk.emit(Op::Const(c));
+            chunk.emit(Op::Call(0));
+            chunk.emit(Op::Pop);
+        }
+        // The last entry above only set the target winders; replace its
+        // call with one that passes the arguments along.
+        chunk.code.truncate(chunk.code.len() - 2);
+        let args = self.stack.split_off(callee + 1);
+        let argc = args.len();
+        for a in args {
+            let c = chunk.constant(a);
+            chunk.emit(Op::Const(c));
+        }
+        chunk.emit(Op::TailCall(argc as u32));
+        self.push_frame(Rc::new(chunk), None, callee);
+        Ok(())
+    }
+
+    /// Pops the current activation, leaving its result where its callee was.
+    /// Returns true when that was the activation `run` was entered with.
+    fn return_from(&mut self, depth: usize) -> bool {
+        let v = self.pop();
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.base);
+        self.stack.push(v);
+        self.frames.len() == depth
+    }
+
+    /// Runs until the activation at `depth` returns. An error is raised as
+    /// an error object if there is a handler for it.
+    fn run(&mut self, depth: usize) -> Result<Value> {
+        loop {
+            match self.run_until_error(depth) {
+                Err(e) => self.raise_error(depth, e)?,
+                res => return res,
+            }
+        }
+    }
+
+    /// Calls `raise` with an error object for `e` in the current dynamic
+    /// environment, or gives `e` back if no handler is installed. `raise`
+    /// does not return, so the state that the error left is never resumed.
+    fn raise_error(&mut self, depth: usize, e: RuntimeError) -> Result<()> {
+        let raise = self.globals.slot("%raise-non-continuable");
+        let raise = match (&self.handlers, self.globals.get(raise), &e) {
+            (Value::Pair(_), Some(raise), RuntimeError::Basic(_))
+                if self.frames.len() > depth => raise.clone(),
+            _ => return Err(e),
+        };
+        let message = match e {
+            RuntimeError::Basic(message) => message,
+            _ => unreachable!(),
+        };
+        let message = Value::String(self.heap.alloc(Object::String(message)));
+        let error = self.heap.alloc(Object::Error { message, irritants: Value::Nil });
+        let callee = self.stack.len();
+        self.stack.push(raise);
+        self.stack.push(Value::Error(error));
+        self.apply(callee)
+    }
+
+    fn check_limits(&mut self) -> Result<()> {
+        self.steps += 1;
+        self.fuel += 1;
+        if let Some(max) = self.limits.steps {
+            if self.steps > max {
+                return Err(RuntimeError::Limit(format!("Step limit of {} exceeded", max)));
+            }
+        }
+        if let Some(max) = self.limits.fuel {
+            if self.fuel > max {
+                return Err(RuntimeError::Limit(format!("Fuel of {} steps exhausted", max)));
+            }
+        }
+        if let Some(max) = self.limits.time {
+            if self.steps.is_multiple_of(CLOCK_INTERVAL) && self.started.elapsed() > max {
+                return Err(RuntimeError::Limit(format!("Time limit of {:?} exceeded", max)));
+            }
+        }
+        Ok(())
+    }
+
+    fn run_until_error(&mut self, depth: usize) -> Result<Value> {
+        loop {
+            self.check_limits()?;
+            let over = self.limits.heap.is_some_and(|max| self.heap.size() > max);
+            if over || self.heap.should_collect() {
+                self.collect();
+                if let Some(max) = self.limits.heap {
+                    if self.heap.size() > max {
+                        return Err(heap_limit(max));
+                    }
+                }
+            }
+            let (op, code) = {
+                let frame = self.frames.last_mut().unwrap();
+                let op = frame.code.code[frame.pc];
+                frame.pc += 1;
+                (op, frame.code.clone())
+            };
+            match op {
+                Op::Const(c) => self.stack.push(code.constants[c as usize].clone()),
+                Op::Unspecified => self.stack.push(Value::Unspecified),
+                Op::Global(g) => {
+                    let v = match self.globals.get(g) {
+                        Some(v) => v.clone(),
+                        None => return Err(RuntimeError::Basic(
+                            format!("Unbound variable {}", self.globals.name(g)))),
+                    };
+                    self.stack.push(v);
+                },
+                Op::DefineGlobal(g) => {
+                    let v = self.pop();
+                    self.globals.set(g, v);
+                },
+                Op::SetGlobal(g) => {
+                    if self.globals.get(g).is_none() {
+                        return Err(RuntimeError::Basic(
+                            format!("set!: Unbound variable {}", self.globals.name(g))));
+                    }
+                    let v = self.pop();
+                    self.globals.set(g, v);
+                },
+                Op::Local(d, i) => {
+                    let v = self.frame_slots(d)[i as usize].clone();
+                    self.stack.push(v);
+                },
+                Op::SetLocal(d, i) => {
+                    let v = self.pop();
+                    self.frame_slots(d)[i as usize] = v;
+                },
+                Op::Pop => {
+                    self.pop();
+                },
+                Op::Dup => {
+                    let v = self.stack.last().unwrap().clone();
+                    self.stack.push(v);
+                },
+                Op::Swap => {
+                    let n = self.stack.len();
+                    self.stack.swap(n - 1, n - 2);
+                },
+                Op::Jump(t) => self.frames.last_mut().unwrap().pc = t as usize,
+                Op::JumpIfFalse(t) =>
+                    if !self.pop().is_true() {
+                        self.frames.last_mut().unwrap().pc = t as usize;
+                    },
+                Op::AndJump(t) =>
+                    if self.stack.last().unwrap().is_true() {
+                        self.pop();
+                    } else {
+                        self.frames.last_mut().unwrap().pc = t as usize;
+                    },
+                Op::OrJump(t) =>
+                    if self.stack.last().unwrap().is_true() {
+                        self.frames.last_mut().unwrap().pc = t as usize;
+                    } else {
+                        self.pop();
+                    },
+                Op::CaseJump(t) => {
+                    let key = self.pop();
+                    let target = code.case_tables[t as usize].lookup(&key);
+                    self.frames.last_mut().unwrap().pc = target as usize;
+                },
+                Op::MakePromise(done) => {
+                    let value = self.pop();
+                    let promise = self.heap.alloc(Object::Promise { done, value });
+                    self.stack.push(Value::Promise(promise));
+                },
+                Op::Closure(l) => {
+                    let env = self.frames.last().unwrap().env;
+                    let closure = self.heap.alloc(Object::Closure(Closure {
+                        code: code.lambdas[l as usize].clone(),
+                        env,
+                    }));
+                    self.stack.push(Value::Closure(closure));
+                },
+                Op::Call(argc) => {
+                    let callee = self.stack.len() - argc as usize - 1;
+                    self.apply(callee)?;
+                    if self.frames.len() == depth {
+                        return Ok(self.pop());
+                    }
+                },
+                Op::TailCall(argc) => {
+                    let callee = self.stack.len() - argc as usize - 1;
+                    let frame = self.frames.pop().unwrap();
+                    let call = self.stack.split_off(callee);
+                    self.stack.truncate(frame.base);
+                    self.stack.extend(call);
+                    self.apply(frame.base)?;
+                    // A primitive or a continuation may have produced the
+                    // result of the outermost activation directly.
+                    if self.frames.len() == depth {
+                        return Ok(self.pop());
+                    }
+                },
+                Op::Return =>
+                    if self.return_from(depth) {
+                        return Ok(self.pop());
+                    },
+                Op::TimeStart => self.timers.push(Instant::now()),
+                Op::TimeEnd =>
+                    if let (Some(start), true) = (self.timers.pop(), self.report_time) {
+                        eprintln!("time: {:?} elapsed", start.elapsed());
+                    },
+                Op::PushWinder => {
+                    let after = self.pop();
+                    let before = self.pop();
+                    let parent = self.winders.take();
+                    let depth = winders_depth(&parent) + 1;
+                    self.winders = Some(Rc::new(Winder {
+                        before,
+                        after,
+                        parent,
+                        depth,
+                    }));
+                },
+                Op::PopWinder => {
+                    let parent = self.winders.as_ref().and_then(|w| w.parent.clone());
+                    self.winders = parent;
+                },
+                Op::SetWinders(w) => self.winders = code.winders[w as usize].clone(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use lexer::RegexLexer;
+    use parser::Parser;
+    use scheme::parse_expression;
+    use scheme::syntax::{Expression, Formals};
+    use super::Vm;
+    use value::{Value, Primitive, Arity, Result};
+
+    fn run_in(vm: &mut Vm, s: &str) -> Value {
+        let mut res = Value::Unspecified;
+        for node in Parser::new(RegexLexer::new(s)) {
+            let e = parse_expression(node.unwrap()).unwrap();
+            res = vm.eval(&e).unwrap();
+        }
+        res
+    }
+
+    fn show(vm: &mut Vm, s: &str) -> String {
+        let v = run_in(vm, s);
+        vm.write(&v)
+    }
+
+    fn run(s: &str) -> String {
+        show(&mut Vm::new(), s)
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(run("42"), "42");
+        assert_eq!(run("\"a\\nb\""), "\"a\\nb\"");
+        assert_eq!(run("(quote (1 (2 #t) x))"), "(1 (2 #t) x)");
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_eq!(run("(if #f 1 2)"), "2");
+        assert_eq!(run("(and 1 #f 3)"), "#f");
+        assert_eq!(run("(and 1 2 3)"), "3");
+        assert_eq!(run("(or #f 2 3)"), "2");
+        assert_eq!(run("(or)"), "#f");
+        assert_eq!(run("(unless #f 7)"), "7");
+        assert_eq!(run("(if #f 1)"), "#<unspecified>");
+        assert_eq!(run("(when 1 2 3)"), "3");
+        assert_eq!(run("(when #f 2 3)"), "#<unspecified>");
+    }
+
+    #[test]
+    fn assignment() {
+        assert_eq!(run("(define x 1) (set! x (+ x 1)) x"), "2");
+        assert_eq!(run("(define (counter) (let ((n 0)) (lambda () (set! n (+ n 1)) n))) \
+                        (define c (counter)) (c) (c)"), "2");
+        let mut vm = Vm::new();
+        for node in Parser::new(RegexLexer::new("(set! nowhere 1)")) {
+            let e = parse_expression(node.unwrap()).unwrap();
+            assert_eq!(vm.eval(&e).unwrap_err().to_string(),
+                       "RuntimeError: set!: Unbound variable nowhere");
+        }
+    }
+
+    #[test]
+    fn do_loop() {
+        assert_eq!(run("(do ((i 0 (+ i 1)) (acc (quote ()) (cons i acc))) ((= i 3) acc))"),
+                   "(2 1 0)");
+        assert_eq!(run("(define v (make-vector 3 0)) \
+                        (do ((i 0 (+ i 1))) ((= i 3)) (vector-set! v i (* i i))) v"),
+                   "#(0 1 4)");
+        assert_eq!(run("(do ((i 0 (+ i 1))) ((= i 100000) i))"), "100000");
+    }
+
+    #[test]
+    fn records() {
+        let mut vm = Vm::new();
+        run_in(&mut vm, "(define-record-type point (make-point y x) point? \
+                           (x point-x set-point-x!) (y point-y) (z point-z))");
+        run_in(&mut vm, "(define p (make-point 2 1))");
+        assert_eq!(show(&mut vm, "(list (point-x p) (point-y p) (point? p) (point? 1))"),
+                   "(1 2 #t #f)");
+        assert_eq!(show(&mut vm, "(set-point-x! p 5) (point-x p)"), "5");
+        assert_eq!(show(&mut vm, "(list p point)"), "(#<record point> #<record-type point>)");
+        assert_eq!(show(&mut vm, "(point-z p)"), "#<unspecified>");
+        run_in(&mut vm, "(define-record-type point (make-other) other?)");
+        assert_eq!(show(&mut vm, "(list (other? (make-other)) (other? p))"), "(#t #f)");
+        let e = parse_expression(Parser::new(RegexLexer::new("(point-x (make-other))"))
+                                 .next().unwrap().unwrap()).unwrap();
+        assert_eq!(vm.eval(&e).unwrap_err().to_string(),
+                   "RuntimeError: Expected record of type point, got record #<record point>");
+        assert_eq!(run("(define (f) (define-record-type box (box v) box? (v unbox)) (unbox (box 3))) \
+                        (f)"), "3");
+    }
+
+    #[test]
+    fn multiple_values() {
+        assert_eq!(run("(call-with-values (lambda () (values 1 2)) cons)"), "(1 . 2)");
+        assert_eq!(run("(call-with-values (lambda () 1) list)"), "(1)");
+        assert_eq!(run("(let-values (((q r) (values 5 2)) (all (values)) ((x) 3)) \
+                          (list q r all x))"), "(5 2 () 3)");
+        assert_eq!(run("(let ((x 1)) (let*-values (((x y) (values 2 x)) ((z) (+ x y))) \
+                          (list x y z)))"), "(2 1 3)");
+        assert_eq!(run("(define-values (a . b) (values 1 2 3)) (list a b)"), "(1 (2 3))");
+        assert_eq!(run("(define (f) (define-values (x y) (values 1 2)) (+ x y)) (f)"), "3");
+        assert_eq!(run("(receive (a . rest) (values 1 2 3) (list a rest))"), "(1 (2 3))");
+        assert_eq!(run("(define (car x) 0) (define (cdr x) 0) (define (apply . x) 0) \
+                        (define (append . x) 0) (define-values (a b) (values 1 2)) \
+                        (let-values (((c) (values 3)) ((d . e) (values 4 5))) (list a b c d e))"),
+                   "(1 2 3 4 (5))");
+        assert_eq!(run("(call-with-values (lambda () (call/cc (lambda (k) (k 1 2)))) list)"),
+                   "(1 2)");
+        assert_eq!(run("(values 1 2)"), "1 2");
+        let mut vm = Vm::new();
+        let e = parse_expression(Parser::new(RegexLexer::new("(receive (a b) (values 1) a)"))
+                                 .next().unwrap().unwrap()).unwrap();
+        assert_eq!(vm.eval(&e).unwrap_err().to_string(),
+                   "RuntimeError: Wrong number of values: expected 2, got 1");
+    }
+
+    #[test]
+    fn exceptions() {
+        assert_eq!(run("(guard (e ((symbol? e) (list e)) ((number? e) e)) (raise 1))"), "1");
+        assert_eq!(run("(guard (e ((number? e) (* e 10))) (guard (e ((symbol? e) e)) (raise 2)))"),
+                   "20");
+        assert_eq!(run("(guard (e ((error-object? e) \
+                                   (cons (error-object-message e) (error-object-irritants e)))) \
+                          (error \"bad\" 1 2))"), "(\"bad\" 1 2)");
+        assert_eq!(run("(guard (e (#t (error-object-message e))) (vector-ref (vector) 0))"),
+                   "\"Vector index out of range: 0\"");
+        assert_eq!(run("(with-exception-handler (lambda (c) (* c 2)) \
+                          (lambda () (+ 1 (raise-continuable 20))))"), "41");
+        assert_eq!(run("(guard (e (#t (error-object-irritants e))) \
+                          (with-exception-handler (lambda (c) 0) (lambda () (raise 5))))"), "(5)");
+        assert_eq!(run("(define (f n) (if (= n 0) 0 (guard (e (#t (f (- n 1)))) (raise n)))) \
+                        (f 1000)"), "0");
+        let mut vm = Vm::new();
+        for &(text, message) in &[("(raise (list 1))", "Uncaught exception: (1)"),
+                                    ("(error \"Oops:\" \"a\" 1)", "Oops: \"a\" 1"),
+                                    ("(guard (e ((string? e) 0)) (car 1))",
+                                     "Expected pair, got number 1")] {
+            let e = parse_expression(
+                Parser::new(RegexLexer::new(text)).next().unwrap().unwrap()).unwrap();
+            assert_eq!(vm.eval(&e).unwrap_err().to_string(), format!("RuntimeError: {}", message));
+        }
+        assert_eq!(show(&mut vm, "(guard (e (#t e)) (raise 3))"), "3");
+        run_in(&mut vm, "(define (raise obj) 0) (define (raise-continuable obj) 0)");
+        assert_eq!(show(&mut vm, "(guard (e ((error-object? e) (error-object-message e))) \
+                                  (guard (e ((string? e) 0)) (error \"bad\")))"), "\"bad\"");
+        assert_eq!(show(&mut vm, "(guard (e ((symbol? e) 0) (#t (error-object-message e))) (car 1))"),
+                   "\"Expected pair, got number 1\"");
+    }
+
+    #[test]
+    fn parameters() {
+        let mut vm = Vm::new();
+        run_in(&mut vm, "(define radix (make-parameter 10)) \
+                         (define width (make-parameter 5 (lambda (x) (* x 2))))");
+        assert_eq!(show(&mut vm, "(list (radix) (width))"), "(10 10)");
+        assert_eq!(show(&mut vm, "(parameterize ((radix 2) (width 3)) (list (radix) (width)))"),
+                   "(2 6)");
+        assert_eq!(show(&mut vm, "(list (radix) (width))"), "(10 10)");
+        assert_eq!(show(&mut vm, "(call/cc (lambda (k) (parameterize ((radix 8)) (k (radix)))))"),
+                   "8");
+        assert_eq!(show(&mut vm, "(radix)"), "10");
+        run_in(&mut vm, "(define (dynamic-wind before thunk after) 0)");
+        assert_eq!(show(&mut vm, "(parameterize ((radix 2)) (radix))"), "2");
+        assert_eq!(show(&mut vm, "(with-exception-handler (lambda (e) 1) (lambda () 2))"), "2");
+    }
+
+    #[test]
+    fn promises() {
+        assert_eq!(run("(define n 0) (define p (delay (begin (set! n (+ n 1)) n))) \
+                        (list (force p) (force p) n)"), "(1 1 1)");
+        assert_eq!(run("(force (make-promise 5))"), "5");
+        assert_eq!(run("(force 5)"), "5");
+        assert_eq!(run("(list (promise? (delay 1)) (promise? 1) (delay 1))"),
+                   "(#t #f #<promise>)");
+        assert_eq!(run("(define (stream-tail s n) \
+                          (delay-force (if (= n 0) s (stream-tail s (- n 1))))) \
+                        (force (stream-tail (delay 7) 100000))"), "7");
+    }
+
+    #[test]
+    fn cond() {
+        assert_eq!(run("(cond (#f 1) ((+ 1 1) => (lambda (x) (* x 10))) (else 3))"), "20");
+        assert_eq!(run("(cond (#f 1) (5) (else 3))"), "5");
+        assert_eq!(run("(cond (#f 1) (else 3))"), "3");
+        assert_eq!(run("(cond (#t (define x 1) (+ x 1)) (else 3))"), "2");
+        assert_eq!(run("(cond (#f 1) (else 2 3))"), "3");
+        assert_eq!(run("(cond (#f 1))"), "#<unspecified>");
+    }
+
+    #[test]
+    fn case() {
+        let prog = "(define (f x) (case x ((1 2) (quote low)) ((a #\\b) (quote sym)) (else (quote other))))";
+        let mut vm = Vm::new();
+        run_in(&mut vm, prog);
+        assert_eq!(show(&mut vm, "(f 2)"), "low");
+        assert_eq!(show(&mut vm, "(f (quote a))"), "sym");
+        assert_eq!(show(&mut vm, "(f #\\b)"), "sym");
+        assert_eq!(show(&mut vm, "(f 3)"), "other");
+        run_in(&mut vm, "(define (g x) (case x ((1) (quote one) (quote uno)) ((2) => -) \
+                                        (else => (lambda (y) (* y 10)))))");
+        assert_eq!(show(&mut vm, "(g 1)"), "uno");
+        assert_eq!(show(&mut vm, "(g 2)"), "-2");
+        assert_eq!(show(&mut vm, "(g 3)"), "30");
+        run_in(&mut vm, "(define (h x) (case x ((1) => -)))");
+        assert_eq!(show(&mut vm, "(h 1)"), "-1");
+        assert_eq!(show(&mut vm, "(h 2)"), "#<unspecified>");
+    }
+
+    #[test]
+    fn closures() {
+        assert_eq!(run("(define (adder n) (lambda (x) (+ x n))) ((adder 3) 4)"), "7");
+        assert_eq!(run("((lambda args args) 1 2 3)"), "(1 2 3)");
+        assert_eq!(run("(let ((x 1) (y 2)) (define z 3) (+ x y z))"), "6");
+    }
+
+    #[test]
+    fn tail_calls() {
+        assert_eq!(run("(let loop ((i 0)) (if (= i 1000000) i (loop (+ i 1))))"), "1000000");
+        assert_eq!(run("(define (even n) (if (= n 0) #t (odd (- n 1)))) \
+                        (define (odd n) (if (= n 0) #f (even (- n 1)))) \
+                        (even 100001)"), "#f");
+    }
+
+    #[test]
+    fn errors() {
+        let mut vm = Vm::new();
+        let e = parse_expression(
+            Parser::new(RegexLexer::new("((lambda (x) x))")).next().unwrap().unwrap()).unwrap();
+        assert_eq!(format!("{}", vm.eval(&e).unwrap_err()),
+                   "RuntimeError: Wrong number of arguments: expected 1, got 0");
+        assert_eq!(show(&mut vm, "(+ 1 2)"), "3");
+    }
+
+    #[test]
+    fn disassemble() {
+        let mut vm = Vm::new();
+        let e = parse_expression(
+            Parser::new(RegexLexer::new("(case 1 ((1) 2) (else 3))")).next().unwrap().unwrap())
+            .unwrap();
+        let chunk = vm.compile(&e).unwrap();
+        let listing = vm.disassemble(&chunk);
+        assert!(listing.contains("CaseJump(0)\t; Number(1) -> 2 else -> 4"), "{}", listing);
+    }
+
+    #[test]
+    fn deep_recursion() {
+        assert_eq!(run("(define (count n) (if (= n 0) 0 (+ 1 (count (- n 1))))) \
+                        (count 200000)"), "200000");
+    }
+
+    #[test]
+    fn call_cc_escape() {
+        assert_eq!(run("(+ 1 (call/cc (lambda (k) (+ 10 (k 1)))))"), "2");
+        assert_eq!(run("(call-with-current-continuation (lambda (k) 5))"), "5");
+        assert_eq!(run("(let loop ((i 0)) \
+                          (if (= i 100000) i (loop (call/cc (lambda (k) (k (+ i 1)))))))"),
+                   "100000");
+    }
+
+    #[test]
+    fn call_cc_reentry() {
+        assert_eq!(run("(let ((x (call/cc (lambda (k) k)))) (x (lambda (y) 42)))"), "42");
+    }
+
+    #[test]
+    fn call_ec() {
+        assert_eq!(run("(+ 1 (call/ec (lambda (k) (+ 10 (k 1)))))"), "2");
+        let mut vm = Vm::new();
+        run_in(&mut vm, "(define k (call/ec (lambda (k) k)))");
+        let e = parse_expression(
+            Parser::new(RegexLexer::new("(k 1)")).next().unwrap().unwrap()).unwrap();
+        assert_eq!(format!("{}", vm.eval(&e).unwrap_err()),
+                   "RuntimeError: Escape continuation invoked outside its extent");
+    }
+
+    thread_local! {
+        static TRACE: RefCell<Vec<i32>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn trace(_: &mut Vm, args: &[Value]) -> Result<Value> {
+        if let Value::Number(n) = args[0] {
+            TRACE.with(|t| t.borrow_mut().push(n));
+        }
+        Ok(Value::Unspecified)
+    }
+
+    fn traced(s: &str) -> (String, Vec<i32>) {
+        let mut vm = Vm::new();
+        vm.define("trace", Value::Primitive(Rc::new(Primitive {
+            name: "trace",
+            arity: Arity::exactly(1),
+            func: trace,
+        })));
+        TRACE.with(|t| t.borrow_mut().clear());
+        let v = show(&mut vm, s);
+        (v, TRACE.with(|t| t.borrow().clone()))
+    }
+
+    #[test]
+    fn dynamic_wind() {
+        assert_eq!(traced("(dynamic-wind (lambda () (trace 1)) \
+                                         (lambda () (trace 2) 3) \
+                                         (lambda () (trace 4)))"),
+                   ("3".to_string(), vec![1, 2, 4]));
+        assert_eq!(traced("(call/cc (lambda (k) \
+                             (dynamic-wind (lambda () (trace 1)) \
+                                           (lambda () (k 2) (trace 3)) \
+                                           (lambda () (trace 4)))))"),
+                   ("2".to_string(), vec![1, 4]));
+    }
+
+    #[test]
+    fn dynamic_wind_reentry() {
+        // Jumping back into the extent runs `before` again.
+        assert_eq!(traced("(let ((k (dynamic-wind \
+                                      (lambda () (trace 1)) \
+                                      (lambda () (call/cc (lambda (k) k))) \
+                                      (lambda () (trace 2))))) \
+                             (case k ((7) k) (else (k 7))))"),
+                   ("7".to_string(), vec![1, 2, 1, 2]));
+    }
+
+    #[test]
+    fn mutation() {
+        assert_eq!(run("(let ((p (cons 1 2))) (set-car! p 3) p)"), "(3 . 2)");
+        assert_eq!(run("(let ((v (vector 1 2))) (vector-set! v 0 (quote a)) v)"), "#(a 2)");
+        assert_eq!(run("(let ((p (list 1 2))) (set-cdr! (cdr p) p) p)"), "(1 2 . ...)");
+        assert_eq!(run("(let ((x (list 1))) (eq? x (car (list x))))"), "#t");
+    }
+
+    #[test]
+    fn garbage_is_collected() {
+        let mut vm = Vm::new();
+        run_in(&mut vm, "(define keep (list 1 2 3)) \
+                         (define (churn n) \
+                           (if (= n 0) 0 \
+                               (let ((p (cons n n))) (set-cdr! p p) (churn (- n 1)))))");
+        run_in(&mut vm, "(churn 100000)");
+        assert!(vm.heap.live_objects() < 10000, "{}", vm.heap.live_objects());
+        vm.collect();
+        assert_eq!(show(&mut vm, "keep"), "(1 2 3)");
+    }
+
+    #[test]
+    fn too_many_variables() {
+        let params: Vec<String> = (0..70000).map(|i| format!("x{}", i)).collect();
+        let e = Expression::Lambda(Formals { required: params, rest: None },
+                                   vec![Expression::Variable("x69999".to_string())]);
+        assert_eq!(Vm::new().compile(&e).err().unwrap().to_string(),
+                   "RuntimeError: Too many variables or nested scopes to compile");
+    }
+
+    #[test]
+    fn constants_survive_collection() {
+        let mut vm = Vm::new();
+        run_in(&mut vm, "(define (f) (quote (a \"b\" (c))))");
+        let e = parse_expression(
+            Parser::new(RegexLexer::new("(quote (pending))")).next().unwrap().unwrap()).unwrap();
+        let pending = vm.compile(&e).unwrap();
+        vm.collect();
+        run_in(&mut vm, "(define k #f) (call/cc (lambda (c) (define k c) 1))");
+        vm.collect();
+        assert_eq!(show(&mut vm, "(f)"), "(a \"b\" (c))");
+        let v = vm.execute(pending).unwrap();
+        assert_eq!(vm.write(&v), "(pending)");
+    }
+}